@@ -0,0 +1,49 @@
+use std::time::Instant;
+
+use sqrldb::Database;
+
+/*
+    Times queries that read a whole table, the ones the batched executor is
+    for. Run with
+
+        cargo bench --bench scan [rows]
+
+    which loads `rows` rows, ten million when not given, into an in memory
+    table and runs each query a few times, printing the fastest run.
+ */
+
+const QUERIES: [&str; 5] = [
+    "select count(*) from t",
+    "select count(*) from t where n > 500000 and g = 3",
+    "select sum(n), min(x), max(x), avg(x) from t",
+    "select g, count(*), sum(x) from t group by g",
+    "select n + 1, x * 2 from t where g = 0 limit 10",
+];
+
+const RUNS: usize = 3;
+
+fn main() {
+    // cargo passes --bench along with anything given after --
+    let rows: usize = std::env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(10_000_000);
+
+    let db = Database::in_memory();
+    db.execute("create table t (n int, g int, x real)").unwrap();
+    let start = Instant::now();
+    for chunk in (0..rows).collect::<Vec<_>>().chunks(10_000) {
+        let values: Vec<String> = chunk.iter().map(|n| format!("({}, {}, {}.5)", n, n % 10, n % 1000)).collect();
+        db.execute(&format!("insert into t values {}", values.join(", "))).unwrap();
+    }
+    println!("loaded {} rows in {:.2?}", rows, start.elapsed());
+
+    for sql in QUERIES {
+        let mut fastest = None;
+        for _ in 0..RUNS {
+            let start = Instant::now();
+            let count = db.query(sql).unwrap().count();
+            let elapsed = start.elapsed();
+            fastest = Some(fastest.map_or(elapsed, |fastest: std::time::Duration| fastest.min(elapsed)));
+            assert!(count > 0, "{} returned no rows", sql);
+        }
+        println!("{:>10.2?}  {}", fastest.unwrap(), sql);
+    }
+}
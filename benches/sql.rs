@@ -0,0 +1,123 @@
+use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
+use sqrldb::Database;
+use sqrldb::lexer::lex;
+use sqrldb::parser::{parse, split_statements};
+
+/*
+    Synthetic workloads, each timed through the lexer, the parser and
+    execution so a change to any one of them shows up on its own:
+
+        wide insert    one insert of 1000 rows of eight columns of every kind
+        scan           filters, aggregates and sorts over a 100000 row table
+        deep expr      a select whose expression nests 64 parentheses deep
+                       around a chain of 500 additions
+
+    Criterion keeps the last run's numbers, so running the suite before and
+    after a change reports the difference:
+
+        cargo bench --bench sql -- --save-baseline before
+        cargo bench --bench sql -- --baseline before
+ */
+
+const SCAN_ROWS: usize = 100_000;
+
+fn wide_insert() -> String {
+    let rows: Vec<String> = (0..1000)
+        .map(|i| {
+            format!(
+                "({}, {}.25, 'name {}', {}, null, date '2024-01-{:02}', 'it''s {}', {})",
+                i,
+                i,
+                i,
+                i % 2 == 0,
+                i % 28 + 1,
+                i,
+                -i
+            )
+        })
+        .collect();
+    format!("insert into wide values {}", rows.join(", "))
+}
+
+const WIDE_TABLE: &str = "create table wide (id int, score real, name text, flag boolean, missing int, day date,
+                          note varchar(20), neg bigint)";
+
+fn deep_expr() -> String {
+    let chain: Vec<String> = (0..500).map(|i| format!("n * {}", i % 7)).collect();
+    format!("select {}{}{} from t where n = 1", "(1 + ".repeat(64), chain.join(" + "), ")".repeat(64))
+}
+
+const SCANS: [&str; 4] = [
+    "select count(*) from t where n > 50000 and g = 3",
+    "select g, count(*), sum(x), avg(x) from t group by g",
+    "select n, x from t where g = 1 order by x desc limit 10",
+    "select n + g * 2, x / 2 from t where x > 500",
+];
+
+fn scan_database() -> Database {
+    let db = Database::in_memory();
+    db.execute("create table t (n int, g int, x real)").unwrap();
+    let rows: Vec<String> = (0..SCAN_ROWS).map(|n| format!("({}, {}, {}.5)", n, n % 10, n % 1000)).collect();
+    for chunk in rows.chunks(10_000) {
+        db.execute(&format!("insert into t values {}", chunk.join(", "))).unwrap();
+    }
+    db
+}
+
+fn lexer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lex");
+    for (name, sql) in [("wide insert", wide_insert()), ("deep expr", deep_expr())] {
+        group.bench_function(name, |b| b.iter(|| lex(black_box(&sql)).unwrap()));
+    }
+    group.finish();
+}
+
+fn parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, sql) in [("wide insert", wide_insert()), ("deep expr", deep_expr())] {
+        let tokens = lex(&sql).unwrap();
+        group.bench_function(name, |b| b.iter_batched(|| tokens.clone(), parse, BatchSize::SmallInput));
+    }
+    let script = SCANS.join("; ");
+    let tokens = lex(&script).unwrap();
+    group.bench_function("scans", |b| {
+        b.iter_batched(
+            || tokens.clone(),
+            |tokens| {
+                let statements = split_statements(tokens).into_iter().map(|statement| parse(statement).unwrap());
+                statements.collect::<Vec<_>>()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn execution(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    group.sample_size(20);
+
+    let insert = wide_insert();
+    group.bench_function("wide insert", |b| {
+        b.iter_batched(
+            || {
+                let db = Database::in_memory();
+                db.execute(WIDE_TABLE).unwrap();
+                db
+            },
+            |db| db.execute(&insert).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let db = scan_database();
+    for (i, sql) in SCANS.iter().enumerate() {
+        group.bench_function(format!("scan {}", i + 1), |b| b.iter(|| db.query(sql).unwrap().count()));
+    }
+    let deep = deep_expr();
+    group.bench_function("deep expr", |b| b.iter(|| db.query(&deep).unwrap().count()));
+    group.finish();
+}
+
+criterion_group!(benches, lexer, parser, execution);
+criterion_main!(benches);
@@ -0,0 +1,3862 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::lexer::Location;
+use crate::parser::{AlterOp, Assignment, BinaryOp, ColumnConstraint, ColumnDef, CopyDirection, DataType, Expr, FunctionArgs, InsertSource, QualifiedName, Select, Statement, TransactionOp, UnaryOp};
+
+mod aggregate;
+mod cancel;
+mod cast;
+mod catalog;
+mod csv;
+mod disk;
+mod dump;
+mod engine;
+mod foreign_key;
+mod function;
+mod index;
+mod json;
+mod log;
+mod pager;
+mod plan;
+mod planner;
+mod storage;
+mod temporal;
+mod wal;
+
+pub use cancel::{CancelToken, with_cancellation};
+pub use disk::PagerStorage;
+pub use dump::dump;
+pub use engine::{Engine, register_engine};
+pub use log::LogStorage;
+pub use pager::Backup;
+pub use storage::{IndexDef, Storage, StorageBackend, TableDef};
+pub(crate) use function::{UserFunctions, with_user_functions};
+#[cfg(test)]
+pub(crate) use pager::temp_path;
+use foreign_key::Schema;
+use index::{Index, candidates};
+pub(crate) use json::Json;
+
+/*
+    A Backend is where statements end up once they are parsed. The trait only
+    knows about tables, rows and the AST so that a disk based store can slot in
+    next to the in memory one without the parser or callers changing. Below
+    it, the Storage trait of the storage module is all a new storage engine
+    has to implement, and the engine module picks one by name.
+ */
+
+// Int holds both INT and BIGINT values, Real is a REAL. Null is missing
+// data and fits a column of any type. The temporal values are stored as
+// counts, see the temporal module. Json holds the compact text of a valid
+// document, see the json module.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Bool(bool),
+    Null,
+    Date(i32),
+    Time(i64),
+    Timestamp(i64),
+    Json(String),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Real(_) => "real",
+            Value::Text(_) => "text",
+            Value::Bool(_) => "bool",
+            Value::Null => "null",
+            Value::Date(_) => "date",
+            Value::Time(_) => "time",
+            Value::Timestamp(_) => "timestamp",
+            Value::Json(_) => "json",
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Int(_) => 0,
+            Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Bool(_) => 3,
+            Value::Null => 4,
+            Value::Date(_) => 5,
+            Value::Time(_) => 6,
+            Value::Timestamp(_) => 7,
+            Value::Json(_) => 8,
+        }
+    }
+}
+
+/*
+    Values are ordered and hashed so that indexes and unique checks can key
+    on them. Only values of the same type end up next to each other, so the
+    order between types just has to be consistent. Reals use their total
+    order, which only differs from numeric order on NaN and negative zero,
+    and arithmetic never produces either.
+ */
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => l.cmp(r),
+            (Value::Real(l), Value::Real(r)) => l.total_cmp(r),
+            (Value::Text(l), Value::Text(r)) | (Value::Json(l), Value::Json(r)) => l.cmp(r),
+            (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+            (Value::Date(l), Value::Date(r)) => l.cmp(r),
+            (Value::Time(l), Value::Time(r)) | (Value::Timestamp(l), Value::Timestamp(r)) => l.cmp(r),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Value {}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+        match self {
+            Value::Int(i) => i.hash(state),
+            Value::Real(r) => r.to_bits().hash(state),
+            Value::Text(s) | Value::Json(s) => s.hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Null => {}
+            Value::Date(d) => d.hash(state),
+            Value::Time(t) | Value::Timestamp(t) => t.hash(state),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            // Debug keeps the ".0" of whole numbers, so 1.0 does not read as an int
+            Value::Real(r) => write!(f, "{:?}", r),
+            Value::Text(s) | Value::Json(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Null => write!(f, "NULL"),
+            Value::Date(d) => write!(f, "{}", temporal::format_date(*d)),
+            Value::Time(t) => write!(f, "{}", temporal::format_time(*t)),
+            Value::Timestamp(t) => write!(f, "{}", temporal::format_timestamp(*t)),
+        }
+    }
+}
+
+// The rows a select produced, with one name per column in the same order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+// Renders the result as an ASCII table with every column padded to its
+// widest value, the way psql and sqlite's box mode print them
+impl fmt::Display for ResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|v| v.to_string()).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(name.chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let border: String = widths.iter().map(|w| format!("+{}", "-".repeat(w + 2))).collect::<String>() + "+";
+        let line = |f: &mut fmt::Formatter<'_>, row: &[String]| -> fmt::Result {
+            for (cell, width) in row.iter().zip(&widths) {
+                write!(f, "| {}{} ", cell, " ".repeat(width - cell.chars().count()))?;
+            }
+            writeln!(f, "|")
+        };
+
+        writeln!(f, "{}", border)?;
+        line(f, &self.columns)?;
+        writeln!(f, "{}", border)?;
+        for row in &cells {
+            line(f, row)?;
+        }
+        write!(f, "{}", border)
+    }
+}
+
+// Which constraint a statement broke, so callers can tell a duplicate key
+// apart from other errors without matching on the message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    pub constraint: ColumnConstraint,
+    pub table: String,
+    pub column: String,
+}
+
+// What went wrong, for callers that handle some errors and not others. A
+// broken constraint is told apart by its violation instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    UndefinedTable,
+    UndefinedColumn,
+    // No function or operator takes arguments of those types
+    UndefinedFunction,
+    DatatypeMismatch,
+    // A column outside of GROUP BY, or an aggregate where none is allowed
+    Grouping,
+    DivisionByZero,
+    OutOfRange,
+    // Text that does not read as a value of the type
+    InvalidText,
+    TransactionActive,
+    NoTransaction,
+    // A statement in a transaction failed, see the server
+    TransactionAborted,
+    // Another transaction changed what this one wrote, it can be run again
+    SerializationFailure,
+    NotAllowed,
+    // The statement was stopped before it finished, see cancel
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendError {
+    message: String,
+    loc: Location,
+    // Boxed, a foreign key would make every error as large as it is
+    violation: Option<Box<ConstraintViolation>>,
+    kind: ErrorKind,
+}
+
+impl BackendError {
+    pub fn new(message: impl Into<String>, loc: Location) -> BackendError {
+        BackendError { message: message.into(), loc, violation: None, kind: ErrorKind::Other }
+    }
+
+    pub fn of_kind(kind: ErrorKind, message: impl Into<String>, loc: Location) -> BackendError {
+        BackendError { message: message.into(), loc, violation: None, kind }
+    }
+
+    pub fn constraint(violation: ConstraintViolation, message: impl Into<String>, loc: Location) -> BackendError {
+        BackendError { message: message.into(), loc, violation: Some(Box::new(violation)), kind: ErrorKind::Other }
+    }
+
+    pub fn cancelled(message: impl Into<String>, loc: Location) -> BackendError {
+        BackendError::of_kind(ErrorKind::Cancelled, message, loc)
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> Location {
+        self.loc
+    }
+
+    pub fn violation(&self) -> Option<&ConstraintViolation> {
+        self.violation.as_deref()
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.kind == ErrorKind::Cancelled
+    }
+}
+
+// An error computing a value, which the caller places where the value was
+// asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl ValueError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> ValueError {
+        ValueError { kind, message: message.into() }
+    }
+
+    pub fn at(self, loc: Location) -> BackendError {
+        BackendError::of_kind(self.kind, self.message, loc)
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.loc.line(), self.loc.col())
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+// What running a statement produced. Statements that change rows report how
+// many they touched, the way psql prints "UPDATE 3".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResult {
+    Rows(ResultSet),
+    Affected(usize),
+    Done,
+}
+
+// The insert, update and delete methods return the number of rows affected
+// The rows an insert stores, handed over one at a time so the statement
+// never holds them in another form as well. A row that could not be made
+// fails the insert.
+pub type InsertRows<'a> = dyn Iterator<Item = Result<Vec<Value>, BackendError>> + 'a;
+
+// Where the rows of an insert come from: rows the statement made, or a
+// select, planned by the backend and run as the insert takes its rows
+pub enum InsertFrom<'r> {
+    Rows(&'r mut InsertRows<'r>),
+    Select(&'r Select),
+}
+
+pub trait Backend {
+    fn create_table(&mut self, name: &QualifiedName, columns: &[ColumnDef]) -> Result<(), BackendError>;
+    fn create_index(
+        &mut self,
+        name: &QualifiedName,
+        table: &QualifiedName,
+        column: &QualifiedName,
+    ) -> Result<(), BackendError>;
+    // `columns` are the ones each row gives values for, every column in
+    // order when it is empty
+    fn insert(
+        &mut self,
+        table: &QualifiedName,
+        columns: &[QualifiedName],
+        rows: InsertFrom,
+    ) -> Result<usize, BackendError>;
+    fn select(&self, query: &Select) -> Result<ResultSet, BackendError>;
+    fn explain(&self, query: &Select) -> Result<ResultSet, BackendError>;
+    fn update(
+        &mut self,
+        table: &QualifiedName,
+        assignments: &[Assignment],
+        filter: Option<&Expr>,
+    ) -> Result<usize, BackendError>;
+    fn delete(&mut self, table: &QualifiedName, filter: Option<&Expr>) -> Result<usize, BackendError>;
+    fn columns(&self, table: &QualifiedName) -> Result<Vec<ColumnDef>, BackendError>;
+    fn alter_table(&mut self, table: &QualifiedName, op: &AlterOp) -> Result<(), BackendError>;
+    // Drops the table's indexes along with it
+    fn drop_table(&mut self, name: &QualifiedName) -> Result<(), BackendError>;
+    fn has_index(&self, name: &QualifiedName) -> bool;
+    // Views share their names with tables, and are read but never written
+    fn create_view(&mut self, name: &QualifiedName, query: &Select) -> Result<(), BackendError>;
+    fn drop_view(&mut self, name: &QualifiedName) -> Result<(), BackendError>;
+    fn has_view(&self, name: &QualifiedName) -> bool;
+
+    // Between begin and commit nothing a statement changes is kept for
+    // good, and rollback undoes all of it. Transactions do not nest, `loc`
+    // is where the statement was written, for reporting misuse. A commit
+    // can also fail when another session changed the same data first, the
+    // transaction is over either way.
+    fn begin(&mut self, loc: Location) -> Result<(), BackendError>;
+    fn commit(&mut self, loc: Location) -> Result<(), BackendError>;
+    fn rollback(&mut self, loc: Location) -> Result<(), BackendError>;
+
+    // Another connection to the same tables, with transactions of its own,
+    // for a backend that can serve several at once
+    fn connect(&self) -> Option<Box<dyn Backend + Send>> {
+        None
+    }
+
+    // The committed tables, to be copied into a new database file once the
+    // backend is free again, and replaces every table with the ones of such
+    // a copy, for a backend that keeps its tables in a file
+    fn backup(&mut self) -> io::Result<Backup> {
+        Err(not_in_a_file())
+    }
+
+    fn restore(&mut self, _path: &Path) -> io::Result<()> {
+        Err(not_in_a_file())
+    }
+}
+
+fn not_in_a_file() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Only a database kept in a file can be backed up or restored")
+}
+
+fn already_in_transaction(loc: Location) -> BackendError {
+    BackendError::of_kind(ErrorKind::TransactionActive, "A transaction is already in progress", loc)
+}
+
+fn no_transaction(loc: Location) -> BackendError {
+    BackendError::of_kind(ErrorKind::NoTransaction, "No transaction in progress", loc)
+}
+
+pub fn execute(backend: &mut dyn Backend, statement: &Statement) -> Result<QueryResult, BackendError> {
+    match statement {
+        Statement::Select(query) => backend.select(query).map(QueryResult::Rows),
+        Statement::Explain(query) => backend.explain(query).map(QueryResult::Rows),
+        Statement::CreateTable { name, .. } if catalog::is_catalog(name) => Err(BackendError::new(
+            format!("Cannot create {}, {} is read only", name, catalog::SCHEMA),
+            name.loc,
+        )),
+        Statement::CreateTable { name, if_not_exists: true, .. }
+            if backend.columns(name).is_ok() || backend.has_view(name) =>
+        {
+            Ok(QueryResult::Done)
+        }
+        Statement::CreateTable { name, columns, .. } => backend.create_table(name, columns).map(|_| QueryResult::Done),
+        Statement::CreateIndex { name, if_not_exists: true, .. } if backend.has_index(name) => Ok(QueryResult::Done),
+        Statement::CreateIndex { name, table, column, .. } => {
+            backend.create_index(name, table, column).map(|_| QueryResult::Done)
+        }
+        Statement::DropTable { name, if_exists: true } if backend.columns(name).is_err() && !backend.has_view(name) => {
+            Ok(QueryResult::Done)
+        }
+        Statement::DropTable { name, .. } => backend.drop_table(name).map(|_| QueryResult::Done),
+        Statement::CreateView { name, .. } if catalog::is_catalog(name) => Err(BackendError::new(
+            format!("Cannot create {}, {} is read only", name, catalog::SCHEMA),
+            name.loc,
+        )),
+        Statement::CreateView { name, query } => backend.create_view(name, query).map(|_| QueryResult::Done),
+        Statement::DropView { name, if_exists: true } if !backend.has_view(name) => Ok(QueryResult::Done),
+        Statement::DropView { name, .. } => backend.drop_view(name).map(|_| QueryResult::Done),
+        Statement::Insert { table, columns, source: InsertSource::Values(rows) } => {
+            let mut select = |query: &Select| backend.select(query);
+            let rows = rows
+                .iter()
+                .map(|row| row.iter().map(|expr| run_subqueries(expr, &mut select)).collect())
+                .collect::<Result<Vec<Vec<Expr>>, _>>()?;
+            let mut values = rows.iter().map(|row| row.iter().map(|expr| eval(expr, None, table.loc)).collect());
+            backend.insert(table, columns, InsertFrom::Rows(&mut values)).map(QueryResult::Affected)
+        }
+        Statement::Insert { table, columns, source: InsertSource::Query(query) } => {
+            backend.insert(table, columns, InsertFrom::Select(query)).map(QueryResult::Affected)
+        }
+        Statement::Update { table, assignments, filter } => {
+            let mut select = |query: &Select| backend.select(query);
+            let assignments = assignments
+                .iter()
+                .map(|a| Ok(Assignment { value: run_subqueries(&a.value, &mut select)?, ..a.clone() }))
+                .collect::<Result<Vec<_>, BackendError>>()?;
+            let filter = filter.as_ref().map(|filter| run_subqueries(filter, &mut select)).transpose()?;
+            backend.update(table, &assignments, filter.as_ref()).map(QueryResult::Affected)
+        }
+        Statement::Delete { table, filter } => {
+            let filter = filter.as_ref().map(|filter| run_subqueries(filter, &mut |query| backend.select(query)));
+            backend.delete(table, filter.transpose()?.as_ref()).map(QueryResult::Affected)
+        }
+        Statement::Transaction { op, loc } => match op {
+            TransactionOp::Begin => backend.begin(*loc),
+            TransactionOp::Commit => backend.commit(*loc),
+            TransactionOp::Rollback => backend.rollback(*loc),
+        }
+        .map(|_| QueryResult::Done),
+        Statement::AlterTable { table, op } => {
+            let op = match op {
+                AlterOp::AddColumn(column @ ColumnDef { default: Some(default), .. }) => AlterOp::AddColumn(ColumnDef {
+                    default: Some(run_subqueries(default, &mut |query| backend.select(query))?),
+                    ..column.clone()
+                }),
+                op => op.clone(),
+            };
+            backend.alter_table(table, &op).map(|_| QueryResult::Done)
+        }
+        Statement::Copy { table, direction: CopyDirection::From, path, options } => {
+            csv::copy_from(backend, table, path, *options).map(QueryResult::Affected)
+        }
+        Statement::Copy { table, direction: CopyDirection::To, path, options } => {
+            csv::copy_to(backend, table, path, *options).map(QueryResult::Affected)
+        }
+    }
+}
+
+// Integer literals keep their source spelling, so "0xFF" and "1_000" are
+// decoded here. Fractions and exponents are not integers, they are reals.
+// A leading minus is kept with the digits, so that the smallest int, whose
+// digits alone are too large for one, can be read.
+fn parse_int(value: &str) -> Option<i64> {
+    let digits = value.replace('_', "");
+    let (sign, digits) = match digits.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", digits.as_str()),
+    };
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        return i64::from_str_radix(&format!("{}{}", sign, hex), 16).ok();
+    }
+    if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        return i64::from_str_radix(&format!("{}{}", sign, bin), 2).ok();
+    }
+    format!("{}{}", sign, digits).parse().ok()
+}
+
+fn parse_numeric(value: &str) -> Option<Value> {
+    let unsigned = value.trim_start_matches('-');
+    let is_real = !unsigned.starts_with("0x") && !unsigned.starts_with("0X") && value.contains(['.', 'e', 'E']);
+    if is_real {
+        return value.replace('_', "").parse().ok().map(Value::Real);
+    }
+    parse_int(value).map(Value::Int)
+}
+
+// Checks a real result, and turns -0 into 0 so reals that compare equal
+// are also the same value
+fn real(value: f64, loc: Location) -> Result<Value, BackendError> {
+    if !value.is_finite() {
+        return Err(BackendError::of_kind(ErrorKind::OutOfRange, "Real out of range", loc));
+    }
+    Ok(Value::Real(value + 0.0))
+}
+
+// The tables a row is made of, each with what its columns are qualified
+// with. A joined row holds the values of each table one after the other.
+struct Scope<'a> {
+    tables: Vec<(&'a [String], &'a [ColumnDef])>,
+}
+
+impl<'a> Scope<'a> {
+    fn single(qualifier: &'a [String], columns: &'a [ColumnDef]) -> Scope<'a> {
+        Scope { tables: vec![(qualifier, columns)] }
+    }
+
+    // Resolves a column reference to its index in the row. A qualified
+    // column, t.id, is looked up in table t only, an unqualified one in
+    // every table and has to be found in just one.
+    fn resolve(&self, column: &QualifiedName) -> Result<usize, BackendError> {
+        let (name, qualifier) = column.parts.split_last().expect("a name has at least one part");
+        let mut known = qualifier.is_empty();
+        let mut found = None;
+        let mut offset = 0;
+        for (table, columns) in &self.tables {
+            if qualifier.is_empty() || qualifier == *table {
+                known = true;
+                // A subquery can return two columns of the same name
+                for (i, _) in columns.iter().enumerate().filter(|(_, c)| &c.name == name) {
+                    if found.is_some() {
+                        return Err(BackendError::new(format!("Column {} is ambiguous", column), column.loc));
+                    }
+                    found = Some(offset + i);
+                }
+            }
+            offset += columns.len();
+        }
+        if !known {
+            let message = format!("Unknown table {}", qualifier.join("."));
+            return Err(BackendError::of_kind(ErrorKind::UndefinedTable, message, column.loc));
+        }
+        found.ok_or_else(|| unknown_column(column, column.loc))
+    }
+
+    // The position of the table a column reference resolves to
+    fn table_of(&self, column: &QualifiedName) -> Result<usize, BackendError> {
+        let mut index = self.resolve(column)?;
+        for (table, (_, columns)) in self.tables.iter().enumerate() {
+            if index < columns.len() {
+                return Ok(table);
+            }
+            index -= columns.len();
+        }
+        unreachable!("a resolved column is in one of the tables")
+    }
+}
+
+fn column_index(qualifier: &[String], columns: &[ColumnDef], column: &QualifiedName) -> Result<usize, BackendError> {
+    Scope::single(qualifier, columns).resolve(column)
+}
+
+// The row an expression is evaluated against. Expressions in an insert have
+// no row, so any column they mention is unknown.
+struct Row<'a> {
+    scope: &'a Scope<'a>,
+    values: &'a [Value],
+}
+
+/*
+    Evaluates an expression to a value. Literals and operators have no
+    location of their own, so errors that are not about a column are reported
+    at `loc`, the table the statement works on.
+ */
+fn eval(expr: &Expr, row: Option<&Row>, loc: Location) -> Result<Value, BackendError> {
+    match expr {
+        Expr::NumericLiteral(n) => parse_numeric(n).ok_or_else(|| not_a_number(n, loc)),
+        // The minus goes with the number before it is checked to fit
+        Expr::Unary { op: UnaryOp::Neg, expr } if let Expr::NumericLiteral(n) = expr.as_ref()
+            && !n.starts_with('-') =>
+        {
+            let n = format!("-{}", n);
+            parse_numeric(&n).ok_or_else(|| not_a_number(&n, loc))
+        }
+        Expr::StringLiteral(s) => Ok(Value::Text(s.clone())),
+        Expr::BoolLiteral(b) => Ok(Value::Bool(*b)),
+        Expr::NullLiteral => Ok(Value::Null),
+        Expr::TypedLiteral { data_type, value } => {
+            cast::cast(Value::Text(value.clone()), *data_type).map_err(|err| err.at(loc))
+        }
+        Expr::Column(name) => match row {
+            Some(row) => Ok(row.values[row.scope.resolve(name)?].clone()),
+            None => Err(unknown_column(name, name.loc)),
+        },
+        Expr::Cast { expr, data_type } => {
+            cast::cast(eval(expr, row, loc)?, *data_type).map_err(|err| err.at(loc))
+        }
+        Expr::Unary { op, expr } => match (op, eval(expr, row, loc)?) {
+            (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+            (_, Value::Null) => Ok(Value::Null),
+            (UnaryOp::Neg, Value::Int(i)) => i
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or_else(|| BackendError::of_kind(ErrorKind::OutOfRange, "Integer out of range", loc)),
+            (UnaryOp::Neg, Value::Real(r)) => real(0.0 - r, loc),
+            (UnaryOp::Not, value) => Err(BackendError::of_kind(
+                ErrorKind::DatatypeMismatch,
+                format!("Expected bool after NOT, got {}", value),
+                loc,
+            )),
+            (UnaryOp::Neg, value) => Err(BackendError::of_kind(
+                ErrorKind::UndefinedFunction,
+                format!("Expected int after -, got {}", value),
+                loc,
+            )),
+        },
+        Expr::Binary { left, op, right } => eval_binary(*op, eval(left, row, loc)?, eval(right, row, loc)?, loc),
+        Expr::Chain { first, rest } => rest.iter().try_fold(eval(first, row, loc)?, |left, (op, right)| {
+            eval_binary(*op, left, eval(right, row, loc)?, loc)
+        }),
+        Expr::IsNull { expr, negated } => Ok(Value::Bool((eval(expr, row, loc)? == Value::Null) != *negated)),
+        // True when any item is equal, otherwise null when an item was null
+        Expr::InList { expr, list, negated } => {
+            let value = eval(expr, row, loc)?;
+            let mut found = Value::Bool(false);
+            for item in list {
+                match eval_binary(BinaryOp::Eq, value.clone(), eval(item, row, loc)?, loc)? {
+                    Value::Bool(true) => {
+                        found = Value::Bool(true);
+                        break;
+                    }
+                    Value::Null => found = Value::Null,
+                    _ => {}
+                }
+            }
+            match found {
+                Value::Bool(b) => Ok(Value::Bool(b != *negated)),
+                found => Ok(found),
+            }
+        }
+        Expr::InSubquery { .. } => unreachable!("Subqueries are run before the statement"),
+        // A prepared statement replaces its parameters before it runs
+        Expr::Parameter(n) => Err(BackendError::new(format!("No value bound for parameter ${}", n), loc)),
+        // Aggregates are only computed by a grouping select, which replaces
+        // them before anything is evaluated
+        Expr::Function { name, .. } if aggregate::is_aggregate(name) => {
+            let message = format!("Aggregate function {} is not allowed here", name);
+            Err(BackendError::of_kind(ErrorKind::Grouping, message, name.loc))
+        }
+        Expr::Function { name, args: FunctionArgs::List(args) } => {
+            let values = args.iter().map(|arg| eval(arg, row, loc)).collect::<Result<Vec<_>, _>>()?;
+            function::call(name, &values, loc)
+        }
+        Expr::Function { name, args: FunctionArgs::Wildcard } => {
+            Err(BackendError::new(format!("Only count accepts *, not {}", name), name.loc))
+        }
+    }
+}
+
+// Calls `f` on an expression and everything inside it
+fn visit<'a>(expr: &'a Expr, f: &mut dyn FnMut(&'a Expr)) {
+    f(expr);
+    match expr {
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => visit(expr, f),
+        Expr::Binary { left, right, .. } => {
+            visit(left, f);
+            visit(right, f);
+        }
+        Expr::Chain { first, rest } => {
+            visit(first, f);
+            rest.iter().for_each(|(_, expr)| visit(expr, f));
+        }
+        Expr::Function { args: FunctionArgs::List(args), .. } => args.iter().for_each(|arg| visit(arg, f)),
+        Expr::InList { expr, list, .. } => {
+            visit(expr, f);
+            list.iter().for_each(|item| visit(item, f));
+        }
+        // The subquery's own expressions are about its own tables
+        Expr::InSubquery { expr, .. } => visit(expr, f),
+        _ => {}
+    }
+}
+
+/*
+    Null follows SQL's three-valued logic: it stands for a value that is not
+    known, so anything computed from it is not known either. `null = null`
+    and `1 + null` are null, and a WHERE that comes out null drops the row.
+    AND and OR are the exception where the known side decides the result,
+    `false AND null` is false and `true OR null` is true.
+ */
+
+// The truth value of an AND or OR operand, None for null
+fn truth(value: &Value) -> Option<Option<bool>> {
+    match value {
+        Value::Bool(b) => Some(Some(*b)),
+        Value::Null => Some(None),
+        _ => None,
+    }
+}
+
+// Converts one side of a comparison to the type of the other when that is
+// implicit, so text compares with a date as a date
+fn comparable(left: Value, right: Value) -> Result<(Value, Value), ValueError> {
+    if let Some(data_type) = cast::type_of(&right).filter(|data_type| cast::is_implicit(&left, *data_type)) {
+        return Ok((cast::cast(left, data_type)?, right));
+    }
+    if let Some(data_type) = cast::type_of(&left).filter(|data_type| cast::is_implicit(&right, *data_type)) {
+        return Ok((left, cast::cast(right, data_type)?));
+    }
+    Ok((left, right))
+}
+
+fn eval_binary(op: BinaryOp, left: Value, right: Value, loc: Location) -> Result<Value, BackendError> {
+    let mismatch = |left: &Value, right: &Value| {
+        let message = format!("Cannot apply {} to {} and {}", op.as_str(), left.type_name(), right.type_name());
+        BackendError::of_kind(ErrorKind::UndefinedFunction, message, loc)
+    };
+
+    match op {
+        BinaryOp::And | BinaryOp::Or => {
+            let (Some(l), Some(r)) = (truth(&left), truth(&right)) else {
+                return Err(mismatch(&left, &right));
+            };
+            // The value that decides the result on its own, false for AND
+            let decisive = op == BinaryOp::Or;
+            Ok(match (l, r) {
+                (Some(l), _) if l == decisive => Value::Bool(decisive),
+                (_, Some(r)) if r == decisive => Value::Bool(decisive),
+                (Some(_), Some(_)) => Value::Bool(!decisive),
+                _ => Value::Null,
+            })
+        }
+        _ if left == Value::Null || right == Value::Null => Ok(Value::Null),
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+            let (left, right) = comparable(left, right).map_err(|err| err.at(loc))?;
+            let ordering = match (&left, &right) {
+                (Value::Int(l), Value::Int(r)) => l.cmp(r),
+                (Value::Text(l), Value::Text(r)) => l.cmp(r),
+                (Value::Bool(l), Value::Bool(r)) => l.cmp(r),
+                (Value::Date(_), Value::Date(_))
+                | (Value::Time(_), Value::Time(_))
+                | (Value::Timestamp(_), Value::Timestamp(_)) => left.cmp(&right),
+                _ => match (as_real(&left), as_real(&right)) {
+                    (Some(l), Some(r)) => l.total_cmp(&r),
+                    _ => return Err(mismatch(&left, &right)),
+                },
+            };
+            Ok(Value::Bool(match op {
+                BinaryOp::Eq => ordering.is_eq(),
+                BinaryOp::NotEq => ordering.is_ne(),
+                BinaryOp::Lt => ordering.is_lt(),
+                BinaryOp::LtEq => ordering.is_le(),
+                BinaryOp::Gt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+            let (Value::Int(l), Value::Int(r)) = (&left, &right) else {
+                // An int mixed with a real is computed as a real
+                let (Some(l), Some(r)) = (as_real(&left), as_real(&right)) else {
+                    return Err(mismatch(&left, &right));
+                };
+                if op == BinaryOp::Div && r == 0.0 {
+                    return Err(BackendError::of_kind(ErrorKind::DivisionByZero, "Division by zero", loc));
+                }
+                return real(
+                    match op {
+                        BinaryOp::Add => l + r,
+                        BinaryOp::Sub => l - r,
+                        BinaryOp::Mul => l * r,
+                        _ => l / r,
+                    },
+                    loc,
+                );
+            };
+            if op == BinaryOp::Div && *r == 0 {
+                return Err(BackendError::of_kind(ErrorKind::DivisionByZero, "Division by zero", loc));
+            }
+            let result = match op {
+                BinaryOp::Add => l.checked_add(*r),
+                BinaryOp::Sub => l.checked_sub(*r),
+                BinaryOp::Mul => l.checked_mul(*r),
+                _ => l.checked_div(*r),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| BackendError::of_kind(ErrorKind::OutOfRange, "Integer out of range", loc))
+        }
+        // A text key picks an object member and an int one an array element,
+        // anything missing comes out null
+        BinaryOp::JsonGet | BinaryOp::JsonGetText => {
+            let Value::Json(text) = &left else {
+                return Err(mismatch(&left, &right));
+            };
+            let json = json::parse(text).map_err(|err| BackendError::of_kind(ErrorKind::InvalidText, err, loc))?;
+            let found = match &right {
+                Value::Text(key) => json.field(key),
+                Value::Int(index) => json.element(*index),
+                _ => return Err(mismatch(&left, &right)),
+            };
+            Ok(match (found, op) {
+                (None, _) | (Some(Json::Null), BinaryOp::JsonGetText) => Value::Null,
+                (Some(Json::String(s)), BinaryOp::JsonGetText) => Value::Text(s.clone()),
+                (Some(found), BinaryOp::JsonGetText) => Value::Text(found.to_string()),
+                (Some(found), _) => Value::Json(found.to_string()),
+            })
+        }
+    }
+}
+
+fn as_real(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(i) => Some(*i as f64),
+        Value::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+// Whether a row passes a condition, a null is as good as false
+fn holds(condition: &Expr, row: &Row, clause: &str, loc: Location) -> Result<bool, BackendError> {
+    match eval(condition, Some(row), loc)? {
+        Value::Bool(b) => Ok(b),
+        Value::Null => Ok(false),
+        value => Err(BackendError::of_kind(
+            ErrorKind::DatatypeMismatch,
+            format!("Expected bool in {}, got {}", clause, value),
+            loc,
+        )),
+    }
+}
+
+// Whether a row passes a WHERE filter, no filter lets every row through
+fn matches(filter: Option<&Expr>, row: &Row, loc: Location) -> Result<bool, BackendError> {
+    match filter {
+        Some(filter) => holds(filter, row, "WHERE", loc),
+        None => Ok(true),
+    }
+}
+
+// Checks that a value fits the column it is about to be stored in, after
+// the implicit conversions of the cast module
+fn check_type(value: Value, table: &QualifiedName, column: &ColumnDef, loc: Location) -> Result<Value, BackendError> {
+    let mismatch = |value: &Value| {
+        let message = format!("Expected {} for column {}, got {}", column.data_type, column.name, value);
+        BackendError::of_kind(ErrorKind::DatatypeMismatch, message, loc)
+    };
+    match (&value, column.data_type) {
+        (Value::Null, _) if column.is_not_null() => {
+            let violation = ConstraintViolation {
+                constraint: ColumnConstraint::NotNull,
+                table: table.to_string(),
+                column: column.name.clone(),
+            };
+            Err(BackendError::constraint(violation, format!("Null value for not null column {}", column.name), loc))
+        }
+        (Value::Int(i), DataType::Int) if i32::try_from(*i).is_err() => Err(BackendError::of_kind(
+            ErrorKind::OutOfRange,
+            format!("Value {} out of range for int column {}", i, column.name),
+            loc,
+        )),
+        (Value::Text(s), DataType::Varchar(length)) if s.chars().count() > length as usize => Err(BackendError::new(
+            format!("Value too long for {} column {}", column.data_type, column.name),
+            loc,
+        )),
+        (Value::Text(s), DataType::Json) => json::parse(s).map(|json| Value::Json(json.to_string())).map_err(|err| {
+            let message = format!("Invalid json for column {}: {}", column.name, err);
+            BackendError::of_kind(ErrorKind::InvalidText, message, loc)
+        }),
+        (_, data_type) if cast::is_implicit(&value, data_type) => {
+            cast::cast(value.clone(), data_type).map_err(|_| mismatch(&value))
+        }
+        (Value::Null, _)
+        | (Value::Int(_), DataType::Int | DataType::BigInt)
+        | (Value::Real(_), DataType::Real)
+        | (Value::Text(_), DataType::Text | DataType::Varchar(_))
+        | (Value::Bool(_), DataType::Boolean)
+        | (Value::Date(_), DataType::Date)
+        | (Value::Time(_), DataType::Time)
+        | (Value::Timestamp(_), DataType::Timestamp)
+        | (Value::Json(_), DataType::Json) => Ok(value),
+        _ => Err(mismatch(&value)),
+    }
+}
+
+/*
+    The work behind each statement, written against a table's column list and
+    its rows. Backends only decide how rows are stored, they load them, hand
+    them to these and store what comes back. Nothing is changed until every
+    row has been checked, so a failing statement leaves the table as it was.
+ */
+
+// Builds a new index over the rows of a table. Index names are shared by
+// every table, `taken` tells whether one is already in use.
+fn create_index(
+    name: &QualifiedName,
+    table: &QualifiedName,
+    column: &QualifiedName,
+    columns: &[ColumnDef],
+    rows: &[Vec<Value>],
+    taken: bool,
+) -> Result<Index, BackendError> {
+    if taken {
+        return Err(BackendError::new(format!("Index {} already exists", name), name.loc));
+    }
+    let position = column_index(&table.parts, columns, column)?;
+    Ok(Index::new(name.to_string(), position, rows))
+}
+
+// The positions of the rows a filter has to look at
+fn positions(
+    filter: Option<&Expr>,
+    qualifier: &[String],
+    columns: &[ColumnDef],
+    rows: &[Vec<Value>],
+    indexes: &[Index],
+    loc: Location,
+) -> Vec<usize> {
+    candidates(filter, qualifier, columns, indexes, loc).unwrap_or_else(|| (0..rows.len()).collect())
+}
+
+// Checks the columns of a table as it is about to be created or altered. A
+// default is evaluated once here so one that could never be stored, or that
+// reads a column, is refused up front.
+fn check_columns(table: &QualifiedName, columns: &[ColumnDef], schema: &Schema) -> Result<(), BackendError> {
+    for (i, column) in columns.iter().enumerate() {
+        if columns[..i].iter().any(|c| c.name == column.name) {
+            return Err(BackendError::new(format!("Duplicate column {}", column.name), column.loc));
+        }
+        if column.is_primary_key() && columns[..i].iter().any(|c| c.is_primary_key()) {
+            return Err(BackendError::new("A table can only have one primary key", column.loc));
+        }
+        if column.is_auto_increment() {
+            let message = if !matches!(column.data_type, DataType::Int | DataType::BigInt) {
+                format!("Auto-increment column {} must be int or bigint", column.name)
+            } else if column.default.is_some() {
+                format!("Auto-increment column {} cannot have a default", column.name)
+            } else if columns[..i].iter().any(|c| c.is_auto_increment()) {
+                "A table can only have one auto-increment column".to_string()
+            } else {
+                continue;
+            };
+            return Err(BackendError::new(message, column.loc));
+        }
+        if let Some(default) = &column.default {
+            let mut subquery = false;
+            visit(default, &mut |expr| subquery |= matches!(expr, Expr::InSubquery { .. }));
+            if subquery {
+                return Err(BackendError::new(format!("Default of column {} has a subquery", column.name), column.loc));
+            }
+            check_type(eval(default, None, column.loc)?, table, column, column.loc)?;
+        }
+    }
+    foreign_key::check_definitions(table, columns, schema)
+}
+
+// A table as ALTER TABLE leaves it, worked out before anything changes so
+// that a failing statement leaves the table as it was. Rows are None when
+// they stay the same. Indexes keep their order, each with the position of
+// its column now or None when the column was dropped.
+struct Altered {
+    columns: Vec<ColumnDef>,
+    rows: Option<Vec<Vec<Value>>>,
+    index_columns: Vec<Option<usize>>,
+}
+
+fn alter_table(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    rows: &[Vec<Value>],
+    indexes: &[Index],
+    op: &AlterOp,
+    schema: &Schema,
+) -> Result<Altered, BackendError> {
+    let unchanged = indexes.iter().map(|index| Some(index.column)).collect();
+    match op {
+        AlterOp::AddColumn(column) => {
+            if column.is_auto_increment() && !rows.is_empty() {
+                return Err(BackendError::new(
+                    format!("Cannot add auto-increment column {} to a table with rows", column.name),
+                    column.loc,
+                ));
+            }
+            let mut altered = columns.to_vec();
+            altered.push(column.clone());
+            check_columns(table, &altered, schema)?;
+
+            // An empty table has no rows for a NOT NULL column to fail on
+            let value = match &column.default {
+                Some(default) => eval(default, None, column.loc)?,
+                None => Value::Null,
+            };
+            let value = match value {
+                Value::Null if rows.is_empty() => value,
+                value => check_type(value, table, column, column.loc)?,
+            };
+            let rows: Vec<Vec<Value>> = rows
+                .iter()
+                .map(|row| row.iter().cloned().chain([value.clone()]).collect())
+                .collect();
+            if column.is_unique() {
+                check_unique(table, &altered, &[(columns.len(), column.loc)], rows.iter().map(Vec::as_slice))?;
+            }
+            let checked: Vec<&[Value]> = rows.iter().map(Vec::as_slice).collect();
+            foreign_key::check_references(table, &altered, &checked, Some(&checked), schema)?;
+            Ok(Altered { columns: altered, rows: Some(rows), index_columns: unchanged })
+        }
+        AlterOp::DropColumn(column) => {
+            let dropped = column_index(&table.parts, columns, column)?;
+            if columns.len() == 1 {
+                return Err(BackendError::new(
+                    format!("Cannot drop {}, the only column of {}", column, table),
+                    column.loc,
+                ));
+            }
+            if let Some(referencing) = schema.referenced_by(&table.to_string(), Some(&columns[dropped].name)) {
+                return Err(BackendError::new(
+                    format!("Cannot drop {}, {} references it", column, referencing),
+                    column.loc,
+                ));
+            }
+            let mut altered = columns.to_vec();
+            altered.remove(dropped);
+            let rows = rows
+                .iter()
+                .map(|row| row.iter().enumerate().filter(|(i, _)| *i != dropped).map(|(_, v)| v.clone()).collect())
+                .collect();
+            let index_columns = indexes
+                .iter()
+                .map(|index| match index.column {
+                    c if c == dropped => None,
+                    c if c > dropped => Some(c - 1),
+                    c => Some(c),
+                })
+                .collect();
+            Ok(Altered { columns: altered, rows: Some(rows), index_columns })
+        }
+        AlterOp::RenameColumn { column, name } => {
+            let renamed = column_index(&table.parts, columns, column)?;
+            if columns.iter().any(|c| &c.name == name) {
+                return Err(BackendError::new(format!("Column {} already exists", name), column.loc));
+            }
+            let mut altered = columns.to_vec();
+            altered[renamed].name = name.clone();
+            foreign_key::rename(&mut altered, table, &columns[renamed].name, name);
+            Ok(Altered { columns: altered, rows: None, index_columns: unchanged })
+        }
+    }
+}
+
+// Moves indexes to the new positions of their columns, dropping the ones
+// whose column went away. The rows keep their order, so the indexed
+// positions stay right.
+fn move_indexes(indexes: &mut Vec<Index>, columns: &[Option<usize>]) {
+    let mut columns = columns.iter();
+    indexes.retain_mut(|index| match columns.next().copied().flatten() {
+        Some(column) => {
+            index.column = column;
+            true
+        }
+        None => false,
+    });
+}
+
+// Checks that the rows, the whole table as it would be after the statement,
+// hold no value twice in the given unique columns. Nulls are all distinct
+// from each other and never clash. Each column comes with the location to
+// report a duplicate at.
+fn check_unique<'a>(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    checks: &[(usize, Location)],
+    rows: impl Iterator<Item = &'a [Value]> + Clone,
+) -> Result<(), BackendError> {
+    for &(index, loc) in checks {
+        let mut seen = HashSet::new();
+        for row in rows.clone() {
+            let value = &row[index];
+            if *value != Value::Null && !seen.insert(value) {
+                return Err(duplicate(table, &columns[index], value, loc));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checks the rows an insert adds to a table against the rows it has, looked
+// up in the keys of its unique columns, and against each other
+fn check_keys(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    indexes: &[Index],
+    rows: &[Vec<Value>],
+) -> Result<(), BackendError> {
+    for index in indexes.iter().filter(|index| index.key) {
+        let mut seen = HashSet::new();
+        for row in rows {
+            let value = &row[index.column];
+            if *value != Value::Null && (index.contains(value) || !seen.insert(value)) {
+                return Err(duplicate(table, &columns[index.column], value, table.loc));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn duplicate(table: &QualifiedName, column: &ColumnDef, value: &Value, loc: Location) -> BackendError {
+    let (constraint, kind) = if column.is_primary_key() {
+        (ColumnConstraint::PrimaryKey, "primary key")
+    } else {
+        (ColumnConstraint::Unique, "unique column")
+    };
+    let violation = ConstraintViolation { constraint, table: table.to_string(), column: column.name.clone() };
+    BackendError::constraint(violation, format!("Duplicate value {} for {} {}", value, kind, column.name), loc)
+}
+
+// Evaluates the tuples of an insert into rows ready to be stored. A tuple
+// holds a value for each of `targets`, or for every column when there are
+// none, and the columns it leaves out get their default. The rows already in
+// the table are never read, only looked up in the keys among its `indexes`.
+//
+// `sequence` is the last number the table's auto-increment column was given.
+// A row that leaves the column out takes the next one, and a row that stores
+// a larger number of its own moves the sequence up to it, so later rows
+// don't run into it.
+//
+// A select reads its tables through `load`, and its rows are converted as
+// they come out of its plan rather than collected into a result first. The
+// converted rows are all held until the backend stores them, in one write
+// once every row is checked, so an insert of many rows needs room for them
+// all. A select reading the table inserted into only sees the rows it had.
+#[allow(clippy::too_many_arguments)]
+fn insert_rows<'a>(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    indexes: &[Index],
+    targets: &[QualifiedName],
+    rows: InsertFrom,
+    load: &dyn Fn(&QualifiedName) -> Result<Source<'a>, BackendError>,
+    sequence: &mut i64,
+    schema: &Schema,
+) -> Result<Vec<Vec<Value>>, BackendError> {
+    // The position of the column each value of a tuple goes to
+    let mut positions: Vec<usize> = Vec::with_capacity(targets.len());
+    for target in targets {
+        let position = column_index(&table.parts, columns, target)?;
+        if positions.contains(&position) {
+            return Err(BackendError::new(format!("Column {} is given more than once", target), target.loc));
+        }
+        positions.push(position);
+    }
+    if targets.is_empty() {
+        positions.extend(0..columns.len());
+    }
+
+    let plan;
+    let mut selected;
+    let rows: &mut InsertRows = match rows {
+        InsertFrom::Rows(rows) => rows,
+        InsertFrom::Select(query) => {
+            plan = planner::plan(query, load)?;
+            if plan.columns.len() != positions.len() {
+                return Err(BackendError::new(
+                    format!("Expected {} values, got {}", positions.len(), plan.columns.len()),
+                    table.loc,
+                ));
+            }
+            selected = plan.rows(load)?;
+            &mut selected
+        }
+    };
+    let mut values = Vec::with_capacity(rows.size_hint().0);
+    for row in rows {
+        let row = row?;
+        if row.len() != positions.len() {
+            return Err(BackendError::new(
+                format!("Expected {} values, got {}", positions.len(), row.len()),
+                table.loc,
+            ));
+        }
+        let mut given = vec![None; columns.len()];
+        for (value, &position) in row.into_iter().zip(&positions) {
+            given[position] = Some(value);
+        }
+        let mut converted = Vec::with_capacity(columns.len());
+        for (value, column) in given.into_iter().zip(columns) {
+            let value = match (value, &column.default) {
+                (Some(value), _) => value,
+                (None, _) if column.is_auto_increment() => {
+                    *sequence = sequence.checked_add(1).ok_or_else(|| {
+                        BackendError::new(format!("Auto-increment column {} is out of numbers", column.name), table.loc)
+                    })?;
+                    Value::Int(*sequence)
+                }
+                (None, Some(default)) => eval(default, None, table.loc)?,
+                (None, None) => Value::Null,
+            };
+            let value = check_type(value, table, column, table.loc)?;
+            if let Value::Int(i) = value
+                && column.is_auto_increment()
+            {
+                *sequence = (*sequence).max(i);
+            }
+            converted.push(value);
+        }
+        values.push(converted);
+    }
+
+    check_keys(table, columns, indexes, &values)?;
+    let checked: Vec<&[Value]> = values.iter().map(Vec::as_slice).collect();
+    foreign_key::check_references(table, columns, &checked, None, schema)?;
+    Ok(values)
+}
+
+// The order ORDER BY sorts values in. Nulls come after every other value,
+// as in PostgreSQL, and ints and reals compare by their numeric value.
+fn compare(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (Value::Int(_), Value::Real(_)) | (Value::Real(_), Value::Int(_)) => {
+            as_real(left).unwrap().total_cmp(&as_real(right).unwrap())
+        }
+        _ => left.cmp(right),
+    }
+}
+
+// A table a select reads, as its backend hands it over or as a subquery
+// in FROM returned it
+struct Source<'a> {
+    columns: Cow<'a, [ColumnDef]>,
+    rows: SourceRows<'a>,
+    indexes: &'a [Index],
+    // The query of a view, which the planner runs in place of a scan
+    view: Option<&'a Select>,
+}
+
+impl<'a> Source<'a> {
+    // A view has no columns or rows of its own
+    fn view(query: &'a Select) -> Source<'a> {
+        let rows = SourceRows::Loaded(Cow::Borrowed(&[]));
+        Source { columns: Cow::Borrowed(&[]), rows, indexes: &[], view: Some(query) }
+    }
+}
+
+// The rows of a source, read up front, or left in a storage engine to be
+// read once a scan gets to them, only the ones an index found if it did
+enum SourceRows<'a> {
+    Loaded(Cow<'a, [Vec<Value>]>),
+    Stored { storage: &'a dyn Storage, table: &'a str, count: usize },
+}
+
+impl SourceRows<'_> {
+    fn len(&self) -> usize {
+        match self {
+            SourceRows::Loaded(rows) => rows.len(),
+            SourceRows::Stored { count, .. } => *count,
+        }
+    }
+}
+
+// Turns a value back into an expression that evaluates to it
+pub(crate) fn literal(value: Value) -> Expr {
+    match value {
+        Value::Int(i) => Expr::NumericLiteral(i.to_string()),
+        // Debug output always has a point or an exponent, so it reads back as a real
+        Value::Real(r) => Expr::NumericLiteral(format!("{:?}", r)),
+        Value::Text(s) => Expr::StringLiteral(s),
+        Value::Bool(b) => Expr::BoolLiteral(b),
+        Value::Null => Expr::NullLiteral,
+        Value::Date(days) => Expr::TypedLiteral { data_type: DataType::Date, value: temporal::format_date(days) },
+        Value::Time(micros) => Expr::TypedLiteral { data_type: DataType::Time, value: temporal::format_time(micros) },
+        Value::Timestamp(micros) => {
+            Expr::TypedLiteral { data_type: DataType::Timestamp, value: temporal::format_timestamp(micros) }
+        }
+        Value::Json(s) => Expr::TypedLiteral { data_type: DataType::Json, value: s },
+    }
+}
+
+/*
+    Subqueries cannot read the row of the statement around them, so each
+    IN subquery is run once, before the statement, and replaced by the list
+    of values it returned. `select` runs a subquery.
+ */
+fn run_subqueries(
+    expr: &Expr,
+    select: &mut dyn FnMut(&Select) -> Result<ResultSet, BackendError>,
+) -> Result<Expr, BackendError> {
+    let mut run = |expr: &Expr| run_subqueries(expr, select).map(Box::new);
+    let expr = match expr {
+        Expr::InSubquery { expr, query, negated } => {
+            let result = select(query)?;
+            if result.columns.len() != 1 {
+                return Err(BackendError::new("Subquery has too many columns", query.from.loc()));
+            }
+            let list = result.rows.into_iter().map(|mut row| literal(row.remove(0))).collect();
+            Expr::InList { expr: Box::new(run_subqueries(expr, select)?), list, negated: *negated }
+        }
+        Expr::InList { expr, list, negated } => Expr::InList {
+            expr: run(expr)?,
+            list: list.iter().map(|item| run(item).map(|item| *item)).collect::<Result<_, _>>()?,
+            negated: *negated,
+        },
+        Expr::Cast { expr, data_type } => Expr::Cast { expr: run(expr)?, data_type: *data_type },
+        Expr::Unary { op, expr } => Expr::Unary { op: *op, expr: run(expr)? },
+        Expr::Binary { left, op, right } => Expr::Binary { left: run(left)?, op: *op, right: run(right)? },
+        Expr::Chain { first, rest } => Expr::Chain {
+            first: run(first)?,
+            rest: rest.iter().map(|(op, expr)| Ok((*op, *run(expr)?))).collect::<Result<_, BackendError>>()?,
+        },
+        Expr::IsNull { expr, negated } => Expr::IsNull { expr: run(expr)?, negated: *negated },
+        Expr::Function { name, args: FunctionArgs::List(args) } => Expr::Function {
+            name: name.clone(),
+            args: FunctionArgs::List(args.iter().map(|arg| run(arg).map(|arg| *arg)).collect::<Result<_, _>>()?),
+        },
+        expr => expr.clone(),
+    };
+    Ok(expr)
+}
+
+// Runs a select, reading its tables through `load`
+fn select_rows<'a>(
+    query: &Select,
+    load: &dyn Fn(&QualifiedName) -> Result<Source<'a>, BackendError>,
+) -> Result<ResultSet, BackendError> {
+    planner::plan(query, load)?.execute(load)
+}
+
+// The plan of a select, one line of text per row
+fn explain_rows<'a>(
+    query: &Select,
+    load: &dyn Fn(&QualifiedName) -> Result<Source<'a>, BackendError>,
+) -> Result<ResultSet, BackendError> {
+    let plan = planner::plan(query, load)?;
+    Ok(ResultSet {
+        columns: vec!["QUERY PLAN".to_string()],
+        rows: plan.to_string().lines().map(|line| vec![Value::Text(line.to_string())]).collect(),
+    })
+}
+
+// Plans a view's query once, so one that could never run is refused when
+// the view is created rather than each time it is read. The names of its
+// columns become the view's, no two can be the same. Only the query is
+// kept, a view reading a table that is later dropped or altered fails
+// when it is read.
+fn check_view<'a>(
+    query: &Select,
+    load: &dyn Fn(&QualifiedName) -> Result<Source<'a>, BackendError>,
+) -> Result<(), BackendError> {
+    let plan = planner::plan(query, load)?;
+    for (i, column) in plan.columns.iter().enumerate() {
+        if plan.columns[..i].contains(column) {
+            return Err(BackendError::new(
+                format!("Column {} appears more than once in the view", column),
+                query.from.loc(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Returns the index and new contents of every row the update changes
+fn update_rows(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    rows: &[Vec<Value>],
+    indexes: &[Index],
+    assignments: &[Assignment],
+    filter: Option<&Expr>,
+    schema: &Schema,
+) -> Result<Vec<(usize, Vec<Value>)>, BackendError> {
+    let mut targets = Vec::with_capacity(assignments.len());
+    for assignment in assignments {
+        let index = columns
+            .iter()
+            .position(|c| c.name == assignment.column)
+            .ok_or_else(|| unknown_column(&assignment.column, assignment.loc))?;
+        if targets.contains(&index) {
+            return Err(BackendError::new(
+                format!("Column {} assigned more than once", assignment.column),
+                assignment.loc,
+            ));
+        }
+        targets.push(index);
+    }
+
+    // Every new value is computed from the row as it was before the update
+    let scope = Scope::single(&table.parts, columns);
+    for expr in assignments.iter().map(|a| &a.value).chain(filter) {
+        function::check(expr, &scope, &[true])?;
+    }
+    let mut changes = Vec::new();
+    for i in positions(filter, &table.parts, columns, rows, indexes, table.loc) {
+        cancel::check(table.loc)?;
+        let values = &rows[i];
+        let row = Row { scope: &scope, values };
+        if !matches(filter, &row, table.loc)? {
+            continue;
+        }
+        let mut updated = values.clone();
+        for (assignment, &index) in assignments.iter().zip(&targets) {
+            let value = eval(&assignment.value, Some(&row), assignment.loc)?;
+            updated[index] = check_type(value, table, &columns[index], assignment.loc)?;
+        }
+        changes.push((i, updated));
+    }
+
+    // Only assigned columns can end up with a duplicate
+    let checks: Vec<(usize, Location)> = assignments
+        .iter()
+        .zip(&targets)
+        .filter(|(_, index)| columns[**index].is_unique())
+        .map(|(assignment, index)| (*index, assignment.loc))
+        .collect();
+    let mut after: Vec<&[Value]> = rows.iter().map(Vec::as_slice).collect();
+    for (i, updated) in &changes {
+        after[*i] = updated;
+    }
+    if !checks.is_empty() {
+        check_unique(table, columns, &checks, after.iter().copied())?;
+    }
+    let checked: Vec<&[Value]> = changes.iter().map(|(_, updated)| updated.as_slice()).collect();
+    foreign_key::check_references(table, columns, &checked, Some(&after), schema)?;
+    if !changes.is_empty() {
+        let before: Vec<&[Value]> = rows.iter().map(Vec::as_slice).collect();
+        foreign_key::check_update(table, columns, &before, &after, schema)?;
+    }
+    Ok(changes)
+}
+
+// Returns, for every row, whether the delete keeps it
+fn delete_rows(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    rows: &[Vec<Value>],
+    indexes: &[Index],
+    filter: Option<&Expr>,
+) -> Result<Vec<bool>, BackendError> {
+    let scope = Scope::single(&table.parts, columns);
+    if let Some(filter) = filter {
+        function::check(filter, &scope, &[true])?;
+    }
+    let mut keep = vec![true; rows.len()];
+    for i in positions(filter, &table.parts, columns, rows, indexes, table.loc) {
+        cancel::check(table.loc)?;
+        let row = Row { scope: &scope, values: &rows[i] };
+        keep[i] = !matches(filter, &row, table.loc)?;
+    }
+    Ok(keep)
+}
+
+#[derive(Clone)]
+struct Table {
+    columns: Vec<ColumnDef>,
+    rows: Vec<Vec<Value>>,
+    // Which row each row is and which transaction wrote it, see Stamp
+    stamps: Vec<Stamp>,
+    indexes: Vec<Index>,
+    // The last number given to the auto-increment column, see insert_rows
+    sequence: i64,
+    // The query of a view, which has no columns or rows of its own
+    view: Option<Select>,
+}
+
+impl Table {
+    fn new(columns: Vec<ColumnDef>, view: Option<Select>) -> Table {
+        Table { columns, rows: Vec::new(), stamps: Vec::new(), indexes: Vec::new(), sequence: 0, view }
+    }
+}
+
+/*
+    The tables of a MemoryBackend are kept in versions. A change to a table
+    makes a new version of it, stamped with the id of the transaction that
+    made it, while the version it replaces lives on for as long as someone
+    still reads it: versions are shared by every snapshot taken while they
+    were current and copied only when written to while shared. Every row
+    carries a stamp too, an id that stays with the row through updates and
+    the id of the transaction that wrote the version of it the table holds,
+    so the versions of a table chain the versions of each of its rows.
+
+    A transaction reads the versions that were current at its BEGIN, all of
+    them together, so it sees one consistent database whatever other
+    sessions commit in the meantime, and its own writes go to copies no one
+    else sees. COMMIT makes its versions current. Where another transaction
+    committed a table since, the rows this one inserted, updated and deleted
+    are applied to that version instead, unless one of the rows it updated
+    or deleted was changed in the meantime: then the first committer wins,
+    the commit fails with a serialization failure and its changes are
+    dropped, for the transaction to be run again. Conflicts are found per
+    table rather than per row when either side changed the table's columns
+    or indexes, for tables with foreign keys, whose checks saw only one
+    side's rows, and when both took numbers from an auto-increment column.
+    Two sides that put the same value in a unique column conflict as well.
+
+    Outside a transaction a statement works out its changes against the
+    current versions without holding the store, and takes it only to put
+    them in. Such statements, and commits, write one at a time, so what a
+    statement worked out is still current when it is put in. Readers never
+    wait on a writer, a writer never waits on readers.
+ */
+#[derive(Clone)]
+struct Version {
+    table: Arc<Table>,
+    // The transaction that made this version
+    txid: u64,
+}
+
+// A row of a table, in one of its versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    id: u64,
+    // The transaction that wrote the row as it is in this version
+    txid: u64,
+}
+
+type Tables = HashMap<String, Version>;
+
+// What the sessions of a MemoryBackend share
+#[derive(Default)]
+struct Shared {
+    store: Mutex<Store>,
+    // Held by a statement writing outside a transaction and by a commit
+    writer: Mutex<()>,
+    last_row_id: AtomicU64,
+}
+
+#[derive(Default)]
+struct Store {
+    tables: Tables,
+    last_txid: u64,
+}
+
+impl Store {
+    fn next_txid(&mut self) -> u64 {
+        self.last_txid += 1;
+        self.last_txid
+    }
+}
+
+// Stamps the rows a statement writes
+struct Stamps<'s> {
+    txid: u64,
+    last_row_id: &'s AtomicU64,
+}
+
+impl Stamps<'_> {
+    // A row the statement adds
+    fn new_row(&self) -> Stamp {
+        Stamp { id: self.last_row_id.fetch_add(1, AtomicOrdering::Relaxed) + 1, txid: self.txid }
+    }
+}
+
+struct Transaction {
+    txid: u64,
+    // The snapshot taken at BEGIN
+    start: Tables,
+    // The snapshot with the transaction's own changes
+    tables: Tables,
+    // Every table the transaction changed, created or dropped
+    written: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct MemoryBackend {
+    shared: Arc<Shared>,
+    transaction: Option<Transaction>,
+}
+
+fn unknown_table(name: &QualifiedName) -> BackendError {
+    BackendError::of_kind(ErrorKind::UndefinedTable, format!("Unknown table {}", name), name.loc)
+}
+
+fn unknown_view(name: &QualifiedName) -> BackendError {
+    BackendError::of_kind(ErrorKind::UndefinedTable, format!("Unknown view {}", name), name.loc)
+}
+
+fn unknown_column(name: &impl fmt::Display, loc: Location) -> BackendError {
+    BackendError::of_kind(ErrorKind::UndefinedColumn, format!("Unknown column {}", name), loc)
+}
+
+// A numeric literal too large for an int that is not a real either
+fn not_a_number(n: &str, loc: Location) -> BackendError {
+    BackendError::of_kind(ErrorKind::OutOfRange, format!("Expected int, got {}", n), loc)
+}
+
+fn not_a_table(name: &QualifiedName) -> BackendError {
+    BackendError::new(format!("{} is a view, not a table", name), name.loc)
+}
+
+// What each backend keeps of a table or view, for the lookups by name that
+// every backend makes the same way
+trait Entry {
+    fn columns(&self) -> &[ColumnDef];
+    fn indexes(&self) -> &[Index];
+    // The query of a view, None for a table
+    fn view(&self) -> Option<&Select>;
+}
+
+impl Entry for Version {
+    fn columns(&self) -> &[ColumnDef] {
+        &self.table.columns
+    }
+
+    fn indexes(&self) -> &[Index] {
+        &self.table.indexes
+    }
+
+    fn view(&self) -> Option<&Select> {
+        self.table.view.as_ref()
+    }
+}
+
+// A table, a view is refused as it has no rows to read or change
+fn entry<'t, T: Entry>(tables: &'t HashMap<String, T>, name: &QualifiedName) -> Result<&'t T, BackendError> {
+    match tables.get(&name.to_string()) {
+        Some(entry) if entry.view().is_some() => Err(not_a_table(name)),
+        Some(entry) => Ok(entry),
+        None => Err(unknown_table(name)),
+    }
+}
+
+// A table of information_schema, or a view, neither of which has rows kept
+// by the backend
+fn catalog_or_view<'t, T: Entry>(tables: &'t HashMap<String, T>, name: &QualifiedName) -> Option<Source<'t>> {
+    let infos = tables.iter().map(|(name, entry)| catalog::TableInfo {
+        name,
+        columns: entry.columns(),
+        indexes: entry.indexes(),
+        view: entry.view(),
+    });
+    catalog::load(name, infos).or_else(|| tables.get(&name.to_string())?.view().map(Source::view))
+}
+
+// Whether an index made by CREATE INDEX has the name, the keys of unique
+// columns take none up
+fn has_index<T: Entry>(tables: &HashMap<String, T>, name: &QualifiedName) -> bool {
+    let key = name.to_string();
+    tables.values().any(|entry| entry.indexes().iter().any(|i| !i.key && i.name == key))
+}
+
+fn has_view<T: Entry>(tables: &HashMap<String, T>, name: &QualifiedName) -> bool {
+    tables.get(&name.to_string()).is_some_and(|entry| entry.view().is_some())
+}
+
+// The error for creating a table or view whose name is taken
+fn already_exists<T: Entry>(tables: &HashMap<String, T>, name: &QualifiedName) -> BackendError {
+    let kind = if has_view(tables, name) { "View" } else { "Table" };
+    BackendError::new(format!("{} {} already exists", kind, name), name.loc)
+}
+
+// The columns of every other table whose foreign keys change when the
+// column `from` of `table` is renamed to `to`, with the keys following it
+fn renamed_references<T: Entry>(
+    tables: &HashMap<String, T>,
+    table: &QualifiedName,
+    from: &str,
+    to: &str,
+) -> Vec<(String, Vec<ColumnDef>)> {
+    let key = table.to_string();
+    let mut renamed = Vec::new();
+    for (other, entry) in tables.iter().filter(|(other, _)| **other != key) {
+        let mut columns = entry.columns().to_vec();
+        if foreign_key::rename(&mut columns, table, from, to) {
+            renamed.push((other.clone(), columns));
+        }
+    }
+    renamed
+}
+
+fn table<'t>(tables: &'t Tables, name: &QualifiedName) -> Result<&'t Table, BackendError> {
+    entry(tables, name).map(|version| version.table.as_ref())
+}
+
+fn load<'t>(tables: &'t Tables, name: &QualifiedName) -> Result<Source<'t>, BackendError> {
+    if let Some(source) = catalog_or_view(tables, name) {
+        return Ok(source);
+    }
+    let table = table(tables, name)?;
+    let columns = Cow::Borrowed(table.columns.as_slice());
+    let rows = SourceRows::Loaded(Cow::Borrowed(table.rows.as_slice()));
+    Ok(Source { columns, rows, indexes: &table.indexes, view: None })
+}
+
+// The tables for foreign keys to be checked against
+fn schema(tables: &Tables) -> Schema<'_> {
+    Schema::new(
+        tables.iter().map(|(name, version)| (name.as_str(), version.columns(), version.indexes())),
+        Box::new(|name| Ok(Cow::Borrowed(tables.get(name).map_or(&[][..], |version| &version.table.rows)))),
+    )
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    // Another session on the same tables, with transactions of its own. A
+    // session can be moved to another thread.
+    pub fn session(&self) -> MemoryBackend {
+        MemoryBackend { shared: Arc::clone(&self.shared), transaction: None }
+    }
+
+    // A session that panicked left the store whole, changes are made only
+    // once they are known to succeed
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.shared.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn writer(&self) -> MutexGuard<'_, ()> {
+        self.shared.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Runs `f` on the tables the session sees: its transaction's snapshot,
+    // or the current versions outside a transaction
+    fn read<T>(&self, f: impl FnOnce(&Tables) -> T) -> T {
+        match &self.transaction {
+            Some(transaction) => f(&transaction.tables),
+            None => {
+                let tables = self.store().tables.clone();
+                f(&tables)
+            }
+        }
+    }
+
+    // Runs `f`, which works out a change to the table called `name`, on
+    // the tables the session sees, and makes the change it returns
+    fn write<T, C: FnOnce(&mut Tables)>(
+        &mut self,
+        name: &QualifiedName,
+        f: impl FnOnce(&Tables, &Stamps) -> Result<(T, C), BackendError>,
+    ) -> Result<T, BackendError> {
+        self.write_tables(|tables, stamps| {
+            let (result, change) = f(tables, stamps)?;
+            Ok((result, vec![name.to_string()], change))
+        })
+    }
+
+    // Like write, for an `f` that may change several tables and also
+    // returns the names of the ones it changes
+    fn write_tables<T, C: FnOnce(&mut Tables)>(
+        &mut self,
+        f: impl FnOnce(&Tables, &Stamps) -> Result<(T, Vec<String>, C), BackendError>,
+    ) -> Result<T, BackendError> {
+        let last_row_id = &self.shared.last_row_id;
+        if let Some(transaction) = &mut self.transaction {
+            let stamps = Stamps { txid: transaction.txid, last_row_id };
+            let (result, changed, change) = f(&transaction.tables, &stamps)?;
+            change(&mut transaction.tables);
+            transaction.written.extend(changed);
+            return Ok(result);
+        }
+
+        let _writer = self.writer();
+        let (txid, tables) = {
+            let mut store = self.store();
+            (store.next_txid(), store.tables.clone())
+        };
+        let (result, changed, change) = f(&tables, &Stamps { txid, last_row_id })?;
+        // Without the snapshot a version no reader holds is changed in place
+        drop(tables);
+        let mut store = self.store();
+        change(&mut store.tables);
+        for name in changed {
+            if let Some(version) = store.tables.get_mut(&name) {
+                version.txid = txid;
+            }
+        }
+        Ok(result)
+    }
+}
+
+// The table a change was worked out for, copied first when some snapshot
+// still shares it
+fn changed<'t>(tables: &'t mut Tables, name: &str) -> &'t mut Table {
+    Arc::make_mut(&mut tables.get_mut(name).expect("a change is made to the tables it was worked out on").table)
+}
+
+// Whether a foreign key references the table or is one of its columns
+fn has_foreign_keys(tables: &Tables, name: &str, table: &Table) -> bool {
+    let references = |columns: &[ColumnDef]| columns.iter().any(|column| column.references().is_some());
+    references(&table.columns)
+        || tables.values().any(|version| {
+            let columns = version.table.columns.iter();
+            columns.filter_map(ColumnDef::references).any(|key| key.table.to_string() == name)
+        })
+}
+
+fn conflict(message: String, loc: Location) -> BackendError {
+    BackendError::of_kind(ErrorKind::SerializationFailure, message, loc)
+}
+
+/*
+    The version of a table a transaction commits, None when it dropped the
+    table. `start` is the version the transaction began with, `own` the one
+    it made and `current` the one committed since.
+ */
+fn merge(
+    name: &str,
+    start: Option<&Version>,
+    own: Option<&Version>,
+    current: &Tables,
+    loc: Location,
+) -> Result<Option<Arc<Table>>, BackendError> {
+    let txid = |version: Option<&Version>| version.map(|version| version.txid);
+    if txid(current.get(name)) == txid(start) {
+        return Ok(own.map(|version| Arc::clone(&version.table)));
+    }
+    let table_conflict =
+        || conflict(format!("Could not commit, table {} was changed by another transaction", name), loc);
+    let (Some(start), Some(own), Some(now)) = (start, own, current.get(name)) else {
+        return Err(table_conflict());
+    };
+    let (start, own, now) = (&start.table, &own.table, &now.table);
+    let index_columns =
+        |table: &Table| table.indexes.iter().map(|index| (index.name.clone(), index.column)).collect::<Vec<_>>();
+    let same_shape = |table: &Table| {
+        table.view.is_none() && table.columns == start.columns && index_columns(table) == index_columns(start)
+    };
+    let numbered = own.sequence != start.sequence && now.sequence != start.sequence;
+    let reshaped = start.view.is_some() || !same_shape(own) || !same_shape(now);
+    if reshaped || numbered || has_foreign_keys(current, name, now) {
+        return Err(table_conflict());
+    }
+
+    // The rows are changed where they are now, if they are as the
+    // transaction found them
+    let row_conflict = || {
+        conflict(format!("Could not commit, a row of table {} was changed by another transaction", name), loc)
+    };
+    let found: HashMap<u64, u64> = start.stamps.iter().map(|stamp| (stamp.id, stamp.txid)).collect();
+    let positions: HashMap<u64, usize> = now.stamps.iter().enumerate().map(|(i, stamp)| (stamp.id, i)).collect();
+    let unchanged = |id: u64| {
+        positions.get(&id).copied().filter(|&i| Some(&now.stamps[i].txid) == found.get(&id)).ok_or_else(row_conflict)
+    };
+    let mut merged = Table::clone(now);
+    let mut deleted = vec![false; merged.rows.len()];
+    let kept: HashSet<u64> = own.stamps.iter().map(|stamp| stamp.id).collect();
+    for stamp in start.stamps.iter().filter(|stamp| !kept.contains(&stamp.id)) {
+        deleted[unchanged(stamp.id)?] = true;
+    }
+    for (row, stamp) in own.rows.iter().zip(&own.stamps) {
+        match found.get(&stamp.id) {
+            Some(&txid) if txid == stamp.txid => {}
+            Some(_) => {
+                let i = unchanged(stamp.id)?;
+                merged.rows[i] = row.clone();
+                merged.stamps[i] = *stamp;
+            }
+            None => {
+                merged.rows.push(row.clone());
+                merged.stamps.push(*stamp);
+                deleted.push(false);
+            }
+        }
+    }
+    let mut keep = deleted.iter().map(|deleted| !deleted);
+    merged.rows.retain(|_| keep.next().unwrap());
+    let mut keep = deleted.iter().map(|deleted| !deleted);
+    merged.stamps.retain(|_| keep.next().unwrap());
+    merged.sequence = own.sequence.max(now.sequence);
+
+    // Each side kept its unique columns unique, but not necessarily together
+    for (i, column) in merged.columns.iter().enumerate().filter(|(_, column)| column.is_unique()) {
+        let mut seen = HashSet::new();
+        if merged.rows.iter().any(|row| row[i] != Value::Null && !seen.insert(&row[i])) {
+            let message = format!(
+                "Could not commit, another transaction put the same {} in table {}",
+                column.name, name
+            );
+            return Err(conflict(message, loc));
+        }
+    }
+    let Table { rows, indexes, .. } = &mut merged;
+    indexes.iter_mut().for_each(|index| index.rebuild(rows));
+    Ok(Some(Arc::new(merged)))
+}
+
+impl Backend for MemoryBackend {
+    fn create_table(&mut self, name: &QualifiedName, columns: &[ColumnDef]) -> Result<(), BackendError> {
+        let key = name.to_string();
+        self.write(name, |tables, _| {
+            if tables.contains_key(&key) {
+                return Err(already_exists(tables, name));
+            }
+            check_columns(name, columns, &schema(tables))?;
+
+            let mut table = Table::new(columns.to_vec(), None);
+            index::update_keys(&mut table.indexes, &key, columns, &[]);
+            Ok(((), |tables: &mut Tables| {
+                tables.insert(key, Version { table: Arc::new(table), txid: 0 });
+            }))
+        })
+    }
+
+    fn create_index(
+        &mut self,
+        name: &QualifiedName,
+        table: &QualifiedName,
+        column: &QualifiedName,
+    ) -> Result<(), BackendError> {
+        self.write(table, |tables, _| {
+            let taken = has_index(tables, name);
+            let target = self::table(tables, table)?;
+            let index = create_index(name, table, column, &target.columns, &target.rows, taken)?;
+            Ok(((), |tables: &mut Tables| changed(tables, &table.to_string()).indexes.push(index)))
+        })
+    }
+
+    fn insert(
+        &mut self,
+        table: &QualifiedName,
+        columns: &[QualifiedName],
+        rows: InsertFrom,
+    ) -> Result<usize, BackendError> {
+        self.write(table, |tables, stamps| {
+            let target = self::table(tables, table)?;
+            let mut sequence = target.sequence;
+            let (indexes, source) = (&target.indexes, |name: &QualifiedName| load(tables, name));
+            let values =
+                insert_rows(table, &target.columns, indexes, columns, rows, &source, &mut sequence, &schema(tables))?;
+            let count = values.len();
+            let added: Vec<Stamp> = values.iter().map(|_| stamps.new_row()).collect();
+            Ok((count, move |tables: &mut Tables| {
+                let target = changed(tables, &table.to_string());
+                target.sequence = sequence;
+                for row in &values {
+                    target.indexes.iter_mut().for_each(|index| index.push(row));
+                }
+                target.rows.extend(values);
+                target.stamps.extend(added);
+            }))
+        })
+    }
+
+    fn select(&self, query: &Select) -> Result<ResultSet, BackendError> {
+        self.read(|tables| select_rows(query, &|name| load(tables, name)))
+    }
+
+    fn explain(&self, query: &Select) -> Result<ResultSet, BackendError> {
+        self.read(|tables| explain_rows(query, &|name| load(tables, name)))
+    }
+
+    fn update(
+        &mut self,
+        table: &QualifiedName,
+        assignments: &[Assignment],
+        filter: Option<&Expr>,
+    ) -> Result<usize, BackendError> {
+        self.write(table, |tables, stamps| {
+            let target = self::table(tables, table)?;
+            let (columns, rows, indexes) = (&target.columns, &target.rows, &target.indexes);
+            let changes = update_rows(table, columns, rows, indexes, assignments, filter, &schema(tables))?;
+            let count = changes.len();
+            let txid = stamps.txid;
+            Ok((count, move |tables: &mut Tables| {
+                let target = changed(tables, &table.to_string());
+                for (i, updated) in changes {
+                    target.rows[i] = updated;
+                    target.stamps[i].txid = txid;
+                }
+                target.indexes.iter_mut().for_each(|index| index.rebuild(&target.rows));
+            }))
+        })
+    }
+
+    fn delete(&mut self, table: &QualifiedName, filter: Option<&Expr>) -> Result<usize, BackendError> {
+        self.write_tables(|tables, stamps| {
+            let target = self::table(tables, table)?;
+            let keep = delete_rows(table, &target.columns, &target.rows, &target.indexes, filter)?;
+            let count = keep.iter().filter(|k| !**k).count();
+            let cascaded = match count {
+                0 => HashMap::new(),
+                _ => foreign_key::on_delete(table, &target.rows, &keep, &schema(tables))?,
+            };
+
+            // With no foreign key to follow only the table itself changes.
+            // The rows a cascade leaves are written anew, they are not
+            // followed one by one.
+            let names = match cascaded.is_empty() {
+                true => vec![table.to_string()],
+                false => cascaded.keys().cloned().collect(),
+            };
+            let cascaded: Vec<_> = cascaded
+                .into_iter()
+                .map(|(name, rows)| {
+                    let added: Vec<Stamp> = rows.iter().map(|_| stamps.new_row()).collect();
+                    (name, rows, added)
+                })
+                .collect();
+            Ok((count, names, move |tables: &mut Tables| {
+                if cascaded.is_empty() {
+                    let target = changed(tables, &table.to_string());
+                    let mut kept = keep.iter();
+                    target.rows.retain(|_| *kept.next().unwrap());
+                    let mut kept = keep.iter();
+                    target.stamps.retain(|_| *kept.next().unwrap());
+                    target.indexes.iter_mut().for_each(|index| index.rebuild(&target.rows));
+                }
+                for (name, rows, added) in cascaded {
+                    let target = changed(tables, &name);
+                    target.rows = rows;
+                    target.stamps = added;
+                    target.indexes.iter_mut().for_each(|index| index.rebuild(&target.rows));
+                }
+            }))
+        })
+    }
+
+    fn columns(&self, table: &QualifiedName) -> Result<Vec<ColumnDef>, BackendError> {
+        self.read(|tables| Ok(self::table(tables, table)?.columns.clone()))
+    }
+
+    fn alter_table(&mut self, table: &QualifiedName, op: &AlterOp) -> Result<(), BackendError> {
+        self.write_tables(|tables, stamps| {
+            let target = self::table(tables, table)?;
+            let altered = alter_table(table, &target.columns, &target.rows, &target.indexes, op, &schema(tables))?;
+
+            // The foreign keys of other tables follow a renamed column
+            let key = table.to_string();
+            let renamed = match op {
+                AlterOp::RenameColumn { column, name } => {
+                    renamed_references(tables, table, column.parts.last().unwrap(), name)
+                }
+                _ => Vec::new(),
+            };
+            let names = std::iter::once(key.clone()).chain(renamed.iter().map(|(name, _)| name.clone())).collect();
+            let txid = stamps.txid;
+            Ok(((), names, move |tables: &mut Tables| {
+                let target = changed(tables, &key);
+                target.columns = altered.columns;
+                if let Some(rows) = altered.rows {
+                    target.rows = rows;
+                    target.stamps.iter_mut().for_each(|stamp| stamp.txid = txid);
+                }
+                move_indexes(&mut target.indexes, &altered.index_columns);
+                index::update_keys(&mut target.indexes, &key, &target.columns, &target.rows);
+                for (name, columns) in renamed {
+                    changed(tables, &name).columns = columns;
+                }
+            }))
+        })
+    }
+
+    fn drop_table(&mut self, name: &QualifiedName) -> Result<(), BackendError> {
+        self.write(name, |tables, _| {
+            if let Some(referencing) = schema(tables).referenced_by(&name.to_string(), None) {
+                return Err(BackendError::new(
+                    format!("Cannot drop table {}, {} references it", name, referencing),
+                    name.loc,
+                ));
+            }
+            self::table(tables, name)?;
+            Ok(((), |tables: &mut Tables| {
+                tables.remove(&name.to_string());
+            }))
+        })
+    }
+
+    fn has_index(&self, name: &QualifiedName) -> bool {
+        self.read(|tables| has_index(tables, name))
+    }
+
+    fn create_view(&mut self, name: &QualifiedName, query: &Select) -> Result<(), BackendError> {
+        self.write(name, |tables, _| {
+            if tables.contains_key(&name.to_string()) {
+                return Err(already_exists(tables, name));
+            }
+            check_view(query, &|name| load(tables, name))?;
+
+            let table = Table::new(Vec::new(), Some(query.clone()));
+            Ok(((), |tables: &mut Tables| {
+                tables.insert(name.to_string(), Version { table: Arc::new(table), txid: 0 });
+            }))
+        })
+    }
+
+    fn drop_view(&mut self, name: &QualifiedName) -> Result<(), BackendError> {
+        self.write(name, |tables, _| {
+            if !has_view(tables, name) {
+                return Err(unknown_view(name));
+            }
+            Ok(((), |tables: &mut Tables| {
+                tables.remove(&name.to_string());
+            }))
+        })
+    }
+
+    fn has_view(&self, name: &QualifiedName) -> bool {
+        self.read(|tables| has_view(tables, name))
+    }
+
+    fn begin(&mut self, loc: Location) -> Result<(), BackendError> {
+        if self.transaction.is_some() {
+            return Err(already_in_transaction(loc));
+        }
+        let mut store = self.store();
+        let txid = store.next_txid();
+        let start = store.tables.clone();
+        drop(store);
+        let tables = start.clone();
+        self.transaction = Some(Transaction { txid, start, tables, written: HashSet::new() });
+        Ok(())
+    }
+
+    fn commit(&mut self, loc: Location) -> Result<(), BackendError> {
+        let transaction = self.transaction.take().ok_or_else(|| no_transaction(loc))?;
+        if transaction.written.is_empty() {
+            return Ok(());
+        }
+        let _writer = self.writer();
+        let current = self.store().tables.clone();
+        let mut names: Vec<&String> = transaction.written.iter().collect();
+        // The same conflict is reported whichever order the tables are kept in
+        names.sort();
+        let mut merged = Vec::with_capacity(names.len());
+        for name in names {
+            let table = merge(name, transaction.start.get(name), transaction.tables.get(name), &current, loc)?;
+            merged.push((name.clone(), table));
+        }
+        drop(current);
+
+        let mut store = self.store();
+        for (name, table) in merged {
+            match table {
+                Some(table) => store.tables.insert(name, Version { table, txid: transaction.txid }),
+                None => store.tables.remove(&name),
+            };
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self, loc: Location) -> Result<(), BackendError> {
+        self.transaction.take().map(|_| ()).ok_or_else(|| no_transaction(loc))
+    }
+
+    fn connect(&self) -> Option<Box<dyn Backend + Send>> {
+        Some(Box::new(self.session()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    // Lexes, parses and executes a script, returning the last statement's result
+    fn run(backend: &mut MemoryBackend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    // Like run, for scripts that end in a select
+    fn query(backend: &mut MemoryBackend, source: &str) -> Result<ResultSet, BackendError> {
+        match run(backend, source)? {
+            QueryResult::Rows(rows) => Ok(rows),
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    fn setup() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table users (id int, name text);
+             insert into users values (1, 'Ada'), (2, 'Grace');",
+        )
+        .unwrap();
+        backend
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_select_wildcard() {
+        let mut backend = setup();
+        let result = query(&mut backend, "select * from users").unwrap();
+        assert_eq!(result.columns, vec!["id", "name"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Int(1), text("Ada")], vec![Value::Int(2), text("Grace")]]
+        );
+    }
+
+    #[test]
+    fn test_select_columns_and_aliases() {
+        let mut backend = setup();
+        let result = query(&mut backend, "select name as who, users.id from users").unwrap();
+        assert_eq!(result.columns, vec!["who", "id"]);
+        assert_eq!(result.rows[1], vec![text("Grace"), Value::Int(2)]);
+    }
+
+    #[test]
+    fn test_long_operator_chains() {
+        let mut backend = setup();
+        // 1000 terms of an OR, only the first of which holds
+        let chain = (0..1000).map(|i| format!("id = {}", i + 2)).collect::<Vec<_>>().join(" or ");
+        let result = query(&mut backend, &format!("select name from users where {}", chain)).unwrap();
+        assert_eq!(result.rows, vec![vec![text("Grace")]]);
+        let sum = query(&mut backend, &format!("select 0{} from users where id = 1", " + 1 - 2".repeat(500))).unwrap();
+        assert_eq!(sum.rows, vec![vec![Value::Int(-500)]]);
+        // The planner splits a long AND into its parts and joins them again
+        let side = |i| if i % 2 == 0 { "u" } else { "v" };
+        let chain = (0..1000).map(|i| format!("{}.id <> {}", side(i), i + 3)).collect::<Vec<_>>().join(" and ");
+        let source = format!("select u.name from users u join users v on true where {}", chain);
+        let result = query(&mut backend, &source).unwrap();
+        assert_eq!(result.rows.len(), 4);
+    }
+
+    #[test]
+    fn test_create_and_insert_results() {
+        let mut backend = MemoryBackend::new();
+        assert_eq!(run(&mut backend, "create table t (id int)"), Ok(QueryResult::Done));
+        assert_eq!(run(&mut backend, "insert into t values (1), (2)"), Ok(QueryResult::Affected(2)));
+    }
+
+    #[test]
+    fn test_insert_decodes_integer_spellings() {
+        let mut backend = MemoryBackend::new();
+        let result = query(
+            &mut backend,
+            "create table t (n int); insert into t values (0xFF), (0b101), (1_000); select n from t",
+        )
+        .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(255)], vec![Value::Int(5)], vec![Value::Int(1000)]]);
+    }
+
+    #[test]
+    fn test_unknown_table_points_at_name() {
+        let mut backend = setup();
+        let err = run(&mut backend, "select *\nfrom nope").unwrap_err();
+        assert_eq!(err.message(), "Unknown table nope");
+        assert_eq!(err.location(), Location::new(2, 6));
+    }
+
+    #[test]
+    fn test_unknown_column_points_at_column() {
+        let mut backend = setup();
+        let err = run(&mut backend, "select id, age from users").unwrap_err();
+        assert_eq!(err.message(), "Unknown column age");
+        assert_eq!(err.location(), Location::new(1, 12));
+
+        let err = run(&mut backend, "select other.id from users").unwrap_err();
+        assert_eq!(err.message(), "Unknown table other");
+    }
+
+    #[test]
+    fn test_create_existing_table_fails() {
+        let mut backend = setup();
+        let err = run(&mut backend, "create table users (id int)").unwrap_err();
+        assert_eq!(err.message(), "Table users already exists");
+    }
+
+    #[test]
+    fn test_create_duplicate_column_fails() {
+        let mut backend = MemoryBackend::new();
+        let err = run(&mut backend, "create table t (id int, id text)").unwrap_err();
+        assert_eq!(err.message(), "Duplicate column id");
+        assert_eq!(err.location(), Location::new(1, 25));
+    }
+
+    #[test]
+    fn test_insert_type_mismatch_stores_nothing() {
+        let mut backend = setup();
+        let err = run(&mut backend, "insert into users values (3, 'Linus'), ('4', 'Ken')").unwrap_err();
+        assert_eq!(err.message(), "Expected int for column id, got 4");
+
+        let result = query(&mut backend, "select id from users").unwrap();
+        assert_eq!(result.rows.len(), 2);
+    }
+
+    #[test]
+    fn test_insert_wrong_arity_fails() {
+        let mut backend = setup();
+        let err = run(&mut backend, "insert into users values (3)").unwrap_err();
+        assert_eq!(err.message(), "Expected 2 values, got 1");
+    }
+
+    fn setup_people() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table people (name text, age int);
+             insert into people values ('alice', 25), ('bob', 35), ('carol', 40), ('bob', 20);",
+        )
+        .unwrap();
+        backend
+    }
+
+    fn names(result: ResultSet) -> Vec<Value> {
+        result.rows.into_iter().map(|mut row| row.remove(0)).collect()
+    }
+
+    #[test]
+    fn test_order_by_sorts_on_several_keys() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select name, age from people order by name desc, age").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("carol"), Value::Int(40)],
+                vec![text("bob"), Value::Int(20)],
+                vec![text("bob"), Value::Int(35)],
+                vec![text("alice"), Value::Int(25)],
+            ]
+        );
+
+        // By a column that is not selected, an expression or an alias
+        let result = query(&mut backend, "select name from people order by age asc").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("alice"), text("bob"), text("carol")]);
+        let result = query(&mut backend, "select name from people order by 0 - age").unwrap();
+        assert_eq!(names(result), vec![text("carol"), text("bob"), text("alice"), text("bob")]);
+        let result = query(&mut backend, "select age as years, name from people order by years desc").unwrap();
+        assert_eq!(names(result), vec![Value::Int(40), Value::Int(35), Value::Int(25), Value::Int(20)]);
+    }
+
+    #[test]
+    fn test_order_by_puts_nulls_last() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (n int); insert into t values (2), (null), (1)").unwrap();
+        let result = query(&mut backend, "select n from t order by n").unwrap();
+        assert_eq!(names(result), vec![Value::Int(1), Value::Int(2), Value::Null]);
+        let result = query(&mut backend, "select n from t order by n desc").unwrap();
+        assert_eq!(names(result), vec![Value::Null, Value::Int(2), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_limit_and_offset() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select name from people order by age limit 2").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("alice")]);
+        let result = query(&mut backend, "select name from people order by age limit 2 offset 1").unwrap();
+        assert_eq!(names(result), vec![text("alice"), text("bob")]);
+        let result = query(&mut backend, "select name from people offset 3").unwrap();
+        assert_eq!(names(result), vec![text("bob")]);
+        let result = query(&mut backend, "select name from people where age > 20 limit 1 + 1").unwrap();
+        assert_eq!(names(result), vec![text("alice"), text("bob")]);
+        assert_eq!(query(&mut backend, "select name from people limit null").unwrap().rows.len(), 4);
+        assert_eq!(query(&mut backend, "select name from people limit 0").unwrap().rows.len(), 0);
+        assert_eq!(query(&mut backend, "select name from people offset 10").unwrap().rows.len(), 0);
+
+        let err = run(&mut backend, "select name from people limit -1").unwrap_err();
+        assert_eq!(err.message(), "Expected a non-negative int for LIMIT, got -1");
+        let err = run(&mut backend, "select name from people offset 'a'").unwrap_err();
+        assert_eq!(err.message(), "Expected a non-negative int for OFFSET, got a");
+        let err = run(&mut backend, "select name from people order by height").unwrap_err();
+        assert_eq!(err.message(), "Unknown column height");
+    }
+
+    #[test]
+    fn test_select_list_expressions() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select name, age * 2 as double, age > 30 from people limit 2").unwrap();
+        assert_eq!(result.columns, vec!["name", "double", "?column?"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("alice"), Value::Int(50), Value::Bool(false)],
+                vec![text("bob"), Value::Int(70), Value::Bool(true)],
+            ]
+        );
+
+        // Unknown columns are reported even without rows to evaluate
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (a int)").unwrap();
+        let err = run(&mut backend, "select a + b from t").unwrap_err();
+        assert_eq!(err.message(), "Unknown column b");
+        assert_eq!(err.location(), Location::new(1, 12));
+    }
+
+    #[test]
+    fn test_group_by_with_aggregates() {
+        let mut backend = setup_people();
+        run(&mut backend, "insert into people values ('dave', null)").unwrap();
+        let result = query(
+            &mut backend,
+            "select name, count(*), count(age), sum(age), min(age), max(age), avg(age)
+             from people group by name order by name",
+        )
+        .unwrap();
+        assert_eq!(result.columns, vec!["name", "count", "count", "sum", "min", "max", "avg"]);
+        let int = Value::Int;
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("alice"), int(1), int(1), int(25), int(25), int(25), Value::Real(25.0)],
+                vec![text("bob"), int(2), int(2), int(55), int(20), int(35), Value::Real(27.5)],
+                vec![text("carol"), int(1), int(1), int(40), int(40), int(40), Value::Real(40.0)],
+                vec![text("dave"), int(1), int(0), Value::Null, Value::Null, Value::Null, Value::Null],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_having_and_ordering_by_aggregates() {
+        let mut backend = setup_people();
+        let result = query(
+            &mut backend,
+            "select name, sum(age) as total from people where age > 20
+             group by name having count(*) >= 1 and sum(age) > 30 order by total desc",
+        )
+        .unwrap();
+        assert_eq!(result.rows, vec![vec![text("carol"), Value::Int(40)], vec![text("bob"), Value::Int(35)]]);
+
+        let source = "select age > 30, count(*) from people group by age > 30 order by count(*)";
+        let result = query(&mut backend, source).unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Bool(false), Value::Int(2)], vec![Value::Bool(true), Value::Int(2)]]);
+    }
+
+    #[test]
+    fn test_aggregates_without_group_by() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select count(*), max(name), sum(age) / count(*) from people").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(4), text("carol"), Value::Int(30)]]);
+
+        // An empty table still gives one row
+        let result = query(&mut backend, "select count(*), sum(age) from people where age > 100").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(0), Value::Null]]);
+        let result = query(&mut backend, "select name from people where age > 100 group by name").unwrap();
+        assert_eq!(result.rows.len(), 0);
+    }
+
+    #[test]
+    fn test_aggregate_errors() {
+        let mut backend = setup_people();
+        let cases = [
+            ("select name, count(*) from people", "Column name must appear in GROUP BY or be used in an aggregate"),
+            ("select * from people group by name", "Column age must appear in GROUP BY or be used in an aggregate"),
+            ("select sum(name) from people", "Cannot apply sum to text"),
+            ("select sum(*) from people", "Only count accepts *, not sum"),
+            ("select max(age, 1) from people", "Function max takes 1 argument, got 2"),
+            ("select max(count(*)) from people", "Aggregate calls cannot be nested"),
+            ("select name from people where count(*) > 1", "Aggregate function count is not allowed here"),
+            ("select name from people group by name having name", "Expected bool in HAVING, got alice"),
+            ("select nope(age) from people", "Unknown function nope"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_rows_across_batches() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (n int, g int)").unwrap();
+        let values: Vec<String> = (0..3000).map(|n| format!("({}, {})", n, n % 3)).collect();
+        run(&mut backend, &format!("insert into t values {}", values.join(", "))).unwrap();
+
+        let result = query(&mut backend, "select g, count(*), sum(n), min(n), max(n) from t group by g order by g");
+        let int = Value::Int;
+        assert_eq!(
+            result.unwrap().rows,
+            vec![
+                vec![int(0), int(1000), int(1498500), int(0), int(2997)],
+                vec![int(1), int(1000), int(1499500), int(1), int(2998)],
+                vec![int(2), int(1000), int(1500500), int(2), int(2999)],
+            ]
+        );
+        let result = query(&mut backend, "select n from t where n in (999, 1999, 2999) offset 1").unwrap();
+        assert_eq!(names(result), vec![int(1999), int(2999)]);
+        let result = query(&mut backend, "select n * 2 from t offset 1023 limit 3").unwrap();
+        assert_eq!(names(result), vec![int(2046), int(2048), int(2050)]);
+        assert_eq!(query(&mut backend, "select * from t order by n desc").unwrap().rows.len(), 3000);
+
+        // A limit stops reading rows once it has enough, the rows it never
+        // reads are not evaluated
+        let result = query(&mut backend, "select 1 / (n - 2500) from t limit 2").unwrap();
+        assert_eq!(names(result), vec![int(0), int(0)]);
+        let err = run(&mut backend, "select 1 / (n - 2500) from t").unwrap_err();
+        assert_eq!(err.message(), "Division by zero");
+    }
+
+    fn setup_orders() -> MemoryBackend {
+        let mut backend = setup();
+        run(
+            &mut backend,
+            "create table admins (id int);
+             create table orders (id int, user_id int, item text);
+             insert into users values (3, 'Linus');
+             insert into orders values (10, 1, 'book'), (11, 2, 'pen'), (12, 1, 'lamp'), (13, 9, 'cup');",
+        )
+        .unwrap();
+        backend
+    }
+
+    #[test]
+    fn test_inner_join() {
+        let mut backend = setup_orders();
+        let result = query(
+            &mut backend,
+            "select u.name, o.item from users u join orders as o on u.id = o.user_id order by o.id",
+        )
+        .unwrap();
+        assert_eq!(result.columns, vec!["name", "item"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("Ada"), text("book")],
+                vec![text("Grace"), text("pen")],
+                vec![text("Ada"), text("lamp")],
+            ]
+        );
+
+        // Unqualified columns resolve when only one table has them
+        let sql = "select name, item from users inner join orders on users.id = user_id where item <> 'pen'";
+        let result = query(&mut backend, sql).unwrap();
+        assert_eq!(names(result), vec![text("Ada"), text("Ada")]);
+
+        let sql = "select * from users join orders on users.id = orders.user_id limit 1";
+        let result = query(&mut backend, sql).unwrap();
+        assert_eq!(result.columns, vec!["id", "name", "id", "user_id", "item"]);
+        assert_eq!(result.rows, vec![vec![Value::Int(1), text("Ada"), Value::Int(10), Value::Int(1), text("book")]]);
+    }
+
+    #[test]
+    fn test_left_join_pads_with_nulls() {
+        let mut backend = setup_orders();
+        let result = query(
+            &mut backend,
+            "select u.name, o.item from users u left join orders o on u.id = o.user_id order by u.id, o.id",
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("Ada"), text("book")],
+                vec![text("Ada"), text("lamp")],
+                vec![text("Grace"), text("pen")],
+                vec![text("Linus"), Value::Null],
+            ]
+        );
+
+        // WHERE runs after the join, ON before it
+        let sql = "select u.name from users u left outer join orders o on u.id = o.user_id and o.item = 'pen'";
+        let result = query(&mut backend, &format!("{} where o.id is null order by u.id", sql)).unwrap();
+        assert_eq!(names(result), vec![text("Ada"), text("Linus")]);
+
+        let result = query(
+            &mut backend,
+            "select u.name, count(o.id) as n from users u left join orders o on o.user_id = u.id
+             group by u.name order by n desc, u.name",
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("Ada"), Value::Int(2)],
+                vec![text("Grace"), Value::Int(1)],
+                vec![text("Linus"), Value::Int(0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_chains_and_indexes() {
+        let mut backend = setup_orders();
+        run(&mut backend, "insert into admins values (1), (1); create index by_id on users (id)").unwrap();
+        let result = query(
+            &mut backend,
+            "select o.item from users join orders o on users.id = o.user_id join admins on admins.id = users.id
+             where users.id = 1 order by o.id",
+        )
+        .unwrap();
+        assert_eq!(names(result), vec![text("book"), text("book"), text("lamp"), text("lamp")]);
+    }
+
+    #[test]
+    fn test_join_errors() {
+        let mut backend = setup_orders();
+        let cases = [
+            ("select id from users join orders on true", "Column id is ambiguous"),
+            ("select * from users join users on true", "Table users specified more than once"),
+            ("select * from users u join orders on users.id = 1", "Unknown table users"),
+            ("select * from users join orders on orders.nope = 1", "Unknown column orders.nope"),
+            ("select * from users join orders on 1", "Expected bool in ON, got 1"),
+            ("select * from users join nope on true", "Unknown table nope"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_subquery_in_from() {
+        let mut backend = setup_orders();
+        let result = query(
+            &mut backend,
+            "select sub.user_id, sub.n from (select user_id, count(*) as n from orders group by user_id) as sub
+             where n > 1",
+        )
+        .unwrap();
+        assert_eq!(result.columns, vec!["user_id", "n"]);
+        assert_eq!(result.rows, vec![vec![Value::Int(1), Value::Int(2)]]);
+
+        // A subquery joins like a table
+        let result = query(
+            &mut backend,
+            "select u.name, o.item from users u join (select * from orders where item <> 'book') o on o.user_id = u.id
+             order by o.item",
+        )
+        .unwrap();
+        assert_eq!(result.rows, vec![vec![text("Ada"), text("lamp")], vec![text("Grace"), text("pen")]]);
+
+        let sql = "select id from (select o.id, u.id from orders o join users u on true) s";
+        let err = run(&mut backend, sql).unwrap_err();
+        assert_eq!(err.message(), "Column id is ambiguous");
+    }
+
+    #[test]
+    fn test_in_lists_and_subqueries() {
+        let mut backend = setup_orders();
+        let matching = |backend: &mut MemoryBackend, filter: &str| {
+            names(query(backend, &format!("select name from users where {} order by id", filter)).unwrap())
+        };
+        assert_eq!(matching(&mut backend, "id in (3, 1)"), vec![text("Ada"), text("Linus")]);
+        assert_eq!(matching(&mut backend, "id not in (3, 1)"), vec![text("Grace")]);
+        assert_eq!(matching(&mut backend, "id in (select user_id from orders)"), vec![text("Ada"), text("Grace")]);
+        assert_eq!(
+            matching(&mut backend, "id not in (select user_id from orders where item in ('pen', 'cup'))"),
+            vec![text("Ada"), text("Linus")]
+        );
+
+        // With a null in the list, a value that is not found is unknown
+        assert_eq!(eval_str("1 in (2, null)"), Value::Null);
+        assert_eq!(eval_str("1 not in (2, null)"), Value::Null);
+        assert_eq!(eval_str("1 in (1, null)"), Value::Bool(true));
+        assert_eq!(eval_str("null in (1)"), Value::Null);
+        assert_eq!(eval_str("1 in (1.0)"), Value::Bool(true));
+
+        // Subquery results go back into the statement as literals
+        let values = [Value::Int(-7), Value::Real(1e300), Value::Real(-0.5), text("it's"), Value::Date(-1), Value::Time(1)];
+        for value in values {
+            assert_eq!(eval(&literal(value.clone()), None, Location::new(1, 1)).unwrap(), value);
+        }
+
+        run(&mut backend, "delete from orders where user_id not in (select id from users)").unwrap();
+        assert_eq!(query(&mut backend, "select id from orders").unwrap().rows.len(), 3);
+        run(&mut backend, "update users set name = 'buyer' where id in (select user_id from orders)").unwrap();
+        assert_eq!(matching(&mut backend, "name = 'buyer'").len(), 2);
+
+        let cases = [
+            ("select name from users where id in (select id, name from users)", "Subquery has too many columns"),
+            ("select name from users where id in (select nope from orders)", "Unknown column nope"),
+            ("select name from users where id in ('a')", "Cannot apply = to int and text"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    fn explain(backend: &mut MemoryBackend, source: &str) -> Vec<String> {
+        let result = query(backend, &format!("explain {}", source)).unwrap();
+        assert_eq!(result.columns, vec!["QUERY PLAN"]);
+        result.rows.iter().map(|row| row[0].to_string()).collect()
+    }
+
+    #[test]
+    fn test_explain_shows_the_plan() {
+        let mut backend = setup_orders();
+        run(&mut backend, "create index by_id on users (id)").unwrap();
+        assert_eq!(
+            explain(
+                &mut backend,
+                "select u.name, count(*) as n from users u join orders o on o.user_id = u.id where u.id = 1
+                 group by u.name having count(*) > 1 order by n desc limit 2 offset 1",
+            ),
+            vec![
+                "Limit: 2 offset 1",
+                "  -> Sort: n DESC",
+                "    -> Project: u.name, count(*), count(*)",
+                "      -> Filter: (count(*) > 1)",
+                "        -> Aggregate: count(*); group by u.name",
+                "          -> Nested Loop Join: (o.user_id = u.id)",
+                "            -> Index Scan on users AS u using by_id: (u.id = 1)",
+                "            -> Seq Scan on orders AS o",
+            ]
+        );
+        assert_eq!(
+            explain(&mut backend, "select * from (select item from orders) s where item in ('pen')"),
+            vec![
+                "Project: item",
+                "  -> Filter: item IN ('pen')",
+                "    -> Subquery Scan on s",
+                "      -> Project: item",
+                "        -> Seq Scan on orders",
+            ]
+        );
+        assert_eq!(
+            explain(&mut backend, "select distinct item from orders union all select name from users order by item"),
+            vec![
+                "Sort: item",
+                "  -> UNION ALL",
+                "    -> Distinct",
+                "      -> Project: item",
+                "        -> Seq Scan on orders",
+                "    -> Project: name",
+                "      -> Seq Scan on users",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_planner_pushes_filters_down_and_reorders_joins() {
+        let mut backend = setup_orders();
+        run(&mut backend, "insert into admins values (1)").unwrap();
+        let sql = "select o.item, u.name from orders o join users u on u.id = o.user_id join admins a on a.id = u.id
+                   where o.item <> 'pen' and u.name = 'Ada'";
+        assert_eq!(
+            explain(&mut backend, sql),
+            vec![
+                "Project: o.item, u.name",
+                "  -> Nested Loop Join: (u.id = o.user_id)",
+                "    -> Nested Loop Join: (a.id = u.id)",
+                "      -> Seq Scan on admins AS a",
+                "      -> Filter: (u.name = 'Ada')",
+                "        -> Seq Scan on users AS u",
+                "    -> Filter: (o.item <> 'pen')",
+                "      -> Seq Scan on orders AS o",
+            ]
+        );
+        let result = query(&mut backend, &format!("{} order by o.id", sql)).unwrap();
+        assert_eq!(result.rows, vec![vec![text("book"), text("Ada")], vec![text("lamp"), text("Ada")]]);
+
+        // The columns of * keep the order of the query
+        let result = query(&mut backend, "select * from orders o join admins a on a.id = o.user_id").unwrap();
+        assert_eq!(result.columns, vec!["id", "user_id", "item", "id"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Int(10), Value::Int(1), text("book"), Value::Int(1)],
+                vec![Value::Int(12), Value::Int(1), text("lamp"), Value::Int(1)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_planner_keeps_left_joins_padded() {
+        let mut backend = setup_orders();
+        let sql = "select u.name from users u left join orders o on o.user_id = u.id and o.item <> 'pen'
+                   where o.id is null";
+        assert_eq!(
+            explain(&mut backend, sql),
+            vec![
+                "Project: u.name",
+                "  -> Filter: o.id IS NULL",
+                "    -> Nested Loop Left Join: (o.user_id = u.id)",
+                "      -> Seq Scan on users AS u",
+                "      -> Filter: (o.item <> 'pen')",
+                "        -> Seq Scan on orders AS o",
+            ]
+        );
+        assert_eq!(names(query(&mut backend, sql).unwrap()), vec![text("Grace"), text("Linus")]);
+    }
+
+    #[test]
+    fn test_planner_uses_indexes_of_joined_tables() {
+        let mut backend = setup_orders();
+        run(&mut backend, "create index by_item on orders (item)").unwrap();
+        let sql = "select u.name from users u join orders o on o.user_id = u.id where o.item = 'lamp' and u.id > 0";
+        assert_eq!(
+            explain(&mut backend, sql),
+            vec![
+                "Project: u.name",
+                "  -> Nested Loop Join: (o.user_id = u.id)",
+                "    -> Filter: (u.id > 0)",
+                "      -> Seq Scan on users AS u",
+                "    -> Index Scan on orders AS o using by_item: (o.item = 'lamp')",
+            ]
+        );
+        assert_eq!(names(query(&mut backend, sql).unwrap()), vec![text("Ada")]);
+    }
+
+    #[test]
+    fn test_explain_does_not_run_the_select() {
+        let mut backend = setup_orders();
+        // The subquery would fail, but only once it is run
+        let sql = "select name from users where id in (select id, item from orders)";
+        assert_eq!(run(&mut backend, sql).unwrap_err().message(), "Subquery has too many columns");
+        assert_eq!(
+            explain(&mut backend, sql),
+            vec!["Project: name", "  -> Filter: id IN (SELECT id, item FROM orders)", "    -> Seq Scan on users"]
+        );
+
+        // Planning errors are still reported
+        let err = run(&mut backend, "explain select nope from users").unwrap_err();
+        assert_eq!(err.message(), "Unknown column nope");
+    }
+
+    #[test]
+    fn test_where_filters_rows() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select * from people where age > 30 and name = 'bob'").unwrap();
+        assert_eq!(result.rows, vec![vec![text("bob"), Value::Int(35)]]);
+    }
+
+    #[test]
+    fn test_where_precedence_and_arithmetic() {
+        let mut backend = setup_people();
+        let result = query(
+            &mut backend,
+            "select name from people where name = 'alice' or age * 2 >= 80 and not people.name = 'bob'",
+        )
+        .unwrap();
+        assert_eq!(names(result), vec![text("alice"), text("carol")]);
+    }
+
+    #[test]
+    fn test_where_text_comparison_is_lexicographic() {
+        let mut backend = setup_people();
+        let result = query(&mut backend, "select name from people where name < 'bz' and name <> 'alice'").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("bob")]);
+    }
+
+    #[test]
+    fn test_where_type_errors() {
+        let mut backend = setup_people();
+        let err = run(&mut backend, "select * from people where age = 'old'").unwrap_err();
+        assert_eq!(err.message(), "Cannot apply = to int and text");
+
+        let err = run(&mut backend, "select * from people where age").unwrap_err();
+        assert_eq!(err.message(), "Expected bool in WHERE, got 25");
+
+        let err = run(&mut backend, "select * from people where age / 0 = 1").unwrap_err();
+        assert_eq!(err.message(), "Division by zero");
+    }
+
+    #[test]
+    fn test_where_unknown_column_points_at_column() {
+        let mut backend = setup_people();
+        let err = run(&mut backend, "select * from people where height > 2").unwrap_err();
+        assert_eq!(err.message(), "Unknown column height");
+        assert_eq!(err.location(), Location::new(1, 28));
+    }
+
+    #[test]
+    fn test_insert_evaluates_expressions() {
+        let mut backend = MemoryBackend::new();
+        let result = query(&mut backend, "create table t (n int); insert into t values (-1), (2 * (3 + 4)); select n from t")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(-1)], vec![Value::Int(14)]]);
+
+        let err = run(&mut backend, "insert into t values (x)").unwrap_err();
+        assert_eq!(err.message(), "Unknown column x");
+    }
+
+    #[test]
+    fn test_update_reports_affected_rows() {
+        let mut backend = setup_people();
+        let result = run(&mut backend, "update people set age = age + 1 where name = 'bob'").unwrap();
+        assert_eq!(result, QueryResult::Affected(2));
+
+        let result = query(&mut backend, "select age from people").unwrap();
+        assert_eq!(names(result), vec![Value::Int(25), Value::Int(36), Value::Int(40), Value::Int(21)]);
+    }
+
+    #[test]
+    fn test_update_reads_the_old_row() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (a int, b int); insert into t values (1, 2)").unwrap();
+        run(&mut backend, "update t set a = b, b = a").unwrap();
+        let result = query(&mut backend, "select * from t").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(2), Value::Int(1)]]);
+    }
+
+    #[test]
+    fn test_update_without_where_touches_every_row() {
+        let mut backend = setup_people();
+        assert_eq!(run(&mut backend, "update people set name = 'x'"), Ok(QueryResult::Affected(4)));
+    }
+
+    #[test]
+    fn test_update_errors_leave_table_unchanged() {
+        let mut backend = setup_people();
+        let err = run(&mut backend, "update people set age = 100 / (age - 35)").unwrap_err();
+        assert_eq!(err.message(), "Division by zero");
+
+        let err = run(&mut backend, "update people set age = 'old' where name = 'carol'").unwrap_err();
+        assert_eq!(err.message(), "Expected int for column age, got old");
+        assert_eq!(err.location(), Location::new(1, 19));
+
+        let result = query(&mut backend, "select age from people").unwrap();
+        assert_eq!(names(result), vec![Value::Int(25), Value::Int(35), Value::Int(40), Value::Int(20)]);
+    }
+
+    #[test]
+    fn test_update_unknown_or_repeated_column() {
+        let mut backend = setup_people();
+        let err = run(&mut backend, "update people set height = 2").unwrap_err();
+        assert_eq!(err.message(), "Unknown column height");
+        assert_eq!(err.location(), Location::new(1, 19));
+
+        let err = run(&mut backend, "update people set age = 1, age = 2").unwrap_err();
+        assert_eq!(err.message(), "Column age assigned more than once");
+        assert_eq!(err.location(), Location::new(1, 28));
+    }
+
+    #[test]
+    fn test_delete_reports_affected_rows() {
+        let mut backend = setup_people();
+        assert_eq!(run(&mut backend, "delete from people where age < 30"), Ok(QueryResult::Affected(2)));
+
+        let result = query(&mut backend, "select name from people").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("carol")]);
+
+        assert_eq!(run(&mut backend, "delete from people"), Ok(QueryResult::Affected(2)));
+        assert_eq!(query(&mut backend, "select * from people").unwrap().rows.len(), 0);
+    }
+
+    #[test]
+    fn test_delete_errors() {
+        let mut backend = setup_people();
+        let err = run(&mut backend, "delete from nope").unwrap_err();
+        assert_eq!(err.message(), "Unknown table nope");
+
+        let err = run(&mut backend, "delete from people where age").unwrap_err();
+        assert_eq!(err.message(), "Expected bool in WHERE, got 25");
+        assert_eq!(query(&mut backend, "select * from people").unwrap().rows.len(), 4);
+    }
+
+    #[test]
+    fn test_index_gives_the_same_rows_as_a_scan() {
+        let conditions = [
+            "age = 35",
+            "age > 20 and age <= 35",
+            "30 < age",
+            "age >= 25 and name = 'bob'",
+            "age = 99",
+        ];
+        let mut scanned = setup_people();
+        let mut indexed = setup_people();
+        run(&mut indexed, "create index by_age on people (age)").unwrap();
+
+        for condition in conditions {
+            let source = format!("select name, age from people where {}", condition);
+            assert_eq!(query(&mut indexed, &source), query(&mut scanned, &source), "{}", condition);
+        }
+    }
+
+    #[test]
+    fn test_index_follows_changes() {
+        let mut backend = setup_people();
+        run(
+            &mut backend,
+            "create index by_age on people (age);
+             insert into people values ('dave', 35);
+             update people set age = 36 where name = 'carol';
+             delete from people where name = 'alice';",
+        )
+        .unwrap();
+
+        let result = query(&mut backend, "select name from people where age = 35").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("dave")]);
+        let result = query(&mut backend, "select name from people where age >= 36").unwrap();
+        assert_eq!(names(result), vec![text("carol")]);
+
+        assert_eq!(run(&mut backend, "update people set age = 0 where age < 30"), Ok(QueryResult::Affected(1)));
+        assert_eq!(run(&mut backend, "delete from people where age = 0"), Ok(QueryResult::Affected(1)));
+        let result = query(&mut backend, "select name from people where age > 0").unwrap();
+        assert_eq!(names(result), vec![text("bob"), text("carol"), text("dave")]);
+    }
+
+    #[test]
+    fn test_indexed_type_mismatch_still_fails() {
+        let mut backend = setup_people();
+        run(&mut backend, "create index by_age on people (age)").unwrap();
+        let err = run(&mut backend, "select * from people where age = 'old'").unwrap_err();
+        assert_eq!(err.message(), "Cannot apply = to int and text");
+    }
+
+    #[test]
+    fn test_create_index_errors() {
+        let mut backend = setup_people();
+        run(&mut backend, "create index i on people (age); create table other (x int)").unwrap();
+
+        let err = run(&mut backend, "create index i on other (x)").unwrap_err();
+        assert_eq!(err.message(), "Index i already exists");
+        assert_eq!(err.location(), Location::new(1, 14));
+
+        let err = run(&mut backend, "create index j on nope (x)").unwrap_err();
+        assert_eq!(err.message(), "Unknown table nope");
+
+        let err = run(&mut backend, "create index j on people (height)").unwrap_err();
+        assert_eq!(err.message(), "Unknown column height");
+        assert_eq!(err.location(), Location::new(1, 27));
+    }
+
+    #[test]
+    fn test_rollback_undoes_the_transaction() {
+        let mut backend = setup_people();
+        run(
+            &mut backend,
+            "begin;
+             insert into people values ('dave', 50);
+             update people set age = 0;
+             delete from people where name = 'bob';
+             create table other (x int);",
+        )
+        .unwrap();
+        assert_eq!(query(&mut backend, "select * from people").unwrap().rows.len(), 3);
+
+        run(&mut backend, "rollback").unwrap();
+        let result = query(&mut backend, "select age from people").unwrap();
+        assert_eq!(names(result), vec![Value::Int(25), Value::Int(35), Value::Int(40), Value::Int(20)]);
+        assert_eq!(run(&mut backend, "select * from other").unwrap_err().message(), "Unknown table other");
+    }
+
+    #[test]
+    fn test_commit_keeps_the_transaction() {
+        let mut backend = setup_people();
+        run(&mut backend, "begin; delete from people where age < 30; commit").unwrap();
+        assert_eq!(run(&mut backend, "rollback").unwrap_err().message(), "No transaction in progress");
+        assert_eq!(query(&mut backend, "select * from people").unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn test_transactions_do_not_nest() {
+        let mut backend = setup_people();
+        run(&mut backend, "begin").unwrap();
+        let err = run(&mut backend, "\n  begin transaction").unwrap_err();
+        assert_eq!(err.message(), "A transaction is already in progress");
+        assert_eq!(err.location(), Location::new(2, 3));
+
+        run(&mut backend, "commit").unwrap();
+        assert_eq!(run(&mut backend, "commit").unwrap_err().message(), "No transaction in progress");
+    }
+
+    #[test]
+    fn test_failed_statement_keeps_the_transaction_open() {
+        let mut backend = setup_people();
+        run(&mut backend, "begin; delete from people where name = 'alice'").unwrap();
+        assert!(run(&mut backend, "insert into people values (1, 2)").is_err());
+        run(&mut backend, "rollback").unwrap();
+        assert_eq!(query(&mut backend, "select * from people").unwrap().rows.len(), 4);
+    }
+
+    #[test]
+    fn test_transaction_reads_a_snapshot() {
+        let mut writer = setup_people();
+        let mut reader = writer.session();
+        run(&mut reader, "begin").unwrap();
+        run(&mut writer, "delete from people where age < 30; create table other (x int)").unwrap();
+
+        // Outside a transaction the changes show at once, inside it nothing changed
+        assert_eq!(query(&mut writer, "select * from people").unwrap().rows.len(), 2);
+        assert_eq!(query(&mut reader, "select * from people").unwrap().rows.len(), 4);
+        assert_eq!(run(&mut reader, "select * from other").unwrap_err().message(), "Unknown table other");
+
+        run(&mut reader, "commit").unwrap();
+        assert_eq!(query(&mut reader, "select * from people").unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn test_first_committer_wins() {
+        let mut first = setup_people();
+        let mut second = first.session();
+        run(&mut first, "begin; update people set age = age + 1 where name = 'alice'").unwrap();
+        run(&mut second, "begin; delete from people where age < 30; create table other (x int)").unwrap();
+        assert_eq!(query(&mut second, "select * from people").unwrap().rows.len(), 2);
+
+        run(&mut first, "commit").unwrap();
+        let err = run(&mut second, "commit").unwrap_err();
+        assert_eq!(err.message(), "Could not commit, a row of table people was changed by another transaction");
+        assert_eq!(err.kind(), ErrorKind::SerializationFailure);
+
+        // The failed transaction is over and none of it was kept
+        assert_eq!(run(&mut second, "rollback").unwrap_err().message(), "No transaction in progress");
+        assert_eq!(query(&mut second, "select * from people").unwrap().rows.len(), 4);
+        assert!(run(&mut second, "select * from other").is_err());
+        let result = query(&mut second, "select age from people where name = 'alice'").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(26)]]);
+    }
+
+    #[test]
+    fn test_commits_merge_rows() {
+        let mut first = setup_people();
+        let mut second = first.session();
+        let mut third = first.session();
+        run(&mut first, "begin; update people set age = age + 1 where name = 'alice'").unwrap();
+        run(&mut second, "begin; delete from people where name = 'carol'; insert into people values ('dave', 50)")
+            .unwrap();
+        run(&mut third, "update people set age = 0 where name = 'bob' and age = 20").unwrap();
+        run(&mut first, "commit").unwrap();
+        run(&mut second, "commit").unwrap();
+
+        let result = query(&mut first, "select name, age from people order by name, age").unwrap();
+        let expected = [("alice", 26), ("bob", 0), ("bob", 35), ("dave", 50)];
+        let expected: Vec<_> =
+            expected.iter().map(|(name, age)| vec![Value::Text(name.to_string()), Value::Int(*age)]).collect();
+        assert_eq!(result.rows, expected);
+
+        // Rows that are unique on each side need not be unique together
+        run(&mut first, "create table tags (name text unique)").unwrap();
+        run(&mut first, "begin; insert into tags values ('a')").unwrap();
+        run(&mut second, "begin; insert into tags values ('a')").unwrap();
+        run(&mut first, "commit").unwrap();
+        let err = run(&mut second, "commit").unwrap_err();
+        assert_eq!(err.message(), "Could not commit, another transaction put the same name in table tags");
+
+        // A change to the columns conflicts with any change to the rows
+        run(&mut first, "begin; insert into tags values ('b')").unwrap();
+        run(&mut second, "alter table tags add column n int").unwrap();
+        let err = run(&mut first, "commit").unwrap_err();
+        assert_eq!(err.message(), "Could not commit, table tags was changed by another transaction");
+    }
+
+    #[test]
+    fn test_sessions_on_other_threads() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (n int)").unwrap();
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let mut session = backend.session();
+                std::thread::spawn(move || {
+                    for n in 0..25 {
+                        run(&mut session, &format!("insert into t values ({})", i * 25 + n)).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // A reader in a transaction sees the same rows however long it reads
+        let mut reader = backend.session();
+        run(&mut reader, "begin").unwrap();
+        let count = query(&mut reader, "select count(*) from t").unwrap().rows;
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(query(&mut reader, "select count(*) from t").unwrap().rows, count);
+        run(&mut reader, "commit").unwrap();
+        assert_eq!(query(&mut reader, "select count(*) from t").unwrap().rows, vec![vec![Value::Int(100)]]);
+    }
+
+    #[test]
+    fn test_primary_key_and_unique_reject_duplicates() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (id int primary key, email text unique, name text);
+             insert into t values (1, 'a@x', 'Ada'), (2, 'g@x', 'Ada');",
+        )
+        .unwrap();
+
+        let err = run(&mut backend, "insert into t values (3, 'c@x', 'Cy'), (1, 'd@x', 'Dee')").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 1 for primary key id");
+        assert_eq!(
+            err.violation(),
+            Some(&ConstraintViolation {
+                constraint: ColumnConstraint::PrimaryKey,
+                table: "t".to_string(),
+                column: "id".to_string(),
+            })
+        );
+
+        // A duplicate within the inserted rows counts too
+        let err = run(&mut backend, "insert into t values (3, 'c@x', 'Cy'), (4, 'c@x', 'Dee')").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value c@x for unique column email");
+        assert_eq!(err.violation().unwrap().constraint, ColumnConstraint::Unique);
+
+        assert_eq!(query(&mut backend, "select id from t").unwrap().rows.len(), 2);
+        assert_eq!(run(&mut backend, "insert into t values (3, 'c@x', 'Ada')"), Ok(QueryResult::Affected(1)));
+    }
+
+    #[test]
+    fn test_unique_columns_have_keys() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (id int primary key, email text unique, n int);
+             insert into t values (1, 'a@x', 10), (2, 'g@x', 20), (3, null, 30), (4, null, 40);",
+        )
+        .unwrap();
+        let plan = |backend: &mut MemoryBackend, sql| query(backend, &format!("explain {}", sql)).unwrap().rows;
+        let scan = plan(&mut backend, "select n from t where id = 2");
+        assert_eq!(scan[1][0], text("  -> Index Scan on t using t_pkey: (id = 2)"));
+        assert_eq!(query(&mut backend, "select n from t where id = 2").unwrap().rows, vec![vec![Value::Int(20)]]);
+
+        // Keys follow their columns and are not indexes to list, dump or drop
+        run(&mut backend, "alter table t rename column email to mail").unwrap();
+        run(&mut backend, "alter table t add column code int unique").unwrap();
+        let scan = plan(&mut backend, "select n from t where mail = 'g@x'");
+        assert_eq!(scan[1][0], text("  -> Index Scan on t using t_mail_key: (mail = 'g@x')"));
+        let err = run(&mut backend, "insert into t values (5, 'a@x', 50, null)").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value a@x for unique column mail");
+        run(&mut backend, "update t set code = id; alter table t drop column mail").unwrap();
+        let err = run(&mut backend, "insert into t values (5, 50, 50), (6, 60, 3)").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 3 for unique column code");
+        assert!(query(&mut backend, "select * from information_schema.indexes").unwrap().rows.is_empty());
+        assert_eq!(run(&mut backend, "create index t_pkey on t (n)"), Ok(QueryResult::Done));
+    }
+
+    #[test]
+    fn test_update_checks_unique_columns() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (id int primary key, n int);
+             insert into t values (1, 10), (2, 20), (3, 30);",
+        )
+        .unwrap();
+
+        let err = run(&mut backend, "update t set n = 0, id = 2 where id = 1").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 2 for primary key id");
+        assert_eq!(err.location(), Location::new(1, 21));
+        assert!(err.violation().is_some());
+
+        // Keys may be shuffled as long as they stay distinct afterwards
+        assert_eq!(run(&mut backend, "update t set id = id + 1"), Ok(QueryResult::Affected(3)));
+        assert_eq!(run(&mut backend, "update t set n = 1"), Ok(QueryResult::Affected(3)));
+        let result = query(&mut backend, "select id from t").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(2)], vec![Value::Int(3)], vec![Value::Int(4)]]);
+    }
+
+    #[test]
+    fn test_only_one_primary_key() {
+        let mut backend = MemoryBackend::new();
+        let err = run(&mut backend, "create table t (a int primary key, b int primary key)").unwrap_err();
+        assert_eq!(err.message(), "A table can only have one primary key");
+        assert_eq!(err.location(), Location::new(1, 36));
+        assert_eq!(err.violation(), None);
+    }
+
+    fn eval_str(expr: &str) -> Value {
+        let mut backend = MemoryBackend::new();
+        let source = format!("create table one (x int); insert into one values (1); select {} from one", expr);
+        query(&mut backend, &source).unwrap().rows[0][0].clone()
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let cases = [
+            ("null = null", Value::Null),
+            ("1 + null", Value::Null),
+            ("-null", Value::Null),
+            ("not null", Value::Null),
+            ("null / 0", Value::Null),
+            ("true and null", Value::Null),
+            ("false and null", Value::Bool(false)),
+            ("null and false", Value::Bool(false)),
+            ("true or null", Value::Bool(true)),
+            ("null or true", Value::Bool(true)),
+            ("false or null", Value::Null),
+            ("null is null", Value::Bool(true)),
+            ("(1 = null) is not null", Value::Bool(false)),
+            ("x is not null", Value::Bool(true)),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(eval_str(expr), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_null_in_boolean_operator_must_still_be_bool() {
+        let mut backend = setup();
+        let err = run(&mut backend, "select * from users where null and 1").unwrap_err();
+        assert_eq!(err.message(), "Cannot apply AND to null and int");
+    }
+
+    #[test]
+    fn test_nulls_are_stored_and_filtered() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (id int, note text);
+             insert into t values (1, 'a'), (2, null), (null, 'c');
+             create index by_id on t (id);",
+        )
+        .unwrap();
+
+        // A comparison with null is never true, only IS NULL finds the rows
+        assert_eq!(query(&mut backend, "select id from t where note = null").unwrap().rows.len(), 0);
+        assert_eq!(query(&mut backend, "select id from t where note <> 'a'").unwrap().rows, vec![vec![Value::Null]]);
+        assert_eq!(query(&mut backend, "select id from t where id > 0").unwrap().rows.len(), 2);
+        assert_eq!(query(&mut backend, "select note from t where id is null").unwrap().rows, vec![vec![text("c")]]);
+        assert_eq!(
+            query(&mut backend, "select note from t where not (id = 1)").unwrap().rows,
+            vec![vec![Value::Null]]
+        );
+
+        assert_eq!(run(&mut backend, "update t set note = null where id = 1"), Ok(QueryResult::Affected(1)));
+        assert_eq!(run(&mut backend, "delete from t where note is null"), Ok(QueryResult::Affected(2)));
+        assert_eq!(query(&mut backend, "select note from t").unwrap().rows, vec![vec![text("c")]]);
+    }
+
+    #[test]
+    fn test_not_null_columns_reject_null() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (id int primary key, name text not null, email text unique);
+             insert into t values (1, 'Ada', null), (2, 'Grace', null);",
+        )
+        .unwrap();
+
+        let err = run(&mut backend, "insert into t values (3, null, 'x')").unwrap_err();
+        assert_eq!(err.message(), "Null value for not null column name");
+        assert_eq!(
+            err.violation(),
+            Some(&ConstraintViolation {
+                constraint: ColumnConstraint::NotNull,
+                table: "t".to_string(),
+                column: "name".to_string(),
+            })
+        );
+
+        // A primary key is not null as well
+        let err = run(&mut backend, "insert into t values (null, 'Cy', 'x')").unwrap_err();
+        assert_eq!(err.message(), "Null value for not null column id");
+
+        let err = run(&mut backend, "update t set name = null where id = 2").unwrap_err();
+        assert_eq!(err.message(), "Null value for not null column name");
+        assert_eq!(err.location(), Location::new(1, 14));
+        assert_eq!(query(&mut backend, "select name from t where id = 2").unwrap().rows, vec![vec![text("Grace")]]);
+    }
+
+    #[test]
+    fn test_result_set_renders_aligned_table() {
+        let mut backend = setup();
+        let result = query(&mut backend, "select * from users").unwrap();
+        assert_eq!(
+            result.to_string(),
+            "+----+-------+\n\
+             | id | name  |\n\
+             +----+-------+\n\
+             | 1  | Ada   |\n\
+             | 2  | Grace |\n\
+             +----+-------+"
+        );
+    }
+
+    #[test]
+    fn test_insert_fraction_into_int_fails() {
+        let mut backend = setup();
+        let err = run(&mut backend, "insert into users values (1.5, 'x')").unwrap_err();
+        assert_eq!(err.message(), "Expected int for column id, got 1.5");
+    }
+
+    #[test]
+    fn test_typed_columns_store_and_coerce() {
+        let mut backend = MemoryBackend::new();
+        let result = query(
+            &mut backend,
+            "create table t (small int, big bigint, price real, code varchar(3), active boolean);
+             insert into t values (1, 5000000000, 2, 'abc', true), (-2, -1, 1.5e1, 'de', 1 < 0);
+             select * from t",
+        )
+        .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Int(1), Value::Int(5000000000), Value::Real(2.0), text("abc"), Value::Bool(true)],
+                vec![Value::Int(-2), Value::Int(-1), Value::Real(15.0), text("de"), Value::Bool(false)],
+            ]
+        );
+
+        let cases = [
+            ("insert into t values (3000000000, 1, 1, 'a', true)", "Value 3000000000 out of range for int column small"),
+            ("insert into t values (1, 1.5, 1, 'a', true)", "Expected bigint for column big, got 1.5"),
+            ("insert into t values (1, 1, 'x', 'a', true)", "Expected real for column price, got x"),
+            ("insert into t values (1, 1, 1, 'abcd', true)", "Value too long for varchar(3) column code"),
+            ("insert into t values (1, 1, 1, 'a', 1)", "Expected boolean for column active, got 1"),
+            ("update t set code = 'long'", "Value too long for varchar(3) column code"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_temporal_columns_and_literals() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table events (name text, day date, at time, logged timestamp);
+             insert into events values
+                 ('a', date '2024-01-31', time '09:30:00', timestamp '2024-01-31 09:30:00'),
+                 ('b', '2024-02-29', '23:59:59.5', '2024-02-29T23:59:59.5'),
+                 ('c', '2023-12-25', '00:00:00', '2023-12-25');
+             create index by_logged on events (logged);",
+        )
+        .unwrap();
+
+        let result = query(&mut backend, "select day, at, logged from events where name = 'b'").unwrap();
+        let shown: Vec<String> = result.rows[0].iter().map(|v| v.to_string()).collect();
+        assert_eq!(shown, vec!["2024-02-29", "23:59:59.5", "2024-02-29 23:59:59.5"]);
+
+        let matching = |backend: &mut MemoryBackend, filter: &str| {
+            names(query(backend, &format!("select name from events where {}", filter)).unwrap())
+        };
+        assert_eq!(
+            matching(&mut backend, "logged >= timestamp '2024-01-01' and logged < timestamp '2024-02-01'"),
+            vec![text("a")]
+        );
+        assert_eq!(matching(&mut backend, "day > date '2024-01-31'"), vec![text("b")]);
+        assert_eq!(matching(&mut backend, "at < time '09:30:00.000001'"), vec![text("a"), text("c")]);
+        // Text compares as the other side's type, the same as it is stored
+        assert_eq!(matching(&mut backend, "day = '2024-01-31'"), vec![text("a")]);
+        assert_eq!(matching(&mut backend, "'2024-02-01 00:00:00' <= logged"), vec![text("b")]);
+
+        let cases = [
+            ("select name from events where day = date '2024-02-30'", "Invalid date '2024-02-30'"),
+            ("select name from events where day = '2024-01-32'", "Invalid date '2024-01-32'"),
+            ("select name from events where day = 20240131", "Cannot apply = to date and int"),
+            ("select name from events where day < logged", "Cannot apply < to date and timestamp"),
+            ("insert into events values ('d', 'soon', '00:00:00', '2024-01-01')", "Expected date for column day, got soon"),
+            ("update events set at = 5", "Expected time for column at, got 5"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_drop_table_and_if_exists() {
+        let mut backend = setup();
+        run(
+            &mut backend,
+            "create index by_name on users (name);
+             create table if not exists users (other int);
+             create index if not exists by_name on users (id);
+             insert into users values (3, 'Linus');",
+        )
+        .unwrap();
+        let result = query(&mut backend, "select name from users where id = 3").unwrap();
+        assert_eq!(result.rows, vec![vec![text("Linus")]]);
+
+        run(&mut backend, "drop table users").unwrap();
+        assert_eq!(run(&mut backend, "select * from users").unwrap_err().message(), "Unknown table users");
+        assert_eq!(run(&mut backend, "drop table users").unwrap_err().message(), "Unknown table users");
+        run(&mut backend, "drop table if exists users").unwrap();
+
+        // The indexes went with the table, so their names are free again
+        run(&mut backend, "create table users (id int); create index by_name on users (id)").unwrap();
+        assert!(query(&mut backend, "select * from users").unwrap().rows.is_empty());
+    }
+
+    #[test]
+    fn test_alter_table_add_column() {
+        let mut backend = setup();
+        run(&mut backend, "alter table users add column score int default 10 * 2 not null").unwrap();
+        run(&mut backend, "alter table users add born date").unwrap();
+        run(&mut backend, "insert into users values (3, 'Linus', 5, '1969-12-28')").unwrap();
+
+        let result = query(&mut backend, "select * from users order by id").unwrap();
+        assert_eq!(result.columns, vec!["id", "name", "score", "born"]);
+        assert_eq!(result.rows[0], vec![Value::Int(1), text("Ada"), Value::Int(20), Value::Null]);
+        assert_eq!(result.rows[2][2], Value::Int(5));
+
+        let cases = [
+            ("alter table users add tag text not null", "Null value for not null column tag"),
+            ("alter table users add tag text unique default 'x'", "Duplicate value x for unique column tag"),
+            ("alter table users add name text", "Duplicate column name"),
+            ("alter table users add tag int default 'x'", "Expected int for column tag, got x"),
+            ("alter table missing add tag int", "Unknown table missing"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+        assert_eq!(query(&mut backend, "select * from users").unwrap().columns.len(), 4);
+
+        run(&mut backend, "alter table users add known boolean default (1 in (select id from users))").unwrap();
+        let result = query(&mut backend, "select known from users").unwrap();
+        assert!(result.rows.iter().all(|row| row[0] == Value::Bool(true)));
+
+        // An empty table takes a NOT NULL column without a default
+        run(&mut backend, "create table empty (a int); alter table empty add b int not null").unwrap();
+    }
+
+    #[test]
+    fn test_defaults_and_auto_increment() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table notes (id serial primary key, body text not null, tags int default 2 * 3, seen boolean);
+             insert into notes (body) values ('a'), ('b');
+             insert into notes (seen, body, tags) values (true, 'c', null);
+             insert into notes values (10, 'd', 1, false);
+             insert into notes (body) values ('e')",
+        )
+        .unwrap();
+        let result = query(&mut backend, "select * from notes order by id").unwrap();
+        let ids: Vec<&Value> = result.rows.iter().map(|row| &row[0]).collect();
+        assert_eq!(ids, vec![&Value::Int(1), &Value::Int(2), &Value::Int(3), &Value::Int(10), &Value::Int(11)]);
+        assert_eq!(result.rows[0], vec![Value::Int(1), text("a"), Value::Int(6), Value::Null]);
+        assert_eq!(result.rows[2][1..], [text("c"), Value::Null, Value::Bool(true)]);
+
+        // A failed insert and a rolled back one give their numbers back
+        assert!(run(&mut backend, "insert into notes (body) values ('f'), (null)").is_err());
+        run(&mut backend, "begin; insert into notes (body) values ('g'); rollback").unwrap();
+        run(&mut backend, "delete from notes where id = 11; insert into notes (body) values ('h')").unwrap();
+        let result = query(&mut backend, "select id from notes where body = 'h'").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(12)]]);
+
+        let cases = [
+            ("insert into notes (id) values (20)", "Null value for not null column body"),
+            ("insert into notes (body, body) values ('x', 'y')", "Column body is given more than once"),
+            ("insert into notes (body, nope) values ('x', 1)", "Unknown column nope"),
+            ("insert into notes (body) values ('x', 1)", "Expected 1 values, got 2"),
+            ("insert into notes (id, body) values (null, 'x')", "Null value for not null column id"),
+            ("create table t (a text autoincrement)", "Auto-increment column a must be int or bigint"),
+            ("create table t (a serial, b serial)", "A table can only have one auto-increment column"),
+            ("create table t (a int autoincrement default 1)", "Auto-increment column a cannot have a default"),
+            ("create table t (a int default 'x')", "Expected int for column a, got x"),
+            ("create table t (a int, b int default a)", "Unknown column a"),
+            ("create table t (a boolean default (1 in (select id from notes)))", "Default of column a has a subquery"),
+            ("create table t (a int not null default null)", "Null value for not null column a"),
+            ("alter table notes add n serial", "Cannot add auto-increment column n to a table with rows"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+
+        // A column added with a default keeps it for the rows inserted later
+        run(&mut backend, "alter table notes add rank int default 7; insert into notes (body) values ('i')").unwrap();
+        let result = query(&mut backend, "select rank from notes where body = 'i'").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(7)]]);
+    }
+
+    fn shop() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table users (id int primary key, name text);
+             create table orders (id int primary key, user_id int not null references users (id) on delete cascade);
+             create table items (order_id int references orders (id) on delete cascade, sku text);
+             create table reviews (user_id int references users (id) on delete set null, body text);
+             insert into users values (1, 'ann'), (2, 'bob');
+             insert into orders values (10, 1), (11, 1), (12, 2);
+             insert into items values (10, 'a'), (11, 'b'), (12, 'c'), (null, 'd');
+             insert into reviews values (1, 'good'), (2, 'bad')",
+        )
+        .unwrap();
+        backend
+    }
+
+    #[test]
+    fn test_foreign_keys_on_insert_and_update() {
+        let mut backend = shop();
+        let cases = [
+            ("insert into orders values (13, 3)", "Value 3 for column user_id is not in users.id"),
+            ("update orders set user_id = 5 where id = 10", "Value 5 for column user_id is not in users.id"),
+            ("update users set id = 3 where id = 2", "Cannot change users.id from 2, orders.user_id references it"),
+            ("drop table users", "Cannot drop table users, orders.user_id references it"),
+            ("alter table users drop column id", "Cannot drop id, orders.user_id references it"),
+            (
+                "alter table reviews add order_id int default 99 references orders (id)",
+                "Value 99 for column order_id is not in orders.id",
+            ),
+            ("create table t (a int references nope (id))", "Unknown table nope"),
+            ("create table t (a int references users (nope))", "Unknown column users.nope"),
+            (
+                "create table t (a text references users (name))",
+                "Column users.name is not unique, a foreign key can't reference it",
+            ),
+            ("create table t (a text references users (id))", "Foreign key column a is text but users.id is int"),
+            (
+                "create table t (a int not null references users (id) on delete set null)",
+                "Column a is not null, ON DELETE SET NULL can't clear it",
+            ),
+        ];
+        for (source, message) in cases {
+            let err = run(&mut backend, source).unwrap_err();
+            assert_eq!(err.message(), message, "{}", source);
+        }
+        let err = run(&mut backend, "insert into items values (99, 'x')").unwrap_err();
+        assert!(matches!(err.violation().unwrap().constraint, ColumnConstraint::References(_)));
+
+        // Null keys name no row, and a value no row references can change
+        run(&mut backend, "insert into users values (3, 'cy'); update users set id = 4 where id = 3").unwrap();
+        run(&mut backend, "insert into reviews values (null, 'x'); update orders set user_id = 4 where id = 12").unwrap();
+        run(&mut backend, "update users set id = 2 where id = 2").unwrap();
+        let result = query(&mut backend, "select count(*) from orders where user_id = 4").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(1)]]);
+
+        // The keys of other tables follow a renamed column
+        run(&mut backend, "alter table users rename column id to uid").unwrap();
+        let err = run(&mut backend, "insert into orders values (13, 9)").unwrap_err();
+        assert_eq!(err.message(), "Value 9 for column user_id is not in users.uid");
+    }
+
+    #[test]
+    fn test_foreign_keys_on_delete() {
+        let mut backend = shop();
+        run(&mut backend, "delete from users where id = 1").unwrap();
+        let orders = query(&mut backend, "select id from orders").unwrap();
+        assert_eq!(orders.rows, vec![vec![Value::Int(12)]]);
+        let items = query(&mut backend, "select sku from items order by sku").unwrap();
+        assert_eq!(items.rows, vec![vec![text("c")], vec![text("d")]]);
+        let reviews = query(&mut backend, "select user_id, body from reviews order by body").unwrap();
+        assert_eq!(reviews.rows, vec![vec![Value::Int(2), text("bad")], vec![Value::Null, text("good")]]);
+
+        // RESTRICT refuses the whole delete, including what it would have cascaded
+        run(&mut backend, "create table notes (order_id int references orders (id)); insert into notes values (12)")
+            .unwrap();
+        let err = run(&mut backend, "delete from users").unwrap_err();
+        assert_eq!(err.message(), "Cannot delete 12 from orders.id, notes.order_id references it");
+        assert_eq!(query(&mut backend, "select count(*) from items").unwrap().rows[0][0], Value::Int(2));
+
+        // A transaction that cascades conflicts on every table it changed
+        let mut other = backend.session();
+        run(&mut backend, "begin; delete from notes; delete from users where id = 2").unwrap();
+        run(&mut other, "insert into items values (12, 'e')").unwrap();
+        assert!(run(&mut backend, "commit").is_err());
+        assert_eq!(query(&mut backend, "select count(*) from orders").unwrap().rows[0][0], Value::Int(1));
+    }
+
+    #[test]
+    fn test_self_referencing_foreign_key() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table nodes (id int primary key, parent int references nodes (id) on delete cascade);
+             insert into nodes values (1, null), (2, 1), (3, 2), (4, null);
+             insert into nodes values (5, 6), (6, null)",
+        )
+        .unwrap();
+        run(&mut backend, "delete from nodes where id = 1").unwrap();
+        let result = query(&mut backend, "select id from nodes order by id").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(4)], vec![Value::Int(5)], vec![Value::Int(6)]]);
+
+        // A renamed column keeps the keys that reference it
+        run(&mut backend, "alter table nodes rename column id to node_id").unwrap();
+        let err = run(&mut backend, "insert into nodes values (7, 8)").unwrap_err();
+        assert_eq!(err.message(), "Value 8 for column parent is not in nodes.node_id");
+    }
+
+    #[test]
+    fn test_views() {
+        let mut backend = setup();
+        run(
+            &mut backend,
+            "create table posts (user_id int, title text);
+             insert into posts values (1, 'notes'), (1, 'engines'), (2, 'cobol');
+             create view authors as select u.name, p.title from users u join posts p on u.id = p.user_id;
+             create view counts as select name, count(*) as n from authors group by name",
+        )
+        .unwrap();
+        let result = query(&mut backend, "select title from authors where name = 'Ada' order by title").unwrap();
+        assert_eq!(result.rows, vec![vec![text("engines")], vec![text("notes")]]);
+        let result = query(&mut backend, "select c.n from counts c join users on users.name = c.name where id = 2");
+        assert_eq!(result.unwrap().rows, vec![vec![Value::Int(1)]]);
+        let plan = query(&mut backend, "explain select * from authors").unwrap();
+        assert!(plan.rows.iter().any(|row| row[0].to_string().contains("Join")), "{}", plan);
+
+        // The view runs its query each time it is read
+        run(&mut backend, "insert into posts values (2, 'compilers')").unwrap();
+        assert_eq!(query(&mut backend, "select n from counts where name = 'Grace'").unwrap().rows[0][0], Value::Int(2));
+
+        let cases = [
+            ("insert into authors values ('x', 'y')", "authors is a view, not a table"),
+            ("delete from counts", "counts is a view, not a table"),
+            ("drop table authors", "authors is a view, not a table"),
+            ("drop view users", "Unknown view users"),
+            ("create view authors as select * from users", "View authors already exists"),
+            ("create view users as select * from posts", "Table users already exists"),
+            ("create table counts (a int)", "View counts already exists"),
+            ("create view v as select * from nope", "Unknown table nope"),
+            (
+                "create view v as select users.id, posts.user_id as id from users join posts on true",
+                "Column id appears more than once in the view",
+            ),
+            (
+                "create view information_schema.v as select * from users",
+                "Cannot create information_schema.v, information_schema is read only",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+
+        // Views come and go with the transaction that made them
+        run(&mut backend, "begin; drop view counts; create view counts as select 1 as n from users; rollback").unwrap();
+        assert_eq!(query(&mut backend, "select name from counts").unwrap().rows.len(), 2);
+        run(&mut backend, "drop view counts; drop view if exists counts; create table if not exists authors (a int)")
+            .unwrap();
+        assert_eq!(run(&mut backend, "select * from counts").unwrap_err().message(), "Unknown table counts");
+    }
+
+    #[test]
+    fn test_alter_table_drop_and_rename_column() {
+        let mut backend = setup();
+        run(
+            &mut backend,
+            "alter table users add age int default 30;
+             create index by_name on users (name);
+             create index by_age on users (age);
+             alter table users drop column name;",
+        )
+        .unwrap();
+        let result = query(&mut backend, "select * from users where age = 30 order by id").unwrap();
+        assert_eq!(result.columns, vec!["id", "age"]);
+        assert_eq!(result.rows, vec![vec![Value::Int(1), Value::Int(30)], vec![Value::Int(2), Value::Int(30)]]);
+
+        // The index on age moved along with its column
+        let plan = query(&mut backend, "explain select id from users where age = 30").unwrap();
+        assert!(plan.rows.iter().any(|row| row[0].to_string().contains("by_age")), "{}", plan);
+
+        run(&mut backend, "alter table users rename column age to years").unwrap();
+        let result = query(&mut backend, "select years from users where id = 2").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(30)]]);
+
+        let cases = [
+            ("alter table users drop column age", "Unknown column age"),
+            ("alter table users rename id to years", "Column years already exists"),
+            ("alter table users drop id; alter table users drop years", "Cannot drop years, the only column of users"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_json_columns_and_extraction() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            r#"create table docs (id int, body json);
+               insert into docs values
+                   (1, '{ "name": "ann", "tags": ["a", "b"], "age": 30, "boss": null }'),
+                   (2, json '{"name": "bob", "tags": []}'),
+                   (3, null);"#,
+        )
+        .unwrap();
+
+        let result = query(&mut backend, "select body from docs where id = 1").unwrap();
+        assert_eq!(result.rows[0][0], Value::Json(r#"{"name":"ann","tags":["a","b"],"age":30,"boss":null}"#.to_string()));
+
+        let result = query(
+            &mut backend,
+            "select body -> 'name', body ->> 'name', body -> 'tags' ->> -1, body ->> 'age', body ->> 'boss', body -> 'none'
+             from docs order by id",
+        )
+        .unwrap();
+        let json = |s: &str| Value::Json(s.to_string());
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![json("\"ann\""), text("ann"), text("b"), text("30"), Value::Null, Value::Null],
+                vec![json("\"bob\""), text("bob"), Value::Null, Value::Null, Value::Null, Value::Null],
+                vec![Value::Null; 6],
+            ]
+        );
+
+        let result = query(&mut backend, "select id from docs where body ->> 'name' = 'bob'").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Int(2)]]);
+
+        let cases = [
+            ("insert into docs values (4, '{\"a\": }')", "Invalid json for column body: Expected json value, got }"),
+            ("insert into docs values (4, 5)", "Expected json for column body, got 5"),
+            ("select json '[1,' from docs", "Invalid json '[1,': Expected json value, got end of input"),
+            ("select id -> 'a' from docs", "Cannot apply -> to int and text"),
+            ("select body -> true from docs", "Cannot apply -> to json and bool"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_real_arithmetic_and_comparison() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table p (name text, price real, active boolean);
+             insert into p values ('a', 1.25, true), ('b', 2.5, false), ('c', 10, true);
+             create index by_price on p (price);",
+        )
+        .unwrap();
+
+        let matching = |backend: &mut MemoryBackend, filter: &str| {
+            names(query(backend, &format!("select name from p where {}", filter)).unwrap())
+        };
+        assert_eq!(matching(&mut backend, "price * 2 = 5"), vec![text("b")]);
+        assert_eq!(matching(&mut backend, "price > 2"), vec![text("b"), text("c")]);
+        assert_eq!(matching(&mut backend, "price >= 2.5 and active"), vec![text("c")]);
+        assert_eq!(matching(&mut backend, "1 / 4 = 0 and 1 / 4.0 = 0.25 and price = 1.25"), vec![text("a")]);
+        assert_eq!(matching(&mut backend, "active = false"), vec![text("b")]);
+
+        run(&mut backend, "update p set price = price / 2 + 1 where name = 'c'").unwrap();
+        assert_eq!(matching(&mut backend, "price = 6"), vec![text("c")]);
+
+        let err = run(&mut backend, "select name from p where price / 0.0 > 1").unwrap_err();
+        assert_eq!(err.message(), "Division by zero");
+        let err = run(&mut backend, "select name from p where price * 1e308 * 10 > 1").unwrap_err();
+        assert_eq!(err.message(), "Real out of range");
+        let err = run(&mut backend, "select name from p where price = 'x'").unwrap_err();
+        assert_eq!(err.message(), "Cannot apply = to real and text");
+    }
+
+    #[test]
+    fn test_cast() {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (name text, score real, at timestamp);
+             insert into t values ('a', 2.5, '2024-01-31 18:00:00'), ('b', 3.5, null);",
+        )
+        .unwrap();
+
+        let result = query(
+            &mut backend,
+            "select cast(score as int), cast(score as text), cast(at as date), cast(score > 3 as int)
+             from t where name = 'a'",
+        )
+        .unwrap();
+        let shown: Vec<String> = result.rows[0].iter().map(|v| v.to_string()).collect();
+        assert_eq!(shown, vec!["2", "2.5", "2024-01-31", "0"]);
+
+        let matching = |backend: &mut MemoryBackend, filter: &str| {
+            names(query(backend, &format!("select name from t where {}", filter)).unwrap())
+        };
+        assert_eq!(matching(&mut backend, "cast(score as int) = 4"), vec![text("b")]);
+        assert_eq!(matching(&mut backend, "cast(at as date) is null"), vec![text("b")]);
+
+        run(&mut backend, "insert into t values (cast(42 as text), cast('1.5' as real), null)").unwrap();
+        assert_eq!(matching(&mut backend, "score = 1.5"), vec![text("42")]);
+
+        let cases = [
+            ("select cast(name as int) from t", "Invalid int 'a'"),
+            ("select cast(at as int) from t", "Cannot cast timestamp to int"),
+            ("select cast(score * 1e10 as int) from t", "Integer out of range"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::{BackendError, ErrorKind, Row, Scope, Value, as_real, compare, eval, eval_binary, real, visit};
+use crate::lexer::Location;
+use crate::parser::{BinaryOp, ColumnDef, DataType, Expr, FunctionArgs, QualifiedName, Select};
+
+/*
+    Aggregation, for a select with GROUP BY or HAVING or with an aggregate
+    call in its select list or ORDER BY. The matching rows are hashed into
+    groups on their GROUP BY values, and the select produces one row per
+    group. Without GROUP BY all rows form a single group, even when there
+    are none, so `select count(*) from t` always returns a row.
+
+    Every group computes a row of its own: its GROUP BY values followed by
+    the result of each aggregate call. When the select is planned, its
+    select list, HAVING and ORDER BY are rewritten to read that row, each
+    GROUP BY expression and aggregate call becoming a reference to its
+    slot, and are then evaluated as usual. A column that is left after the
+    rewrite is neither grouped nor inside an aggregate, which is an error.
+ */
+
+pub const AGGREGATES: [&str; 5] = ["count", "sum", "avg", "min", "max"];
+
+pub fn is_aggregate(name: &QualifiedName) -> bool {
+    matches!(name.parts.as_slice(), [name] if AGGREGATES.contains(&name.to_lowercase().as_str()))
+}
+
+pub fn contains_aggregate(expr: &Expr) -> bool {
+    let mut found = false;
+    visit(expr, &mut |expr| found |= matches!(expr, Expr::Function { name, .. } if is_aggregate(name)));
+    found
+}
+
+// A slot is named after the expression it holds. Rewritten expressions read
+// nothing but slots, so the name only has to tell slots apart, and it reads
+// well in EXPLAIN.
+fn slot(expr: &Expr, loc: Location) -> Expr {
+    Expr::Column(QualifiedName { parts: vec![expr.to_string()], loc })
+}
+
+// The argument an aggregate call is computed over, None for count(*)
+fn argument(call: &Expr) -> Result<Option<&Expr>, BackendError> {
+    let Expr::Function { name, args } = call else {
+        unreachable!("Only calls are aggregated");
+    };
+    match args {
+        FunctionArgs::Wildcard if name.parts[0].eq_ignore_ascii_case("count") => Ok(None),
+        FunctionArgs::Wildcard => Err(BackendError::new(format!("Only count accepts *, not {}", name), name.loc)),
+        FunctionArgs::List(args) if args.len() == 1 => {
+            if contains_aggregate(&args[0]) {
+                return Err(BackendError::of_kind(ErrorKind::Grouping, "Aggregate calls cannot be nested", name.loc));
+            }
+            Ok(Some(&args[0]))
+        }
+        FunctionArgs::List(args) => Err(BackendError::of_kind(
+            ErrorKind::UndefinedFunction,
+            format!("Function {} takes 1 argument, got {}", name, args.len()),
+            name.loc,
+        )),
+    }
+}
+
+struct Rewriter<'a> {
+    keys: &'a [Expr],
+    calls: Vec<&'a Expr>,
+    loc: Location,
+}
+
+impl<'a> Rewriter<'a> {
+    fn rewrite(&mut self, expr: &'a Expr) -> Result<Expr, BackendError> {
+        if self.keys.contains(expr) {
+            return Ok(slot(expr, self.loc));
+        }
+        let rewritten = match expr {
+            Expr::Function { name, .. } if is_aggregate(name) => {
+                argument(expr)?;
+                if !self.calls.contains(&expr) {
+                    self.calls.push(expr);
+                }
+                slot(expr, name.loc)
+            }
+            Expr::Column(name) => {
+                return Err(BackendError::of_kind(
+                    ErrorKind::Grouping,
+                    format!("Column {} must appear in GROUP BY or be used in an aggregate", name),
+                    name.loc,
+                ));
+            }
+            Expr::Cast { expr, data_type } => Expr::Cast { expr: Box::new(self.rewrite(expr)?), data_type: *data_type },
+            Expr::Unary { op, expr } => Expr::Unary { op: *op, expr: Box::new(self.rewrite(expr)?) },
+            Expr::Binary { left, op, right } => {
+                Expr::Binary { left: Box::new(self.rewrite(left)?), op: *op, right: Box::new(self.rewrite(right)?) }
+            }
+            Expr::Chain { first, rest } => Expr::Chain {
+                first: Box::new(self.rewrite(first)?),
+                rest: rest.iter().map(|(op, expr)| Ok((*op, self.rewrite(expr)?))).collect::<Result<_, _>>()?,
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull { expr: Box::new(self.rewrite(expr)?), negated: *negated },
+            Expr::InList { expr, list, negated } => Expr::InList {
+                expr: Box::new(self.rewrite(expr)?),
+                list: list.iter().map(|item| self.rewrite(item)).collect::<Result<_, _>>()?,
+                negated: *negated,
+            },
+            Expr::Function { name, args: FunctionArgs::List(args) } => Expr::Function {
+                name: name.clone(),
+                args: FunctionArgs::List(args.iter().map(|arg| self.rewrite(arg)).collect::<Result<_, _>>()?),
+            },
+            expr => expr.clone(),
+        };
+        Ok(rewritten)
+    }
+}
+
+// An aggregate call, checked once before any row is seen
+struct Call<'c> {
+    name: &'c QualifiedName,
+    // The name in lower case
+    function: String,
+    arg: Option<&'c Expr>,
+}
+
+// Where a call stands in one group: how many values it took, and for min
+// and max the value kept, for sum and avg the total so far
+struct State {
+    count: usize,
+    value: Value,
+}
+
+impl Call<'_> {
+    fn add(&self, state: &mut State, row: &Row, loc: Location) -> Result<(), BackendError> {
+        let Some(arg) = self.arg else {
+            state.count += 1;
+            return Ok(());
+        };
+        // Nulls are skipped by every aggregate
+        let value = eval(arg, Some(row), loc)?;
+        if value == Value::Null {
+            return Ok(());
+        }
+        state.count += 1;
+        let first = state.count == 1;
+        match self.function.as_str() {
+            "count" => {}
+            // The first of equal minimums and the last of equal maximums
+            "min" if first || compare(&value, &state.value).is_lt() => state.value = value,
+            "max" if first || compare(&value, &state.value).is_ge() => state.value = value,
+            "min" | "max" => {}
+            _ => {
+                if as_real(&value).is_none() {
+                    return Err(BackendError::of_kind(
+                        ErrorKind::UndefinedFunction,
+                        format!("Cannot apply {} to {}", self.function, value.type_name()),
+                        self.name.loc,
+                    ));
+                }
+                state.value = match first {
+                    true => value,
+                    false => eval_binary(BinaryOp::Add, std::mem::replace(&mut state.value, Value::Null), value, loc)?,
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self, state: State, loc: Location) -> Result<Value, BackendError> {
+        match self.function.as_str() {
+            "count" => Ok(Value::Int(state.count as i64)),
+            "avg" if state.count > 0 => real(as_real(&state.value).unwrap() / state.count as f64, loc),
+            _ => Ok(state.value),
+        }
+    }
+}
+
+// The select list, ORDER BY keys and HAVING of a grouping select rewritten
+// to read the rows of its groups: the GROUP BY values followed by the
+// result of each aggregate call
+pub struct Grouping {
+    pub group_by: Vec<Expr>,
+    pub calls: Vec<Expr>,
+    pub items: Vec<Expr>,
+    pub keys: Vec<Expr>,
+    pub having: Option<Expr>,
+}
+
+impl Grouping {
+    pub fn new(query: &Select, items: &[Expr], keys: &[Expr], loc: Location) -> Result<Grouping, BackendError> {
+        // A key given twice would make two slots of the same name
+        let mut group_by: Vec<Expr> = Vec::new();
+        for key in &query.group_by {
+            if !group_by.contains(key) {
+                group_by.push(key.clone());
+            }
+        }
+        let mut rewriter = Rewriter { keys: &group_by, calls: Vec::new(), loc };
+        let items = items.iter().map(|item| rewriter.rewrite(item)).collect::<Result<Vec<_>, _>>()?;
+        let keys = keys.iter().map(|key| rewriter.rewrite(key)).collect::<Result<Vec<_>, _>>()?;
+        let having = query.having.as_ref().map(|having| rewriter.rewrite(having)).transpose()?;
+        let calls = rewriter.calls.into_iter().cloned().collect();
+        Ok(Grouping { group_by, calls, items, keys, having })
+    }
+
+    // The columns of a group's row
+    pub fn slots(&self, loc: Location) -> Vec<ColumnDef> {
+        self.group_by
+            .iter()
+            .chain(&self.calls)
+            .map(|expr| ColumnDef {
+                name: expr.to_string(),
+                data_type: DataType::Text,
+                constraints: Vec::new(),
+                default: None,
+                loc,
+            })
+            .collect()
+    }
+}
+
+/*
+    Rows are hashed into their groups as they arrive, a batch at a time,
+    and every group keeps the state of each of its aggregate calls rather
+    than its rows. Once the last batch is in, each group's row is computed.
+ */
+pub struct Groups<'c> {
+    group_by: &'c [Expr],
+    calls: Vec<Call<'c>>,
+    // Groups in the order their first row was seen, nulls all group together
+    groups: Vec<(Vec<Value>, Vec<State>)>,
+    lookup: HashMap<Vec<Value>, usize>,
+}
+
+impl<'c> Groups<'c> {
+    pub fn new(group_by: &'c [Expr], calls: &'c [Expr]) -> Result<Groups<'c>, BackendError> {
+        let calls = calls
+            .iter()
+            .map(|call| {
+                let Expr::Function { name, .. } = call else {
+                    unreachable!("Only calls are aggregated");
+                };
+                Ok(Call { name, function: name.parts[0].to_lowercase(), arg: argument(call)? })
+            })
+            .collect::<Result<_, BackendError>>()?;
+        Ok(Groups { group_by, calls, groups: Vec::new(), lookup: HashMap::new() })
+    }
+
+    // The states of a group no row has reached yet
+    fn states(&self) -> Vec<State> {
+        self.calls.iter().map(|_| State { count: 0, value: Value::Null }).collect()
+    }
+
+    pub fn add(&mut self, scope: &Scope, rows: &[Cow<[Value]>], loc: Location) -> Result<(), BackendError> {
+        for values in rows {
+            let row = Row { scope, values };
+            let key: Vec<Value> =
+                self.group_by.iter().map(|expr| eval(expr, Some(&row), loc)).collect::<Result<_, _>>()?;
+            let group = match self.lookup.get(&key) {
+                Some(group) => *group,
+                None => {
+                    self.lookup.insert(key.clone(), self.groups.len());
+                    self.groups.push((key, self.states()));
+                    self.groups.len() - 1
+                }
+            };
+            let states = &mut self.groups[group].1;
+            for (call, state) in self.calls.iter().zip(states) {
+                call.add(state, &row, loc)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Each group's row
+    pub fn finish(mut self, loc: Location) -> Result<Vec<Vec<Value>>, BackendError> {
+        if self.group_by.is_empty() && self.groups.is_empty() {
+            self.groups.push((Vec::new(), self.states()));
+        }
+        let mut output = Vec::new();
+        for (mut values, states) in self.groups {
+            for (call, state) in self.calls.iter().zip(states) {
+                values.push(call.finish(state, loc)?);
+            }
+            output.push(values);
+        }
+        Ok(output)
+    }
+}
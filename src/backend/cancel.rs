@@ -0,0 +1,127 @@
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use super::BackendError;
+use crate::lexer::Location;
+
+/*
+    Stopping a statement that runs too long. A statement can't be stopped
+    from outside the thread running it, so it stops itself: the executor
+    calls `check` between batches of rows, in the loops of joins, updates
+    and deletes, and gives up with a cancelled error once the statement's
+    token has been cancelled or its deadline has passed.
+
+    The token and deadline are handed to the statements run inside
+    `with_cancellation`, the way user functions are. Outside of it nothing
+    is ever cancelled. A statement that fails this way changes nothing, as
+    with any other error.
+ */
+
+// Cancels whatever runs with it, from any thread. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+struct Cancellation {
+    token: CancelToken,
+    deadline: Option<(Instant, Duration)>,
+}
+
+thread_local! {
+    static CANCELLATION: RefCell<Option<Cancellation>> = const { RefCell::new(None) };
+    // Checks since the clock was last read
+    static CHECKS: Cell<u32> = const { Cell::new(0) };
+}
+
+// How many checks pass between looks at the clock, reading it costs more
+// than reading the flag
+const CLOCK_EVERY: u32 = 64;
+
+// Runs `f` so that it stops once `token` is cancelled or, with a timeout,
+// once that much time has passed. What was in effect before is back once
+// it returns or panics.
+pub fn with_cancellation<T>(token: &CancelToken, timeout: Option<Duration>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Cancellation>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CANCELLATION.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+    // A timeout too long for the clock to reach is no deadline at all
+    let deadline = timeout.and_then(|timeout| Some((Instant::now().checked_add(timeout)?, timeout)));
+    let cancellation = Cancellation { token: token.clone(), deadline };
+    let _restore = Restore(CANCELLATION.with(|current| current.replace(Some(cancellation))));
+    f()
+}
+
+// An error once the statement being run should stop
+pub(crate) fn check(loc: Location) -> Result<(), BackendError> {
+    CANCELLATION.with(|current| {
+        let current = current.borrow();
+        let Some(cancellation) = current.as_ref() else {
+            return Ok(());
+        };
+        if cancellation.token.is_cancelled() {
+            return Err(BackendError::cancelled("Query cancelled", loc));
+        }
+        let Some((deadline, timeout)) = cancellation.deadline else {
+            return Ok(());
+        };
+        let checks = CHECKS.with(|checks| {
+            let count = checks.get().wrapping_add(1);
+            checks.set(count);
+            count
+        });
+        if checks % CLOCK_EVERY == 1 && Instant::now() >= deadline {
+            return Err(BackendError::cancelled(format!("Query cancelled after running for {:?}", timeout), loc));
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let loc = Location::new(1, 1);
+        assert!(check(loc).is_ok());
+
+        let token = CancelToken::new();
+        with_cancellation(&token, None, || {
+            assert!(check(loc).is_ok());
+            token.clone().cancel();
+            let err = check(loc).unwrap_err();
+            assert!(err.is_cancelled());
+            assert_eq!(err.message(), "Query cancelled");
+        });
+        // Only inside with_cancellation
+        assert!(check(loc).is_ok());
+
+        let err = with_cancellation(&CancelToken::new(), Some(Duration::ZERO), || {
+            (0..CLOCK_EVERY).find_map(|_| check(loc).err())
+        });
+        assert_eq!(err.unwrap().message(), "Query cancelled after running for 0ns");
+
+        let err = with_cancellation(&CancelToken::new(), Some(Duration::MAX), || {
+            (0..CLOCK_EVERY).find_map(|_| check(loc).err())
+        });
+        assert!(err.is_none());
+    }
+}
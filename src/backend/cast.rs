@@ -0,0 +1,219 @@
+use super::temporal::{self, MICROS_PER_DAY};
+use super::{ErrorKind, Value, ValueError, json, parse_int};
+use crate::parser::DataType;
+
+/*
+    Conversions between types. Two kinds exist:
+
+    Implicit ones happen without being asked for, when a value is stored in
+    a column or compared with a value of another type:
+
+        int    -> real
+        text   -> date, time, timestamp, json    the text is parsed
+
+    so `born = '2024-01-31'` compares dates, the same as inserting the text
+    into the column would store one. Nothing else converts on its own, an
+    int is never text and text is never a number.
+
+    Explicit ones are asked for with CAST(expr AS type) and cover more:
+
+        int, bigint    from real (rounded, halves to even), boolean, text
+        real           from int, text
+        boolean        from int (non-zero is true), text (t, yes, on, 1, ...)
+        text, varchar  from anything, varchar(n) cuts the text to n characters
+        date           from timestamp (its day), text
+        time           from timestamp (its time of day), text
+        timestamp      from date (its midnight), text
+        json           from text
+
+    Null casts to null of any type and a value always casts to its own type.
+ */
+
+// Whether values of one type are converted to the other without a CAST
+pub fn converts(from: DataType, to: DataType) -> bool {
+    let parsed = matches!(to, DataType::Date | DataType::Time | DataType::Timestamp | DataType::Json);
+    match from {
+        DataType::Int | DataType::BigInt => to == DataType::Real,
+        DataType::Text | DataType::Varchar(_) => parsed,
+        _ => false,
+    }
+}
+
+// Whether a value is converted to the type without a CAST
+pub fn is_implicit(value: &Value, data_type: DataType) -> bool {
+    type_of(value).is_some_and(|from| converts(from, data_type))
+}
+
+// The type a value has, None for null which has every type
+pub fn type_of(value: &Value) -> Option<DataType> {
+    match value {
+        Value::Int(_) => Some(DataType::Int),
+        Value::Real(_) => Some(DataType::Real),
+        Value::Text(_) => Some(DataType::Text),
+        Value::Bool(_) => Some(DataType::Boolean),
+        Value::Null => None,
+        Value::Date(_) => Some(DataType::Date),
+        Value::Time(_) => Some(DataType::Time),
+        Value::Timestamp(_) => Some(DataType::Timestamp),
+        Value::Json(_) => Some(DataType::Json),
+    }
+}
+
+// The words a boolean can be written as, in any case
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "t" | "true" | "y" | "yes" | "on" | "1" => Some(true),
+        "f" | "false" | "n" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn out_of_range(type_name: &str) -> ValueError {
+    ValueError::new(ErrorKind::OutOfRange, format!("{} out of range", type_name))
+}
+
+fn int(i: i64, data_type: DataType) -> Result<Value, ValueError> {
+    if data_type == DataType::Int && i32::try_from(i).is_err() {
+        return Err(out_of_range("Integer"));
+    }
+    Ok(Value::Int(i))
+}
+
+// Converts a value as CAST does. Errors are the message alone, the caller
+// knows where the cast was.
+pub fn cast(value: Value, data_type: DataType) -> Result<Value, ValueError> {
+    let invalid = |s: &str| ValueError::new(ErrorKind::InvalidText, format!("Invalid {} '{}'", data_type, s));
+    match (value, data_type) {
+        (Value::Null, _) => Ok(Value::Null),
+        (Value::Int(i), DataType::Int | DataType::BigInt) => int(i, data_type),
+        (Value::Real(r), DataType::Int | DataType::BigInt) => {
+            let rounded = r.round_ties_even();
+            // i64::MAX as a real is 2^63, which is already out of range
+            if !(-9.223372036854776e18..9.223372036854776e18).contains(&rounded) {
+                return Err(out_of_range("Integer"));
+            }
+            int(rounded as i64, data_type)
+        }
+        (Value::Bool(b), DataType::Int | DataType::BigInt) => Ok(Value::Int(i64::from(b))),
+        (Value::Text(s), DataType::Int | DataType::BigInt) => {
+            int(parse_int(s.trim()).ok_or_else(|| invalid(&s))?, data_type)
+        }
+        (Value::Int(i), DataType::Real) => Ok(Value::Real(i as f64)),
+        (Value::Real(r), DataType::Real) => Ok(Value::Real(r)),
+        (Value::Text(s), DataType::Real) => match s.trim().parse::<f64>() {
+            Ok(r) if r.is_finite() => Ok(Value::Real(r + 0.0)),
+            _ => Err(invalid(&s)),
+        },
+        (Value::Bool(b), DataType::Boolean) => Ok(Value::Bool(b)),
+        (Value::Int(i), DataType::Boolean) => Ok(Value::Bool(i != 0)),
+        (Value::Text(s), DataType::Boolean) => parse_bool(s.trim()).map(Value::Bool).ok_or_else(|| invalid(&s)),
+        (Value::Text(s), DataType::Text) => Ok(Value::Text(s)),
+        (value, DataType::Text) => Ok(Value::Text(value.to_string())),
+        (value, DataType::Varchar(length)) => {
+            let text = match value {
+                Value::Text(s) => s,
+                value => value.to_string(),
+            };
+            Ok(Value::Text(text.chars().take(length as usize).collect()))
+        }
+        (Value::Text(s), DataType::Date | DataType::Time | DataType::Timestamp) => {
+            temporal::parse(data_type, s.trim()).ok_or_else(|| invalid(&s))
+        }
+        (Value::Timestamp(t), DataType::Date) => i32::try_from(t.div_euclid(MICROS_PER_DAY))
+            .map(Value::Date)
+            .map_err(|_| out_of_range("Date")),
+        (Value::Timestamp(t), DataType::Time) => Ok(Value::Time(t.rem_euclid(MICROS_PER_DAY))),
+        (Value::Date(d), DataType::Timestamp) => Ok(Value::Timestamp(d as i64 * MICROS_PER_DAY)),
+        (value @ (Value::Date(_) | Value::Time(_) | Value::Timestamp(_)), _) if type_of(&value) == Some(data_type) => {
+            Ok(value)
+        }
+        (Value::Text(s), DataType::Json) => json::parse(&s)
+            .map(|json| Value::Json(json.to_string()))
+            .map_err(|err| ValueError::new(ErrorKind::InvalidText, format!("Invalid json '{}': {}", s, err))),
+        (Value::Json(s), DataType::Json) => Ok(Value::Json(s)),
+        (value, _) => Err(ValueError::new(
+            ErrorKind::DatatypeMismatch,
+            format!("Cannot cast {} to {}", value.type_name(), data_type),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    // The message of a failed cast, which is all most checks are about
+    fn cast(value: Value, data_type: DataType) -> Result<Value, String> {
+        super::cast(value, data_type).map_err(|err| err.message)
+    }
+
+    #[test]
+    fn test_numeric_casts() {
+        assert_eq!(cast(Value::Real(2.5), DataType::Int), Ok(Value::Int(2)));
+        assert_eq!(cast(Value::Real(-3.5), DataType::BigInt), Ok(Value::Int(-4)));
+        assert_eq!(cast(Value::Real(1e10), DataType::Int), Err("Integer out of range".to_string()));
+        assert_eq!(cast(Value::Real(1e10), DataType::BigInt), Ok(Value::Int(10_000_000_000)));
+        assert_eq!(cast(Value::Real(1e19), DataType::BigInt), Err("Integer out of range".to_string()));
+        assert_eq!(cast(Value::Bool(true), DataType::Int), Ok(Value::Int(1)));
+        assert_eq!(cast(text(" 0x10 "), DataType::Int), Ok(Value::Int(16)));
+        assert_eq!(cast(text("1.5"), DataType::Int), Err("Invalid int '1.5'".to_string()));
+        assert_eq!(cast(text("1.5"), DataType::Real), Ok(Value::Real(1.5)));
+        assert_eq!(cast(text("nan"), DataType::Real), Err("Invalid real 'nan'".to_string()));
+        assert_eq!(cast(Value::Int(3), DataType::Real), Ok(Value::Real(3.0)));
+    }
+
+    #[test]
+    fn test_text_and_boolean_casts() {
+        assert_eq!(cast(Value::Real(1.0), DataType::Text), Ok(text("1.0")));
+        assert_eq!(cast(Value::Bool(false), DataType::Text), Ok(text("false")));
+        assert_eq!(cast(Value::Date(0), DataType::Text), Ok(text("1970-01-01")));
+        assert_eq!(cast(text("héllo"), DataType::Varchar(2)), Ok(text("hé")));
+        assert_eq!(cast(Value::Int(12345), DataType::Varchar(3)), Ok(text("123")));
+        assert_eq!(cast(text(" Yes"), DataType::Boolean), Ok(Value::Bool(true)));
+        assert_eq!(cast(Value::Int(0), DataType::Boolean), Ok(Value::Bool(false)));
+        assert_eq!(cast(text("maybe"), DataType::Boolean), Err("Invalid boolean 'maybe'".to_string()));
+    }
+
+    #[test]
+    fn test_temporal_and_json_casts() {
+        let noon = MICROS_PER_DAY + MICROS_PER_DAY / 2;
+        assert_eq!(cast(Value::Timestamp(noon), DataType::Date), Ok(Value::Date(1)));
+        assert_eq!(cast(Value::Timestamp(-MICROS_PER_DAY / 2), DataType::Date), Ok(Value::Date(-1)));
+        assert_eq!(cast(Value::Timestamp(noon), DataType::Time), Ok(Value::Time(MICROS_PER_DAY / 2)));
+        assert_eq!(cast(Value::Date(1), DataType::Timestamp), Ok(Value::Timestamp(MICROS_PER_DAY)));
+        assert_eq!(cast(text("1970-01-02"), DataType::Date), Ok(Value::Date(1)));
+        assert_eq!(cast(text("1970-13-01"), DataType::Date), Err("Invalid date '1970-13-01'".to_string()));
+        assert_eq!(cast(text("[1, 2]"), DataType::Json), Ok(Value::Json("[1,2]".to_string())));
+        assert_eq!(cast(Value::Json("{}".to_string()), DataType::Text), Ok(text("{}")));
+    }
+
+    #[test]
+    fn test_casts_that_do_not_exist() {
+        assert_eq!(cast(Value::Date(0), DataType::Int), Err("Cannot cast date to int".to_string()));
+        assert_eq!(cast(Value::Time(0), DataType::Date), Err("Cannot cast time to date".to_string()));
+        assert_eq!(cast(Value::Int(1), DataType::Json), Err("Cannot cast int to json".to_string()));
+        assert_eq!(cast(Value::Null, DataType::Date), Ok(Value::Null));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let kind = |value, data_type| super::cast(value, data_type).unwrap_err().kind;
+        assert_eq!(kind(Value::Real(1e10), DataType::Int), ErrorKind::OutOfRange);
+        assert_eq!(kind(text("x"), DataType::Int), ErrorKind::InvalidText);
+        assert_eq!(kind(text("{"), DataType::Json), ErrorKind::InvalidText);
+        assert_eq!(kind(Value::Date(0), DataType::Int), ErrorKind::DatatypeMismatch);
+    }
+
+    #[test]
+    fn test_implicit_conversions() {
+        assert!(is_implicit(&Value::Int(1), DataType::Real));
+        assert!(is_implicit(&text("2024-01-31"), DataType::Date));
+        assert!(!is_implicit(&Value::Real(1.0), DataType::Int));
+        assert!(!is_implicit(&text("1"), DataType::Int));
+        assert!(!is_implicit(&Value::Int(1), DataType::Text));
+    }
+}
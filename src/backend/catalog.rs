@@ -0,0 +1,239 @@
+use std::borrow::Cow;
+
+use super::index::Index;
+use super::{Source, SourceRows, Value};
+use crate::lexer::Location;
+use crate::parser::{ColumnDef, DataType, QualifiedName, Select};
+
+/*
+    information_schema holds read only tables that describe the database
+    itself:
+
+        information_schema.tables    one row per table or view
+        information_schema.columns   one row per column of every table
+        information_schema.indexes   one row per index made by CREATE INDEX
+        information_schema.views     one row per view, with its query
+
+    They are built from the backend's own tables whenever they are read, so
+    they are never out of date. The names follow the information_schema of
+    the SQL standard and Postgres, with a subset of the columns there. A
+    table without a qualifier is in the public schema, as in Postgres.
+ */
+
+pub const SCHEMA: &str = "information_schema";
+
+// One table of a backend as the catalog sees it
+pub struct TableInfo<'a> {
+    pub name: &'a str,
+    pub columns: &'a [ColumnDef],
+    pub indexes: &'a [Index],
+    pub view: Option<&'a Select>,
+}
+
+// Whether a name is in information_schema, where no table can be created
+pub fn is_catalog(name: &QualifiedName) -> bool {
+    name.parts.len() > 1 && name.parts[0] == SCHEMA
+}
+
+fn column(name: &str, data_type: DataType) -> ColumnDef {
+    ColumnDef { name: name.to_string(), data_type, constraints: Vec::new(), default: None, loc: Location::new(1, 1) }
+}
+
+fn text(s: &str) -> Value {
+    Value::Text(s.to_string())
+}
+
+// The schema and the table part of a table's name
+fn split_name(name: &str) -> (&str, &str) {
+    name.rsplit_once('.').unwrap_or(("public", name))
+}
+
+// The catalog table called `name`, None when there is no such table
+pub fn load<'a>(name: &QualifiedName, tables: impl Iterator<Item = TableInfo<'a>>) -> Option<Source<'static>> {
+    let [schema, table] = name.parts.as_slice() else {
+        return None;
+    };
+    if schema != SCHEMA {
+        return None;
+    }
+    let mut tables: Vec<TableInfo> = tables.collect();
+    tables.sort_by_key(|info| split_name(info.name));
+
+    let (columns, rows) = match table.as_str() {
+        "tables" => {
+            let columns = ["table_schema", "table_name", "table_type"].map(|name| column(name, DataType::Text));
+            let rows = tables
+                .iter()
+                .map(|info| {
+                    let (schema, table) = split_name(info.name);
+                    let kind = if info.view.is_some() { "VIEW" } else { "BASE TABLE" };
+                    vec![text(schema), text(table), text(kind)]
+                })
+                .collect();
+            (columns.to_vec(), rows)
+        }
+        "columns" => {
+            let columns = vec![
+                column("table_schema", DataType::Text),
+                column("table_name", DataType::Text),
+                column("column_name", DataType::Text),
+                column("ordinal_position", DataType::Int),
+                column("data_type", DataType::Text),
+                column("character_maximum_length", DataType::Int),
+                column("is_nullable", DataType::Text),
+                column("column_default", DataType::Text),
+            ];
+            let mut rows = Vec::new();
+            for info in &tables {
+                let (schema, table) = split_name(info.name);
+                for (i, def) in info.columns.iter().enumerate() {
+                    let (data_type, length) = match def.data_type {
+                        DataType::Varchar(length) => (text("varchar"), Value::Int(length as i64)),
+                        data_type => (Value::Text(data_type.to_string()), Value::Null),
+                    };
+                    rows.push(vec![
+                        text(schema),
+                        text(table),
+                        text(&def.name),
+                        Value::Int(i as i64 + 1),
+                        data_type,
+                        length,
+                        text(if def.is_not_null() { "NO" } else { "YES" }),
+                        def.default.as_ref().map_or(Value::Null, |default| Value::Text(default.to_string())),
+                    ]);
+                }
+            }
+            (columns, rows)
+        }
+        "indexes" => {
+            let columns =
+                ["table_schema", "table_name", "index_name", "column_name"].map(|name| column(name, DataType::Text));
+            let mut rows = Vec::new();
+            for info in &tables {
+                let (schema, table) = split_name(info.name);
+                for index in info.indexes.iter().filter(|index| !index.key) {
+                    let indexed = &info.columns[index.column].name;
+                    rows.push(vec![text(schema), text(table), text(&index.name), text(indexed)]);
+                }
+            }
+            (columns.to_vec(), rows)
+        }
+        "views" => {
+            let columns = ["table_schema", "table_name", "view_definition"].map(|name| column(name, DataType::Text));
+            let mut rows = Vec::new();
+            for info in &tables {
+                let (schema, table) = split_name(info.name);
+                if let Some(view) = info.view {
+                    rows.push(vec![text(schema), text(table), Value::Text(view.to_string())]);
+                }
+            }
+            (columns.to_vec(), rows)
+        }
+        _ => return None,
+    };
+
+    Some(Source { columns: Cow::Owned(columns), rows: SourceRows::Loaded(Cow::Owned(rows)), indexes: &[], view: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{Backend, BackendError, Engine, MemoryBackend, QueryResult, Value, execute, temp_path};
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    fn run(backend: &mut dyn Backend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    // The rows of a select, every value shown as text
+    fn shown(backend: &mut dyn Backend, source: &str) -> Vec<Vec<String>> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(result) => {
+                result.rows.iter().map(|row| row.iter().map(Value::to_string).collect()).collect()
+            }
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    const SETUP: &str = "create table users (id int primary key, name varchar(20) not null, bio text);
+                         create table app.events (at timestamp);
+                         create index by_name on users (name);
+                         create view names as select name from users where id > 1;";
+
+    fn check_catalog(backend: &mut dyn Backend) {
+        assert_eq!(
+            shown(backend, "select * from information_schema.tables"),
+            vec![
+                vec!["app", "events", "BASE TABLE"],
+                vec!["public", "names", "VIEW"],
+                vec!["public", "users", "BASE TABLE"]
+            ]
+        );
+        assert_eq!(
+            shown(backend, "select table_name, view_definition from information_schema.views"),
+            vec![vec!["names", "SELECT name FROM users WHERE (id > 1)"]]
+        );
+        assert_eq!(
+            shown(
+                backend,
+                "select column_name, ordinal_position, data_type, character_maximum_length, is_nullable
+                 from information_schema.columns where table_name = 'users' order by ordinal_position"
+            ),
+            vec![
+                vec!["id", "1", "int", "NULL", "NO"],
+                vec!["name", "2", "varchar", "20", "NO"],
+                vec!["bio", "3", "text", "NULL", "YES"],
+            ]
+        );
+        assert_eq!(
+            shown(backend, "select i.index_name, i.column_name from information_schema.indexes i"),
+            vec![vec!["by_name", "name"]]
+        );
+    }
+
+    #[test]
+    fn test_catalog_describes_the_tables() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, SETUP).unwrap();
+        check_catalog(&mut backend);
+
+        // Changes show up straight away
+        run(&mut backend, "alter table users drop column bio; drop table app.events").unwrap();
+        assert_eq!(shown(&mut backend, "select count(*) from information_schema.columns"), vec![vec!["2"]]);
+        run(&mut backend, "drop view names").unwrap();
+        assert_eq!(shown(&mut backend, "select table_name from information_schema.tables"), vec![vec!["users"]]);
+
+        run(&mut backend, "alter table users add score int default 1 + 1").unwrap();
+        assert_eq!(
+            shown(
+                &mut backend,
+                "select column_name, column_default from information_schema.columns where ordinal_position > 1"
+            ),
+            vec![vec!["name", "NULL"], vec!["score", "(1 + 1)"]]
+        );
+    }
+
+    #[test]
+    fn test_catalog_of_a_disk_backend() {
+        let mut backend = Engine::disk(temp_path("catalog")).open().unwrap();
+        run(&mut *backend, SETUP).unwrap();
+        check_catalog(&mut *backend);
+    }
+
+    #[test]
+    fn test_catalog_is_read_only() {
+        let mut backend = MemoryBackend::new();
+        let cases = [
+            ("create table information_schema.mine (a int)", "Cannot create information_schema.mine, information_schema is read only"),
+            ("insert into information_schema.tables values ('a', 'b', 'c')", "Unknown table information_schema.tables"),
+            ("select * from information_schema.schemata", "Unknown table information_schema.schemata"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+}
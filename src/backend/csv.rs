@@ -0,0 +1,310 @@
+use std::fs;
+
+use super::cast::cast;
+use super::{Backend, BackendError, InsertFrom, Value};
+use crate::parser::{ColumnDef, CopyOptions, DataType, QualifiedName, Select, SelectItem, TableRef, TableSource};
+
+/*
+    COPY moves a whole table between the database and a CSV file. Fields
+    holding the delimiter, a quote or a line break are quoted, with quotes
+    inside doubled. An empty field is NULL while a quoted empty one, "", is
+    the empty string, the same as Postgres does it.
+
+    On the way in every field is converted to the type of its column, so
+    "42" goes into an int column as 42 and "t" into a boolean one as true.
+    The rows then go through insert like any others, constraints included.
+ */
+
+// The fields of one record and the line it starts on, a quoted field can
+// run over several lines
+#[derive(Debug, PartialEq)]
+struct Record {
+    fields: Vec<Option<String>>,
+    line: usize,
+}
+
+// Errors are the message without the file name, which the caller adds
+fn read_records(text: &str, delimiter: char) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut chars = text.strip_prefix('\u{feff}').unwrap_or(text).chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let quoted = chars.next_if_eq(&'"').is_some();
+            if quoted {
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => return Err(format!("Unterminated quoted field on line {}", start)),
+                    }
+                }
+            }
+            while let Some(c) = chars.next_if(|c| *c != delimiter && *c != '\n' && *c != '\r') {
+                if quoted {
+                    return Err(format!("Expected delimiter after quoted field, got {} on line {}", c, line));
+                }
+                field.push(c);
+            }
+            fields.push(if quoted || !field.is_empty() { Some(field) } else { None });
+
+            match chars.next() {
+                Some(c) if c == delimiter => continue,
+                Some('\r') => {
+                    chars.next_if_eq(&'\n');
+                    line += 1;
+                }
+                Some(_) => line += 1,
+                None => {}
+            }
+            break;
+        }
+        records.push(Record { fields, line: start });
+    }
+
+    Ok(records)
+}
+
+// The value a field holds for a column of the given type, None when the
+// text does not read as one. Other types are read the way CAST reads text,
+// which ignores surrounding spaces, while text is kept exactly.
+fn coerce(field: Option<&str>, data_type: DataType) -> Option<Value> {
+    match (field, data_type) {
+        (None, _) => Some(Value::Null),
+        // Too long a value is left for the insert to reject
+        (Some(field), DataType::Text | DataType::Varchar(_)) => Some(Value::Text(field.to_string())),
+        // So is an int out of range, which names the column
+        (Some(field), DataType::Int) => cast(Value::Text(field.to_string()), DataType::BigInt).ok(),
+        (Some(field), data_type) => cast(Value::Text(field.to_string()), data_type).ok(),
+    }
+}
+
+// Loads the file into the table and returns how many rows it held. Either
+// every row goes in or, on the first one that does not fit, none do.
+pub fn copy_from(
+    backend: &mut dyn Backend,
+    table: &QualifiedName,
+    path: &str,
+    options: CopyOptions,
+) -> Result<usize, BackendError> {
+    let columns: Vec<ColumnDef> = backend.columns(table)?;
+    let text = fs::read_to_string(path)
+        .map_err(|err| BackendError::new(format!("Could not read {}: {}", path, err), table.loc))?;
+    let error = |message: String| BackendError::new(format!("{} of {}", message, path), table.loc);
+
+    let records = read_records(&text, options.delimiter).map_err(error)?;
+    let records = records.into_iter().skip(usize::from(options.header));
+
+    let mut rows = Vec::new();
+    for record in records {
+        if record.fields.len() != columns.len() {
+            return Err(error(format!(
+                "Expected {} fields, got {} on line {}",
+                columns.len(),
+                record.fields.len(),
+                record.line
+            )));
+        }
+        let row = record
+            .fields
+            .iter()
+            .zip(&columns)
+            .map(|(field, column)| {
+                let value = coerce(field.as_deref(), column.data_type).ok_or_else(|| {
+                    error(format!(
+                        "Expected {} for column {}, got {} on line {}",
+                        column.data_type,
+                        column.name,
+                        field.as_deref().unwrap_or_default(),
+                        record.line
+                    ))
+                })?;
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>, BackendError>>()?;
+        rows.push(row);
+    }
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    backend.insert(table, &[], InsertFrom::Rows(&mut rows.into_iter().map(Ok)))
+}
+
+fn push_field(out: &mut String, field: &str, delimiter: char) {
+    if field.is_empty() || field.contains([delimiter, '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+// Writes every row of the table to the file, replacing what it held, and
+// returns how many there were
+pub fn copy_to(backend: &dyn Backend, table: &QualifiedName, path: &str, options: CopyOptions) -> Result<usize, BackendError> {
+    let query = Select {
+        distinct: false,
+        columns: vec![SelectItem::Wildcard],
+        from: TableRef { source: TableSource::Table(table.clone()), alias: None },
+        joins: Vec::new(),
+        filter: None,
+        group_by: Vec::new(),
+        having: None,
+        set_operations: Vec::new(),
+        order_by: Vec::new(),
+        limit: None,
+        offset: None,
+    };
+    let result = backend.select(&query)?;
+
+    let mut out = String::new();
+    let mut push_record = |fields: &mut dyn Iterator<Item = Option<String>>| {
+        for (i, field) in fields.enumerate() {
+            if i > 0 {
+                out.push(options.delimiter);
+            }
+            if let Some(field) = field {
+                push_field(&mut out, &field, options.delimiter);
+            }
+        }
+        out.push('\n');
+    };
+    if options.header {
+        push_record(&mut backend.columns(table)?.into_iter().map(|column| Some(column.name)));
+    }
+    for row in &result.rows {
+        push_record(&mut row.iter().map(|value| match value {
+            Value::Null => None,
+            value => Some(value.to_string()),
+        }));
+    }
+
+    fs::write(path, out).map_err(|err| BackendError::new(format!("Could not write {}: {}", path, err), table.loc))?;
+    Ok(result.rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, QueryResult, ResultSet, execute, temp_path};
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    fn run(backend: &mut dyn Backend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    fn rows(backend: &mut dyn Backend, source: &str) -> Vec<Vec<Value>> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(ResultSet { rows, .. }) => rows,
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    fn record(fields: &[Option<&str>], line: usize) -> Record {
+        Record { fields: fields.iter().map(|field| field.map(str::to_string)).collect(), line }
+    }
+
+    #[test]
+    fn test_read_records() {
+        let text = "a,\"b,c\",\r\n\"say \"\"hi\"\"\",\"\",\"two\nlines\"\nlast";
+        assert_eq!(
+            read_records(text, ',').unwrap(),
+            vec![
+                record(&[Some("a"), Some("b,c"), None], 1),
+                record(&[Some("say \"hi\""), Some(""), Some("two\nlines")], 2),
+                record(&[Some("last")], 4),
+            ]
+        );
+        assert_eq!(read_records("1;2\n", ';').unwrap(), vec![record(&[Some("1"), Some("2")], 1)]);
+
+        assert_eq!(read_records("a\n\"open", ',').unwrap_err(), "Unterminated quoted field on line 2");
+        assert_eq!(
+            read_records("\"a\"b", ',').unwrap_err(),
+            "Expected delimiter after quoted field, got b on line 1"
+        );
+    }
+
+    #[test]
+    fn test_coerce() {
+        assert_eq!(coerce(Some(" 42 "), DataType::Int), Some(Value::Int(42)));
+        assert_eq!(coerce(Some("2"), DataType::Real), Some(Value::Real(2.0)));
+        assert_eq!(coerce(Some("inf"), DataType::Real), None);
+        assert_eq!(coerce(Some(" x "), DataType::Text), Some(Value::Text(" x ".to_string())));
+        assert_eq!(coerce(Some("T"), DataType::Boolean), Some(Value::Bool(true)));
+        assert_eq!(coerce(Some("no"), DataType::Boolean), Some(Value::Bool(false)));
+        assert_eq!(coerce(Some("1970-01-02"), DataType::Date), Some(Value::Date(1)));
+        assert_eq!(coerce(Some("abc"), DataType::Int), None);
+        assert_eq!(coerce(None, DataType::Int), Some(Value::Null));
+    }
+
+    #[test]
+    fn test_copy_round_trip() {
+        let path = temp_path("copy-round-trip");
+        let path = path.to_str().unwrap();
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (id int, name text, score real, ok boolean, born date)").unwrap();
+        run(
+            &mut backend,
+            "insert into t values (1, 'a;b', 1.5, true, date '2000-01-01'), (2, '', null, false, null), (3, null, 2.0, null, null)",
+        )
+        .unwrap();
+
+        let copy = format!("copy t to '{}' with (header, delimiter ';')", path);
+        assert_eq!(run(&mut backend, &copy).unwrap(), QueryResult::Affected(3));
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "id;name;score;ok;born\n1;\"a;b\";1.5;true;2000-01-01\n2;\"\";;false;\n3;;2.0;;\n"
+        );
+
+        run(&mut backend, "create table u (id int, name text, score real, ok boolean, born date)").unwrap();
+        let copy = format!("copy u from '{}' with (header, delimiter ';')", path);
+        assert_eq!(run(&mut backend, &copy).unwrap(), QueryResult::Affected(3));
+        assert_eq!(rows(&mut backend, "select * from u"), rows(&mut backend, "select * from t"));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_copy_from_errors() {
+        let path = temp_path("copy-from-errors");
+        let path = path.to_str().unwrap();
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, "create table t (id int primary key, name varchar(3))").unwrap();
+
+        fs::write(path, "1,abc\n2\n").unwrap();
+        let err = run(&mut backend, &format!("copy t from '{}'", path)).unwrap_err();
+        assert_eq!(err.message(), format!("Expected 2 fields, got 1 on line 2 of {}", path));
+
+        fs::write(path, "1,abc\nx,y\n").unwrap();
+        let err = run(&mut backend, &format!("copy t from '{}'", path)).unwrap_err();
+        assert_eq!(err.message(), format!("Expected int for column id, got x on line 2 of {}", path));
+
+        // Constraints are checked by the insert, which adds nothing when a row fails
+        fs::write(path, "1,abc\n1,def\n").unwrap();
+        assert!(run(&mut backend, &format!("copy t from '{}'", path)).is_err());
+        assert!(rows(&mut backend, "select * from t").is_empty());
+
+        let _ = fs::remove_file(path);
+        let err = run(&mut backend, &format!("copy t from '{}'", path)).unwrap_err();
+        assert!(err.message().starts_with("Could not read"));
+
+        let err = run(&mut backend, &format!("copy missing to '{}'", path)).unwrap_err();
+        assert_eq!(err.message(), "Unknown table missing");
+    }
+}
@@ -0,0 +1,1237 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use super::Value;
+use super::pager::{Backup, PAGE_SIZE, PageId, Pager};
+use super::storage::{IndexDef, Storage, TableDef};
+use crate::lexer::{Location, lex};
+use crate::parser::{
+    ColumnConstraint, ColumnDef, DataType, Expr, ForeignKey, QualifiedName, ReferentialAction, Select, Statement,
+    parse, parse_expression,
+};
+
+/*
+    A storage engine that keeps its tables in a single file, through the
+    pager, for a StorageBackend to run statements on.
+
+    Rows and catalog entries are both stored as records in chains of pages.
+    A record page starts with a small header followed by the records, each
+    prefixed by its length:
+
+        next page   u32      next page of the chain, 0 on the last page
+        used        u16      bytes of records on this page
+        records     [len u16, bytes]...
+
+    A record too long for a page overflows: its bytes go to a chain of
+    overflow pages of their own, each the u32 id of the next one followed
+    by as many bytes as fit, and the record page holds where they went
+    instead, with OVERFLOW set in its length:
+
+        len         u16      OVERFLOW | 8
+        bytes       u32      length of the record
+        first page  u32      first overflow page
+
+    The catalog is one chain, starting at the pager's root page, with a record
+    per table holding its name, columns with their constraints and defaults,
+    the first and last page of its row chain, the name and column of each of
+    its indexes and the last number of its auto-increment sequence. A view is an entry with
+    no columns followed by its query. Defaults and queries are kept as the
+    SQL they print as and parsed again on open. Only the definition of an
+    index is stored, its entries are rebuilt from the rows on open. A row is
+    its values one after the other, each a tag byte followed by the value:
+
+        0  int        i64
+        1  text       u32 length, UTF-8 bytes
+        2  bool       u8
+        3  null
+        4  real       f64
+        5  date       i32
+        6  time       i64
+        7  timestamp  i64
+
+    Which pages a table's rows are on, and how many rows each holds, is
+    kept in memory, read by following each chain on open, so that reading
+    a range of rows or the rows at some positions reads only their pages.
+
+    A commit writes the catalog, when a table changed, and flushes. The
+    flush goes through the write-ahead log, so after a crash the file holds
+    either all of a commit or none of it. A rollback drops the changed
+    pages instead, and going back to a savepoint the pages changed since
+    it. Changed pages stay in memory until the commit, so a transaction is
+    limited by memory rather than by the buffer pool.
+ */
+
+const PAGE_HEADER: usize = 6;
+
+// The largest record that fits on a page next to its length prefix, a
+// longer one overflows
+const MAX_RECORD: usize = PAGE_SIZE - PAGE_HEADER - 2;
+
+// Set in the length of a record that overflows
+const OVERFLOW: u16 = 0x8000;
+
+// The bytes of a record an overflow page holds
+const OVERFLOW_BYTES: usize = PAGE_SIZE - 4;
+
+// Pages kept in memory when a database is opened with `open`
+const DEFAULT_POOL_PAGES: usize = 256;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+// Reads values back out of a record, any read past its end means the file is corrupt
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Decoder<'a> {
+        Decoder { bytes, pos: 0 }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + n).ok_or_else(|| invalid("Record is truncated"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("Text is not UTF-8"))
+    }
+}
+
+pub(super) fn encode_row(row: &[Value]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for value in row {
+        match value {
+            Value::Int(i) => {
+                buf.push(0);
+                buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Text(s) => {
+                buf.push(1);
+                put_str(&mut buf, s);
+            }
+            Value::Bool(b) => {
+                buf.push(2);
+                buf.push(*b as u8);
+            }
+            Value::Null => buf.push(3),
+            Value::Real(r) => {
+                buf.push(4);
+                buf.extend_from_slice(&r.to_le_bytes());
+            }
+            Value::Date(d) => {
+                buf.push(5);
+                buf.extend_from_slice(&d.to_le_bytes());
+            }
+            Value::Time(t) => {
+                buf.push(6);
+                buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::Timestamp(t) => {
+                buf.push(7);
+                buf.extend_from_slice(&t.to_le_bytes());
+            }
+            Value::Json(s) => {
+                buf.push(8);
+                put_str(&mut buf, s);
+            }
+        }
+    }
+    buf
+}
+
+pub(super) fn decode_row(record: &[u8]) -> io::Result<Vec<Value>> {
+    let mut decoder = Decoder::new(record);
+    let mut row = Vec::new();
+    while !decoder.is_at_end() {
+        row.push(match decoder.u8()? {
+            0 => Value::Int(decoder.i64()?),
+            1 => Value::Text(decoder.str()?),
+            2 => Value::Bool(decoder.u8()? != 0),
+            3 => Value::Null,
+            4 => Value::Real(f64::from_bits(decoder.i64()? as u64)),
+            5 => Value::Date(decoder.u32()? as i32),
+            6 => Value::Time(decoder.i64()?),
+            7 => Value::Timestamp(decoder.i64()?),
+            8 => Value::Json(decoder.str()?),
+            _ => return Err(invalid("Unknown value tag")),
+        });
+    }
+    Ok(row)
+}
+
+// The pages of a chain of records in order, with how many records each holds
+type Pages = Vec<(PageId, usize)>;
+
+fn read_u16(page: &[u8], at: usize) -> usize {
+    u16::from_le_bytes(page[at..at + 2].try_into().unwrap()) as usize
+}
+
+fn read_u32(page: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(page[at..at + 4].try_into().unwrap())
+}
+
+// A record as a page of a chain holds it
+enum Slot<'a> {
+    Inline(&'a [u8]),
+    Overflow { len: usize, first: PageId },
+}
+
+// The records on a page of a chain
+fn page_records(page: &[u8]) -> io::Result<Vec<Slot<'_>>> {
+    let used = read_u16(page, 4);
+    if used > PAGE_SIZE - PAGE_HEADER {
+        return Err(invalid("Page is corrupt"));
+    }
+    let mut records = Vec::new();
+    let mut at = PAGE_HEADER;
+    while at < PAGE_HEADER + used {
+        let len = page.get(at..at + 2).map(|len| read_u16(len, 0)).ok_or_else(|| invalid("Page is corrupt"))?;
+        let overflows = len & OVERFLOW as usize != 0;
+        let len = len & !(OVERFLOW as usize);
+        let bytes = page.get(at + 2..at + 2 + len).ok_or_else(|| invalid("Page is corrupt"))?;
+        records.push(match overflows {
+            false => Slot::Inline(bytes),
+            true if len == 8 => Slot::Overflow { len: read_u32(bytes, 0) as usize, first: read_u32(bytes, 4) },
+            true => return Err(invalid("Page is corrupt")),
+        });
+        at += 2 + len;
+    }
+    Ok(records)
+}
+
+// Writes the bytes of a record that overflows to new overflow pages,
+// returning the first of them
+fn write_overflow(pager: &mut Pager, record: &[u8]) -> io::Result<PageId> {
+    let (mut first, mut last) = (0, 0);
+    for bytes in record.chunks(OVERFLOW_BYTES) {
+        let id = pager.allocate()?;
+        if last == 0 {
+            first = id;
+        } else {
+            pager.write(last)?[..4].copy_from_slice(&id.to_le_bytes());
+        }
+        pager.write(id)?[4..4 + bytes.len()].copy_from_slice(bytes);
+        last = id;
+    }
+    Ok(first)
+}
+
+fn read_overflow(pager: &mut Pager, len: usize, first: PageId) -> io::Result<Vec<u8>> {
+    let mut record = Vec::with_capacity(len);
+    let mut id = first;
+    while record.len() < len {
+        if id == 0 {
+            return Err(invalid("Overflow chain is truncated"));
+        }
+        let page = pager.read(id)?;
+        let bytes = (len - record.len()).min(OVERFLOW_BYTES);
+        record.extend_from_slice(&page[4..4 + bytes]);
+        id = read_u32(page, 0);
+    }
+    Ok(record)
+}
+
+// Reads the records of a page, leaving out the first `skip` and keeping at
+// most `take`, each as `f` turns its bytes into a T
+fn read_records<T>(
+    pager: &mut Pager,
+    id: PageId,
+    skip: usize,
+    take: usize,
+    f: impl Fn(&[u8]) -> io::Result<T>,
+) -> io::Result<Vec<T>> {
+    let mut records = Vec::new();
+    // The records that overflow are read once the page is not borrowed
+    let mut overflowing = Vec::new();
+    for slot in page_records(pager.read(id)?)?.into_iter().skip(skip).take(take) {
+        match slot {
+            Slot::Inline(bytes) => records.push(Some(f(bytes)?)),
+            Slot::Overflow { len, first } => {
+                overflowing.push((records.len(), len, first));
+                records.push(None);
+            }
+        }
+    }
+    for (i, len, first) in overflowing {
+        records[i] = Some(f(&read_overflow(pager, len, first)?)?);
+    }
+    Ok(records.into_iter().flatten().collect())
+}
+
+// The pages of the chain starting at `first`, which is 0 for an empty chain
+fn read_pages(pager: &mut Pager, first: PageId) -> io::Result<Pages> {
+    let mut pages = Vec::new();
+    let mut id = first;
+    while id != 0 {
+        let page = pager.read(id)?;
+        pages.push((id, page_records(page)?.len()));
+        id = read_u32(page, 0);
+    }
+    Ok(pages)
+}
+
+fn read_chain(pager: &mut Pager, first: PageId) -> io::Result<Vec<Vec<u8>>> {
+    let mut records = Vec::new();
+    for (id, _) in read_pages(pager, first)? {
+        records.extend(read_records(pager, id, 0, usize::MAX, |bytes| Ok(bytes.to_vec()))?);
+    }
+    Ok(records)
+}
+
+// Adds a record to the last page of the chain, or to a new page when it is
+// full, and its bytes to overflow pages when it is too long for any page
+fn append(pager: &mut Pager, pages: &mut Pages, record: &[u8]) -> io::Result<()> {
+    let stub;
+    let (len, record) = if record.len() > MAX_RECORD {
+        let len = u32::try_from(record.len()).map_err(|_| invalid("Record is too large"))?;
+        stub = [len.to_le_bytes(), write_overflow(pager, record)?.to_le_bytes()].concat();
+        (OVERFLOW | stub.len() as u16, stub.as_slice())
+    } else {
+        (record.len() as u16, record)
+    };
+
+    let fits = match pages.last() {
+        Some(&(last, _)) => PAGE_HEADER + read_u16(pager.read(last)?, 4) + 2 + record.len() <= PAGE_SIZE,
+        None => false,
+    };
+    if !fits {
+        let id = pager.allocate()?;
+        if let Some(&(last, _)) = pages.last() {
+            pager.write(last)?[..4].copy_from_slice(&id.to_le_bytes());
+        }
+        pages.push((id, 0));
+    }
+
+    let (id, count) = pages.last_mut().unwrap();
+    let page = pager.write(*id)?;
+    let used = read_u16(page, 4);
+    let at = PAGE_HEADER + used;
+    page[at..at + 2].copy_from_slice(&len.to_le_bytes());
+    page[at + 2..at + 2 + record.len()].copy_from_slice(record);
+    page[4..6].copy_from_slice(&((used + 2 + record.len()) as u16).to_le_bytes());
+    *count += 1;
+    Ok(())
+}
+
+// Frees the pages of the chain, and the overflow pages of its records
+fn free_chain(pager: &mut Pager, pages: &Pages) -> io::Result<()> {
+    for &(id, _) in pages {
+        let overflows = page_records(pager.read(id)?)?.into_iter().filter_map(|slot| match slot {
+            Slot::Overflow { first, .. } => Some(first),
+            Slot::Inline(_) => None,
+        });
+        for first in overflows.collect::<Vec<_>>() {
+            let mut id = first;
+            while id != 0 {
+                let next = read_u32(pager.read(id)?, 0);
+                pager.free(id)?;
+                id = next;
+            }
+        }
+        pager.free(id)?;
+    }
+    Ok(())
+}
+
+const CONSTRAINTS: [ColumnConstraint; 4] = [
+    ColumnConstraint::PrimaryKey,
+    ColumnConstraint::Unique,
+    ColumnConstraint::NotNull,
+    ColumnConstraint::AutoIncrement,
+];
+
+// A column's constraints are stored as one byte of flags, with one more flag
+// for a column with a default. The default and then the foreign key follow
+// the flags: its table, its column and a byte for the ON DELETE action.
+fn constraint_flag(constraint: &ColumnConstraint) -> u8 {
+    match constraint {
+        ColumnConstraint::PrimaryKey => 1,
+        ColumnConstraint::Unique => 2,
+        ColumnConstraint::NotNull => 4,
+        ColumnConstraint::AutoIncrement => 8,
+        ColumnConstraint::References(_) => REFERENCES_FLAG,
+    }
+}
+
+const DEFAULT_FLAG: u8 = 16;
+const REFERENCES_FLAG: u8 = 32;
+
+const ACTIONS: [ReferentialAction; 3] =
+    [ReferentialAction::Restrict, ReferentialAction::Cascade, ReferentialAction::SetNull];
+
+// A default as it was stored by encode_entry
+fn decode_default(sql: &str) -> io::Result<Expr> {
+    let tokens = lex(sql).map_err(|_| invalid("Column default is corrupt"))?;
+    parse_expression(tokens).map_err(|_| invalid("Column default is corrupt"))
+}
+
+// A view's query as it was stored by encode_entry
+fn decode_view(sql: &str) -> io::Result<Select> {
+    match lex(sql).ok().and_then(|tokens| parse(tokens).ok()) {
+        Some(Statement::Select(query)) => Ok(*query),
+        _ => Err(invalid("View query is corrupt")),
+    }
+}
+
+// The entry of a table whose rows are on the chain from the first to the
+// last of `rows`
+fn encode_entry(name: &str, def: &TableDef, rows: (PageId, PageId)) -> Vec<u8> {
+    let mut buf = Vec::new();
+    put_str(&mut buf, name);
+    buf.extend_from_slice(&rows.0.to_le_bytes());
+    buf.extend_from_slice(&rows.1.to_le_bytes());
+    buf.extend_from_slice(&(def.columns.len() as u16).to_le_bytes());
+    for column in &def.columns {
+        put_str(&mut buf, &column.name);
+        match column.data_type {
+            DataType::Int => buf.push(0),
+            DataType::Text => buf.push(1),
+            DataType::BigInt => buf.push(2),
+            DataType::Real => buf.push(3),
+            DataType::Boolean => buf.push(4),
+            DataType::Date => buf.push(6),
+            DataType::Time => buf.push(7),
+            DataType::Timestamp => buf.push(8),
+            DataType::Json => buf.push(9),
+            DataType::Varchar(length) => {
+                buf.push(5);
+                buf.extend_from_slice(&length.to_le_bytes());
+            }
+        }
+        let flags = column.constraints.iter().fold(0, |flags, c| flags | constraint_flag(c));
+        buf.push(if column.default.is_some() { flags | DEFAULT_FLAG } else { flags });
+        if let Some(default) = &column.default {
+            put_str(&mut buf, &default.to_string());
+        }
+        if let Some(key) = column.references() {
+            put_str(&mut buf, &key.table.to_string());
+            put_str(&mut buf, &key.column);
+            buf.push(ACTIONS.iter().position(|action| *action == key.on_delete).unwrap() as u8);
+        }
+    }
+    buf.extend_from_slice(&(def.indexes.len() as u16).to_le_bytes());
+    for index in &def.indexes {
+        put_str(&mut buf, &index.name);
+        buf.extend_from_slice(&(index.column as u16).to_le_bytes());
+    }
+    buf.extend_from_slice(&def.sequence.to_le_bytes());
+    if let Some(view) = &def.view {
+        put_str(&mut buf, &view.to_string());
+    }
+    buf
+}
+
+// A table's name and definition, and the first page of its rows
+fn decode_entry(record: &[u8]) -> io::Result<(String, TableDef, PageId)> {
+    let mut decoder = Decoder::new(record);
+    let name = decoder.str()?;
+    // Only the first page is read, the chain is followed from there
+    let (rows, _) = (decoder.u32()?, decoder.u32()?);
+    let mut columns = Vec::new();
+    for _ in 0..decoder.u16()? {
+        let name = decoder.str()?;
+        let data_type = match decoder.u8()? {
+            0 => DataType::Int,
+            1 => DataType::Text,
+            2 => DataType::BigInt,
+            3 => DataType::Real,
+            4 => DataType::Boolean,
+            5 => DataType::Varchar(decoder.u32()?),
+            6 => DataType::Date,
+            7 => DataType::Time,
+            8 => DataType::Timestamp,
+            9 => DataType::Json,
+            _ => return Err(invalid("Unknown column type")),
+        };
+        let flags = decoder.u8()?;
+        let mut constraints: Vec<ColumnConstraint> =
+            CONSTRAINTS.iter().filter(|c| flags & constraint_flag(c) != 0).cloned().collect();
+        let default = if flags & DEFAULT_FLAG != 0 { Some(decode_default(&decoder.str()?)?) } else { None };
+        if flags & REFERENCES_FLAG != 0 {
+            let parts = decoder.str()?.split('.').map(str::to_string).collect();
+            let table = QualifiedName { parts, loc: Location::new(1, 1) };
+            let column = decoder.str()?;
+            let on_delete = *ACTIONS.get(decoder.u8()? as usize).ok_or_else(|| invalid("Unknown ON DELETE action"))?;
+            constraints.push(ColumnConstraint::References(ForeignKey { table, column, on_delete }));
+        }
+        // Columns read back from disk have no source to point at
+        columns.push(ColumnDef { name, data_type, constraints, default, loc: Location::new(1, 1) });
+    }
+    let mut indexes = Vec::new();
+    for _ in 0..decoder.u16()? {
+        let name = decoder.str()?;
+        let column = decoder.u16()? as usize;
+        if column >= columns.len() {
+            return Err(invalid("Index on a column that does not exist"));
+        }
+        indexes.push(IndexDef { name, column });
+    }
+    // Files written before tables had a sequence end here
+    let sequence = if decoder.is_at_end() { 0 } else { decoder.i64()? };
+    let view = if decoder.is_at_end() { None } else { Some(decode_view(&decoder.str()?)?) };
+    Ok((name, TableDef { columns, indexes, sequence, view }, rows))
+}
+
+// A definition kept by another storage engine, encoded like a catalog
+// entry with no rows
+pub(super) fn encode_def(name: &str, def: &TableDef) -> Vec<u8> {
+    encode_entry(name, def, (0, 0))
+}
+
+pub(super) fn decode_def(record: &[u8]) -> io::Result<(String, TableDef)> {
+    let (name, def, _) = decode_entry(record)?;
+    Ok((name, def))
+}
+
+
+// A table or view of the catalog
+#[derive(Clone)]
+struct Table {
+    def: TableDef,
+    pages: Pages,
+}
+
+// A table's catalog entry
+fn encode_table(name: &str, table: &Table) -> Vec<u8> {
+    let ends = |page: Option<&(PageId, usize)>| page.map_or(0, |&(id, _)| id);
+    encode_entry(name, &table.def, (ends(table.pages.first()), ends(table.pages.last())))
+}
+
+// Every table of the catalog, with the pages of its rows
+fn read_catalog(pager: &mut Pager) -> io::Result<HashMap<String, Table>> {
+    let root = pager.root();
+    let mut tables = HashMap::new();
+    for record in read_chain(pager, root)? {
+        let (name, def, first) = decode_entry(&record)?;
+        let pages = read_pages(pager, first)?;
+        tables.insert(name, Table { def, pages });
+    }
+    Ok(tables)
+}
+
+pub struct PagerStorage {
+    // Reading a page changes the buffer pool, so scans, which only have
+    // &self, need to borrow the pager mutably too
+    pager: RefCell<Pager>,
+    tables: HashMap<String, Table>,
+    // The tables changed since the last commit, as they were committed,
+    // None for a table that did not exist
+    undo: HashMap<String, Option<Table>>,
+    // The same of the tables changed since the savepoint
+    savepoint: Option<HashMap<String, Option<Table>>>,
+}
+
+impl PagerStorage {
+    // Opens the database at `path`, creating an empty one if the file does not exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<PagerStorage> {
+        PagerStorage::open_with_pool(path, DEFAULT_POOL_PAGES)
+    }
+
+    // Like open, keeping at most `pages` pages in memory
+    pub fn open_with_pool(path: impl AsRef<Path>, pages: usize) -> io::Result<PagerStorage> {
+        let mut pager = Pager::open(path.as_ref(), pages)?;
+        let tables = read_catalog(&mut pager)?;
+        Ok(PagerStorage { pager: RefCell::new(pager), tables, undo: HashMap::new(), savepoint: None })
+    }
+
+    fn table(&self, name: &str) -> io::Result<&Table> {
+        self.tables.get(name).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No table {}", name)))
+    }
+
+    // Keeps the table as it is, for a rollback and going back to the
+    // savepoint to put back, the first time it changes since either
+    fn save(&mut self, name: &str) {
+        let table = self.tables.get(name);
+        self.undo.entry(name.to_string()).or_insert_with(|| table.cloned());
+        if let Some(savepoint) = &mut self.savepoint {
+            savepoint.entry(name.to_string()).or_insert_with(|| table.cloned());
+        }
+    }
+
+    fn put_back(&mut self, undo: HashMap<String, Option<Table>>) {
+        for (name, table) in undo {
+            match table {
+                Some(table) => self.tables.insert(name, table),
+                None => self.tables.remove(&name),
+            };
+        }
+    }
+
+    // Up to `take` rows of the pages, leaving out the first `skip`
+    fn read_rows(&self, pages: &[(PageId, usize)], skip: usize, take: usize) -> io::Result<Vec<Vec<Value>>> {
+        let mut pager = self.pager.borrow_mut();
+        let mut rows = Vec::with_capacity(take);
+        let mut skip = skip;
+        for &(id, count) in pages {
+            if rows.len() == take {
+                break;
+            }
+            if skip >= count {
+                skip -= count;
+                continue;
+            }
+            rows.extend(read_records(&mut pager, id, skip, take - rows.len(), decode_row)?);
+            skip = 0;
+        }
+        Ok(rows)
+    }
+
+    // Writes the catalog over the one at the root
+    fn write_catalog(&mut self) -> io::Result<()> {
+        let pager = self.pager.get_mut();
+        let root = pager.root();
+        let old = read_pages(pager, root)?;
+        free_chain(pager, &old)?;
+
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+        let mut catalog = Vec::new();
+        for name in names {
+            append(pager, &mut catalog, &encode_table(name, &self.tables[name]))?;
+        }
+        pager.set_root(catalog.first().map_or(0, |&(id, _)| id));
+        Ok(())
+    }
+}
+
+impl Storage for PagerStorage {
+    fn tables(&self) -> io::Result<Vec<(String, TableDef)>> {
+        Ok(self.tables.iter().map(|(name, table)| (name.clone(), table.def.clone())).collect())
+    }
+
+    fn scan(&self, table: &str) -> io::Result<Vec<Vec<Value>>> {
+        let table = self.table(table)?;
+        self.read_rows(&table.pages, 0, table.pages.iter().map(|&(_, count)| count).sum())
+    }
+
+    fn count(&self, table: &str) -> io::Result<usize> {
+        Ok(self.table(table)?.pages.iter().map(|&(_, count)| count).sum())
+    }
+
+    fn scan_range(&self, table: &str, range: Range<usize>) -> io::Result<Vec<Vec<Value>>> {
+        let rows = self.read_rows(&self.table(table)?.pages, range.start, range.len())?;
+        if rows.len() < range.len() {
+            return Err(invalid("No row at that position"));
+        }
+        Ok(rows)
+    }
+
+    fn get(&self, table: &str, positions: &[usize]) -> io::Result<Vec<Vec<Value>>> {
+        let pages = &self.table(table)?.pages;
+        // The position of the first row of each page
+        let starts: Vec<usize> = pages
+            .iter()
+            .scan(0, |start, &(_, count)| {
+                *start += count;
+                Some(*start - count)
+            })
+            .collect();
+        positions
+            .iter()
+            .map(|&i| {
+                let page = starts.partition_point(|&start| start <= i).saturating_sub(1);
+                let mut rows = self.read_rows(&pages[page..], i - starts.get(page).unwrap_or(&0), 1)?;
+                rows.pop().ok_or_else(|| invalid("No row at that position"))
+            })
+            .collect()
+    }
+
+    fn define(&mut self, table: &str, def: &TableDef) -> io::Result<()> {
+        let pages = self.tables.get(table).map_or_else(Vec::new, |table| table.pages.clone());
+        self.save(table);
+        self.tables.insert(table.to_string(), Table { def: def.clone(), pages });
+        Ok(())
+    }
+
+    fn drop(&mut self, table: &str) -> io::Result<()> {
+        self.table(table)?;
+        self.save(table);
+        let table = self.tables.remove(table).unwrap();
+        free_chain(self.pager.get_mut(), &table.pages)
+    }
+
+    fn append(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+        self.table(table)?;
+        self.save(table);
+        let (pager, table) = (self.pager.get_mut(), self.tables.get_mut(table).unwrap());
+        rows.iter().try_for_each(|row| append(pager, &mut table.pages, &encode_row(row)))
+    }
+
+    fn replace(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+        self.table(table)?;
+        self.save(table);
+        let (pager, table) = (self.pager.get_mut(), self.tables.get_mut(table).unwrap());
+        free_chain(pager, &std::mem::take(&mut table.pages))?;
+        rows.iter().try_for_each(|row| append(pager, &mut table.pages, &encode_row(row)))
+    }
+
+    // The catalog is only written when a table changed, and goes to the
+    // file in the same flush as the pages
+    fn commit(&mut self) -> io::Result<()> {
+        if !self.undo.is_empty() {
+            self.write_catalog()?;
+        }
+        self.pager.get_mut().flush()?;
+        self.undo.clear();
+        self.savepoint = None;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> io::Result<()> {
+        self.pager.get_mut().discard();
+        let undo = std::mem::take(&mut self.undo);
+        self.put_back(undo);
+        self.savepoint = None;
+        Ok(())
+    }
+
+    fn savepoint(&mut self) -> io::Result<()> {
+        self.pager.get_mut().savepoint();
+        self.savepoint = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> io::Result<()> {
+        let undo = self.savepoint.take().ok_or_else(|| io::Error::other("No savepoint to go back to"))?;
+        self.pager.get_mut().rollback_to_savepoint()?;
+        self.put_back(undo);
+        Ok(())
+    }
+
+    fn backup(&mut self) -> io::Result<Backup> {
+        self.pager.get_mut().backup()
+    }
+
+    fn restore(&mut self, path: &Path) -> io::Result<()> {
+        let pager = self.pager.get_mut();
+        pager.restore(path)?;
+        self.tables = read_catalog(pager)?;
+        self.undo.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pager::temp_path;
+    use super::super::{Backend, BackendError, QueryResult, StorageBackend, execute};
+    use super::*;
+    use crate::parser::split_statements;
+
+    fn open(path: &Path) -> StorageBackend {
+        open_with_pool(path, DEFAULT_POOL_PAGES)
+    }
+
+    fn open_with_pool(path: &Path, pages: usize) -> StorageBackend {
+        StorageBackend::new(Box::new(PagerStorage::open_with_pool(path, pages).unwrap())).unwrap()
+    }
+
+    // The pages of the file, a page is added to it as soon as it is allocated
+    fn page_count(path: &Path) -> u64 {
+        std::fs::metadata(path).unwrap().len() / PAGE_SIZE as u64
+    }
+
+    fn run(backend: &mut StorageBackend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    fn query(backend: &mut StorageBackend, source: &str) -> Vec<Vec<Value>> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(rows) => rows.rows,
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_rows_survive_reopening() {
+        let path = temp_path("disk-reopen");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table users (id int, name text);
+             insert into users values (1, 'Ada'), (2, 'Grace');
+             create table empty (x int);",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select * from users"),
+            vec![vec![Value::Int(1), text("Ada")], vec![Value::Int(2), text("Grace")]]
+        );
+        assert_eq!(query(&mut backend, "select * from empty"), Vec::<Vec<Value>>::new());
+
+        let err = run(&mut backend, "create table users (id int)").unwrap_err();
+        assert_eq!(err.message(), "Table users already exists");
+    }
+
+    #[test]
+    fn test_update_and_delete_survive_reopening() {
+        let path = temp_path("disk-mutate");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (n int, s text);
+             insert into t values (1, 'a'), (2, 'b'), (3, 'c');",
+        )
+        .unwrap();
+        assert_eq!(run(&mut backend, "update t set s = 'z' where n >= 2"), Ok(QueryResult::Affected(2)));
+        assert_eq!(run(&mut backend, "delete from t where n = 1"), Ok(QueryResult::Affected(1)));
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select * from t"),
+            vec![vec![Value::Int(2), text("z")], vec![Value::Int(3), text("z")]]
+        );
+    }
+
+    #[test]
+    fn test_tables_span_many_pages_through_a_small_pool() {
+        let path = temp_path("disk-pages");
+        let mut backend = open_with_pool(&path, 2);
+        run(&mut backend, "create table t (n int, s text); create index t_n on t (n)").unwrap();
+
+        let padding = "x".repeat(500);
+        for n in 0..100 {
+            run(&mut backend, &format!("insert into t values ({}, '{}')", n, padding)).unwrap();
+        }
+        assert!(page_count(&path) > 10);
+        drop(backend);
+
+        let mut backend = open_with_pool(&path, 2);
+        let rows = query(&mut backend, "select n from t where n >= 98");
+        assert_eq!(rows, vec![vec![Value::Int(98)], vec![Value::Int(99)]]);
+        // Read by position, from the pages they are on
+        let plan = query(&mut backend, "explain select n from t where n = 57");
+        assert!(plan.iter().any(|row| row[0].to_string().contains("t_n")), "{:?}", plan);
+        assert_eq!(query(&mut backend, "select n from t where n = 57"), vec![vec![Value::Int(57)]]);
+        let rows = query(&mut backend, "select n from t limit 2 offset 60");
+        assert_eq!(rows, vec![vec![Value::Int(60)], vec![Value::Int(61)]]);
+    }
+
+    #[test]
+    fn test_going_back_to_a_savepoint_keeps_the_writes_before() {
+        let path = temp_path("disk-savepoint");
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (n int); insert into t values (1)").unwrap();
+        drop(backend);
+
+        let mut storage = PagerStorage::open(&path).unwrap();
+        let (_, def) = storage.tables().unwrap().pop().unwrap();
+        storage.savepoint().unwrap();
+        storage.append("t", &[vec![Value::Int(2)]]).unwrap();
+        storage.savepoint().unwrap();
+        storage.replace("t", &[vec![Value::Int(3)]]).unwrap();
+        storage.define("u", &def).unwrap();
+        storage.rollback_to_savepoint().unwrap();
+        assert_eq!(storage.scan("t").unwrap(), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+        storage.commit().unwrap();
+        drop(storage);
+
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+        assert!(run(&mut backend, "select * from u").is_err());
+    }
+
+    #[test]
+    fn test_deleted_pages_are_reused() {
+        let path = temp_path("disk-reuse");
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (s text)").unwrap();
+
+        let padding = "x".repeat(1000);
+        let insert = format!("insert into t values ('{0}'), ('{0}'), ('{0}'), ('{0}'), ('{0}'), ('{0}')", padding);
+        run(&mut backend, &insert).unwrap();
+        let pages = page_count(&path);
+
+        run(&mut backend, "delete from t").unwrap();
+        run(&mut backend, &insert).unwrap();
+        assert_eq!(page_count(&path), pages);
+    }
+
+    #[test]
+    fn test_records_larger_than_a_page_overflow() {
+        let path = temp_path("disk-large");
+        let mut backend = open_with_pool(&path, 2);
+        let (large, larger) = ("x".repeat(PAGE_SIZE), "y".repeat(3 * PAGE_SIZE));
+        let tables = "create table p (id int primary key); create table t (s text, p int references p (id))";
+        run(&mut backend, tables).unwrap();
+        let insert = format!("insert into t values ('small', null), ('{}', null), ('{}', null)", large, larger);
+        run(&mut backend, &insert).unwrap();
+        let view = format!("create view v as select s from t where s = 'small' or s = '{}'", "z".repeat(PAGE_SIZE));
+        run(&mut backend, &view).unwrap();
+        // The foreign key of t follows the column, t's entry grows past a page
+        let column = "c".repeat(PAGE_SIZE);
+        run(&mut backend, &format!("alter table p rename column id to {}", column)).unwrap();
+        drop(backend);
+
+        let mut backend = open_with_pool(&path, 2);
+        let rows = vec![vec![text("small")], vec![text(&large)], vec![text(&larger)]];
+        assert_eq!(query(&mut backend, "select s from t"), rows);
+        assert_eq!(query(&mut backend, "select * from v"), vec![vec![text("small")]]);
+        run(&mut backend, &format!("insert into p values (1); insert into t values ('{}', 1)", larger)).unwrap();
+        let err = run(&mut backend, "insert into t values ('a', 2)").unwrap_err();
+        assert!(err.message().contains(&column), "{}", err.message());
+
+        // The overflow pages go back to the free list with their rows
+        let pages = page_count(&path);
+        run(&mut backend, "delete from t where s <> 'small'").unwrap();
+        run(&mut backend, &format!("insert into t values ('{}', 1), ('{}', null)", larger, large)).unwrap();
+        assert_eq!(page_count(&path), pages);
+    }
+
+    #[test]
+    fn test_statements_run_while_a_backup_is_copied() {
+        let (path, copy) = (temp_path("disk-backup"), temp_path("disk-backup-copy"));
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (n int); insert into t values (1)").unwrap();
+
+        let backup = backend.backup().unwrap();
+        run(&mut backend, "insert into t values (2); create table u (a int)").unwrap();
+        run(&mut backend, "begin; insert into t values (3); rollback").unwrap();
+        assert_eq!(query(&mut backend, "select count(*) from t"), vec![vec![Value::Int(2)]]);
+        backup.write(&copy).unwrap();
+
+        let mut copied = open(&copy);
+        assert_eq!(query(&mut copied, "select n from t"), vec![vec![Value::Int(1)]]);
+        assert!(run(&mut copied, "select * from u").is_err());
+        run(&mut backend, "insert into t values (4)").unwrap();
+        drop(backend);
+        let mut backend = open(&path);
+        let rows = query(&mut backend, "select n from t order by n");
+        assert_eq!(rows, vec![vec![Value::Int(1)], vec![Value::Int(2)], vec![Value::Int(4)]]);
+    }
+
+    #[test]
+    fn test_rollback_restores_pages_and_catalog() {
+        let path = temp_path("disk-rollback");
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (n int); insert into t values (1), (2)").unwrap();
+
+        run(
+            &mut backend,
+            "begin;
+             update t set n = n * 10;
+             delete from t where n = 10;
+             create table other (x int);
+             insert into other values (1);",
+        )
+        .unwrap();
+        assert_eq!(query(&mut backend, "select * from t"), vec![vec![Value::Int(20)]]);
+
+        run(&mut backend, "rollback").unwrap();
+        assert_eq!(query(&mut backend, "select * from t"), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+        assert!(run(&mut backend, "select * from other").is_err());
+
+        // The rolled back pages never reached the file either
+        drop(backend);
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select * from t"), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+        assert!(run(&mut backend, "select * from other").is_err());
+    }
+
+    #[test]
+    fn test_uncommitted_transaction_is_lost_on_close() {
+        let path = temp_path("disk-uncommitted");
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (n int); begin; insert into t values (1)").unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert!(query(&mut backend, "select * from t").is_empty());
+    }
+
+    #[test]
+    fn test_commit_reaches_the_file() {
+        let path = temp_path("disk-commit");
+        let mut backend = open(&path);
+        run(&mut backend, "begin; create table t (n int); insert into t values (1); commit").unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select * from t"), vec![vec![Value::Int(1)]]);
+    }
+
+    #[test]
+    fn test_indexes_are_rebuilt_on_open() {
+        let path = temp_path("disk-index");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (n int, s text);
+             insert into t values (1, 'a'), (2, 'b'), (3, 'c');
+             create index by_n on t (n);
+             insert into t values (2, 'd');
+             delete from t where n = 1;",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select s from t where n = 2"),
+            vec![vec![text("b")], vec![text("d")]]
+        );
+        assert_eq!(query(&mut backend, "select s from t where n > 2"), vec![vec![text("c")]]);
+        assert_eq!(run(&mut backend, "create index by_n on t (s)").unwrap_err().message(), "Index by_n already exists");
+    }
+
+    #[test]
+    fn test_join_reads_each_table() {
+        let path = temp_path("disk-join");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table users (id int, name text);
+             create table orders (user_id int, item text);
+             insert into users values (1, 'Ada'), (2, 'Grace');
+             insert into orders values (2, 'pen'), (1, 'book');",
+        )
+        .unwrap();
+        assert_eq!(
+            query(&mut backend, "select name, item from users left join orders on id = user_id and item <> 'book'"),
+            vec![vec![text("Ada"), Value::Null], vec![text("Grace"), text("pen")]]
+        );
+        assert_eq!(
+            query(&mut backend, "explain select item from orders"),
+            vec![vec![text("Project: item")], vec![text("  -> Seq Scan on orders")]]
+        );
+    }
+
+    #[test]
+    fn test_constraints_survive_reopening() {
+        let path = temp_path("disk-constraints");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (id int primary key, email text not null unique, note text);
+             insert into t values (1, 'a', 'x');",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        let err = run(&mut backend, "insert into t values (2, 'a', 'x')").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value a for unique column email");
+        run(&mut backend, "insert into t values (2, 'b', 'x')").unwrap();
+        let err = run(&mut backend, "update t set id = 1 + 0").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 1 for primary key id");
+        assert_eq!(query(&mut backend, "select id from t"), vec![vec![Value::Int(1)], vec![Value::Int(2)]]);
+    }
+
+    #[test]
+    fn test_column_types_survive_reopening() {
+        let path = temp_path("disk-types");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (a int, b bigint, c real, d varchar(2), e boolean, f date, g time, h timestamp, i json);
+             insert into t values (1, 2, 3, 'x', false, '2024-01-31', '12:00:00', '2024-01-31 12:00:00', '[1, 2]');",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select a, b, c, d, e from t"),
+            vec![vec![Value::Int(1), Value::Int(2), Value::Real(3.0), text("x"), Value::Bool(false)]]
+        );
+        let shown: Vec<String> = query(&mut backend, "select f, g, h, i from t")[0].iter().map(|v| v.to_string()).collect();
+        assert_eq!(shown, vec!["2024-01-31", "12:00:00", "2024-01-31 12:00:00", "[1,2]"]);
+        let err = run(&mut backend, "insert into t values (1, 2, 3, 'xyz', false, null, null, null, null)").unwrap_err();
+        assert_eq!(err.message(), "Value too long for varchar(2) column d");
+    }
+
+    #[test]
+    fn test_dropped_tables_stay_dropped() {
+        let path = temp_path("disk-drop");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table a (x int); create table b (y int);
+             insert into a values (1), (2); insert into b values (3);
+             drop table a;",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(run(&mut backend, "select * from a").unwrap_err().message(), "Unknown table a");
+        assert_eq!(query(&mut backend, "select * from b"), vec![vec![Value::Int(3)]]);
+
+        // Rolling back brings the table and its rows back
+        run(&mut backend, "begin; drop table b; rollback").unwrap();
+        assert_eq!(query(&mut backend, "select * from b"), vec![vec![Value::Int(3)]]);
+    }
+
+    #[test]
+    fn test_altered_tables_survive_reopening() {
+        let path = temp_path("disk-alter");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (a int, b text, c int);
+             insert into t values (1, 'x', 10), (2, 'y', 20);
+             create index by_c on t (c);
+             alter table t drop column b;
+             alter table t add d text default 'new';
+             alter table t rename column a to id;",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select * from t where c = 20"),
+            vec![vec![Value::Int(2), Value::Int(20), text("new")]]
+        );
+        assert_eq!(query(&mut backend, "select id from t where id = 1"), vec![vec![Value::Int(1)]]);
+
+        // A failed alter changes nothing, in memory or on disk
+        assert!(run(&mut backend, "alter table t add e int not null").is_err());
+        drop(backend);
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select * from t where id = 1")[0].len(), 3);
+    }
+
+    #[test]
+    fn test_defaults_and_sequences_survive_reopening() {
+        let path = temp_path("disk-defaults");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (id serial, note text default 'it''s', at date default date '2024-01-31',
+                             n int default - -1);
+             insert into t (note) values ('a'), ('b');
+             delete from t where id = 2;",
+        )
+        .unwrap();
+        drop(backend);
+
+        // The sequence carries on after the deleted row instead of reusing it
+        let mut backend = open(&path);
+        run(&mut backend, "insert into t (n) values (5)").unwrap();
+        let rows = query(&mut backend, "select id, note, n from t order by id");
+        assert_eq!(rows[1], vec![Value::Int(3), text("it's"), Value::Int(5)]);
+        assert_eq!(query(&mut backend, "select at from t where id = 1")[0][0].to_string(), "2024-01-31");
+        assert_eq!(query(&mut backend, "select n from t where id = 1"), vec![vec![Value::Int(1)]]);
+    }
+
+    #[test]
+    fn test_foreign_keys_survive_reopening() {
+        let path = temp_path("disk-foreign-keys");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table users (id int primary key);
+             create table orders (id int unique, user_id int references users (id) on delete cascade);
+             create table notes (order_id int references orders (id) on delete set null, body text);
+             insert into users values (1), (2);
+             insert into orders values (10, 1), (11, 2);
+             insert into notes values (10, 'a'), (11, 'b');",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        let err = run(&mut backend, "insert into orders values (12, 3)").unwrap_err();
+        assert_eq!(err.message(), "Value 3 for column user_id is not in users.id");
+        run(&mut backend, "delete from users where id = 1").unwrap();
+        drop(backend);
+
+        // The cascade reached the file for both tables it changed
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select id from orders"), vec![vec![Value::Int(11)]]);
+        let notes = query(&mut backend, "select order_id, body from notes order by body");
+        assert_eq!(notes, vec![vec![Value::Null, text("a")], vec![Value::Int(11), text("b")]]);
+        let err = run(&mut backend, "drop table orders").unwrap_err();
+        assert_eq!(err.message(), "Cannot drop table orders, notes.order_id references it");
+    }
+
+    #[test]
+    fn test_views_survive_reopening() {
+        let path = temp_path("disk-views");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (a int, b text);
+             insert into t values (1, 'x'), (2, 'it''s'), (3, 'z');
+             create view odd as select a, b as label from t where a <> 2 order by a desc;
+             begin; create view gone as select a from t; rollback;",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select label from odd"), vec![vec![text("z")], vec![text("x")]]);
+        assert_eq!(run(&mut backend, "select * from gone").unwrap_err().message(), "Unknown table gone");
+        assert_eq!(run(&mut backend, "update odd set a = 1").unwrap_err().message(), "odd is a view, not a table");
+        run(&mut backend, "drop view odd").unwrap();
+        drop(backend);
+
+        let backend = open(&path);
+        assert!(!backend.has_view(&QualifiedName { parts: vec!["odd".to_string()], loc: Location::new(1, 1) }));
+    }
+
+    #[test]
+    fn test_row_codec_round_trips() {
+        let row = vec![
+            Value::Int(-7),
+            text("héllo"),
+            Value::Bool(true),
+            Value::Null,
+            Value::Real(-0.5),
+            Value::Date(-3),
+            Value::Time(1),
+            Value::Timestamp(-1),
+            text(""),
+            Value::Json("{\"a\":1}".to_string()),
+        ];
+        assert_eq!(decode_row(&encode_row(&row)).unwrap(), row);
+        assert!(decode_row(&encode_row(&row)[..5]).is_err());
+    }
+}
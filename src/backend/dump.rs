@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use super::catalog::SCHEMA;
+use super::{Backend, BackendError, ResultSet, Value, literal};
+use crate::lexer::{Location, TokenKind, lex};
+use crate::parser::{
+    ColumnConstraint, ColumnDef, Expr, QualifiedName, ReferentialAction, Select, SelectItem, Statement, TableRef,
+    TableSource, UnaryOp, parse,
+};
+
+/*
+    A dump is the SQL that builds a database again from nothing, the way
+    sqlite's .dump is: every table with its rows, then the indexes, then the
+    views, all in one transaction.
+
+        BEGIN;
+        CREATE TABLE users (id INT PRIMARY KEY, name TEXT NOT NULL);
+        INSERT INTO users VALUES (1, 'ann'), (2, 'bob');
+        CREATE INDEX by_name ON users (name);
+        CREATE VIEW names AS SELECT name FROM users;
+        COMMIT;
+
+    A table comes after the tables its foreign keys reference, and a view
+    after the views it reads, so running the dump never names something it
+    has not created yet. The rows of a table that references itself go in
+    a single insert, which is checked as a whole. An auto-increment column
+    gets its values written out, so its sequence carries on from the
+    largest of them.
+ */
+
+// Rows per INSERT statement
+const INSERT_BATCH: usize = 100;
+
+fn name(parts: &[&str]) -> QualifiedName {
+    QualifiedName { parts: parts.iter().map(|part| part.to_string()).collect(), loc: Location::new(1, 1) }
+}
+
+// Every row of a table, in the order the backend keeps them
+fn scan(backend: &dyn Backend, table: QualifiedName) -> Result<ResultSet, BackendError> {
+    backend.select(&Select {
+        distinct: false,
+        columns: vec![SelectItem::Wildcard],
+        from: TableRef { source: TableSource::Table(table), alias: None },
+        joins: Vec::new(),
+        filter: None,
+        group_by: Vec::new(),
+        having: None,
+        set_operations: Vec::new(),
+        order_by: Vec::new(),
+        limit: None,
+        offset: None,
+    })
+}
+
+fn text(value: &Value) -> &str {
+    match value {
+        Value::Text(s) => s,
+        _ => "",
+    }
+}
+
+// The name of a table from its schema and table name in the catalog
+fn table_name(schema: &Value, table: &Value) -> QualifiedName {
+    match text(schema) {
+        "public" => name(&[text(table)]),
+        schema => name(&[schema, text(table)]),
+    }
+}
+
+// A name as it has to be written to read back the same, in double quotes
+// unless it lexes as a plain identifier of that name
+fn identifier(name: &str) -> String {
+    let plain = lex(name).is_ok_and(|tokens| {
+        matches!(tokens.as_slice(), [token] if token.kind() == &TokenKind::Identifier && token.value() == name)
+    });
+    if plain { name.to_string() } else { format!("\"{}\"", name.replace('"', "\"\"")) }
+}
+
+fn qualified(name: &QualifiedName) -> String {
+    name.parts.iter().map(|part| identifier(part)).collect::<Vec<_>>().join(".")
+}
+
+fn column_sql(column: &ColumnDef) -> String {
+    let mut sql = format!("{} {}", identifier(&column.name), column.data_type.to_string().to_uppercase());
+    for constraint in &column.constraints {
+        match constraint {
+            ColumnConstraint::PrimaryKey => sql.push_str(" PRIMARY KEY"),
+            ColumnConstraint::Unique => sql.push_str(" UNIQUE"),
+            ColumnConstraint::NotNull => sql.push_str(" NOT NULL"),
+            ColumnConstraint::AutoIncrement => sql.push_str(" AUTOINCREMENT"),
+            ColumnConstraint::References(key) => {
+                write!(sql, " REFERENCES {} ({})", qualified(&key.table), identifier(&key.column)).unwrap();
+                match key.on_delete {
+                    ReferentialAction::Restrict => {}
+                    ReferentialAction::Cascade => sql.push_str(" ON DELETE CASCADE"),
+                    ReferentialAction::SetNull => sql.push_str(" ON DELETE SET NULL"),
+                }
+            }
+        }
+    }
+    // A default stops before the operators that print without parentheses
+    match &column.default {
+        Some(default @ (Expr::Unary { op: UnaryOp::Not, .. } | Expr::IsNull { .. } | Expr::InList { .. })) => {
+            write!(sql, " DEFAULT ({})", default).unwrap()
+        }
+        Some(default) => write!(sql, " DEFAULT {}", default).unwrap(),
+        None => {}
+    }
+    sql
+}
+
+// The tables and views a view reads, subqueries in FROM and the selects
+// of set operations included, none when its query does not parse
+fn view_reads(sql: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(Ok(Statement::Select(query))) = lex(sql).map(parse) {
+        reads(&query, &mut names);
+    }
+    names
+}
+
+fn reads(query: &Select, names: &mut Vec<String>) {
+    for table in query.tables() {
+        match &table.source {
+            TableSource::Table(name) => names.push(name.to_string()),
+            TableSource::Subquery(query) => reads(query, names),
+        }
+    }
+    for operation in &query.set_operations {
+        reads(&operation.query, names);
+    }
+}
+
+// Orders `items` so that each comes after the ones it depends on that are
+// among them. Items caught in a cycle keep their order at the end.
+fn ordered<T>(items: Vec<(String, T)>, depends: impl Fn(&T) -> Vec<String>) -> Vec<(String, T)> {
+    let names: HashSet<String> = items.iter().map(|(name, _)| name.clone()).collect();
+    let mut done = HashSet::new();
+    let mut pending = items;
+    let mut result = Vec::new();
+    loop {
+        let (ready, waiting): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(name, item)| {
+            depends(item).iter().all(|other| other == name || !names.contains(other) || done.contains(other))
+        });
+        if ready.is_empty() {
+            result.extend(waiting);
+            return result;
+        }
+        done.extend(ready.iter().map(|(name, _)| name.clone()));
+        result.extend(ready);
+        pending = waiting;
+    }
+}
+
+// The SQL that creates every table, index and view of the backend again
+pub fn dump(backend: &dyn Backend) -> Result<String, BackendError> {
+    let catalog = |table: &str| scan(backend, name(&[SCHEMA, table]));
+
+    let mut tables = Vec::new();
+    let mut views = Vec::new();
+    for row in catalog("tables")?.rows {
+        let name = table_name(&row[0], &row[1]);
+        if text(&row[2]) == "VIEW" {
+            views.push(name);
+        } else {
+            tables.push((name.to_string(), (backend.columns(&name)?, name)));
+        }
+    }
+    let tables = ordered(tables, |(columns, _)| {
+        columns.iter().filter_map(|column| Some(column.references()?.table.to_string())).collect()
+    });
+
+    let mut definitions = HashMap::new();
+    for row in catalog("views")?.rows {
+        definitions.insert(table_name(&row[0], &row[1]).to_string(), text(&row[2]).to_string());
+    }
+    let views = views
+        .into_iter()
+        .map(|name| {
+            let sql = definitions.remove(&name.to_string()).unwrap_or_default();
+            let read = view_reads(&sql);
+            (name.to_string(), (name, sql, read))
+        })
+        .collect();
+    let views = ordered(views, |(_, _, read)| read.clone());
+
+    let mut sql = String::from("BEGIN;\n");
+    for (_, (columns, name)) in &tables {
+        let columns: Vec<String> = columns.iter().map(column_sql).collect();
+        writeln!(sql, "CREATE TABLE {} ({});", qualified(name), columns.join(", ")).unwrap();
+    }
+    for (_, (columns, name)) in &tables {
+        let rows = scan(backend, name.clone())?.rows;
+        let references_itself = columns.iter().any(|column| column.references().is_some_and(|k| k.table == *name));
+        let batch = if references_itself { rows.len().max(1) } else { INSERT_BATCH };
+        for chunk in rows.chunks(batch) {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|row| {
+                    let values: Vec<String> = row.iter().map(|value| literal(value.clone()).to_string()).collect();
+                    format!("({})", values.join(", "))
+                })
+                .collect();
+            writeln!(sql, "INSERT INTO {} VALUES {};", qualified(name), values.join(", ")).unwrap();
+        }
+    }
+    for row in catalog("indexes")?.rows {
+        let table = qualified(&table_name(&row[0], &row[1]));
+        let (index, column) = (identifier(text(&row[2])), identifier(text(&row[3])));
+        writeln!(sql, "CREATE INDEX {} ON {} ({});", index, table, column).unwrap();
+    }
+    for (_, (name, query, _)) in &views {
+        writeln!(sql, "CREATE VIEW {} AS {};", qualified(name), query).unwrap();
+    }
+    sql.push_str("COMMIT;\n");
+    Ok(sql)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Engine, MemoryBackend, QueryResult, execute, temp_path};
+    use crate::parser::split_statements;
+
+    fn run(backend: &mut dyn Backend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    const SETUP: &str = "
+        create table users (id int primary key, name varchar(20) not null default 'it''s', \"Odd Name\" boolean);
+        create table orders (id serial primary key, user_id int references users (id) on delete cascade, total real);
+        create table app.tree (id int unique, parent int references app.tree (id) on delete set null, at date);
+        create index by_user on orders (user_id);
+        create view big as select o.id, u.name from orders o join users u on o.user_id = u.id where o.total > 10;
+        create view abig as select * from big;
+        insert into users values (1, 'ann', true), (2, 'bob', null);
+        insert into orders (user_id, total) values (1, 5.0), (2, 20.5), (2, null);
+        insert into app.tree values (2, 1, date '2024-02-29'), (1, null, null);";
+
+    #[test]
+    fn test_dump() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, SETUP).unwrap();
+        assert_eq!(
+            dump(&backend).unwrap(),
+            "BEGIN;
+CREATE TABLE app.tree (id INT UNIQUE, parent INT REFERENCES app.tree (id) ON DELETE SET NULL, at DATE);
+CREATE TABLE users (id INT PRIMARY KEY, name VARCHAR(20) NOT NULL DEFAULT 'it''s', \"Odd Name\" BOOLEAN);
+CREATE TABLE orders (id INT AUTOINCREMENT PRIMARY KEY, user_id INT REFERENCES users (id) ON DELETE CASCADE, total REAL);
+INSERT INTO app.tree VALUES (2, 1, DATE '2024-02-29'), (1, NULL, NULL);
+INSERT INTO users VALUES (1, 'ann', true), (2, 'bob', NULL);
+INSERT INTO orders VALUES (1, 1, 5.0), (2, 2, 20.5), (3, 2, NULL);
+CREATE INDEX by_user ON orders (user_id);
+CREATE VIEW big AS SELECT o.id, u.name FROM orders AS o JOIN users AS u ON (o.user_id = u.id) WHERE (o.total > 10);
+CREATE VIEW abig AS SELECT * FROM big;
+COMMIT;
+"
+        );
+    }
+
+    #[test]
+    fn test_dump_reads_back() {
+        let mut backend = MemoryBackend::new();
+        run(&mut backend, SETUP).unwrap();
+        let sql = dump(&backend).unwrap();
+
+        let mut copy = Engine::disk(temp_path("dump")).open().unwrap();
+        run(&mut *copy, &sql).unwrap();
+        assert_eq!(dump(&*copy).unwrap(), sql);
+
+        // The sequence goes on from the rows written out
+        run(&mut *copy, "insert into orders (total) values (1.0)").unwrap();
+        let QueryResult::Rows(result) = run(&mut *copy, "select max(id) from orders").unwrap() else {
+            panic!("Expected rows");
+        };
+        assert_eq!(result.rows, vec![vec![Value::Int(4)]]);
+
+        let empty = MemoryBackend::new();
+        assert_eq!(dump(&empty).unwrap(), "BEGIN;\nCOMMIT;\n");
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use super::log::LogStorage;
+use super::{Backend, MemoryBackend, PagerStorage, StorageBackend};
+
+/*
+    Storage engines by name, so that the engine a database keeps its tables
+    in is picked when it is opened, see Database::open_with. Three are
+    built in:
+
+        memory   tables live as long as the database, the MemoryBackend
+        disk     pages of a single file, a PagerStorage under a StorageBackend
+        log      an append-only log file, a LogStorage under a StorageBackend
+
+    register_engine adds another, or replaces one of these. An engine opens
+    a backend given the path of an Engine, or None for one without a path.
+    One written against the Storage trait only needs to hand its storage to
+    StorageBackend::new:
+
+        register_engine("mine", |path| {
+            Ok(Box::new(StorageBackend::new(Box::new(MyStorage::open(path)?))?))
+        });
+ */
+
+// Opens a backend on the tables at a path, or on tables of its own
+type Open = dyn Fn(Option<&Path>) -> io::Result<Box<dyn Backend + Send>> + Send + Sync;
+
+// The engine to open a database with, and where its tables are kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct Engine {
+    name: String,
+    path: Option<PathBuf>,
+}
+
+impl Engine {
+    pub fn new(name: &str, path: Option<&Path>) -> Engine {
+        Engine { name: name.to_string(), path: path.map(Path::to_path_buf) }
+    }
+
+    pub fn memory() -> Engine {
+        Engine::new("memory", None)
+    }
+
+    pub fn disk(path: impl AsRef<Path>) -> Engine {
+        Engine::new("disk", Some(path.as_ref()))
+    }
+
+    pub fn log(path: impl AsRef<Path>) -> Engine {
+        Engine::new("log", Some(path.as_ref()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn Backend + Send>> {
+        let open = engines().read().unwrap_or_else(PoisonError::into_inner).get(&self.name).cloned();
+        let open = open.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Unknown storage engine {}", self.name))
+        })?;
+        open(self.path())
+    }
+}
+
+fn engines() -> &'static RwLock<HashMap<String, Arc<Open>>> {
+    static ENGINES: OnceLock<RwLock<HashMap<String, Arc<Open>>>> = OnceLock::new();
+    ENGINES.get_or_init(|| {
+        let memory: Arc<Open> = Arc::new(|path| match path {
+            None => Ok(Box::new(MemoryBackend::new())),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "The memory engine keeps no file")),
+        });
+        let disk: Arc<Open> = Arc::new(|path| {
+            let storage = PagerStorage::open(needs_path("disk", path)?)?;
+            Ok(Box::new(StorageBackend::new(Box::new(storage))?))
+        });
+        let log: Arc<Open> = Arc::new(|path| {
+            let storage = LogStorage::open(needs_path("log", path)?)?;
+            Ok(Box::new(StorageBackend::new(Box::new(storage))?))
+        });
+        let engines = [("memory", memory), ("disk", disk), ("log", log)];
+        RwLock::new(engines.into_iter().map(|(name, open)| (name.to_string(), open)).collect())
+    })
+}
+
+fn needs_path<'p>(engine: &str, path: Option<&'p Path>) -> io::Result<&'p Path> {
+    path.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("The {} engine keeps its tables in a file", engine))
+    })
+}
+
+// Makes `open` the engine called `name`, for every database opened after
+pub fn register_engine<F>(name: &str, open: F)
+where
+    F: Fn(Option<&Path>) -> io::Result<Box<dyn Backend + Send>> + Send + Sync + 'static,
+{
+    engines().write().unwrap_or_else(PoisonError::into_inner).insert(name.to_string(), Arc::new(open));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pager::temp_path;
+    use super::*;
+
+    #[test]
+    fn test_engines_by_name() {
+        assert!(Engine::memory().open().is_ok());
+        assert!(Engine::log(temp_path("engine-log")).open().is_ok());
+        assert!(Engine::disk(temp_path("engine-disk")).open().is_ok());
+
+        let err = Engine::new("nope", None).open().err().unwrap();
+        assert_eq!(err.to_string(), "Unknown storage engine nope");
+        let err = Engine::new("log", None).open().err().unwrap();
+        assert_eq!(err.to_string(), "The log engine keeps its tables in a file");
+
+        register_engine("test-memory", |_| Ok(Box::new(MemoryBackend::new())));
+        assert!(Engine::new("test-memory", Some(Path::new("ignored"))).open().is_ok());
+    }
+}
@@ -0,0 +1,315 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use super::index::Index;
+use super::{BackendError, ConstraintViolation, Value, unknown_column, unknown_table};
+use crate::parser::{ColumnConstraint, ColumnDef, DataType, ForeignKey, QualifiedName, ReferentialAction};
+
+/*
+    Foreign keys, `user_id int references users (id)`. Every value of the
+    column other than null has to be in the column it references, which is
+    unique, so it names exactly one row.
+
+    Inserting or updating a row whose key names no row fails. Deleting a row
+    that is still named does what the key's ON DELETE says: RESTRICT, the
+    default, fails, CASCADE deletes the rows that name it, which may go on
+    to delete rows naming those, and SET NULL sets their key to null. An
+    update that changes a referenced value is always refused. A table may
+    reference itself, and rows deleted by the same statement count as gone,
+    so a whole tree of rows can be deleted at once.
+
+    The checks need to see other tables than the one a statement works on,
+    backends hand them over as a Schema. A key is checked by looking its
+    value up in the index every unique column has, so inserting into a
+    table reads none of the rows of the table it references.
+ */
+
+type ReadRows<'a> = Box<dyn Fn(&str) -> Result<Cow<'a, [Vec<Value>]>, BackendError> + 'a>;
+
+// A table's name, columns and indexes
+type SchemaTable<'a> = (&'a str, &'a [ColumnDef], &'a [Index]);
+
+// A backend's tables as a statement sees them
+pub struct Schema<'a> {
+    // Every table, by name so that errors come out the same whatever order
+    // the backend keeps its tables in
+    tables: Vec<SchemaTable<'a>>,
+    // Reads the rows of one of the tables
+    rows: ReadRows<'a>,
+}
+
+impl<'a> Schema<'a> {
+    pub fn new(tables: impl Iterator<Item = SchemaTable<'a>>, rows: ReadRows<'a>) -> Schema<'a> {
+        let mut tables: Vec<_> = tables.collect();
+        tables.sort_by_key(|(name, _, _)| *name);
+        Schema { tables, rows }
+    }
+
+    fn columns(&self, table: &str) -> Option<&[ColumnDef]> {
+        self.tables.iter().find(|(name, _, _)| *name == table).map(|(_, columns, _)| *columns)
+    }
+
+    // The key of a unique column of a table
+    fn key(&self, table: &str, column: usize) -> Option<&Index> {
+        let (_, _, indexes) = self.tables.iter().find(|(name, _, _)| *name == table)?;
+        indexes.iter().find(|index| index.key && index.column == column)
+    }
+
+    // Every column that references `table`, with its table and position
+    fn referencing<'s>(&'s self, table: &'s str) -> impl Iterator<Item = (&'s str, usize, &'s ForeignKey)> + 's {
+        self.tables.iter().flat_map(move |(name, columns, _)| {
+            columns.iter().enumerate().filter_map(move |(i, column)| {
+                let key = column.references().filter(|key| key.table.to_string() == table)?;
+                Some((*name, i, key))
+            })
+        })
+    }
+
+    // A column of a table other than `table` itself that references it, or
+    // of any table that references its `column` when one is given
+    pub fn referenced_by(&self, table: &str, column: Option<&str>) -> Option<String> {
+        self.referencing(table)
+            .find(|(name, _, key)| match column {
+                Some(column) => key.column == column,
+                None => *name != table,
+            })
+            .map(|(name, i, _)| format!("{}.{}", name, self.columns(name).unwrap()[i].name))
+    }
+}
+
+fn violation(table: &str, column: &ColumnDef, key: &ForeignKey) -> ConstraintViolation {
+    ConstraintViolation {
+        constraint: ColumnConstraint::References(key.clone()),
+        table: table.to_string(),
+        column: column.name.clone(),
+    }
+}
+
+// Whether values of the two types can ever be equal
+fn comparable(left: DataType, right: DataType) -> bool {
+    let family = |data_type| match data_type {
+        DataType::Int | DataType::BigInt => DataType::Int,
+        DataType::Varchar(_) => DataType::Text,
+        data_type => data_type,
+    };
+    family(left) == family(right)
+}
+
+// Checks the foreign keys of a table about to be created or altered: each
+// references a unique column of a table that exists, or of the table itself
+pub fn check_definitions(table: &QualifiedName, columns: &[ColumnDef], schema: &Schema) -> Result<(), BackendError> {
+    for column in columns {
+        let Some(key) = column.references() else {
+            continue;
+        };
+        let referenced = if key.table == *table {
+            columns
+        } else {
+            schema
+                .columns(&key.table.to_string())
+                .ok_or_else(|| unknown_table(&key.table))?
+        };
+        let target = referenced
+            .iter()
+            .find(|c| c.name == key.column)
+            .ok_or_else(|| unknown_column(&format!("{}.{}", key.table, key.column), key.table.loc))?;
+
+        let message = if !target.is_unique() {
+            format!("Column {}.{} is not unique, a foreign key can't reference it", key.table, key.column)
+        } else if !comparable(column.data_type, target.data_type) {
+            format!(
+                "Foreign key column {} is {} but {}.{} is {}",
+                column.name, column.data_type, key.table, key.column, target.data_type
+            )
+        } else if key.on_delete == ReferentialAction::SetNull && column.is_not_null() {
+            format!("Column {} is not null, ON DELETE SET NULL can't clear it", column.name)
+        } else {
+            continue;
+        };
+        return Err(BackendError::new(message, column.loc));
+    }
+    Ok(())
+}
+
+/*
+    Checks that every key the `checked` rows of a table hold names a row.
+    `own` is the table's rows as the statement leaves them, which a key on
+    the table itself is checked against, or None when the statement only
+    adds the checked rows to the ones the table has.
+ */
+pub fn check_references(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    checked: &[&[Value]],
+    own: Option<&[&[Value]]>,
+    schema: &Schema,
+) -> Result<(), BackendError> {
+    if checked.is_empty() {
+        return Ok(());
+    }
+    for (i, column) in columns.iter().enumerate() {
+        let Some(key) = column.references() else {
+            continue;
+        };
+        let parent = key.table.to_string();
+        let itself = key.table == *table;
+        let parent_columns = if itself { columns } else { schema.columns(&parent).unwrap_or_default() };
+        let Some(position) = parent_columns.iter().position(|c| c.name == key.column) else {
+            continue;
+        };
+
+        // The rows the table had are only looked up when they are still
+        // there, and the rows the statement writes are all at hand
+        let (rows, stored) = match own {
+            Some(rows) if itself => (rows, None),
+            None if itself => (checked, schema.key(&parent, position)),
+            _ => (&[][..], schema.key(&parent, position)),
+        };
+        let present: HashSet<&Value> = rows.iter().map(|row| &row[position]).collect();
+        let named = |value: &Value| present.contains(value) || stored.is_some_and(|index| index.contains(value));
+        if let Some(row) = checked.iter().find(|row| row[i] != Value::Null && !named(&row[i])) {
+            return Err(BackendError::constraint(
+                violation(&table.to_string(), column, key),
+                format!("Value {} for column {} is not in {}.{}", row[i], column.name, key.table, key.column),
+                table.loc,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+    Checks an update that took `before` rows of a table to `after`, the
+    whole table as the update leaves it, against the keys that reference
+    the table: a value the update took out of a referenced column must not
+    be named by any row.
+ */
+pub fn check_update(
+    table: &QualifiedName,
+    columns: &[ColumnDef],
+    before: &[&[Value]],
+    after: &[&[Value]],
+    schema: &Schema,
+) -> Result<(), BackendError> {
+    let name = table.to_string();
+    for (child, i, key) in schema.referencing(&name) {
+        let Some(position) = columns.iter().position(|c| c.name == key.column) else {
+            continue;
+        };
+        let remaining: HashSet<&Value> = after.iter().map(|row| &row[position]).collect();
+        let gone: HashSet<&Value> =
+            before.iter().map(|row| &row[position]).filter(|value| !remaining.contains(value)).collect();
+        if gone.is_empty() {
+            continue;
+        }
+
+        let loaded;
+        let rows: Vec<&[Value]> = if child == name {
+            after.to_vec()
+        } else {
+            loaded = (schema.rows)(child)?;
+            loaded.iter().map(Vec::as_slice).collect()
+        };
+        if let Some(row) = rows.iter().find(|row| gone.contains(&row[i])) {
+            let column = &schema.columns(child).unwrap()[i];
+            return Err(BackendError::constraint(
+                violation(child, column, key),
+                format!(
+                    "Cannot change {}.{} from {}, {}.{} references it",
+                    name, key.column, row[i], child, column.name
+                ),
+                table.loc,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+    Follows the ON DELETE actions of the keys referencing a table that a
+    delete is about to take the rows `keep` says no to from. Returns the
+    rows of every table that ends up changed, the table itself included,
+    or nothing when no key references the table.
+ */
+pub fn on_delete(
+    table: &QualifiedName,
+    rows: &[Vec<Value>],
+    keep: &[bool],
+    schema: &Schema,
+) -> Result<HashMap<String, Vec<Vec<Value>>>, BackendError> {
+    let name = table.to_string();
+    if schema.referencing(&name).next().is_none() {
+        return Ok(HashMap::new());
+    }
+
+    let (kept, removed): (Vec<_>, Vec<_>) = rows.iter().cloned().zip(keep).partition(|(_, keep)| **keep);
+    let mut changed = HashMap::from([(name.clone(), kept.into_iter().map(|(row, _)| row).collect())]);
+    let mut pending: Vec<(String, Vec<Vec<Value>>)> = vec![(name, removed.into_iter().map(|(row, _)| row).collect())];
+
+    while let Some((parent, removed)) = pending.pop() {
+        let parent_columns = schema.columns(&parent).unwrap_or_default();
+        for (child, i, key) in schema.referencing(&parent) {
+            let Some(position) = parent_columns.iter().position(|c| c.name == key.column) else {
+                continue;
+            };
+            let gone: HashSet<&Value> =
+                removed.iter().map(|row| &row[position]).filter(|value| **value != Value::Null).collect();
+            if gone.is_empty() {
+                continue;
+            }
+
+            // A table is only copied once a row of it has to change
+            if !changed.contains_key(child) {
+                let rows = (schema.rows)(child)?;
+                if !rows.iter().any(|row| gone.contains(&row[i])) {
+                    continue;
+                }
+                changed.insert(child.to_string(), rows.into_owned());
+            }
+            let rows = changed.get_mut(child).unwrap();
+            let column = &schema.columns(child).unwrap()[i];
+            match key.on_delete {
+                ReferentialAction::Restrict => {
+                    if let Some(row) = rows.iter().find(|row| gone.contains(&row[i])) {
+                        return Err(BackendError::constraint(
+                            violation(child, column, key),
+                            format!(
+                                "Cannot delete {} from {}.{}, {}.{} references it",
+                                row[i], parent, key.column, child, column.name
+                            ),
+                            table.loc,
+                        ));
+                    }
+                }
+                ReferentialAction::SetNull => {
+                    rows.iter_mut().filter(|row| gone.contains(&row[i])).for_each(|row| row[i] = Value::Null);
+                }
+                ReferentialAction::Cascade => {
+                    let (deleted, left): (Vec<_>, Vec<_>) =
+                        std::mem::take(rows).into_iter().partition(|row| gone.contains(&row[i]));
+                    *rows = left;
+                    if !deleted.is_empty() {
+                        pending.push((child.to_string(), deleted));
+                    }
+                }
+            }
+        }
+    }
+    Ok(changed)
+}
+
+// The foreign keys of `columns` that reference `table`'s column `from`,
+// renamed along with it. Returns whether any was.
+pub fn rename(columns: &mut [ColumnDef], table: &QualifiedName, from: &str, to: &str) -> bool {
+    let mut renamed = false;
+    for constraint in columns.iter_mut().flat_map(|column| &mut column.constraints) {
+        if let ColumnConstraint::References(key) = constraint
+            && key.table == *table
+            && key.column == from
+        {
+            key.column = to.to_string();
+            renamed = true;
+        }
+    }
+    renamed
+}
@@ -0,0 +1,637 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{BackendError, ErrorKind, Scope, Value, ValueError, aggregate, cast, parse_numeric, visit};
+use crate::lexer::Location;
+use crate::parser::{BinaryOp, DataType, Expr, FunctionArgs, QualifiedName, UnaryOp};
+
+/*
+    Scalar functions, the ones computed from the arguments of a single call
+    as opposed to the aggregates, which see a whole group of rows:
+
+        length(text)                  the number of characters
+        upper(text), lower(text)
+        substr(text, start[, count])  characters from start on, counted from 1
+        abs(number)
+        round(number[, digits])       halves round away from zero
+        coalesce(value, ...)          the first argument that is not null
+        now()                         the current timestamp, in UTC
+
+    A null argument makes the result null, except for coalesce. Every call
+    is checked when its select is planned: the function has to exist, take
+    that many arguments and take arguments of their types, as far as those
+    are known before any row is read.
+ */
+
+// What an argument can be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Any,
+    Text,
+    Number,
+    Int,
+    // An argument of a registered function, which also takes values that
+    // convert to the type without a cast
+    Type(DataType),
+}
+
+impl Kind {
+    fn accepts(self, data_type: DataType) -> bool {
+        match self {
+            Kind::Type(DataType::Text | DataType::Varchar(_)) => Kind::Text.accepts(data_type),
+            Kind::Type(DataType::Int | DataType::BigInt) => Kind::Int.accepts(data_type),
+            Kind::Type(to) => to == data_type || cast::converts(data_type, to),
+            Kind::Any => true,
+            Kind::Text => matches!(data_type, DataType::Text | DataType::Varchar(_)),
+            Kind::Number => matches!(data_type, DataType::Int | DataType::BigInt | DataType::Real),
+            Kind::Int => matches!(data_type, DataType::Int | DataType::BigInt),
+        }
+    }
+}
+
+// The type of a call's result, when it is known from its arguments' types
+#[derive(Debug, Clone, Copy)]
+enum Returns {
+    Type(DataType),
+    // The type of the first argument whose type is known
+    Argument,
+}
+
+struct Function {
+    name: &'static str,
+    // The last one repeats for a variadic function
+    params: &'static [Kind],
+    required: usize,
+    variadic: bool,
+    returns: Returns,
+    call: fn(&[Value]) -> Result<Value, ValueError>,
+}
+
+const FUNCTIONS: [Function; 8] = [
+    Function {
+        name: "length",
+        params: &[Kind::Text],
+        required: 1,
+        variadic: false,
+        returns: Returns::Type(DataType::Int),
+        call: length,
+    },
+    Function {
+        name: "upper",
+        params: &[Kind::Text],
+        required: 1,
+        variadic: false,
+        returns: Returns::Type(DataType::Text),
+        call: upper,
+    },
+    Function {
+        name: "lower",
+        params: &[Kind::Text],
+        required: 1,
+        variadic: false,
+        returns: Returns::Type(DataType::Text),
+        call: lower,
+    },
+    Function {
+        name: "substr",
+        params: &[Kind::Text, Kind::Int, Kind::Int],
+        required: 2,
+        variadic: false,
+        returns: Returns::Type(DataType::Text),
+        call: substr,
+    },
+    Function {
+        name: "abs",
+        params: &[Kind::Number],
+        required: 1,
+        variadic: false,
+        returns: Returns::Argument,
+        call: abs,
+    },
+    Function {
+        name: "round",
+        params: &[Kind::Number, Kind::Int],
+        required: 1,
+        variadic: false,
+        returns: Returns::Argument,
+        call: round,
+    },
+    Function {
+        name: "coalesce",
+        params: &[Kind::Any],
+        required: 1,
+        variadic: true,
+        returns: Returns::Argument,
+        call: coalesce,
+    },
+    Function {
+        name: "now",
+        params: &[],
+        required: 0,
+        variadic: false,
+        returns: Returns::Type(DataType::Timestamp),
+        call: now,
+    },
+];
+
+/*
+    Functions registered by the program embedding the database, see
+    Database::register_function. They are kept by the Database and handed
+    to the statements it runs through `with_user_functions`, for as long as
+    one runs. A registered function comes before a built-in one of the same
+    name, so it can replace one.
+ */
+
+pub type Callback = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+#[derive(Clone)]
+struct UserFunction {
+    params: Vec<DataType>,
+    returns: DataType,
+    call: Arc<Callback>,
+}
+
+// Registered functions by their name in lower case
+#[derive(Clone, Default)]
+pub struct UserFunctions {
+    functions: HashMap<String, UserFunction>,
+}
+
+impl UserFunctions {
+    // Replaces a function registered under the same name. An aggregate
+    // cannot be replaced, a call to one never gets here.
+    pub fn register(
+        &mut self,
+        name: &str,
+        params: &[DataType],
+        returns: DataType,
+        call: Arc<Callback>,
+    ) -> Result<(), String> {
+        let name = name.to_lowercase();
+        if aggregate::AGGREGATES.contains(&name.as_str()) {
+            return Err(format!("Cannot replace aggregate function {}", name));
+        }
+        self.functions.insert(name, UserFunction { params: params.to_vec(), returns, call });
+        Ok(())
+    }
+}
+
+thread_local! {
+    static USER_FUNCTIONS: RefCell<Option<Arc<UserFunctions>>> = const { RefCell::new(None) };
+}
+
+// Runs `f` with the functions callable from SQL, the ones it had before are
+// back once it returns or panics
+pub fn with_user_functions<T>(functions: &Arc<UserFunctions>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<UserFunctions>>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            USER_FUNCTIONS.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+    let _restore = Restore(USER_FUNCTIONS.with(|current| current.replace(Some(functions.clone()))));
+    f()
+}
+
+// What a call resolves to
+enum Callee {
+    Builtin(&'static Function),
+    User(UserFunction),
+}
+
+fn lookup(name: &QualifiedName) -> Result<Callee, BackendError> {
+    let unknown = || {
+        BackendError::of_kind(ErrorKind::UndefinedFunction, format!("Unknown function {}", name), name.loc)
+    };
+    let [part] = name.parts.as_slice() else {
+        return Err(unknown());
+    };
+    let registered = |current: &RefCell<Option<Arc<UserFunctions>>>| {
+        Some(current.borrow().as_ref()?.functions.get(&part.to_lowercase())?.clone())
+    };
+    if let Some(function) = USER_FUNCTIONS.with(registered) {
+        return Ok(Callee::User(function));
+    }
+    FUNCTIONS.iter().find(|function| function.name.eq_ignore_ascii_case(part)).map(Callee::Builtin).ok_or_else(unknown)
+}
+
+impl Callee {
+    // The kind of argument `i`, None past the last one
+    fn param(&self, i: usize) -> Option<Kind> {
+        match self {
+            Callee::Builtin(function) if function.variadic && i >= function.params.len() => {
+                function.params.last().copied()
+            }
+            Callee::Builtin(function) => function.params.get(i).copied(),
+            Callee::User(function) => function.params.get(i).map(|data_type| Kind::Type(*data_type)),
+        }
+    }
+
+    fn variadic(&self) -> bool {
+        matches!(self, Callee::Builtin(function) if function.variadic)
+    }
+
+    fn returns(&self) -> Returns {
+        match self {
+            Callee::Builtin(function) => function.returns,
+            Callee::User(function) => Returns::Type(function.returns),
+        }
+    }
+
+    fn check_arity(&self, name: &QualifiedName, count: usize) -> Result<(), BackendError> {
+        let (required, max) = match self {
+            Callee::Builtin(function) if function.variadic => (function.required, usize::MAX),
+            Callee::Builtin(function) => (function.required, function.params.len()),
+            Callee::User(function) => (function.params.len(), function.params.len()),
+        };
+        if (required..=max).contains(&count) {
+            return Ok(());
+        }
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        let takes = match max {
+            usize::MAX => format!("at least {} {}", required, plural(required)),
+            max if max == required => format!("{} {}", max, plural(max)),
+            max if max == required + 1 => format!("{} or {} arguments", required, max),
+            max => format!("{} to {} arguments", required, max),
+        };
+        let message = format!("Function {} takes {}, got {}", name, takes, count);
+        Err(BackendError::of_kind(ErrorKind::UndefinedFunction, message, name.loc))
+    }
+}
+
+fn mismatch(name: &QualifiedName, type_name: &str) -> BackendError {
+    let message = format!("Cannot apply {} to {}", name.parts[0].to_lowercase(), type_name);
+    BackendError::of_kind(ErrorKind::UndefinedFunction, message, name.loc)
+}
+
+// Converts a value to a type the way storing it in a column would, None
+// when it is of another type
+fn convert(value: Value, data_type: DataType) -> Option<Value> {
+    match cast::type_of(&value) {
+        Some(from) if cast::converts(from, data_type) => cast::cast(value, data_type).ok(),
+        Some(from) if !Kind::Type(data_type).accepts(from) => None,
+        _ => Some(value),
+    }
+}
+
+// Calls a scalar function on the values of its arguments
+pub fn call(name: &QualifiedName, args: &[Value], loc: Location) -> Result<Value, BackendError> {
+    let callee = lookup(name)?;
+    callee.check_arity(name, args.len())?;
+    for (i, arg) in args.iter().enumerate() {
+        let kind = callee.param(i).expect("the arity was checked");
+        if let Some(data_type) = cast::type_of(arg)
+            && !kind.accepts(data_type)
+        {
+            return Err(mismatch(name, arg.type_name()));
+        }
+    }
+
+    let function = match callee {
+        // Coalesce is the one function that is there to look past nulls
+        Callee::Builtin(function) if function.name != "coalesce" && args.contains(&Value::Null) => {
+            return Ok(Value::Null);
+        }
+        Callee::Builtin(function) => return (function.call)(args).map_err(|err| err.at(loc)),
+        Callee::User(function) => function,
+    };
+    // A registered function gets nulls too, and its arguments as the types
+    // it asked for
+    let args: Vec<Value> = args
+        .iter()
+        .zip(&function.params)
+        .map(|(arg, data_type)| convert(arg.clone(), *data_type).ok_or_else(|| mismatch(name, arg.type_name())))
+        .collect::<Result<_, _>>()?;
+    let value = (function.call)(&args).map_err(|message| BackendError::new(message, loc))?;
+    let type_name = value.type_name();
+    convert(value, function.returns).ok_or_else(|| {
+        let message = format!("Function {} returned {}, expected {}", name, type_name, function.returns);
+        BackendError::of_kind(ErrorKind::DatatypeMismatch, message, name.loc)
+    })
+}
+
+/*
+    The type an expression has whatever row it is evaluated against, None
+    when that is only known once it runs. `typed` says for each table of the
+    scope whether its column types are known, the columns of a subquery are
+    not.
+ */
+fn static_type(expr: &Expr, scope: &Scope, typed: &[bool]) -> Option<DataType> {
+    let type_of = |expr: &Expr| static_type(expr, scope, typed);
+    match expr {
+        Expr::NumericLiteral(n) => parse_numeric(n).as_ref().and_then(cast::type_of),
+        Expr::StringLiteral(_) => Some(DataType::Text),
+        Expr::BoolLiteral(_) | Expr::IsNull { .. } | Expr::InList { .. } | Expr::InSubquery { .. } => {
+            Some(DataType::Boolean)
+        }
+        Expr::NullLiteral | Expr::Parameter(_) => None,
+        Expr::TypedLiteral { data_type, .. } | Expr::Cast { data_type, .. } => Some(*data_type),
+        Expr::Column(name) => {
+            let index = scope.resolve(name).ok()?;
+            let table = scope.table_of(name).ok()?;
+            let offset: usize = scope.tables[..table].iter().map(|(_, columns)| columns.len()).sum();
+            typed[table].then(|| scope.tables[table].1[index - offset].data_type)
+        }
+        Expr::Unary { op: UnaryOp::Not, .. } => Some(DataType::Boolean),
+        Expr::Unary { op: UnaryOp::Neg, expr } => type_of(expr),
+        Expr::Binary { left, op, right } => binary_type(*op, type_of(left), type_of(right)),
+        Expr::Chain { first, rest } => {
+            rest.iter().fold(type_of(first), |left, (op, right)| binary_type(*op, left, type_of(right)))
+        }
+        Expr::Function { name, .. } if aggregate::is_aggregate(name) => None,
+        Expr::Function { name, args } => {
+            match (lookup(name).ok()?.returns(), args) {
+                (Returns::Type(data_type), _) => Some(data_type),
+                (Returns::Argument, FunctionArgs::List(args)) => args.iter().find_map(type_of),
+                (Returns::Argument, FunctionArgs::Wildcard) => None,
+            }
+        }
+    }
+}
+
+// The type of `left op right`, given the types of its operands
+fn binary_type(op: BinaryOp, left: Option<DataType>, right: Option<DataType>) -> Option<DataType> {
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => match (left?, right?) {
+            (DataType::Real, _) | (_, DataType::Real) => Some(DataType::Real),
+            _ => Some(DataType::Int),
+        },
+        BinaryOp::JsonGet => Some(DataType::Json),
+        BinaryOp::JsonGetText => Some(DataType::Text),
+        _ => Some(DataType::Boolean),
+    }
+}
+
+// Whether values of the two types can stand in for each other, as the
+// arguments of coalesce have to
+fn compatible(left: DataType, right: DataType) -> bool {
+    let text = |data_type| Kind::Text.accepts(data_type);
+    let number = |data_type| Kind::Number.accepts(data_type);
+    left == right || (text(left) && text(right)) || (number(left) && number(right))
+}
+
+// Checks every scalar call in an expression before it runs, see above
+pub fn check(expr: &Expr, scope: &Scope, typed: &[bool]) -> Result<(), BackendError> {
+    let mut result = Ok(());
+    visit(expr, &mut |expr| {
+        if result.is_err() {
+            return;
+        }
+        let Expr::Function { name, args } = expr else {
+            return;
+        };
+        if aggregate::is_aggregate(name) {
+            return;
+        }
+        result = check_call(name, args, scope, typed);
+    });
+    result
+}
+
+fn check_call(name: &QualifiedName, args: &FunctionArgs, scope: &Scope, typed: &[bool]) -> Result<(), BackendError> {
+    let callee = lookup(name)?;
+    let FunctionArgs::List(args) = args else {
+        return Err(BackendError::new(format!("Only count accepts *, not {}", name), name.loc));
+    };
+    callee.check_arity(name, args.len())?;
+
+    let mut first: Option<DataType> = None;
+    for (i, arg) in args.iter().enumerate() {
+        let Some(data_type) = static_type(arg, scope, typed) else {
+            continue;
+        };
+        if !callee.param(i).expect("the arity was checked").accepts(data_type) {
+            return Err(mismatch(name, &data_type.to_string()));
+        }
+        match first {
+            Some(first) if callee.variadic() && !compatible(first, data_type) => {
+                return Err(BackendError::of_kind(
+                    ErrorKind::UndefinedFunction,
+                    format!("Cannot apply {} to {} and {}", name.parts[0].to_lowercase(), first, data_type),
+                    name.loc,
+                ));
+            }
+            Some(_) => {}
+            None => first = Some(data_type),
+        }
+    }
+    Ok(())
+}
+
+fn text(args: &[Value], i: usize) -> &str {
+    match &args[i] {
+        Value::Text(s) => s,
+        _ => unreachable!("the argument types were checked"),
+    }
+}
+
+fn int(args: &[Value], i: usize) -> i64 {
+    match args[i] {
+        Value::Int(n) => n,
+        _ => unreachable!("the argument types were checked"),
+    }
+}
+
+fn length(args: &[Value]) -> Result<Value, ValueError> {
+    Ok(Value::Int(text(args, 0).chars().count() as i64))
+}
+
+fn upper(args: &[Value]) -> Result<Value, ValueError> {
+    Ok(Value::Text(text(args, 0).to_uppercase()))
+}
+
+fn lower(args: &[Value]) -> Result<Value, ValueError> {
+    Ok(Value::Text(text(args, 0).to_lowercase()))
+}
+
+// As in Postgres a start before the first character still counts towards
+// `count`, so substr('abc', 0, 2) is "a"
+fn substr(args: &[Value]) -> Result<Value, ValueError> {
+    let start = int(args, 1);
+    let end = match args.get(2) {
+        Some(_) if int(args, 2) < 0 => {
+            return Err(ValueError::new(ErrorKind::Other, "Negative substring length not allowed"));
+        }
+        Some(_) => start.saturating_add(int(args, 2)),
+        None => i64::MAX,
+    };
+    let chars = text(args, 0).chars().enumerate().map(|(i, c)| (i as i64 + 1, c));
+    Ok(Value::Text(chars.filter(|(position, _)| *position >= start && *position < end).map(|(_, c)| c).collect()))
+}
+
+fn out_of_range() -> ValueError {
+    ValueError::new(ErrorKind::OutOfRange, "Integer out of range")
+}
+
+fn abs(args: &[Value]) -> Result<Value, ValueError> {
+    match args[0] {
+        Value::Int(i) => i.checked_abs().map(Value::Int).ok_or_else(out_of_range),
+        Value::Real(r) => Ok(Value::Real(r.abs())),
+        _ => unreachable!("the argument types were checked"),
+    }
+}
+
+// Negative digits round to tens, hundreds and so on, round(1250, -2) is 1300
+fn round(args: &[Value]) -> Result<Value, ValueError> {
+    let digits = if args.len() > 1 { int(args, 1) } else { 0 };
+    match args[0] {
+        Value::Int(i) if digits >= 0 => Ok(Value::Int(i)),
+        Value::Int(i) => {
+            let Some(unit) = 10i128.checked_pow(digits.unsigned_abs().min(u32::MAX as u64) as u32) else {
+                return Ok(Value::Int(0));
+            };
+            let (i, half) = (i as i128, unit / 2);
+            let rounded = if i >= 0 { (i + half) / unit * unit } else { (i - half) / unit * unit };
+            i64::try_from(rounded).map(Value::Int).map_err(|_| out_of_range())
+        }
+        Value::Real(r) => {
+            let scale = 10f64.powi(digits.clamp(-400, 400) as i32);
+            let scaled = r * scale;
+            let rounded = match scaled {
+                _ if scale == 0.0 => 0.0,
+                // Past the precision of a real there is nothing to round
+                _ if !scaled.is_finite() => r,
+                _ => scaled.round() / scale,
+            };
+            Ok(Value::Real(rounded + 0.0))
+        }
+        _ => unreachable!("the argument types were checked"),
+    }
+}
+
+fn coalesce(args: &[Value]) -> Result<Value, ValueError> {
+    Ok(args.iter().find(|value| **value != Value::Null).cloned().unwrap_or(Value::Null))
+}
+
+// Timestamps have no time zone, the clock is read in UTC
+fn now(_: &[Value]) -> Result<Value, ValueError> {
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ValueError::new(ErrorKind::Other, "Clock is before 1970"))?;
+    Ok(Value::Timestamp(since.as_micros() as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{Backend, BackendError, MemoryBackend, QueryResult, Value, execute};
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    fn run(backend: &mut dyn Backend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    // The values of a select's single row
+    fn row(backend: &mut dyn Backend, source: &str) -> Vec<Value> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(mut result) if result.rows.len() == 1 => result.rows.remove(0),
+            result => panic!("Expected one row, got {:?}", result),
+        }
+    }
+
+    fn backend() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        run(
+            &mut backend,
+            "create table t (name text, code varchar(5), n int, score real);
+             insert into t values ('Héllo', 'ab', -7, 2.5);",
+        )
+        .unwrap();
+        backend
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_text_functions() {
+        let mut backend = backend();
+        assert_eq!(
+            row(&mut backend, "select length(name), upper(name), LOWER(name), upper(code) from t"),
+            vec![Value::Int(5), text("HÉLLO"), text("héllo"), text("AB")]
+        );
+        let source = "select substr(name, 2), substr(name, 2, 3), substr(name, 0, 2), substr(name, 9) from t";
+        assert_eq!(
+            row(&mut backend, source),
+            vec![text("éllo"), text("éll"), text("H"), text("")]
+        );
+        let err = run(&mut backend, "select substr(name, 1, -1) from t").unwrap_err();
+        assert_eq!(err.message(), "Negative substring length not allowed");
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        let mut backend = backend();
+        assert_eq!(
+            row(&mut backend, "select abs(n), abs(-score), round(score), round(-score), round(1.2345, 2) from t"),
+            vec![Value::Int(7), Value::Real(2.5), Value::Real(3.0), Value::Real(-3.0), Value::Real(1.23)]
+        );
+        let source = "select round(n), round(1250, -2), round(-1250, -2), round(1.5, 400), round(5, -30) from t";
+        assert_eq!(
+            row(&mut backend, source),
+            vec![Value::Int(-7), Value::Int(1300), Value::Int(-1300), Value::Real(1.5), Value::Int(0)]
+        );
+        let err = run(&mut backend, "select abs(-9223372036854775807 - 1) from t").unwrap_err();
+        assert_eq!(err.message(), "Integer out of range");
+    }
+
+    #[test]
+    fn test_nulls_and_coalesce() {
+        let mut backend = backend();
+        run(&mut backend, "insert into t values (null, null, null, null)").unwrap();
+        let source = "select upper(name), substr(name, 1), abs(n), coalesce(name, code, 'none') from t where n is null";
+        assert_eq!(
+            row(&mut backend, source),
+            vec![Value::Null, Value::Null, Value::Null, text("none")]
+        );
+        assert_eq!(row(&mut backend, "select coalesce(null, n, 1) from t where n is not null"), vec![Value::Int(-7)]);
+        assert_eq!(row(&mut backend, "select count(*) from t where coalesce(n, 0) = 0"), vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_now() {
+        let mut backend = backend();
+        let values = row(&mut backend, "select now(), now() > timestamp '2024-01-01' from t");
+        assert!(matches!(values[0], Value::Timestamp(_)));
+        assert_eq!(values[1], Value::Bool(true));
+    }
+
+    #[test]
+    fn test_calls_are_checked_before_any_row() {
+        let mut backend = backend();
+        run(&mut backend, "delete from t").unwrap();
+        let cases = [
+            ("select nope(name) from t", "Unknown function nope"),
+            ("select pg.upper(name) from t", "Unknown function pg.upper"),
+            ("select upper() from t", "Function upper takes 1 argument, got 0"),
+            ("select substr(name) from t", "Function substr takes 2 or 3 arguments, got 1"),
+            ("select now(1) from t", "Function now takes 0 arguments, got 1"),
+            ("select coalesce() from t", "Function coalesce takes at least 1 argument, got 0"),
+            ("select upper(*) from t", "Only count accepts *, not upper"),
+            ("select upper(n) from t", "Cannot apply upper to int"),
+            ("select t.name from t where abs(code) > 1", "Cannot apply abs to varchar(5)"),
+            ("select substr(name, 1.5) from t", "Cannot apply substr to real"),
+            ("select length(n + 1) from t", "Cannot apply length to int"),
+            ("select round(length(name) + score, name) from t", "Cannot apply round to text"),
+            ("select coalesce(name, n) from t", "Cannot apply coalesce to text and int"),
+            ("update t set n = abs(name)", "Cannot apply abs to text"),
+            ("delete from t where lower(n) = 'a'", "Cannot apply lower to int"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(run(&mut backend, source).unwrap_err().message(), message, "{}", source);
+        }
+
+        // The columns of a subquery have no known type, they are checked
+        // when a row is evaluated
+        run(&mut backend, "insert into t values ('a', 'b', 1, 1.0)").unwrap();
+        let err = run(&mut backend, "select upper(s.n) from (select n from t) s").unwrap_err();
+        assert_eq!(err.message(), "Cannot apply upper to int");
+    }
+}
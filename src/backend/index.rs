@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use super::{Row, Value, cast, column_index, eval};
+use crate::lexer::Location;
+use crate::parser::{BinaryOp, ColumnDef, DataType, Expr};
+
+/*
+    A secondary index maps the values of one column to the positions of the
+    rows holding them. The map is the standard library's B-tree, which keeps
+    the values ordered so both `col = x` and `col > x` become a range lookup.
+
+    Indexes only live in memory. The backends rebuild them from the rows when
+    those move around, on delete or update, and when a database is opened,
+    and extend them on insert.
+
+    Besides the ones CREATE INDEX makes, every unique column has an index of
+    its own, a key, named after its table and column as in PostgreSQL. An
+    insert looks the values of its rows up in it rather than reading the
+    table, and lookups on the column use it like any other index. Keys come
+    and go with the columns, they are never written out.
+ */
+
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub name: String,
+    pub column: usize,
+    // Whether this is the key of a unique column
+    pub key: bool,
+    tree: BTreeMap<Value, Vec<usize>>,
+    len: usize,
+}
+
+impl Index {
+    pub fn new(name: String, column: usize, rows: &[Vec<Value>]) -> Index {
+        let mut index = Index { name, column, key: false, tree: BTreeMap::new(), len: 0 };
+        index.rebuild(rows);
+        index
+    }
+
+    pub fn rebuild(&mut self, rows: &[Vec<Value>]) {
+        self.tree.clear();
+        self.len = 0;
+        for row in rows {
+            self.push(row);
+        }
+    }
+
+    // Indexes a row added after every row already indexed. Nulls are left
+    // out, no comparison an index answers can match them.
+    pub fn push(&mut self, row: &[Value]) {
+        if row[self.column] != Value::Null {
+            self.tree.entry(row[self.column].clone()).or_default().push(self.len);
+        }
+        self.len += 1;
+    }
+
+    // Positions of the rows whose value falls in the lookup's range, in
+    // table order
+    pub fn find(&self, lookup: &Lookup) -> Vec<usize> {
+        let range = (lookup.lower.as_ref(), lookup.upper.as_ref());
+        let mut positions: Vec<usize> = self.tree.range(range).flat_map(|(_, p)| p.iter().copied()).collect();
+        positions.sort_unstable();
+        positions
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        self.tree.contains_key(value)
+    }
+}
+
+fn key_name(table: &str, column: &ColumnDef) -> String {
+    match column.is_primary_key() {
+        true => format!("{}_pkey", table),
+        false => format!("{}_{}_key", table, column.name),
+    }
+}
+
+/*
+    Gives the unique columns of a table their keys, once it is created or
+    altered, leaving the keys it has where their columns are still unique.
+    Those are renamed after their column, the rows are only read for the
+    keys of columns that had none.
+ */
+pub fn update_keys(indexes: &mut Vec<Index>, table: &str, columns: &[ColumnDef], rows: &[Vec<Value>]) {
+    indexes.retain(|index| !index.key || columns[index.column].is_unique());
+    for (i, column) in columns.iter().enumerate().filter(|(_, column)| column.is_unique()) {
+        let name = key_name(table, column);
+        match indexes.iter_mut().find(|index| index.key && index.column == i) {
+            Some(index) => index.name = name,
+            None => indexes.push(Index { key: true, ..Index::new(name, i, rows) }),
+        }
+    }
+}
+
+// A range of one index, and the part of a filter it was taken from
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub index: usize,
+    pub condition: Expr,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+}
+
+fn fits(value: &Value, data_type: DataType) -> bool {
+    matches!(
+        (value, data_type),
+        (Value::Int(_), DataType::Int | DataType::BigInt)
+            | (Value::Real(_), DataType::Real)
+            | (Value::Text(_), DataType::Text | DataType::Varchar(_))
+            | (Value::Bool(_), DataType::Boolean)
+            | (Value::Date(_), DataType::Date)
+            | (Value::Time(_), DataType::Time)
+            | (Value::Timestamp(_), DataType::Timestamp)
+            | (Value::Json(_), DataType::Json)
+    )
+}
+
+// The operator as seen from the other side, `5 < x` is `x > 5`
+fn flip(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        op => op,
+    }
+}
+
+// The parts of an expression anded together, `a AND b AND c` is a, b and c
+pub fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Binary { left, op: BinaryOp::And, right } => {
+            let mut parts = conjuncts(left);
+            parts.extend(conjuncts(right));
+            parts
+        }
+        Expr::Chain { first, rest } if rest.iter().all(|(op, _)| *op == BinaryOp::And) => {
+            let mut parts = conjuncts(first);
+            rest.iter().for_each(|(_, expr)| parts.extend(conjuncts(expr)));
+            parts
+        }
+        expr => vec![expr],
+    }
+}
+
+/*
+    Looks for a part of the filter that an index can answer: a comparison
+    between an indexed column and a constant, anded with the rest. The rows
+    it finds are those the part holds for, the rest of the filter still has
+    to be checked on them, so a filter that cannot use one is simply a full
+    scan.
+
+    Constants of a different type than the column are left to the scan, which
+    reports the type error that an index lookup would silently miss.
+ */
+pub fn choose(
+    filter: Option<&Expr>,
+    qualifier: &[String],
+    columns: &[ColumnDef],
+    indexes: &[Index],
+    loc: Location,
+) -> Option<Lookup> {
+    for part in conjuncts(filter?) {
+        let Expr::Binary { left, op, right } = part else {
+            continue;
+        };
+        let (column, op, constant) = match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(column), constant) => (column, *op, constant),
+            (constant, Expr::Column(column)) => (column, flip(*op), constant),
+            _ => continue,
+        };
+        let Ok(position) = column_index(qualifier, columns, column) else {
+            continue;
+        };
+        let Some(index) = indexes.iter().position(|index| index.column == position) else {
+            continue;
+        };
+        let no_row: Option<&Row> = None;
+        let Ok(value) = eval(constant, no_row, loc) else {
+            continue;
+        };
+        // The comparison converts the constant the same way
+        let data_type = columns[position].data_type;
+        let value = match value {
+            value if cast::is_implicit(&value, data_type) => match cast::cast(value, data_type) {
+                Ok(value) => value,
+                Err(_) => continue,
+            },
+            value => value,
+        };
+        if !fits(&value, data_type) {
+            continue;
+        }
+
+        let (lower, upper) = match op {
+            BinaryOp::Eq => (Bound::Included(value.clone()), Bound::Included(value)),
+            BinaryOp::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+            BinaryOp::LtEq => (Bound::Unbounded, Bound::Included(value)),
+            BinaryOp::Gt => (Bound::Excluded(value), Bound::Unbounded),
+            BinaryOp::GtEq => (Bound::Included(value), Bound::Unbounded),
+            _ => continue,
+        };
+        return Some(Lookup { index, condition: part.clone(), lower, upper });
+    }
+    None
+}
+
+// The positions of the rows an index lookup finds, None when no index helps
+pub fn candidates(
+    filter: Option<&Expr>,
+    qualifier: &[String],
+    columns: &[ColumnDef],
+    indexes: &[Index],
+    loc: Location,
+) -> Option<Vec<usize>> {
+    let lookup = choose(filter, qualifier, columns, indexes, loc)?;
+    Some(indexes[lookup.index].find(&lookup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{Statement, parse};
+
+    fn filter(condition: &str) -> Expr {
+        let source = format!("select * from t where {}", condition);
+        match parse(lex(&source).unwrap()).unwrap() {
+            Statement::Select(select) if select.filter.is_some() => select.filter.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn table() -> (Vec<String>, Vec<ColumnDef>, Vec<Index>) {
+        let loc = Location::new(1, 1);
+        let columns = vec![
+            ColumnDef { name: "n".to_string(), data_type: DataType::Int, constraints: vec![], default: None, loc },
+            ColumnDef { name: "s".to_string(), data_type: DataType::Text, constraints: vec![], default: None, loc },
+        ];
+        let rows: Vec<Vec<Value>> = [5, 1, 3, 5, 2]
+            .iter()
+            .map(|n| vec![Value::Int(*n), Value::Text(n.to_string())])
+            .collect();
+        let index = Index::new("by_n".to_string(), 0, &rows);
+        (vec!["t".to_string()], columns, vec![index])
+    }
+
+    fn lookup(condition: &str) -> Option<Vec<usize>> {
+        let (name, columns, indexes) = table();
+        candidates(Some(&filter(condition)), &name, &columns, &indexes, Location::new(1, 1))
+    }
+
+    #[test]
+    fn test_equality_and_ranges() {
+        assert_eq!(lookup("n = 5"), Some(vec![0, 3]));
+        assert_eq!(lookup("n < 3"), Some(vec![1, 4]));
+        assert_eq!(lookup("n <= 3"), Some(vec![1, 2, 4]));
+        assert_eq!(lookup("n > 3"), Some(vec![0, 3]));
+        assert_eq!(lookup("n >= 3"), Some(vec![0, 2, 3]));
+        assert_eq!(lookup("n = 4"), Some(vec![]));
+    }
+
+    #[test]
+    fn test_constant_on_the_left_and_expressions() {
+        assert_eq!(lookup("3 > n"), Some(vec![1, 4]));
+        assert_eq!(lookup("t.n = 2 + 3"), Some(vec![0, 3]));
+    }
+
+    #[test]
+    fn test_uses_one_side_of_and() {
+        assert_eq!(lookup("s = 'x' and n = 1"), Some(vec![1]));
+    }
+
+    #[test]
+    fn test_falls_back_to_a_scan() {
+        // No index on s, an OR, a column on both sides, <>, a type mismatch
+        for condition in ["s = '1'", "n = 1 or n = 2", "n = n", "n <> 1", "n = 'one'", "not n = 1"] {
+            assert_eq!(lookup(condition), None, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn test_push_extends_the_index() {
+        let (name, columns, mut indexes) = table();
+        indexes[0].push(&[Value::Int(1), Value::Text("1".to_string())]);
+        let found = candidates(Some(&filter("n = 1")), &name, &columns, &indexes, Location::new(1, 1));
+        assert_eq!(found, Some(vec![1, 5]));
+    }
+}
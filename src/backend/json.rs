@@ -0,0 +1,334 @@
+use std::fmt;
+
+/*
+    Values of json columns. Text going into one is parsed first, so a json
+    column only ever holds valid JSON, and stored in a compact form without
+    whitespace. Keys keep the order they were written in and numbers keep
+    their spelling, "1.50" stays 1.50.
+
+    `->` and `->>` parse the stored text again to pick a member out of it.
+ */
+
+// Deeper documents are rejected rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // The member of an object named `key`, the last one when a key repeats
+    pub fn field(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().rev().find(|(k, _)| k == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // Element `index` of an array, a negative index counts from the end
+    pub fn element(&self, index: i64) -> Option<&Json> {
+        match self {
+            Json::Array(elements) => {
+                let index = if index < 0 { elements.len() as i64 + index } else { index };
+                elements.get(usize::try_from(index).ok()?)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(s) => write_string(f, s),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// Errors say what was expected where, like the SQL parser's
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let json = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("end of input"));
+    }
+    Ok(json)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn consume(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(c) => format!("Expected {}, got {}", expected, c),
+            None => format!("Expected {}, got end of input", expected),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err("Json nested too deeply".to_string());
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            _ => {
+                for (word, json) in [("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))] {
+                    if self.text[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(json);
+                    }
+                }
+                Err(self.error("json value"))
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Json, String> {
+        self.consume('[');
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.consume(']') {
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            if self.consume(']') {
+                return Ok(Json::Array(elements));
+            }
+            if !self.consume(',') {
+                return Err(self.error(", or ]"));
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Json, String> {
+        self.consume('{');
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.consume('}') {
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("object key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.consume(':') {
+                return Err(self.error(":"));
+            }
+            members.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            if self.consume('}') {
+                return Ok(Json::Object(members));
+            }
+            if !self.consume(',') {
+                return Err(self.error(", or }"));
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).filter(|d| d.chars().all(|c| c.is_ascii_hexdigit()));
+        let code = digits.ok_or_else(|| self.error("4 hex digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(code, 16).unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.consume('"');
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex4()?;
+                        // Characters outside the basic plane come as a
+                        // surrogate pair, "\ud83d\ude00"
+                        if (0xd800..0xdc00).contains(&code) && self.text[self.pos..].starts_with("\\u") {
+                            self.pos += 2;
+                            let low = self.hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(format!("Expected low surrogate, got \\u{:04x}", low));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        s.push(char::from_u32(code).ok_or_else(|| format!("Invalid unicode escape \\u{:04x}", code))?);
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("escape"));
+                    }
+                },
+                Some(c) if (c as u32) < 0x20 => {
+                    self.pos -= 1;
+                    return Err("Expected escape for control character in string".to_string());
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("\"")),
+            }
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        self.consume('-');
+        // No leading zeros, "01" is not a number
+        if !self.consume('0') && self.digits() == 0 {
+            return Err(self.error("digit"));
+        }
+        if self.consume('.') && self.digits() == 0 {
+            return Err(self.error("digit"));
+        }
+        if self.consume('e') || self.consume('E') {
+            let _ = self.consume('+') || self.consume('-');
+            if self.digits() == 0 {
+                return Err(self.error("digit"));
+            }
+        }
+        Ok(Json::Number(self.text[start..self.pos].to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compact(text: &str) -> String {
+        parse(text).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_and_print() {
+        assert_eq!(compact(" { \"a\" : [1, -2.50, 3e+2], \"b\": {\"c\": null}, \"d\": true } "), r#"{"a":[1,-2.50,3e+2],"b":{"c":null},"d":true}"#);
+        assert_eq!(compact(r#""tab\there \"q\" \u00e9 \ud83d\ude00 \/""#), "\"tab\\there \\\"q\\\" é 😀 /\"");
+        assert_eq!(compact("[]"), "[]");
+        assert_eq!(compact("{}"), "{}");
+        assert_eq!(compact("0"), "0");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("").unwrap_err(), "Expected json value, got end of input");
+        assert_eq!(parse("{a: 1}").unwrap_err(), "Expected object key, got a");
+        assert_eq!(parse("[1 2]").unwrap_err(), "Expected , or ], got 2");
+        assert_eq!(parse("{\"a\" 1}").unwrap_err(), "Expected :, got 1");
+        assert_eq!(parse("01").unwrap_err(), "Expected end of input, got 1");
+        assert_eq!(parse("1.").unwrap_err(), "Expected digit, got end of input");
+        assert_eq!(parse("\"abc").unwrap_err(), "Expected \", got end of input");
+        assert_eq!(parse("\"\\x\"").unwrap_err(), "Expected escape, got x");
+        assert_eq!(parse("tru").unwrap_err(), "Expected json value, got t");
+        assert_eq!(parse(&"[".repeat(200)).unwrap_err(), "Json nested too deeply");
+    }
+
+    #[test]
+    fn test_field_and_element() {
+        let json = parse(r#"{"a": 1, "b": [10, 20, 30], "a": 2}"#).unwrap();
+        assert_eq!(json.field("a"), Some(&Json::Number("2".to_string())));
+        assert_eq!(json.field("missing"), None);
+        assert_eq!(json.element(0), None);
+
+        let array = json.field("b").unwrap();
+        assert_eq!(array.element(1).unwrap().to_string(), "20");
+        assert_eq!(array.element(-1).unwrap().to_string(), "30");
+        assert_eq!(array.element(3), None);
+        assert_eq!(array.element(-4), None);
+    }
+}
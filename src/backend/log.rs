@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::Value;
+use super::disk::{decode_def, decode_row, encode_def, encode_row};
+use super::pager::Backup;
+use super::storage::{Storage, TableDef};
+use super::wal::{CHECKSUM_SEED, checksum};
+
+/*
+    A storage engine that keeps a database in a single append-only log.
+    Nothing in the file is ever written over, a new table, new rows and
+    changes to rows all add records to its end. The file starts with MAGIC,
+    then come the records, each prefixed by its length:
+
+        len      u32     bytes after the length
+        kind     u8
+        body
+
+    The body of each kind of record:
+
+        DEFINE   a table or view's definition, as the disk backend's catalog
+                 stores it
+        DROP     the name of a table or view
+        ROW      the name of a table, u32 length and UTF-8 bytes, and then a
+                 row, as the disk backend stores it
+        UPDATE   the name of a table, the u32 position of a row and the row
+                 written over it
+        DELETE   the name of a table and the u32 positions of the rows
+                 removed, in ascending order
+        CLEAR    the name of a table, its rows before this record are gone
+        COMMIT   checksum u64 of the records since the commit before
+
+    Only where each row is in the file is kept in memory, so a point lookup
+    reads the rows asked for and nothing else. The records of a statement,
+    or of a transaction, count once the commit record after them is on
+    disk. The checksum, FNV-1a like the write-ahead log's, tells a commit
+    record apart from one whose records were only partly written. On open
+    the log is replayed up to its last good commit record, and whatever
+    follows, left by a crash or never committed, is cut off.
+
+    Rows written over, deleted or dropped still take space in the file
+    until it is compacted. Each time the log has doubled since it was
+    opened or last looked at, a commit counts the bytes its tables and rows
+    need, and when that is less than half of the file the log is compacted:
+    the tables and rows are written to a new log next to it, as a single
+    commit, which is then renamed over the old one.
+
+    A backup is the log up to its last commit record, which no later write
+    changes: a rollback only cuts off what follows it, and a compaction puts
+    a new file in place of the one the backup reads. Restoring one copies
+    it next to the log, opens the copy and renames it over the log.
+ */
+
+const MAGIC: &[u8; 8] = b"SQRLLOG1";
+
+const DEFINE: u8 = 0;
+const DROP: u8 = 1;
+const ROW: u8 = 2;
+const CLEAR: u8 = 3;
+const COMMIT: u8 = 4;
+const UPDATE: u8 = 5;
+const DELETE: u8 = 6;
+
+// A log is not compacted before it reaches this many bytes
+const COMPACT_FROM: u64 = 1 << 20;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn name_body(name: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + name.len());
+    buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    buf.extend_from_slice(name.as_bytes());
+    buf
+}
+
+// The name a body starts with, and where what follows it starts
+fn read_name(body: &[u8]) -> io::Result<(String, usize)> {
+    let len = body.get(..4).ok_or_else(|| invalid("Log record is truncated"))?;
+    let end = 4 + u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    let name = body.get(4..end).ok_or_else(|| invalid("Log record is truncated"))?;
+    let name = String::from_utf8(name.to_vec()).map_err(|_| invalid("Table name is not UTF-8"))?;
+    Ok((name, end))
+}
+
+// Adds a record to `buf`, returning where its body starts
+fn frame(buf: &mut Vec<u8>, kind: u8, body: &[u8]) -> usize {
+    buf.extend_from_slice(&(body.len() as u32 + 1).to_le_bytes());
+    buf.push(kind);
+    let start = buf.len();
+    buf.extend_from_slice(body);
+    start
+}
+
+#[derive(Clone)]
+struct Table {
+    def: TableDef,
+    // Where the bytes of each row are in the file, and how many there are
+    rows: Vec<(u64, usize)>,
+}
+
+// What a rollback puts back of a table changed since the last commit, or
+// since the savepoint
+enum Undo {
+    // Rows were appended and nothing else, to the rows it had
+    Appended(usize),
+    // The table as it was, None when it did not exist
+    Replaced(Option<Box<Table>>),
+}
+
+// Keeps in `undo` what going back needs to put the table back, the first
+// time a change needs more than the ones before
+fn remember(undo: &mut HashMap<String, Undo>, tables: &HashMap<String, Table>, name: &str, appending: bool) {
+    let table = tables.get(name);
+    let saved = match (undo.get(name), appending) {
+        (None, true) => Undo::Appended(table.map_or(0, |table| table.rows.len())),
+        (None, false) => Undo::Replaced(table.cloned().map(Box::new)),
+        (Some(&Undo::Appended(count)), false) => Undo::Replaced(table.cloned().map(|mut table| {
+            table.rows.truncate(count);
+            Box::new(table)
+        })),
+        _ => return,
+    };
+    undo.insert(name.to_string(), saved);
+}
+
+// Where the statement being run inside a transaction started
+struct Savepoint {
+    len: u64,
+    hash: u64,
+    undo: HashMap<String, Undo>,
+}
+
+pub struct LogStorage {
+    path: PathBuf,
+    file: File,
+    tables: HashMap<String, Table>,
+    undo: HashMap<String, Undo>,
+    savepoint: Option<Savepoint>,
+    // Where the file ends, and where its last commit record ends
+    len: u64,
+    committed: u64,
+    // Of the records since the last commit
+    hash: u64,
+    // The length at which a commit looks at compacting the log
+    compact_at: u64,
+}
+
+impl LogStorage {
+    // Opens the log at `path`, creating an empty one if the file does not exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<LogStorage> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path.as_ref())?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        if bytes.is_empty() {
+            file.write_all(MAGIC)?;
+            file.sync_data()?;
+            bytes.extend_from_slice(MAGIC);
+        }
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid("Not a sqrldb log"));
+        }
+
+        let start = MAGIC.len() as u64;
+        let mut storage = LogStorage {
+            path: path.as_ref().to_path_buf(),
+            file,
+            tables: HashMap::new(),
+            undo: HashMap::new(),
+            savepoint: None,
+            len: start,
+            committed: start,
+            hash: 0,
+            compact_at: 0,
+        };
+        storage.replay(&bytes)?;
+        storage.file.set_len(storage.committed)?;
+        storage.len = storage.committed;
+        storage.hash = CHECKSUM_SEED;
+        storage.compact_at = (2 * storage.len).max(COMPACT_FROM);
+        Ok(storage)
+    }
+
+    // Applies the records of every complete commit, and leaves the rest out
+    fn replay(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let mut at = MAGIC.len();
+        let mut pending = Vec::new();
+        let mut hash = CHECKSUM_SEED;
+        while let Some(len) = bytes.get(at..at + 4) {
+            let end = at + 4 + u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            let Some(&kind) = bytes.get(at + 4).filter(|_| end > at + 4 && end <= bytes.len()) else {
+                break;
+            };
+            if kind != COMMIT {
+                hash = checksum(hash, &bytes[at..end]);
+                pending.push((kind, at + 5, end));
+                at = end;
+                continue;
+            }
+            if bytes[at + 5..end] != hash.to_le_bytes() {
+                break;
+            }
+            for (kind, start, end) in pending.drain(..) {
+                self.apply(kind, &bytes[start..end], start as u64)?;
+            }
+            self.committed = end as u64;
+            hash = CHECKSUM_SEED;
+            at = end;
+        }
+        self.undo.clear();
+        Ok(())
+    }
+
+    // Changes the tables as a record written at `at` says
+    fn apply(&mut self, kind: u8, body: &[u8], at: u64) -> io::Result<()> {
+        if kind == DEFINE {
+            let (name, def) = decode_def(body)?;
+            self.save(&name, false);
+            match self.tables.get_mut(&name) {
+                Some(table) => table.def = def,
+                None => {
+                    self.tables.insert(name, Table { def, rows: Vec::new() });
+                }
+            }
+            return Ok(());
+        }
+
+        let (name, start) = read_name(body)?;
+        if !self.tables.contains_key(&name) {
+            return Err(invalid("Log record for a table that does not exist"));
+        }
+        match kind {
+            DROP => {
+                self.save(&name, false);
+                self.tables.remove(&name);
+            }
+            ROW => {
+                self.save(&name, true);
+                self.tables.get_mut(&name).unwrap().rows.push((at + start as u64, body.len() - start));
+            }
+            UPDATE => {
+                let i = body.get(start..start + 4).ok_or_else(|| invalid("Log record is truncated"))?;
+                let i = u32::from_le_bytes(i.try_into().unwrap()) as usize;
+                self.save(&name, false);
+                let rows = &mut self.tables.get_mut(&name).unwrap().rows;
+                let row = rows.get_mut(i).ok_or_else(|| invalid("No row at that position"))?;
+                *row = (at + start as u64 + 4, body.len() - start - 4);
+            }
+            DELETE => {
+                let positions = &body[start..];
+                if !positions.len().is_multiple_of(4) {
+                    return Err(invalid("Log record is truncated"));
+                }
+                let mut positions = positions.chunks(4).map(|i| u32::from_le_bytes(i.try_into().unwrap()) as usize);
+                let mut next = positions.next();
+                self.save(&name, false);
+                let rows = &mut self.tables.get_mut(&name).unwrap().rows;
+                let mut i = 0;
+                rows.retain(|_| {
+                    i += 1;
+                    if next != Some(i - 1) {
+                        return true;
+                    }
+                    next = positions.next();
+                    false
+                });
+                if next.is_some() {
+                    return Err(invalid("No row at that position"));
+                }
+            }
+            CLEAR => {
+                self.save(&name, false);
+                self.tables.get_mut(&name).unwrap().rows.clear();
+            }
+            _ => return Err(invalid("Unknown log record")),
+        }
+        Ok(())
+    }
+
+    // Keeps what a rollback, and going back to the savepoint, need to put
+    // the table back
+    fn save(&mut self, name: &str, appending: bool) {
+        remember(&mut self.undo, &self.tables, name, appending);
+        if let Some(savepoint) = &mut self.savepoint {
+            remember(&mut savepoint.undo, &self.tables, name, appending);
+        }
+    }
+
+    // Puts the tables back as they were before the changes `undo` was kept for
+    fn put_back(&mut self, undo: HashMap<String, Undo>) {
+        for (name, undo) in undo {
+            match undo {
+                Undo::Appended(count) => self.tables.get_mut(&name).unwrap().rows.truncate(count),
+                Undo::Replaced(Some(table)) => {
+                    self.tables.insert(name, *table);
+                }
+                Undo::Replaced(None) => {
+                    self.tables.remove(&name);
+                }
+            }
+        }
+    }
+
+    // Adds the records to the end of the log and applies them
+    fn write(&mut self, records: &[(u8, Vec<u8>)]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let starts: Vec<usize> = records.iter().map(|(kind, body)| frame(&mut buf, *kind, body)).collect();
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&buf)?;
+
+        let at = self.len;
+        self.len += buf.len() as u64;
+        self.hash = checksum(self.hash, &buf);
+        for ((kind, body), start) in records.iter().zip(starts) {
+            self.apply(*kind, body, at + start as u64)?;
+        }
+        Ok(())
+    }
+
+    fn table(&self, name: &str) -> io::Result<&Table> {
+        self.tables.get(name).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No table {}", name)))
+    }
+
+    fn read_rows<'a>(&self, rows: impl Iterator<Item = &'a (u64, usize)>) -> io::Result<Vec<Vec<Value>>> {
+        let mut file = &self.file;
+        rows.map(|&(at, len)| {
+            let mut buf = vec![0; len];
+            file.seek(SeekFrom::Start(at))?;
+            file.read_exact(&mut buf)?;
+            decode_row(&buf)
+        })
+        .collect()
+    }
+
+    fn rows(table: &str, rows: &[Vec<Value>]) -> impl Iterator<Item = (u8, Vec<u8>)> {
+        rows.iter().map(move |row| {
+            let mut body = name_body(table);
+            body.extend_from_slice(&encode_row(row));
+            (ROW, body)
+        })
+    }
+
+    // How long the log would be compacted: the records of the tables and
+    // their rows, and one commit record
+    fn compacted_len(&self) -> u64 {
+        let records = self.tables.iter().map(|(name, table)| {
+            let rows = table.rows.iter().map(|&(_, len)| 9 + name.len() + len).sum::<usize>();
+            (5 + encode_def(name, &table.def).len() + rows) as u64
+        });
+        MAGIC.len() as u64 + records.sum::<u64>() + 13
+    }
+
+    // Writes the tables as they were committed to a new log and puts it in
+    // place of this one. The new log is written next to this one and
+    // renamed over it once it is on disk, so a crash leaves one or the other.
+    fn compact(&mut self) -> io::Result<()> {
+        let mut partial = self.path.as_os_str().to_owned();
+        partial.push("-compact");
+        let partial = PathBuf::from(partial);
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&partial)?;
+
+        let mut out = BufWriter::new(&file);
+        out.write_all(MAGIC)?;
+        let (mut len, mut hash) = (MAGIC.len() as u64, CHECKSUM_SEED);
+        let mut tables = HashMap::with_capacity(self.tables.len());
+        let mut buf = Vec::new();
+        for (name, table) in &self.tables {
+            buf.clear();
+            frame(&mut buf, DEFINE, &encode_def(name, &table.def));
+            let mut rows = Vec::with_capacity(table.rows.len());
+            let mut row = Vec::new();
+            for &(at, row_len) in &table.rows {
+                row.resize(row_len, 0);
+                self.file.seek(SeekFrom::Start(at))?;
+                self.file.read_exact(&mut row)?;
+                let mut body = name_body(name);
+                let start = body.len();
+                body.extend_from_slice(&row);
+                rows.push((len + buf.len() as u64 + 5 + start as u64, row_len));
+                frame(&mut buf, ROW, &body);
+            }
+            out.write_all(&buf)?;
+            len += buf.len() as u64;
+            hash = checksum(hash, &buf);
+            tables.insert(name.clone(), Table { def: table.def.clone(), rows });
+        }
+        buf.clear();
+        frame(&mut buf, COMMIT, &hash.to_le_bytes());
+        out.write_all(&buf)?;
+        out.flush()?;
+        drop(out);
+        file.sync_all()?;
+        len += buf.len() as u64;
+
+        if let Err(err) = std::fs::rename(&partial, &self.path) {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err);
+        }
+        self.file = file;
+        self.tables = tables;
+        self.len = len;
+        self.committed = len;
+        Ok(())
+    }
+}
+
+impl Storage for LogStorage {
+    fn tables(&self) -> io::Result<Vec<(String, TableDef)>> {
+        Ok(self.tables.iter().map(|(name, table)| (name.clone(), table.def.clone())).collect())
+    }
+
+    fn scan(&self, table: &str) -> io::Result<Vec<Vec<Value>>> {
+        self.read_rows(self.table(table)?.rows.iter())
+    }
+
+    fn count(&self, table: &str) -> io::Result<usize> {
+        Ok(self.table(table)?.rows.len())
+    }
+
+    fn scan_range(&self, table: &str, range: Range<usize>) -> io::Result<Vec<Vec<Value>>> {
+        let rows = self.table(table)?.rows.get(range).ok_or_else(|| invalid("No row at that position"))?;
+        self.read_rows(rows.iter())
+    }
+
+    fn get(&self, table: &str, positions: &[usize]) -> io::Result<Vec<Vec<Value>>> {
+        let rows = &self.table(table)?.rows;
+        let rows = positions.iter().map(|&i| rows.get(i).ok_or_else(|| invalid("No row at that position")));
+        self.read_rows(rows.collect::<io::Result<Vec<_>>>()?.into_iter())
+    }
+
+    fn define(&mut self, table: &str, def: &TableDef) -> io::Result<()> {
+        self.write(&[(DEFINE, encode_def(table, def))])
+    }
+
+    fn drop(&mut self, table: &str) -> io::Result<()> {
+        self.write(&[(DROP, name_body(table))])
+    }
+
+    fn append(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+        self.write(&LogStorage::rows(table, rows).collect::<Vec<_>>())
+    }
+
+    fn replace(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+        let clear = std::iter::once((CLEAR, name_body(table)));
+        self.write(&clear.chain(LogStorage::rows(table, rows)).collect::<Vec<_>>())
+    }
+
+    fn update(&mut self, table: &str, rows: &[(usize, Vec<Value>)]) -> io::Result<()> {
+        let records = rows.iter().map(|(i, row)| {
+            let mut body = name_body(table);
+            body.extend_from_slice(&(*i as u32).to_le_bytes());
+            body.extend_from_slice(&encode_row(row));
+            (UPDATE, body)
+        });
+        self.write(&records.collect::<Vec<_>>())
+    }
+
+    fn delete(&mut self, table: &str, positions: &[usize]) -> io::Result<()> {
+        let mut body = name_body(table);
+        positions.iter().for_each(|&i| body.extend_from_slice(&(i as u32).to_le_bytes()));
+        self.write(&[(DELETE, body)])
+    }
+
+    // A commit that grows the log past compact_at also compacts it if it
+    // can. The commit is on disk by then, so failing to compact is left for
+    // a later commit to try again.
+    fn commit(&mut self) -> io::Result<()> {
+        if self.len == self.committed {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(13);
+        frame(&mut record, COMMIT, &self.hash.to_le_bytes());
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        self.len += record.len() as u64;
+        self.committed = self.len;
+        self.hash = CHECKSUM_SEED;
+        self.undo.clear();
+        self.savepoint = None;
+
+        if self.len >= self.compact_at {
+            if 2 * self.compacted_len() < self.len {
+                let _ = self.compact();
+            }
+            self.compact_at = (2 * self.len).max(COMPACT_FROM);
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> io::Result<()> {
+        let undo = std::mem::take(&mut self.undo);
+        self.put_back(undo);
+        self.savepoint = None;
+        self.len = self.committed;
+        self.hash = CHECKSUM_SEED;
+        self.file.set_len(self.committed)
+    }
+
+    fn savepoint(&mut self) -> io::Result<()> {
+        self.savepoint = Some(Savepoint { len: self.len, hash: self.hash, undo: HashMap::new() });
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self) -> io::Result<()> {
+        let savepoint = self.savepoint.take().ok_or_else(|| io::Error::other("No savepoint to go back to"))?;
+        self.put_back(savepoint.undo);
+        self.len = savepoint.len;
+        self.hash = savepoint.hash;
+        self.file.set_len(self.len)
+    }
+
+    fn backup(&mut self) -> io::Result<Backup> {
+        Backup::of_file(&self.path, self.committed)
+    }
+
+    fn restore(&mut self, path: &Path) -> io::Result<()> {
+        // An empty file would open as an empty log
+        if std::fs::metadata(path)?.len() == 0 {
+            return Err(invalid("Not a sqrldb log"));
+        }
+        let mut partial = self.path.as_os_str().to_owned();
+        partial.push("-restore");
+        let partial = PathBuf::from(partial);
+        let restored = std::fs::copy(path, &partial).and_then(|_| {
+            let restored = LogStorage::open(&partial)?;
+            restored.file.sync_all()?;
+            std::fs::rename(&partial, &self.path)?;
+            Ok(restored)
+        });
+        match restored {
+            Ok(restored) => {
+                *self = LogStorage { path: self.path.clone(), ..restored };
+                Ok(())
+            }
+            Err(err) => {
+                let _ = std::fs::remove_file(&partial);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pager::temp_path;
+    use super::super::{Backend, BackendError, QueryResult, StorageBackend, execute};
+    use crate::Database;
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    fn open(path: &Path) -> StorageBackend {
+        StorageBackend::new(Box::new(LogStorage::open(path).unwrap())).unwrap()
+    }
+
+    fn run(backend: &mut StorageBackend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    fn query(backend: &mut StorageBackend, source: &str) -> Vec<Vec<Value>> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(rows) => rows.rows,
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_tables_survive_reopening() {
+        let path = temp_path("log-reopen");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table users (id serial primary key, name text);
+             insert into users (name) values ('Ada'), ('Grace'), ('Edsger');
+             create index users_name on users (name);
+             update users set name = 'Barbara' where id = 2;
+             delete from users where id = 3;
+             create table gone (x int);
+             drop table gone;
+             create view names as select name from users;",
+        )
+        .unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(
+            query(&mut backend, "select * from users"),
+            vec![vec![Value::Int(1), text("Ada")], vec![Value::Int(2), text("Barbara")]]
+        );
+        assert_eq!(query(&mut backend, "select * from names where name = 'Ada'"), vec![vec![text("Ada")]]);
+        assert_eq!(run(&mut backend, "select * from gone").unwrap_err().message(), "Unknown table gone");
+
+        // The index is rebuilt and the sequence goes on where it was
+        let plan = query(&mut backend, "explain select * from users where name = 'Barbara'");
+        assert!(plan.iter().any(|row| row[0].to_string().contains("users_name")), "{:?}", plan);
+        run(&mut backend, "insert into users (name) values ('Frances')").unwrap();
+        assert_eq!(query(&mut backend, "select id from users where name = 'Frances'"), vec![vec![Value::Int(4)]]);
+    }
+
+    #[test]
+    fn test_only_commits_reach_the_log() {
+        let path = temp_path("log-commit");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (n int);
+             insert into t values (1);
+             begin;
+             insert into t values (2);
+             rollback;
+             begin;
+             insert into t values (3);
+             delete from t where n = 1;
+             commit;
+             begin;
+             insert into t values (4);
+             drop table t;",
+        )
+        .unwrap();
+        // Dropped with the transaction still open
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(3)]]);
+    }
+
+    #[test]
+    fn test_a_torn_tail_is_cut_off() {
+        let path = temp_path("log-torn");
+        let mut backend = open(&path);
+        run(&mut backend, "create table t (n int); insert into t values (1)").unwrap();
+        drop(backend);
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // Rows without their commit record, and a commit record whose
+        // checksum does not match, as a crash could leave them
+        let mut storage = LogStorage::open(&path).unwrap();
+        storage.append("t", &[vec![Value::Int(2)]]).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[9, 0, 0, 0, COMMIT, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        drop(storage);
+
+        let mut backend = open(&path);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(1)]]);
+        run(&mut backend, "insert into t values (3)").unwrap();
+        drop(backend);
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(1)], vec![Value::Int(3)]]);
+    }
+
+    #[test]
+    fn test_point_lookups_read_by_position() {
+        let path = temp_path("log-get");
+        let mut storage = LogStorage::open(&path).unwrap();
+        let def = TableDef { columns: Vec::new(), indexes: Vec::new(), sequence: 0, view: None };
+        storage.define("t", &def).unwrap();
+        storage.append("t", &[vec![Value::Int(1)], vec![text("two")], vec![Value::Null]]).unwrap();
+        storage.commit().unwrap();
+        assert_eq!(storage.get("t", &[2, 0]).unwrap(), vec![vec![Value::Null], vec![Value::Int(1)]]);
+
+        storage.replace("t", &[vec![Value::Bool(true)]]).unwrap();
+        assert_eq!(storage.scan("t").unwrap(), vec![vec![Value::Bool(true)]]);
+        storage.rollback().unwrap();
+        assert_eq!(storage.scan("t").unwrap().len(), 3);
+        assert_eq!(storage.get("t", &[3]).unwrap_err().to_string(), "No row at that position");
+        assert_eq!(storage.scan_range("t", 1..3).unwrap(), vec![vec![text("two")], vec![Value::Null]]);
+        assert_eq!(storage.tables().unwrap(), vec![("t".to_string(), def)]);
+    }
+
+    #[test]
+    fn test_updates_and_deletes_log_only_their_rows() {
+        let path = temp_path("log-rows");
+        let mut storage = LogStorage::open(&path).unwrap();
+        let def = TableDef { columns: Vec::new(), indexes: Vec::new(), sequence: 0, view: None };
+        storage.define("t", &def).unwrap();
+        storage.append("t", &(0..1000).map(|i| vec![Value::Int(i)]).collect::<Vec<_>>()).unwrap();
+        storage.commit().unwrap();
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        storage.update("t", &[(1, vec![text("one")])]).unwrap();
+        storage.delete("t", &[0, 2, 999]).unwrap();
+        storage.commit().unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() - len < 100);
+        drop(storage);
+
+        let storage = LogStorage::open(&path).unwrap();
+        let rows = storage.scan("t").unwrap();
+        assert_eq!(rows.len(), 997);
+        assert_eq!(rows[..2], [vec![text("one")], vec![Value::Int(3)]]);
+        assert_eq!(rows[996], vec![Value::Int(998)]);
+    }
+
+    #[test]
+    fn test_savepoints_drop_a_statements_writes() {
+        let path = temp_path("log-savepoint");
+        let mut storage = LogStorage::open(&path).unwrap();
+        let def = TableDef { columns: Vec::new(), indexes: Vec::new(), sequence: 0, view: None };
+        storage.define("t", &def).unwrap();
+        storage.append("t", &[vec![Value::Int(1)], vec![Value::Int(2)]]).unwrap();
+        storage.commit().unwrap();
+
+        storage.savepoint().unwrap();
+        storage.append("t", &[vec![Value::Int(3)]]).unwrap();
+        storage.savepoint().unwrap();
+        storage.update("t", &[(0, vec![Value::Int(10)])]).unwrap();
+        storage.delete("t", &[1]).unwrap();
+        storage.define("u", &def).unwrap();
+        storage.rollback_to_savepoint().unwrap();
+        let ints = |values: &[i64]| values.iter().map(|&n| vec![Value::Int(n)]).collect::<Vec<_>>();
+        assert_eq!(storage.scan("t").unwrap(), ints(&[1, 2, 3]));
+        assert_eq!(storage.tables().unwrap().len(), 1);
+        storage.commit().unwrap();
+        drop(storage);
+
+        let mut storage = LogStorage::open(&path).unwrap();
+        assert_eq!(storage.scan("t").unwrap(), ints(&[1, 2, 3]));
+        storage.savepoint().unwrap();
+        storage.delete("t", &[0]).unwrap();
+        storage.savepoint().unwrap();
+        storage.rollback().unwrap();
+        assert_eq!(storage.scan("t").unwrap(), ints(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_compaction_keeps_the_rows_and_drops_the_rest() {
+        let path = temp_path("log-compact");
+        let mut backend = open(&path);
+        run(
+            &mut backend,
+            "create table t (id serial primary key, name text);
+             insert into t (name) values ('a'), ('b'), ('c');
+             create table gone (x int);
+             insert into gone values (1), (2);
+             drop table gone;",
+        )
+        .unwrap();
+        for i in 0..50 {
+            run(&mut backend, &format!("update t set name = '{}' where id = 2", "x".repeat(i))).unwrap();
+        }
+        run(&mut backend, "delete from t where id = 1").unwrap();
+        drop(backend);
+
+        let mut storage = LogStorage::open(&path).unwrap();
+        let len = storage.len;
+        storage.compact_at = 0;
+        let mut backend = StorageBackend::new(Box::new(storage)).unwrap();
+        run(&mut backend, "insert into t (name) values ('d')").unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < len / 2);
+        let rows = vec![
+            vec![Value::Int(2), text(&"x".repeat(49))],
+            vec![Value::Int(3), text("c")],
+            vec![Value::Int(4), text("d")],
+        ];
+        assert_eq!(query(&mut backend, "select * from t"), rows);
+        run(&mut backend, "update t set name = 'e' where id = 3").unwrap();
+        drop(backend);
+
+        let mut backend = open(&path);
+        assert_eq!(query(&mut backend, "select name from t where id = 3"), vec![vec![text("e")]]);
+        run(&mut backend, "insert into t (name) values ('f')").unwrap();
+        assert_eq!(query(&mut backend, "select id from t where name = 'f'"), vec![vec![Value::Int(5)]]);
+        assert_eq!(run(&mut backend, "select * from gone").unwrap_err().message(), "Unknown table gone");
+    }
+
+    #[test]
+    fn test_backup_holds_the_last_commit_through_a_compaction() {
+        let (path, copy) = (temp_path("log-backup"), temp_path("log-backup-copy"));
+        let mut backend = open(&path);
+        let values: Vec<String> = (10..50).map(|i| format!("({})", i)).collect();
+        run(&mut backend, &format!("create table t (n int); insert into t values (1), {}", values.join(", "))).unwrap();
+        run(&mut backend, "delete from t where n >= 10").unwrap();
+        drop(backend);
+
+        let mut storage = LogStorage::open(&path).unwrap();
+        storage.compact_at = 0;
+        let mut backend = StorageBackend::new(Box::new(storage)).unwrap();
+        run(&mut backend, "begin; insert into t values (2)").unwrap();
+        let backup = backend.backup().unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        run(&mut backend, "commit").unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < len, "the commit compacted the log");
+        run(&mut backend, "delete from t where n = 1; insert into t values (3)").unwrap();
+        backup.write(&copy).unwrap();
+        assert_eq!(query(&mut open(&copy), "select n from t"), vec![vec![Value::Int(1)]]);
+
+        backend.restore(&copy).unwrap();
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(1)]]);
+        run(&mut backend, "insert into t values (4)").unwrap();
+        drop(backend);
+        assert_eq!(query(&mut open(&path), "select n from t"), vec![vec![Value::Int(1)], vec![Value::Int(4)]]);
+
+        let disk = temp_path("log-backup-disk");
+        drop(Database::open(&disk).unwrap());
+        assert_eq!(open(&path).restore(&disk).unwrap_err().to_string(), "Not a sqrldb log");
+        assert_eq!(query(&mut open(&path), "select count(*) from t"), vec![vec![Value::Int(2)]]);
+    }
+}
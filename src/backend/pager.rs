@@ -0,0 +1,774 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::wal::{Wal, wal_path};
+
+/*
+    The pager splits a database file into fixed size pages and keeps a bounded
+    number of them in memory, the buffer pool. Pages are read from disk the
+    first time they are asked for and written back when the pool is flushed.
+    Changed pages stay in the pool until then, a flush goes through the
+    write-ahead log so that all of its pages reach the file or none do.
+
+    Page 0 is the file header and never enters the pool:
+
+        magic       8 bytes  "SQRLDB01"
+        page count  u32      pages in the file, the header included
+        free list   u32      first free page, 0 when there is none
+        root        u32      first catalog page, 0 when there is none
+
+    A free page stores the id of the next free page in its first 4 bytes.
+    All integers are little endian.
+
+    A savepoint marks where the changes of a statement start, so that they
+    can be dropped on their own, keeping the changes before it. The first
+    time a page is written after the mark the page is kept as it was, and
+    going back to the savepoint puts those pages and the header back.
+
+    A backup is a copy of the database as of the last flush, which is a
+    whole database of its own. It is read from the file without the pager,
+    which goes on flushing meanwhile: while a backup is taken the flushed
+    pages only go to the log, which holds all of them, and stay in the pool
+    until the first checkpoint after it. Restoring a backup writes all of
+    its pages through the log, so a crash halfway leaves either the old
+    database or the backup.
+ */
+
+pub const PAGE_SIZE: usize = 4096;
+pub type PageId = u32;
+pub type Page = [u8; PAGE_SIZE];
+
+const MAGIC: &[u8; 8] = b"SQRLDB01";
+
+struct Frame {
+    data: Box<Page>,
+    dirty: bool,
+    last_used: u64,
+    // The page as of the last flush, when that only reached the log
+    logged: Option<Box<Page>>,
+}
+
+// The pages and header as they were when the savepoint was set, of the
+// pages written since, with whether they were changed already
+struct Savepoint {
+    header: (u32, PageId, PageId),
+    header_dirty: bool,
+    pages: HashMap<PageId, (Box<Page>, bool)>,
+}
+
+pub struct Pager {
+    path: PathBuf,
+    file: File,
+    wal: Wal,
+    frames: HashMap<PageId, Frame>,
+    capacity: usize,
+    clock: u64,
+    page_count: u32,
+    free_head: PageId,
+    root: PageId,
+    header_dirty: bool,
+    // The header as of the last flush, for discard to go back to
+    flushed: (u32, PageId, PageId),
+    // Whether the header of the last flush only reached the log
+    header_logged: bool,
+    // Backups being taken, no checkpoint writes to the file until they are done
+    backups: Arc<AtomicUsize>,
+    savepoint: Option<Savepoint>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(page: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(page[at..at + 4].try_into().unwrap())
+}
+
+// The page count, free list and root of the header of a file `len` bytes long
+fn read_header(header: &[u8], len: u64) -> io::Result<(u32, PageId, PageId)> {
+    if header.len() < 20 || &header[..8] != MAGIC {
+        return Err(invalid("Not a sqrldb file"));
+    }
+    let page_count = read_u32(header, 8);
+    if page_count == 0 || len < page_count as u64 * PAGE_SIZE as u64 {
+        return Err(invalid("Database file is truncated"));
+    }
+    let (free_head, root) = (read_u32(header, 12), read_u32(header, 16));
+    if free_head >= page_count || root >= page_count {
+        return Err(invalid("Database header is corrupt"));
+    }
+    Ok((page_count, free_head, root))
+}
+
+// Page ids are read from the file, in chains and the free list, so one
+// that points outside of it is a corrupt file rather than a bug
+fn check_data_page(id: PageId, page_count: u32) -> io::Result<()> {
+    if id == 0 || id >= page_count {
+        return Err(invalid(&format!("Page {} is not a data page, the database file is corrupt", id)));
+    }
+    Ok(())
+}
+
+impl Pager {
+    // Opens or creates the file at `path`, keeping at most `capacity` pages in memory
+    pub fn open(path: &Path, capacity: usize) -> io::Result<Pager> {
+        assert!(capacity > 0, "the buffer pool needs room for at least one page");
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut wal = Wal::open(path)?;
+
+        // A complete log means the last flush did not get to copy its pages
+        let committed = wal.committed()?;
+        if !committed.is_empty() {
+            for (id, page) in &committed {
+                Self::write_page(&mut file, *id, page)?;
+            }
+            file.sync_data()?;
+        }
+        wal.clear()?;
+
+        let len = file.metadata()?.len();
+        let mut pager = Pager {
+            path: path.to_path_buf(),
+            file,
+            wal,
+            frames: HashMap::new(),
+            capacity,
+            clock: 0,
+            page_count: 1,
+            free_head: 0,
+            root: 0,
+            header_dirty: true,
+            flushed: (1, 0, 0),
+            header_logged: false,
+            backups: Arc::new(AtomicUsize::new(0)),
+            savepoint: None,
+        };
+
+        if len == 0 {
+            pager.flush()?;
+            return Ok(pager);
+        }
+
+        let mut header = [0u8; 20];
+        pager.file.seek(SeekFrom::Start(0))?;
+        pager.file.read_exact(&mut header).map_err(|_| invalid("Not a sqrldb file"))?;
+        (pager.page_count, pager.free_head, pager.root) = read_header(&header, len)?;
+        pager.header_dirty = false;
+        pager.flushed = (pager.page_count, pager.free_head, pager.root);
+        Ok(pager)
+    }
+
+    pub fn root(&self) -> PageId {
+        self.root
+    }
+
+    pub fn set_root(&mut self, root: PageId) {
+        self.root = root;
+        self.header_dirty = true;
+    }
+
+    pub fn read(&mut self, id: PageId) -> io::Result<&Page> {
+        Ok(&self.frame(id)?.data)
+    }
+
+    // Like read, but the page will be written back to disk
+    pub fn write(&mut self, id: PageId) -> io::Result<&mut Page> {
+        self.frame(id)?;
+        let frame = self.frames.get_mut(&id).unwrap();
+        if let Some(savepoint) = &mut self.savepoint {
+            savepoint.pages.entry(id).or_insert_with(|| (frame.data.clone(), frame.dirty));
+        }
+        frame.dirty = true;
+        Ok(&mut frame.data)
+    }
+
+    // Hands out a zeroed page, reusing a free one when there is any
+    pub fn allocate(&mut self) -> io::Result<PageId> {
+        let id = if self.free_head != 0 {
+            let id = self.free_head;
+            self.free_head = read_u32(self.read(id)?, 0);
+            id
+        } else {
+            // Grow the file so the new page can be read back like any other
+            let id = self.page_count;
+            Self::write_page(&mut self.file, id, &[0; PAGE_SIZE])?;
+            self.page_count += 1;
+            id
+        };
+        self.header_dirty = true;
+        self.write(id)?.fill(0);
+        Ok(id)
+    }
+
+    pub fn free(&mut self, id: PageId) -> io::Result<()> {
+        check_data_page(id, self.page_count)?;
+        let next = self.free_head;
+        self.write(id)?[..4].copy_from_slice(&next.to_le_bytes());
+        self.free_head = id;
+        self.header_dirty = true;
+        Ok(())
+    }
+
+    // Writes every dirty page and the header, and waits for the disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.savepoint = None;
+        self.write_log()?;
+        self.checkpoint()
+    }
+
+    // Marks where the changes to keep end, replacing the mark before
+    pub fn savepoint(&mut self) {
+        let header = (self.page_count, self.free_head, self.root);
+        self.savepoint = Some(Savepoint { header, header_dirty: self.header_dirty, pages: HashMap::new() });
+    }
+
+    // Forgets every change since the savepoint
+    pub fn rollback_to_savepoint(&mut self) -> io::Result<()> {
+        let savepoint = self.savepoint.take().ok_or_else(|| io::Error::other("No savepoint to go back to"))?;
+        for (id, (data, dirty)) in savepoint.pages {
+            let frame = self.frames.get_mut(&id).unwrap();
+            frame.data = data;
+            frame.dirty = dirty;
+        }
+        (self.page_count, self.free_head, self.root) = savepoint.header;
+        self.header_dirty = savepoint.header_dirty;
+        Ok(())
+    }
+
+    // Forgets every change since the last flush
+    pub fn discard(&mut self) {
+        self.savepoint = None;
+        self.frames.retain(|_, frame| !frame.dirty || frame.logged.is_some());
+        for frame in self.frames.values_mut().filter(|frame| frame.dirty) {
+            frame.data.clone_from(frame.logged.as_ref().unwrap());
+            frame.dirty = false;
+        }
+        (self.page_count, self.free_head, self.root) = self.flushed;
+        self.header_dirty = false;
+    }
+
+    // The database as of the last flush, to be copied while the pager goes
+    // on. The file stays as it is until the backup is dropped.
+    pub fn backup(&mut self) -> io::Result<Backup> {
+        let file = File::open(&self.path)?;
+        let (page_count, free_head, root) = self.flushed;
+        let mut logged: HashMap<PageId, Box<Page>> =
+            self.frames.iter().filter_map(|(id, frame)| Some((*id, frame.logged.clone()?))).collect();
+        logged.insert(0, Box::new(header(page_count, free_head, root)));
+        self.backups.fetch_add(1, Ordering::SeqCst);
+        let len = page_count as u64 * PAGE_SIZE as u64;
+        Ok(Backup { file, source: self.path.clone(), len, logged, backups: Some(Arc::clone(&self.backups)) })
+    }
+
+    // Replaces every page with the pages of the backup at `path`. Changes
+    // since the last flush are dropped.
+    pub fn restore(&mut self, path: &Path) -> io::Result<()> {
+        if self.backups.load(Ordering::SeqCst) > 0 {
+            return Err(io::Error::other("Cannot restore while a backup is being taken"));
+        }
+        let bytes = std::fs::read(path)?;
+        let (page_count, free_head, root) = read_header(&bytes, bytes.len() as u64)?;
+        let pages: Vec<(PageId, &Page)> = bytes[..page_count as usize * PAGE_SIZE]
+            .chunks_exact(PAGE_SIZE)
+            .enumerate()
+            .map(|(id, page)| (id as PageId, page.try_into().unwrap()))
+            .collect();
+
+        self.discard();
+        self.wal.commit(&pages)?;
+        for (id, page) in &pages {
+            Self::write_page(&mut self.file, *id, page)?;
+        }
+        self.file.set_len(page_count as u64 * PAGE_SIZE as u64)?;
+        self.file.sync_data()?;
+        self.wal.clear()?;
+
+        self.frames.clear();
+        (self.page_count, self.free_head, self.root) = (page_count, free_head, root);
+        self.flushed = (page_count, free_head, root);
+        self.header_logged = false;
+        Ok(())
+    }
+
+    fn header(&self) -> Page {
+        header(self.page_count, self.free_head, self.root)
+    }
+
+    // The pages a flush writes, the changed ones and the ones earlier
+    // flushes only logged
+    fn unwritten(&self) -> Vec<PageId> {
+        let mut ids: Vec<PageId> = self
+            .frames
+            .iter()
+            .filter(|(_, f)| f.dirty || f.logged.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // The first half of a flush, the changed pages go to the log, along
+    // with the pages earlier flushes left there
+    fn write_log(&mut self) -> io::Result<()> {
+        let header = self.header();
+        let mut pages: Vec<(PageId, &Page)> = Vec::new();
+        if self.header_dirty || self.header_logged {
+            pages.push((0, &header));
+        }
+        for id in self.unwritten() {
+            pages.push((id, &self.frames[&id].data));
+        }
+        if pages.is_empty() {
+            return Ok(());
+        }
+        self.wal.commit(&pages)
+    }
+
+    // The second half, the logged pages are copied into the database and the
+    // log is emptied. While a backup is taken they stay in the log and the
+    // pool instead.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        let unwritten = self.unwritten();
+        if unwritten.is_empty() && !self.header_dirty && !self.header_logged {
+            return Ok(());
+        }
+        if self.header_dirty {
+            self.flushed = (self.page_count, self.free_head, self.root);
+        }
+
+        if self.backups.load(Ordering::SeqCst) > 0 {
+            for id in unwritten {
+                let frame = self.frames.get_mut(&id).unwrap();
+                frame.logged = Some(frame.data.clone());
+                frame.dirty = false;
+            }
+            self.header_logged |= self.header_dirty;
+            self.header_dirty = false;
+            return Ok(());
+        }
+
+        for id in unwritten {
+            let frame = self.frames.get_mut(&id).unwrap();
+            Self::write_page(&mut self.file, id, &frame.data)?;
+            frame.dirty = false;
+            frame.logged = None;
+        }
+        if self.header_dirty || self.header_logged {
+            let header = self.header();
+            Self::write_page(&mut self.file, 0, &header)?;
+            self.header_dirty = false;
+            self.header_logged = false;
+        }
+
+        self.file.sync_data()?;
+        self.wal.clear()?;
+
+        // Pages held past the capacity can go now that they are clean
+        self.evict_to(self.capacity);
+        Ok(())
+    }
+
+    fn write_page(file: &mut File, id: PageId, data: &Page) -> io::Result<()> {
+        file.seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        file.write_all(data)
+    }
+
+    fn frame(&mut self, id: PageId) -> io::Result<&mut Frame> {
+        check_data_page(id, self.page_count)?;
+        self.clock += 1;
+
+        if !self.frames.contains_key(&id) {
+            self.evict_to(self.capacity - 1);
+            let mut data = Box::new([0; PAGE_SIZE]);
+            self.file.seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+            self.file.read_exact(&mut data[..])?;
+            self.frames.insert(id, Frame { data, dirty: false, last_used: 0, logged: None });
+        }
+
+        let frame = self.frames.get_mut(&id).unwrap();
+        frame.last_used = self.clock;
+        Ok(frame)
+    }
+
+    // Drops least recently used unchanged pages until at most `size` pages
+    // are left in the pool. Changed pages may only reach the file through the
+    // log, so when every page has changed the pool grows past its capacity
+    // until the next flush, or the next checkpoint for pages a flush only logged.
+    fn evict_to(&mut self, size: usize) {
+        while self.frames.len() > size {
+            let clean = self.frames.iter().filter(|(_, f)| !f.dirty && f.logged.is_none());
+            let clean = clean.min_by_key(|(_, f)| f.last_used);
+            let Some((&id, _)) = clean else {
+                return;
+            };
+            self.frames.remove(&id);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    #[cfg(test)]
+    fn cached(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+fn header(page_count: u32, free_head: PageId, root: PageId) -> Page {
+    let mut header = [0u8; PAGE_SIZE];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&page_count.to_le_bytes());
+    header[12..16].copy_from_slice(&free_head.to_le_bytes());
+    header[16..20].copy_from_slice(&root.to_le_bytes());
+    header
+}
+
+/*
+    A database as of a flush, see Pager::backup. Its pages are read from a
+    file handle of its own, or from `logged` for those the flush only left
+    in the log. The pager writes nothing to them while the backup lives.
+
+    A storage engine that only ever adds to the end of its file backs up
+    the start of it instead, see Backup::of_file.
+ */
+pub struct Backup {
+    file: File,
+    source: PathBuf,
+    // The bytes of the file the copy is made of
+    len: u64,
+    logged: HashMap<PageId, Box<Page>>,
+    // Of the pager the backup is of
+    backups: Option<Arc<AtomicUsize>>,
+}
+
+impl Backup {
+    // The first `len` bytes of the file at `path`, which nothing may write
+    // over while the backup lives
+    pub fn of_file(path: &Path, len: u64) -> io::Result<Backup> {
+        let file = File::open(path)?;
+        Ok(Backup { file, source: path.to_path_buf(), len, logged: HashMap::new(), backups: None })
+    }
+
+    // Writes the database into a new file at `path`. The copy is written
+    // next to `path` and renamed over it once it is on disk, so `path`
+    // never holds half a backup.
+    pub fn write(mut self, path: &Path) -> io::Result<()> {
+        if path.exists() && std::fs::canonicalize(path)? == std::fs::canonicalize(&self.source)? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot back up a database onto itself"));
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push("-partial");
+        let partial = PathBuf::from(partial);
+
+        let mut copy = File::create(&partial)?;
+        let mut page = Box::new([0u8; PAGE_SIZE]);
+        self.file.seek(SeekFrom::Start(0))?;
+        for (id, at) in (0..self.len).step_by(PAGE_SIZE).enumerate() {
+            let len = (self.len - at).min(PAGE_SIZE as u64) as usize;
+            self.file.read_exact(&mut page[..len])?;
+            copy.write_all(&self.logged.get(&(id as PageId)).unwrap_or(&page)[..len])?;
+        }
+        copy.sync_all()?;
+
+        // A log left next to `path` by an older database would be replayed
+        // into the backup when it is opened
+        match std::fs::remove_file(wal_path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        std::fs::rename(&partial, path)
+    }
+}
+
+impl Drop for Backup {
+    fn drop(&mut self) {
+        if let Some(backups) = &self.backups {
+            backups.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// A fresh file path under the system temp directory
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("sqrldb-{}-{}-{}.db", name, std::process::id(), n));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_file_has_only_a_header() {
+        let path = temp_path("pager-new");
+        let pager = Pager::open(&path, 4).unwrap();
+        assert_eq!(pager.page_count(), 1);
+        assert_eq!(pager.root(), 0);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn test_pages_survive_reopening() {
+        let path = temp_path("pager-reopen");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let id = pager.allocate().unwrap();
+        pager.write(id).unwrap()[..5].copy_from_slice(b"hello");
+        pager.set_root(id);
+        pager.flush().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!(pager.root(), id);
+        assert_eq!(&pager.read(id).unwrap()[..5], b"hello");
+    }
+
+    #[test]
+    fn test_pool_evicts_clean_pages() {
+        let path = temp_path("pager-evict");
+        let mut pager = Pager::open(&path, 2).unwrap();
+        let ids: Vec<PageId> = (0..5).map(|_| pager.allocate().unwrap()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            pager.write(*id).unwrap()[0] = i as u8 + 1;
+        }
+        // Nothing can be evicted before the flush
+        assert_eq!(pager.cached(), 5);
+        pager.flush().unwrap();
+
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(pager.read(*id).unwrap()[0], i as u8 + 1);
+            assert!(pager.cached() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_logged_flush_is_replayed_on_open() {
+        let path = temp_path("pager-replay");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let id = pager.allocate().unwrap();
+        pager.write(id).unwrap()[0] = 1;
+        pager.flush().unwrap();
+
+        // Crash after the log is written but before the pages are copied
+        pager.write(id).unwrap()[0] = 2;
+        pager.set_root(id);
+        pager.write_log().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!(pager.root(), id);
+        assert_eq!(pager.read(id).unwrap()[0], 2);
+        assert_eq!(std::fs::metadata(super::super::wal::wal_path(&path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_unflushed_changes_are_lost_whole() {
+        let path = temp_path("pager-lost");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let a = pager.allocate().unwrap();
+        let b = pager.allocate().unwrap();
+        pager.flush().unwrap();
+
+        // Crash before anything reaches the log
+        pager.write(a).unwrap()[0] = 1;
+        pager.write(b).unwrap()[0] = 1;
+        drop(pager);
+
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!(pager.read(a).unwrap()[0], 0);
+        assert_eq!(pager.read(b).unwrap()[0], 0);
+    }
+
+    #[test]
+    fn test_freed_pages_are_reused() {
+        let path = temp_path("pager-free");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let a = pager.allocate().unwrap();
+        let b = pager.allocate().unwrap();
+        pager.write(b).unwrap()[100] = 7;
+        pager.free(a).unwrap();
+        pager.free(b).unwrap();
+        pager.flush().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!(pager.allocate().unwrap(), b);
+        assert_eq!(pager.read(b).unwrap()[100], 0, "reused pages come back zeroed");
+        assert_eq!(pager.allocate().unwrap(), a);
+        assert_eq!(pager.allocate().unwrap(), 3);
+        assert_eq!(pager.page_count(), 4);
+    }
+
+    #[test]
+    fn test_discard_goes_back_to_the_last_flush() {
+        let path = temp_path("pager-discard");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let a = pager.allocate().unwrap();
+        pager.write(a).unwrap()[0] = 1;
+        pager.flush().unwrap();
+
+        pager.write(a).unwrap()[0] = 2;
+        let b = pager.allocate().unwrap();
+        pager.set_root(b);
+        pager.discard();
+
+        assert_eq!(pager.read(a).unwrap()[0], 1);
+        assert_eq!(pager.root(), 0);
+        assert_eq!(pager.allocate().unwrap(), b, "the discarded page is handed out again");
+    }
+
+    #[test]
+    fn test_savepoint_keeps_the_changes_before_it() {
+        let path = temp_path("pager-savepoint");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let a = pager.allocate().unwrap();
+        pager.write(a).unwrap()[0] = 1;
+
+        pager.savepoint();
+        pager.write(a).unwrap()[0] = 2;
+        let b = pager.allocate().unwrap();
+        pager.set_root(b);
+        pager.rollback_to_savepoint().unwrap();
+
+        assert_eq!((pager.read(a).unwrap()[0], pager.root()), (1, 0));
+        assert_eq!(pager.allocate().unwrap(), b, "the page allocated after the savepoint is handed out again");
+        assert!(pager.rollback_to_savepoint().is_err());
+        pager.flush().unwrap();
+        drop(pager);
+
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!((pager.read(a).unwrap()[0], pager.page_count()), (1, 3));
+    }
+
+    #[test]
+    fn test_backup_holds_the_last_flush() {
+        let (path, copy) = (temp_path("pager-backup"), temp_path("pager-backup-copy"));
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let id = pager.allocate().unwrap();
+        pager.write(id).unwrap()[0] = 1;
+        pager.set_root(id);
+        pager.flush().unwrap();
+
+        // Not flushed yet, so not in the backup
+        pager.write(id).unwrap()[0] = 2;
+        pager.allocate().unwrap();
+        pager.backup().unwrap().write(&copy).unwrap();
+        assert_eq!(std::fs::metadata(&copy).unwrap().len(), 2 * PAGE_SIZE as u64);
+        let err = pager.backup().unwrap().write(&path).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot back up a database onto itself");
+
+        let mut backup = Pager::open(&copy, 4).unwrap();
+        assert_eq!((backup.root(), backup.read(id).unwrap()[0]), (id, 1));
+
+        pager.flush().unwrap();
+        assert_eq!(pager.page_count(), 3);
+        pager.restore(&copy).unwrap();
+        assert_eq!((pager.page_count(), pager.read(id).unwrap()[0]), (2, 1));
+        drop(pager);
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!((pager.root(), pager.read(id).unwrap()[0]), (id, 1));
+    }
+
+    #[test]
+    fn test_flushes_wait_in_the_log_while_a_backup_is_taken() {
+        let (path, copy) = (temp_path("pager-backup-flush"), temp_path("pager-backup-flush-copy"));
+        let mut pager = Pager::open(&path, 1).unwrap();
+        let (a, b) = (pager.allocate().unwrap(), pager.allocate().unwrap());
+        pager.write(a).unwrap()[0] = 1;
+        pager.flush().unwrap();
+
+        let backup = pager.backup().unwrap();
+        pager.write(a).unwrap()[0] = 2;
+        pager.set_root(a);
+        pager.flush().unwrap();
+        // Logged pages stay in the pool, a discard goes back to them
+        pager.write(a).unwrap()[0] = 3;
+        pager.write(b).unwrap()[0] = 3;
+        pager.discard();
+        assert_eq!((pager.root(), pager.read(a).unwrap()[0], pager.read(b).unwrap()[0]), (a, 2, 0));
+        assert_eq!(pager.cached(), 2);
+        let err = pager.restore(&copy).unwrap_err();
+        assert_eq!(err.to_string(), "Cannot restore while a backup is being taken");
+
+        // A backup taken now has the flush, the one taken before does not
+        let later = temp_path("pager-backup-flush-later");
+        pager.backup().unwrap().write(&later).unwrap();
+        backup.write(&copy).unwrap();
+        let mut copied = Pager::open(&copy, 4).unwrap();
+        assert_eq!((copied.root(), copied.read(a).unwrap()[0]), (0, 1));
+        let mut copied = Pager::open(&later, 4).unwrap();
+        assert_eq!((copied.root(), copied.read(a).unwrap()[0]), (a, 2));
+
+        // The log holds every flush since, for a crash to replay
+        pager.write(b).unwrap()[0] = 4;
+        let held = pager.backup().unwrap();
+        pager.flush().unwrap();
+        drop(held);
+        drop(pager);
+        let mut pager = Pager::open(&path, 4).unwrap();
+        assert_eq!((pager.root(), pager.read(a).unwrap()[0], pager.read(b).unwrap()[0]), (a, 2, 4));
+
+        // With no backup left the next flush writes the file again
+        let held = pager.backup().unwrap();
+        pager.write(b).unwrap()[0] = 5;
+        pager.flush().unwrap();
+        drop(held);
+        pager.flush().unwrap();
+        assert_eq!(std::fs::metadata(super::super::wal::wal_path(&path)).unwrap().len(), 0);
+        drop(pager);
+        assert_eq!(Pager::open(&path, 4).unwrap().read(b).unwrap()[0], 5);
+    }
+
+    #[test]
+    fn test_restore_rejects_other_files() {
+        let (path, other) = (temp_path("pager-restore"), temp_path("pager-restore-other"));
+        let mut pager = Pager::open(&path, 4).unwrap();
+        std::fs::write(&other, b"definitely not a database").unwrap();
+        assert_eq!(pager.restore(&other).unwrap_err().to_string(), "Not a sqrldb file");
+        std::fs::write(&other, &std::fs::read(&path).unwrap()[..100]).unwrap();
+        assert_eq!(pager.restore(&other).unwrap_err().to_string(), "Database file is truncated");
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let path = temp_path("pager-garbage");
+        std::fs::write(&path, b"definitely not a database").unwrap();
+        let err = Pager::open(&path, 4).err().unwrap();
+        assert_eq!(err.to_string(), "Not a sqrldb file");
+    }
+
+    #[test]
+    fn test_corrupt_page_ids_are_errors() {
+        let path = temp_path("pager-corrupt");
+        let mut pager = Pager::open(&path, 4).unwrap();
+        let id = pager.allocate().unwrap();
+        pager.set_root(id);
+        pager.flush().unwrap();
+
+        // Ids read from a page rather than passed by mistake
+        let err = pager.read(999).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Page 999 is not a data page, the database file is corrupt");
+        assert_eq!(pager.free(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        drop(pager);
+
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(16)).unwrap();
+        file.write_all(&999u32.to_le_bytes()).unwrap();
+        drop(file);
+        let err = Pager::open(&path, 4).err().unwrap();
+        assert_eq!(err.to_string(), "Database header is corrupt");
+        assert!(crate::Database::open(&path).is_err());
+    }
+}
@@ -0,0 +1,437 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+
+use super::aggregate;
+use super::cancel;
+use super::index::Lookup;
+use super::{
+    BackendError, ResultSet, Row, Scope, Source, SourceRows, Storage, Value, compare, eval, holds, run_subqueries,
+    select_rows,
+};
+use crate::lexer::Location;
+use crate::parser::{ColumnDef, Expr, JoinKind, OrderBy, QualifiedName, Select, SetOperator, TableRef};
+
+/*
+    A select is run in two steps. It is planned first, by the planner: its
+    tables are loaded, its names are checked and it becomes a tree of
+    operators, each producing rows from the rows of the operators below it.
+    The tree is then executed, or shown by EXPLAIN.
+
+        Limit       skips OFFSET rows and stops after LIMIT
+        Sort        orders on the ORDER BY keys
+        SetOp       combines the rows of two selects, UNION and the like
+        Distinct    drops the rows it has seen before, for SELECT DISTINCT
+        Project     computes the select list, followed by the sort keys
+        Filter      keeps the rows WHERE, or HAVING, holds for
+        Aggregate   turns the rows into one row per group
+        Join        pairs the rows of two operators, in a nested loop
+        Scan        reads a table, all of it or what an index finds
+        Subquery    runs the plan of a subquery in FROM
+
+    IN subqueries are run by the operator whose expressions hold them, once,
+    before it evaluates anything.
+
+    Rows pass from operator to operator in batches of BATCH_SIZE rows, a
+    join's batches can be larger. Scans, filters, projections, joins on
+    their left side and limits work one batch at a time, pulling the next
+    from below only when asked for it, so a query never holds more of a
+    table than one batch unless an operator needs all of it: a sort, an
+    aggregate, which keeps a running result per group instead of rows, and
+    the right side of a join. A limit stops reading once it has its rows.
+
+    Distinct, UNION, INTERSECT and EXCEPT hash rows to find the ones they
+    have seen. A union and a distinct stream, keeping only a set of the
+    rows passed on so far. INTERSECT and EXCEPT hold the rows of their
+    right side, counted, and stream their left side past them.
+ */
+
+pub type Load<'l, 'a> = &'l dyn Fn(&QualifiedName) -> Result<Source<'a>, BackendError>;
+
+pub const BATCH_SIZE: usize = 1024;
+
+// Rows are borrowed from the tables as long as they pass through unchanged
+type Batch<'p> = Vec<Cow<'p, [Value]>>;
+
+// The batches of an operator, each computed when it is asked for
+type Batches<'p> = Box<dyn Iterator<Item = Result<Batch<'p>, BackendError>> + 'p>;
+
+// Rows an operator already has all of, handed on a batch at a time
+fn batches<'p>(rows: Batch<'p>) -> Batches<'p> {
+    let mut rows = rows.into_iter();
+    Box::new(std::iter::from_fn(move || {
+        let batch: Batch = rows.by_ref().take(BATCH_SIZE).collect();
+        (!batch.is_empty()).then_some(Ok(batch))
+    }))
+}
+
+// The `count` rows of a table a storage engine keeps, read a batch at a
+// time, in order for a full scan or by their positions when an index found
+// them
+fn stored<'p>(
+    storage: &'p dyn Storage,
+    table: &'p str,
+    count: usize,
+    positions: Option<Vec<usize>>,
+    loc: Location,
+) -> Batches<'p> {
+    let io_error = move |err: io::Error| BackendError::new(format!("I/O error: {}", err), loc);
+    let Some(positions) = positions else {
+        return Box::new((0..count).step_by(BATCH_SIZE).map(move |start| {
+            cancel::check(loc)?;
+            let rows = storage.scan_range(table, start..(start + BATCH_SIZE).min(count)).map_err(io_error)?;
+            Ok(rows.into_iter().map(Cow::Owned).collect())
+        }));
+    };
+    Box::new((0..positions.len()).step_by(BATCH_SIZE).map(move |start| {
+        cancel::check(loc)?;
+        let range = start..(start + BATCH_SIZE).min(positions.len());
+        let rows = storage.get(table, &positions[range]).map_err(io_error)?;
+        Ok(rows.into_iter().map(Cow::Owned).collect())
+    }))
+}
+
+// The batches with every row whose first `width` columns were seen in an
+// earlier row left out
+fn distinct<'p>(batches: Batches<'p>, width: usize) -> Batches<'p> {
+    let mut seen: HashSet<Vec<Value>> = HashSet::new();
+    Box::new(batches.map(move |batch| {
+        let mut batch = batch?;
+        batch.retain(|row| seen.insert(row[..width.min(row.len())].to_vec()));
+        Ok(batch)
+    }))
+}
+
+// Every row of every batch
+fn collect<'p>(batches: Batches<'p>) -> Result<Batch<'p>, BackendError> {
+    let mut rows = Vec::new();
+    for batch in batches {
+        rows.extend(batch?);
+    }
+    Ok(rows)
+}
+
+pub struct Plan<'a> {
+    pub root: Node<'a>,
+    // The names of the output columns, the rows of the root can carry their
+    // sort keys after these
+    pub columns: Vec<String>,
+}
+
+pub enum Node<'a> {
+    Scan { table: TableRef, source: Source<'a>, lookup: Option<Lookup> },
+    // A subquery's columns are typed text, which nothing in a select reads
+    Subquery { qualifier: Vec<String>, columns: Vec<ColumnDef>, plan: Box<Plan<'a>> },
+    Join { kind: JoinKind, left: Box<Node<'a>>, right: Box<Node<'a>>, on: Expr, loc: Location },
+    Filter { input: Box<Node<'a>>, predicate: Expr, clause: &'static str, loc: Location },
+    Aggregate { input: Box<Node<'a>>, group_by: Vec<Expr>, calls: Vec<Expr>, slots: Vec<ColumnDef>, loc: Location },
+    Project { input: Box<Node<'a>>, exprs: Vec<Expr>, loc: Location },
+    // Distinct rows are told apart by their first `width` columns, sort
+    // keys that follow them are not compared
+    Distinct { input: Box<Node<'a>>, width: usize },
+    SetOp { op: SetOperator, all: bool, left: Box<Node<'a>>, right: Box<Node<'a>> },
+    // The sort key of each of `keys` is the column at the same place in
+    // `positions`
+    Sort { input: Box<Node<'a>>, keys: Vec<OrderBy>, positions: Vec<usize> },
+    Limit { input: Box<Node<'a>>, limit: Option<usize>, offset: usize },
+}
+
+fn run_all(
+    exprs: &[Expr],
+    select: &mut dyn FnMut(&Select) -> Result<ResultSet, BackendError>,
+) -> Result<Vec<Expr>, BackendError> {
+    exprs.iter().map(|expr| run_subqueries(expr, select)).collect()
+}
+
+impl<'a> Plan<'a> {
+    pub fn execute(&self, load: Load<'_, 'a>) -> Result<ResultSet, BackendError> {
+        let rows = self.rows(load)?.collect::<Result<_, _>>()?;
+        Ok(ResultSet { columns: self.columns.clone(), rows })
+    }
+
+    // The rows one at a time, each batch computed once the rows before it
+    // have been read
+    pub fn rows<'p>(
+        &'p self,
+        load: Load<'_, 'a>,
+    ) -> Result<impl Iterator<Item = Result<Vec<Value>, BackendError>> + 'p, BackendError> {
+        let width = self.columns.len();
+        Ok(self.root.execute(load)?.flat_map(move |batch| match batch {
+            Ok(batch) => batch.into_iter().map(|row| Ok(row[..width].to_vec())).collect(),
+            Err(err) => vec![Err(err)],
+        }))
+    }
+}
+
+impl<'a> Node<'a> {
+    // What the node's rows are made of. Rows past a projection are only
+    // read by position.
+    pub fn scope(&self) -> Scope<'_> {
+        match self {
+            Node::Scan { table, source, .. } => Scope::single(table.qualifier(), &source.columns),
+            Node::Subquery { qualifier, columns, .. } => Scope::single(qualifier, columns),
+            Node::Join { left, right, .. } => {
+                let mut scope = left.scope();
+                scope.tables.extend(right.scope().tables);
+                scope
+            }
+            Node::Aggregate { slots, .. } => Scope::single(&[], slots),
+            Node::Filter { input, .. } | Node::Sort { input, .. } | Node::Limit { input, .. } => input.scope(),
+            Node::Project { .. } | Node::Distinct { .. } | Node::SetOp { .. } => Scope { tables: Vec::new() },
+        }
+    }
+
+    // Opens the node and the nodes below it, running their subqueries. The
+    // batches are computed as they are read.
+    fn execute<'p>(&'p self, load: Load<'_, 'a>) -> Result<Batches<'p>, BackendError> {
+        let mut select = |query: &Select| select_rows(query, load);
+        match self {
+            Node::Scan { table, source, lookup } => {
+                let positions = lookup.as_ref().map(|lookup| source.indexes[lookup.index].find(lookup));
+                let loc = table.loc();
+                let rows = match &source.rows {
+                    SourceRows::Loaded(rows) => rows,
+                    SourceRows::Stored { storage, table, count } => {
+                        return Ok(stored(*storage, table, *count, positions, loc));
+                    }
+                };
+                let count = positions.as_ref().map_or(rows.len(), Vec::len);
+                Ok(Box::new((0..count).step_by(BATCH_SIZE).map(move |start| {
+                    cancel::check(loc)?;
+                    let range = start..(start + BATCH_SIZE).min(count);
+                    let batch: Vec<&Vec<Value>> = match &positions {
+                        Some(positions) => positions[range].iter().map(|&i| &rows[i]).collect(),
+                        None => rows[range].iter().collect(),
+                    };
+                    Ok(batch.into_iter().map(|row| Cow::Borrowed(row.as_slice())).collect())
+                })))
+            }
+            Node::Subquery { plan, .. } => Ok(batches(plan.execute(load)?.rows.into_iter().map(Cow::Owned).collect())),
+            // A left join also keeps a row that found no match, with nulls
+            // for the other side
+            Node::Join { kind, left, right, on, loc } => {
+                let scope = self.scope();
+                let on = run_subqueries(on, &mut select)?;
+                let right_rows = collect(right.execute(load)?)?;
+                let width: usize = right.scope().tables.iter().map(|(_, columns)| columns.len()).sum();
+                let (kind, loc) = (*kind, *loc);
+                Ok(Box::new(left.execute(load)?.map(move |batch| {
+                    let mut joined = Vec::new();
+                    for left in batch? {
+                        // A row of the left side can take as long as a whole scan of the right
+                        cancel::check(loc)?;
+                        let mut matched = false;
+                        for right in &right_rows {
+                            let values: Vec<Value> = left.iter().chain(right.iter()).cloned().collect();
+                            if holds(&on, &Row { scope: &scope, values: &values }, "ON", loc)? {
+                                joined.push(Cow::Owned(values));
+                                matched = true;
+                            }
+                        }
+                        if !matched && kind == JoinKind::Left {
+                            let mut values = left.into_owned();
+                            values.resize(values.len() + width, Value::Null);
+                            joined.push(Cow::Owned(values));
+                        }
+                    }
+                    Ok(joined)
+                })))
+            }
+            Node::Filter { input, predicate, clause, loc } => {
+                let scope = input.scope();
+                let predicate = run_subqueries(predicate, &mut select)?;
+                let (clause, loc) = (*clause, *loc);
+                Ok(Box::new(input.execute(load)?.map(move |batch| {
+                    let mut kept = Vec::new();
+                    for values in batch? {
+                        if holds(&predicate, &Row { scope: &scope, values: &values }, clause, loc)? {
+                            kept.push(values);
+                        }
+                    }
+                    Ok(kept)
+                })))
+            }
+            Node::Aggregate { input, group_by, calls, loc, .. } => {
+                let (group_by, calls) = (run_all(group_by, &mut select)?, run_all(calls, &mut select)?);
+                let scope = input.scope();
+                let mut groups = aggregate::Groups::new(&group_by, &calls)?;
+                for batch in input.execute(load)? {
+                    groups.add(&scope, &batch?, *loc)?;
+                }
+                Ok(batches(groups.finish(*loc)?.into_iter().map(Cow::Owned).collect()))
+            }
+            Node::Project { input, exprs, loc } => {
+                let scope = input.scope();
+                let exprs = run_all(exprs, &mut select)?;
+                let loc = *loc;
+                Ok(Box::new(input.execute(load)?.map(move |batch| {
+                    let mut projected = Vec::new();
+                    for values in batch? {
+                        let row = Row { scope: &scope, values: &values };
+                        let values = exprs.iter().map(|expr| eval(expr, Some(&row), loc)).collect::<Result<_, _>>()?;
+                        projected.push(Cow::Owned(values));
+                    }
+                    Ok(projected)
+                })))
+            }
+            Node::Distinct { input, width } => Ok(distinct(input.execute(load)?, *width)),
+            Node::SetOp { op: SetOperator::Union, all, left, right } => {
+                let rows = Box::new(left.execute(load)?.chain(right.execute(load)?));
+                Ok(if *all { rows } else { distinct(rows, usize::MAX) })
+            }
+            // With ALL a row of the right side cancels out one row of the
+            // left, without it every equal row
+            Node::SetOp { op, all, left, right } => {
+                let mut counts: HashMap<Vec<Value>, usize> = HashMap::new();
+                for batch in right.execute(load)? {
+                    for row in batch? {
+                        *counts.entry(row.into_owned()).or_default() += 1;
+                    }
+                }
+                let (intersect, all) = (*op == SetOperator::Intersect, *all);
+                let mut seen = HashSet::new();
+                Ok(Box::new(left.execute(load)?.map(move |batch| {
+                    let mut kept = Vec::new();
+                    for row in batch? {
+                        let found = match counts.get_mut(&*row) {
+                            Some(count) if all && *count > 0 => {
+                                *count -= 1;
+                                true
+                            }
+                            Some(_) => !all,
+                            None => false,
+                        };
+                        if found == intersect && (all || seen.insert(row.to_vec())) {
+                            kept.push(row);
+                        }
+                    }
+                    Ok(kept)
+                })))
+            }
+            // The sort is stable, rows with equal keys keep their order
+            Node::Sort { input, keys, positions } => {
+                let mut rows = collect(input.execute(load)?)?;
+                rows.sort_by(|left, right| {
+                    positions
+                        .iter()
+                        .zip(keys)
+                        .map(|(&i, key)| {
+                            let (l, r) = (&left[i], &right[i]);
+                            if key.descending { compare(r, l) } else { compare(l, r) }
+                        })
+                        .find(|ordering| ordering.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                });
+                Ok(batches(rows))
+            }
+            Node::Limit { input, limit, offset } => {
+                let mut input = input.execute(load)?;
+                let (mut skip, mut left) = (*offset, limit.unwrap_or(usize::MAX));
+                Ok(Box::new(std::iter::from_fn(move || {
+                    while left > 0 {
+                        let mut batch = match input.next()? {
+                            Ok(batch) => batch,
+                            Err(err) => return Some(Err(err)),
+                        };
+                        let skipped = skip.min(batch.len());
+                        batch.drain(..skipped);
+                        skip -= skipped;
+                        batch.truncate(left);
+                        left -= batch.len();
+                        if !batch.is_empty() {
+                            return Some(Ok(batch));
+                        }
+                    }
+                    None
+                })))
+            }
+        }
+    }
+
+    // How many rows the node produces at most, going by the size of its
+    // tables
+    pub fn estimate(&self) -> usize {
+        match self {
+            Node::Scan { source, .. } => source.rows.len(),
+            Node::Subquery { plan, .. } => plan.root.estimate(),
+            Node::Join { left, right, .. } => left.estimate().saturating_mul(right.estimate()),
+            Node::Limit { input, limit: Some(limit), .. } => input.estimate().min(*limit),
+            Node::SetOp { op: SetOperator::Union, left, right, .. } => left.estimate().saturating_add(right.estimate()),
+            Node::SetOp { left, .. } => left.estimate(),
+            Node::Filter { input, .. }
+            | Node::Distinct { input, .. }
+            | Node::Aggregate { input, .. }
+            | Node::Project { input, .. }
+            | Node::Sort { input, .. }
+            | Node::Limit { input, .. } => input.estimate(),
+        }
+    }
+
+    fn children(&self) -> Vec<&Node<'a>> {
+        match self {
+            Node::Scan { .. } => vec![],
+            Node::Subquery { plan, .. } => vec![&plan.root],
+            Node::Join { left, right, .. } | Node::SetOp { left, right, .. } => vec![left, right],
+            Node::Filter { input, .. }
+            | Node::Distinct { input, .. }
+            | Node::Aggregate { input, .. }
+            | Node::Project { input, .. }
+            | Node::Sort { input, .. }
+            | Node::Limit { input, .. } => vec![input],
+        }
+    }
+
+    // The node's line without its children, `Filter: (age > 30)`
+    fn describe(&self) -> String {
+        let list = |exprs: &[Expr]| exprs.iter().map(|expr| expr.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            Node::Scan { table, source, lookup: Some(lookup) } => {
+                format!("Index Scan on {} using {}: {}", table, source.indexes[lookup.index].name, lookup.condition)
+            }
+            Node::Scan { table, lookup: None, .. } => format!("Seq Scan on {}", table),
+            Node::Subquery { qualifier, .. } => format!("Subquery Scan on {}", qualifier.join(".")),
+            Node::Join { kind: JoinKind::Inner, on, .. } => format!("Nested Loop Join: {}", on),
+            Node::Join { kind: JoinKind::Left, on, .. } => format!("Nested Loop Left Join: {}", on),
+            Node::Filter { predicate, .. } => format!("Filter: {}", predicate),
+            Node::Aggregate { group_by, calls, .. } => {
+                let mut parts = Vec::new();
+                if !calls.is_empty() {
+                    parts.push(list(calls));
+                }
+                if !group_by.is_empty() {
+                    parts.push(format!("group by {}", list(group_by)));
+                }
+                match parts.is_empty() {
+                    true => "Aggregate".to_string(),
+                    false => format!("Aggregate: {}", parts.join("; ")),
+                }
+            }
+            Node::Project { exprs, .. } => format!("Project: {}", list(exprs)),
+            Node::Distinct { .. } => "Distinct".to_string(),
+            Node::SetOp { op, all, .. } => format!("{}{}", op.as_str(), if *all { " ALL" } else { "" }),
+            Node::Sort { keys, .. } => {
+                format!("Sort: {}", keys.iter().map(|key| key.to_string()).collect::<Vec<_>>().join(", "))
+            }
+            Node::Limit { limit: Some(limit), offset: 0, .. } => format!("Limit: {}", limit),
+            Node::Limit { limit: Some(limit), offset, .. } => format!("Limit: {} offset {}", limit, offset),
+            Node::Limit { limit: None, offset, .. } => format!("Limit: all offset {}", offset),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        if depth > 0 {
+            write!(f, "\n{:indent$}-> ", "", indent = depth * 2)?;
+        }
+        write!(f, "{}", self.describe())?;
+        self.children().into_iter().try_for_each(|child| child.write(f, depth + 1))
+    }
+}
+
+// One operator per line, each indented below the operator reading its rows
+impl fmt::Display for Plan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.root.write(f, 0)
+    }
+}
@@ -0,0 +1,389 @@
+use std::{iter, mem};
+
+use super::aggregate::{self, Grouping};
+use super::function;
+use super::index::{choose, conjuncts};
+use super::plan::{Load, Node, Plan};
+use super::{BackendError, Scope, Source, Value, eval, visit};
+use crate::lexer::Location;
+use crate::parser::{
+    BinaryOp, ColumnDef, DataType, Expr, JoinKind, QualifiedName, Select, SelectItem, SetOperation, TableRef,
+    TableSource,
+};
+
+/*
+    The planner turns a select into the plan that runs it. Most of the plan
+    follows from the clauses, in the order SQL defines them, but FROM, the
+    joins and WHERE leave room to choose:
+
+    - WHERE and the ON of inner joins are split on AND, and each part is
+      evaluated as early as the tables it reads allow. A part reading one
+      table filters that table's scan, one reading several is checked where
+      the join bringing in the last of them is.
+    - A scan answers one of the parts pushed down to it with an index when
+      one fits, and only the rest are left to filter on.
+    - Inner joins are run in order of table size, smallest first, each step
+      joining a table a condition ties to those already joined when there is
+      one. Fewer rows are carried along and no cross product is built where a
+      condition could be used instead.
+
+    A left join pads the rows of its left side that match nothing with nulls,
+    which a condition on its right side has to see. WHERE parts reading that
+    side stay above the join, its ON is only pushed down to the right side's
+    scan, and a select with left joins keeps its joins in the order given.
+
+    The selects of a UNION, INTERSECT or EXCEPT are planned one by one and
+    combined in the order the operations come in. The ORDER BY of the whole
+    can only name columns of the result, which are all its rows hold.
+ */
+
+// A part of WHERE or of an ON, with the tables it reads
+struct Conjunct {
+    expr: Expr,
+    clause: &'static str,
+    tables: Vec<usize>,
+    loc: Location,
+}
+
+// ORDER BY may name an output column by its alias, which stands for the
+// expression it was given to
+fn resolve_alias(expr: &Expr, items: &[SelectItem]) -> Expr {
+    if let Expr::Column(name) = expr
+        && let [alias] = name.parts.as_slice()
+    {
+        for item in items {
+            if let SelectItem::Expr { expr: source, alias: Some(a) } = item
+                && a == alias
+            {
+                return source.clone();
+            }
+        }
+    }
+    expr.clone()
+}
+
+// Columns keep their name and calls take the function's, as in PostgreSQL
+fn output_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Column(name) => name.parts.last().unwrap().clone(),
+        Expr::Function { name, .. } => name.to_string().to_lowercase(),
+        _ => "?column?".to_string(),
+    }
+}
+
+// Fails on the first column an expression mentions that the tables lack,
+// so a bad select is reported even when no row would be evaluated
+fn check_references(expr: &Expr, scope: &Scope) -> Result<(), BackendError> {
+    let mut result = Ok(());
+    visit(expr, &mut |expr| {
+        if let Expr::Column(name) = expr
+            && result.is_ok()
+        {
+            result = scope.resolve(name).map(|_| ());
+        }
+    });
+    result
+}
+
+// The row count of a LIMIT or OFFSET, None when it is null and so no limit
+fn row_count(expr: Option<&Expr>, clause: &str, loc: Location) -> Result<Option<usize>, BackendError> {
+    let Some(expr) = expr else {
+        return Ok(None);
+    };
+    match eval(expr, None, loc)? {
+        Value::Null => Ok(None),
+        Value::Int(n) if n >= 0 => Ok(Some(n as usize)),
+        value => Err(BackendError::new(format!("Expected a non-negative int for {}, got {}", clause, value), loc)),
+    }
+}
+
+
+// The tables of the scope an expression mentions. A column that does not
+// resolve has been reported already.
+fn tables_of(expr: &Expr, scope: &Scope) -> Vec<usize> {
+    let mut tables = Vec::new();
+    visit(expr, &mut |expr| {
+        if let Expr::Column(name) = expr
+            && let Ok(table) = scope.table_of(name)
+            && !tables.contains(&table)
+        {
+            tables.push(table);
+        }
+    });
+    tables
+}
+
+// The parts joined by AND, as a Chain when there are many of them, like the
+// parser builds
+fn conjunction(parts: Vec<Expr>) -> Option<Expr> {
+    let mut parts = parts.into_iter();
+    let first = parts.next()?;
+    let mut rest: Vec<_> = parts.map(|part| (BinaryOp::And, part)).collect();
+    Some(match rest.len() {
+        0 => first,
+        1 => Expr::Binary { left: Box::new(first), op: BinaryOp::And, right: Box::new(rest.pop()?.1) },
+        _ => Expr::Chain { first: Box::new(first), rest },
+    })
+}
+
+// The order to join the tables in, starting with the smallest
+fn join_order(sizes: &[usize], parts: &[Conjunct]) -> Vec<usize> {
+    let mut order: Vec<usize> = Vec::new();
+    let mut rest: Vec<usize> = (0..sizes.len()).collect();
+    while !rest.is_empty() {
+        let tied = |table: &usize| {
+            parts.iter().any(|part| {
+                part.tables.len() > 1
+                    && part.tables.contains(table)
+                    && part.tables.iter().all(|t| t == table || order.contains(t))
+            })
+        };
+        let next = match rest.iter().copied().filter(tied).min_by_key(|t| sizes[*t]) {
+            Some(table) => table,
+            None => rest.iter().copied().min_by_key(|t| sizes[*t]).expect("a table is left"),
+        };
+        order.push(next);
+        rest.retain(|t| *t != next);
+    }
+    order
+}
+
+// A filter for each clause the parts come from, so a part that is not a
+// bool is reported for its own clause
+fn filters<'a>(mut node: Node<'a>, parts: Vec<Conjunct>) -> Node<'a> {
+    for clause in ["ON", "WHERE"] {
+        let parts: Vec<&Conjunct> = parts.iter().filter(|part| part.clause == clause).collect();
+        let Some(loc) = parts.first().map(|part| part.loc) else {
+            continue;
+        };
+        let predicate = conjunction(parts.into_iter().map(|part| part.expr.clone()).collect()).unwrap();
+        node = Node::Filter { input: Box::new(node), predicate, clause, loc };
+    }
+    node
+}
+
+// A table with the parts pushed down to it. The part an index answers is
+// not filtered on again, the index finds exactly the rows it holds for.
+fn scan<'a>(mut leaf: Node<'a>, mut parts: Vec<Conjunct>, loc: Location) -> Node<'a> {
+    if let Node::Scan { table, source, lookup } = &mut leaf {
+        let predicate = conjunction(parts.iter().map(|part| part.expr.clone()).collect());
+        *lookup = choose(predicate.as_ref(), table.qualifier(), &source.columns, source.indexes, loc);
+        if let Some(lookup) = lookup {
+            parts.retain(|part| part.expr != lookup.condition);
+        }
+    }
+    filters(leaf, parts)
+}
+
+// FROM, the joins and WHERE as a tree of scans, joins and filters
+fn join_tree<'a>(query: &Select, leaves: Vec<Node<'a>>, loc: Location) -> Node<'a> {
+    let scope = Scope { tables: leaves.iter().flat_map(|leaf| leaf.scope().tables).collect() };
+    let conjunct = |expr: &Expr, clause, loc| {
+        Conjunct { expr: expr.clone(), clause, tables: tables_of(expr, &scope), loc }
+    };
+    let locs: Vec<Location> = query.tables().map(TableRef::loc).collect();
+    // The tables a left join pads with nulls
+    let padded: Vec<bool> =
+        iter::once(false).chain(query.joins.iter().map(|join| join.kind == JoinKind::Left)).collect();
+
+    // What each table is filtered on, and the ON of the join bringing it in
+    let mut pushed: Vec<Vec<Conjunct>> = leaves.iter().map(|_| Vec::new()).collect();
+    let mut on: Vec<Vec<Expr>> = leaves.iter().map(|_| Vec::new()).collect();
+    let mut parts: Vec<Conjunct> = Vec::new();
+    for part in query.filter.iter().flat_map(conjuncts) {
+        parts.push(conjunct(part, "WHERE", loc));
+    }
+    for (table, join) in query.joins.iter().enumerate().map(|(i, join)| (i + 1, join)) {
+        for part in conjuncts(&join.on) {
+            let part = conjunct(part, "ON", locs[table]);
+            match join.kind {
+                JoinKind::Inner => parts.push(part),
+                JoinKind::Left if part.tables == [table] => pushed[table].push(part),
+                JoinKind::Left => on[table].push(part.expr),
+            }
+        }
+    }
+
+    let order = match padded.contains(&true) {
+        true => (0..leaves.len()).collect(),
+        false => join_order(&leaves.iter().map(Node::estimate).collect::<Vec<_>>(), &parts),
+    };
+    let mut rank = vec![0; leaves.len()];
+    for (position, table) in order.iter().enumerate() {
+        rank[*table] = position;
+    }
+
+    // Each part goes to the table of its own, or to the join of the table
+    // joined last, as ON for an inner join and above any join otherwise
+    let mut above: Vec<Vec<Conjunct>> = leaves.iter().map(|_| Vec::new()).collect();
+    for part in parts {
+        let last = part.tables.iter().copied().max_by_key(|table| rank[*table]).unwrap_or(order[0]);
+        match part.tables.len() {
+            0 | 1 if !padded[last] => pushed[last].push(part),
+            _ if part.clause == "ON" && !padded[last] => on[last].push(part.expr),
+            _ => above[last].push(part),
+        }
+    }
+
+    let mut leaves: Vec<Option<Node>> = leaves.into_iter().map(Some).collect();
+    let mut leaf = |table: usize| scan(leaves[table].take().unwrap(), mem::take(&mut pushed[table]), loc);
+    let mut node = leaf(order[0]);
+    for &table in &order[1..] {
+        let kind = if padded[table] { JoinKind::Left } else { JoinKind::Inner };
+        let condition = conjunction(mem::take(&mut on[table])).unwrap_or(Expr::BoolLiteral(true));
+        let (left, right) = (Box::new(node), Box::new(leaf(table)));
+        node = Node::Join { kind, left, right, on: condition, loc: locs[table] };
+        node = filters(node, mem::take(&mut above[table]));
+    }
+    node
+}
+
+// Plans a select, loading its tables through `load`
+pub fn plan<'a>(query: &Select, load: Load<'_, 'a>) -> Result<Plan<'a>, BackendError> {
+    if query.set_operations.is_empty() {
+        return plan_select(query, load);
+    }
+    let loc = query.from.loc();
+    let first = Select { set_operations: Vec::new(), order_by: Vec::new(), limit: None, offset: None, ..query.clone() };
+    let Plan { mut root, columns } = plan_select(&first, load)?;
+    for SetOperation { op, all, query: operand } in &query.set_operations {
+        let right = plan(operand, load)?;
+        if right.columns.len() != columns.len() {
+            return Err(BackendError::new(
+                format!("Each {} query must have the same number of columns", op.as_str()),
+                operand.from.loc(),
+            ));
+        }
+        root = Node::SetOp { op: *op, all: *all, left: Box::new(root), right: Box::new(right.root) };
+    }
+
+    let mut positions = Vec::new();
+    for key in &query.order_by {
+        let position = match &key.expr {
+            Expr::Column(name) if name.parts.len() == 1 => columns.iter().position(|column| *column == name.parts[0]),
+            _ => None,
+        };
+        let Some(position) = position else {
+            let loc = if let Expr::Column(name) = &key.expr { name.loc } else { loc };
+            return Err(BackendError::new(
+                format!("ORDER BY of a {} can only name its columns, got {}", query.set_operations[0].op.as_str(), key),
+                loc,
+            ));
+        };
+        positions.push(position);
+    }
+    if !positions.is_empty() {
+        root = Node::Sort { input: Box::new(root), keys: query.order_by.clone(), positions };
+    }
+    Ok(Plan { root: limit(root, query, loc)?, columns })
+}
+
+// LIMIT and OFFSET on top of the node, when the select has them
+fn limit<'a>(node: Node<'a>, query: &Select, loc: Location) -> Result<Node<'a>, BackendError> {
+    let offset = row_count(query.offset.as_ref(), "OFFSET", loc)?.unwrap_or(0);
+    let limit = row_count(query.limit.as_ref(), "LIMIT", loc)?;
+    if limit.is_some() || offset > 0 {
+        return Ok(Node::Limit { input: Box::new(node), limit, offset });
+    }
+    Ok(node)
+}
+
+// Plans a select that has no set operations
+fn plan_select<'a>(query: &Select, load: Load<'_, 'a>) -> Result<Plan<'a>, BackendError> {
+    let loc = query.from.loc();
+    let tables: Vec<&TableRef> = query.tables().collect();
+    for (i, table) in tables.iter().enumerate() {
+        if tables[..i].iter().any(|t| t.qualifier() == table.qualifier()) {
+            return Err(BackendError::new(
+                format!("Table {} specified more than once", table.qualifier().join(".")),
+                table.loc(),
+            ));
+        }
+    }
+
+    // A view is planned as the subquery it stands for, under its own name
+    // unless it has an alias
+    let subquery = |table: &TableRef, subquery: &Select| -> Result<Node<'a>, BackendError> {
+        let plan = plan(subquery, load)?;
+        let loc = table.loc();
+        let column = |name: &String| ColumnDef {
+            name: name.clone(),
+            data_type: DataType::Text,
+            constraints: Vec::new(),
+            default: None,
+            loc,
+        };
+        let columns = plan.columns.iter().map(column).collect();
+        Ok(Node::Subquery { qualifier: table.qualifier().to_vec(), columns, plan: Box::new(plan) })
+    };
+    let mut leaves = Vec::new();
+    for table in &tables {
+        leaves.push(match &table.source {
+            TableSource::Table(name) => match load(name)? {
+                Source { view: Some(view), .. } => subquery(table, view)?,
+                source => Node::Scan { table: (*table).clone(), source, lookup: None },
+            },
+            TableSource::Subquery(query) => subquery(table, query)?,
+        });
+    }
+
+    // The select list with the wildcard spelled out, and the output names
+    let scope = Scope { tables: leaves.iter().flat_map(|leaf| leaf.scope().tables).collect() };
+    let mut outputs = Vec::new();
+    let mut names = Vec::new();
+    for item in &query.columns {
+        match item {
+            SelectItem::Wildcard => {
+                // Joined columns are qualified, two tables can share a column name
+                for (qualifier, columns) in &scope.tables {
+                    let qualifier = if query.joins.is_empty() { &[] } else { *qualifier };
+                    for column in columns.iter() {
+                        let parts = qualifier.iter().chain([&column.name]).cloned().collect();
+                        outputs.push(Expr::Column(QualifiedName { parts, loc }));
+                        names.push(column.name.clone());
+                    }
+                }
+            }
+            SelectItem::Expr { expr, alias } => {
+                outputs.push(expr.clone());
+                names.push(alias.clone().unwrap_or_else(|| output_name(expr)));
+            }
+        }
+    }
+
+    // Sort keys are computed from the whole row, so a select can be
+    // ordered by columns it does not return
+    let mut keys: Vec<Expr> = query.order_by.iter().map(|key| resolve_alias(&key.expr, &query.columns)).collect();
+    let clauses = outputs.iter().chain(&keys).chain(&query.group_by).chain(&query.having);
+    // Only the column types of tables are known, not those of subqueries
+    let typed: Vec<bool> = leaves.iter().map(|leaf| matches!(leaf, Node::Scan { .. })).collect();
+    for expr in clauses.clone().chain(&query.filter).chain(query.joins.iter().map(|join| &join.on)) {
+        check_references(expr, &scope)?;
+        function::check(expr, &scope, &typed)?;
+    }
+    let grouped = !query.group_by.is_empty() || clauses.clone().any(aggregate::contains_aggregate);
+
+    let mut node = join_tree(query, leaves, loc);
+
+    if grouped {
+        let grouping = Grouping::new(query, &outputs, &keys, loc)?;
+        let slots = grouping.slots(loc);
+        let Grouping { group_by, calls, items, keys: sort_keys, having } = grouping;
+        node = Node::Aggregate { input: Box::new(node), group_by, calls, slots, loc };
+        if let Some(having) = having {
+            node = Node::Filter { input: Box::new(node), predicate: having, clause: "HAVING", loc };
+        }
+        (outputs, keys) = (items, sort_keys);
+    }
+
+    let width = outputs.len();
+    let positions = (width..width + keys.len()).collect();
+    node = Node::Project { input: Box::new(node), exprs: outputs.into_iter().chain(keys).collect(), loc };
+    if query.distinct {
+        node = Node::Distinct { input: Box::new(node), width };
+    }
+    if !query.order_by.is_empty() {
+        node = Node::Sort { input: Box::new(node), keys: query.order_by.clone(), positions };
+    }
+    Ok(Plan { root: limit(node, query, loc)?, columns: names })
+}
@@ -0,0 +1,761 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use super::foreign_key::{self, Schema};
+use super::index::{self, Index};
+use super::pager::Backup;
+use super::{
+    Backend, BackendError, Entry, InsertFrom, ResultSet, Source, SourceRows, Value, already_exists, alter_table,
+    catalog_or_view, check_columns, check_view, create_index, delete_rows, entry, explain_rows, has_index, has_view,
+    insert_rows, move_indexes, not_in_a_file, renamed_references, select_rows, unknown_view, update_rows,
+};
+use crate::lexer::Location;
+use crate::parser::{AlterOp, Assignment, ColumnDef, Expr, QualifiedName, Select};
+
+/*
+    The boundary between the executor and where rows are kept, for storage
+    engines that need nothing but somewhere to put tables and rows. A
+    Storage is handed definitions and rows, and StorageBackend does the
+    rest, the checks of constraints and foreign keys, the planning and the
+    indexes, the same way the in memory and disk backends do.
+
+    Rows are known by their position in their table, counting from 0 in the
+    order they were appended. A storage engine reads them back in that
+    order, a range of them at a time on a scan and the ones asked for on a
+    point lookup, which is how the executor reads what an index found.
+    Writes append rows, write rows over others at their positions, or
+    remove rows, the rows after them moving up to close the gap. Only ALTER
+    TABLE replaces all of a table's rows, when it changes every one of them.
+    An engine gets the positional writes done by reading and replacing the
+    whole table unless it has a better way.
+
+    Every write is pending until commit. Outside a transaction each
+    statement commits as it ends, or rolls back if it fails partway through
+    its writes. Inside one, begin comes first and commit or rollback only
+    once the transaction ends, so an engine sees the writes of several
+    statements before the next commit. Each of those statements starts
+    with a savepoint, and one that fails partway goes back to it, leaving
+    the writes of the statements before.
+
+    An engine that keeps its tables in a file can also copy them, as they
+    were committed, into a backup, and replace them with the ones of such a
+    copy. StorageBackend reads the tables again after a restore.
+ */
+
+// A table's definition, the columns of a table or the query of a view
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDef {
+    pub columns: Vec<ColumnDef>,
+    pub indexes: Vec<IndexDef>,
+    // The last number given to the auto-increment column
+    pub sequence: i64,
+    pub view: Option<Select>,
+}
+
+// An index is only its name and the position of its column, its entries
+// are kept in memory and rebuilt from the rows
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDef {
+    pub name: String,
+    pub column: usize,
+}
+
+pub trait Storage: Send {
+    // Every table and view, as committed, read once when it is opened
+    fn tables(&self) -> io::Result<Vec<(String, TableDef)>>;
+
+    fn scan(&self, table: &str) -> io::Result<Vec<Vec<Value>>>;
+
+    // How many rows the table has
+    fn count(&self, table: &str) -> io::Result<usize> {
+        Ok(self.scan(table)?.len())
+    }
+
+    // The rows at `range`, which all exist
+    fn scan_range(&self, table: &str, range: Range<usize>) -> io::Result<Vec<Vec<Value>>> {
+        Ok(self.scan(table)?.drain(range).collect())
+    }
+
+    // The rows at `positions`, in that order. Each position is one of a row
+    // that exists.
+    fn get(&self, table: &str, positions: &[usize]) -> io::Result<Vec<Vec<Value>>> {
+        let rows = self.scan(table)?;
+        Ok(positions.iter().map(|&i| rows[i].clone()).collect())
+    }
+
+    // Creates the table or view, or replaces its definition, keeping its rows
+    fn define(&mut self, table: &str, def: &TableDef) -> io::Result<()>;
+    fn drop(&mut self, table: &str) -> io::Result<()>;
+
+    fn append(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()>;
+    fn replace(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()>;
+
+    // Writes each row over the one at its position
+    fn update(&mut self, table: &str, rows: &[(usize, Vec<Value>)]) -> io::Result<()> {
+        let mut all = self.scan(table)?;
+        for (i, row) in rows {
+            all[*i] = row.clone();
+        }
+        self.replace(table, &all)
+    }
+
+    // Removes the rows at `positions`, which are in ascending order
+    fn delete(&mut self, table: &str, positions: &[usize]) -> io::Result<()> {
+        let mut all = self.scan(table)?;
+        let mut positions = positions.iter().peekable();
+        let mut i = 0;
+        all.retain(|_| {
+            i += 1;
+            positions.next_if_eq(&&(i - 1)).is_none()
+        });
+        self.replace(table, &all)
+    }
+
+    fn begin(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn commit(&mut self) -> io::Result<()>;
+    // Drops every write since the last commit
+    fn rollback(&mut self) -> io::Result<()>;
+
+    // Inside a transaction, marks where the writes of a statement start,
+    // and drops the writes made since the mark, keeping the ones before it
+    fn savepoint(&mut self) -> io::Result<()>;
+    fn rollback_to_savepoint(&mut self) -> io::Result<()>;
+
+    fn backup(&mut self) -> io::Result<Backup> {
+        Err(not_in_a_file())
+    }
+
+    // Only called outside of a transaction
+    fn restore(&mut self, _path: &Path) -> io::Result<()> {
+        Err(not_in_a_file())
+    }
+}
+
+#[derive(Clone)]
+struct Table {
+    columns: Vec<ColumnDef>,
+    indexes: Vec<Index>,
+    sequence: i64,
+    view: Option<Select>,
+    // How many rows the storage holds for it
+    count: usize,
+}
+
+impl Entry for Table {
+    fn columns(&self) -> &[ColumnDef] {
+        &self.columns
+    }
+
+    fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    fn view(&self) -> Option<&Select> {
+        self.view.as_ref()
+    }
+}
+
+impl Table {
+    fn def(&self) -> TableDef {
+        let indexes = self.indexes.iter().filter(|index| !index.key);
+        let indexes = indexes.map(|index| IndexDef { name: index.name.clone(), column: index.column });
+        TableDef {
+            columns: self.columns.clone(),
+            indexes: indexes.collect(),
+            sequence: self.sequence,
+            view: self.view.clone(),
+        }
+    }
+}
+
+// A backend for any Storage
+pub struct StorageBackend {
+    storage: Box<dyn Storage>,
+    tables: HashMap<String, Table>,
+    // The tables as they were at BEGIN, put back by ROLLBACK
+    snapshot: Option<HashMap<String, Table>>,
+}
+
+impl StorageBackend {
+    pub fn new(storage: Box<dyn Storage>) -> io::Result<StorageBackend> {
+        let tables = read_tables(storage.as_ref())?;
+        Ok(StorageBackend { storage, tables, snapshot: None })
+    }
+
+    fn table(&self, name: &QualifiedName) -> Result<&Table, BackendError> {
+        entry(&self.tables, name)
+    }
+
+    fn load_rows(&self, table: &QualifiedName) -> Result<Vec<Vec<Value>>, BackendError> {
+        self.table(table)?;
+        self.storage.scan(&table.to_string()).map_err(|e| io_error(e, table.loc))
+    }
+
+    // A table's rows are left in the storage, for the scan to read
+    fn load(&self, name: &QualifiedName) -> Result<Source<'_>, BackendError> {
+        if let Some(source) = catalog_or_view(&self.tables, name) {
+            return Ok(source);
+        }
+        self.table(name)?;
+        let (key, table) = self.tables.get_key_value(&name.to_string()).unwrap();
+        let rows = SourceRows::Stored { storage: self.storage.as_ref(), table: key, count: table.count };
+        Ok(Source { columns: Cow::Borrowed(&table.columns), rows, indexes: &table.indexes, view: None })
+    }
+
+    // The tables for foreign keys to be checked against, reading rows fails
+    // at the location of `table`
+    fn schema<'a>(&'a self, table: &'a QualifiedName) -> Schema<'a> {
+        Schema::new(
+            self.tables.iter().map(|(name, table)| (name.as_str(), table.columns(), table.indexes())),
+            Box::new(move |name| {
+                if !self.tables.contains_key(name) {
+                    return Ok(Cow::Borrowed(&[]));
+                }
+                self.storage.scan(name).map(Cow::Owned).map_err(|e| io_error(e, table.loc))
+            }),
+        )
+    }
+
+    // Makes a statement's writes and, outside a transaction, commits them.
+    // Whatever the storage did of a statement that fails is undone, by a
+    // rollback outside a transaction and by going back to the statement's
+    // savepoint inside one. The tables are only changed by the caller once
+    // this succeeded.
+    fn write(&mut self, loc: Location, f: impl FnOnce(&mut dyn Storage) -> io::Result<()>) -> Result<(), BackendError> {
+        let storage = self.storage.as_mut();
+        if self.snapshot.is_none() {
+            return f(storage).and_then(|()| storage.commit()).map_err(|err| {
+                let _ = storage.rollback();
+                io_error(err, loc)
+            });
+        }
+
+        storage.savepoint().map_err(|e| io_error(e, loc))?;
+        let Err(err) = f(storage) else {
+            return Ok(());
+        };
+        // The storage no longer matches the tables unless the statement's
+        // writes are gone, the whole transaction is dropped if they cannot be
+        if storage.rollback_to_savepoint().is_err() {
+            let _ = storage.rollback();
+            self.tables = self.snapshot.take().unwrap();
+            return Err(BackendError::new(format!("I/O error: {}, the transaction was rolled back", err), loc));
+        }
+        Err(io_error(err, loc))
+    }
+
+    // Replaces the rows of the table called `key` in the tables, once the
+    // storage has them
+    fn set_rows(&mut self, key: &str, rows: &[Vec<Value>]) {
+        let table = self.tables.get_mut(key).unwrap();
+        table.count = rows.len();
+        table.indexes.iter_mut().for_each(|index| index.rebuild(rows));
+    }
+}
+
+// The definitions the storage holds, with the rows of the tables that have
+// indexes, or unique columns to give keys, read to rebuild them
+fn read_tables(storage: &dyn Storage) -> io::Result<HashMap<String, Table>> {
+    let mut tables = HashMap::new();
+    for (name, def) in storage.tables()? {
+        if let Some(index) = def.indexes.iter().find(|index| index.column >= def.columns.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Index {} of {} is on a column that does not exist", index.name, name),
+            ));
+        }
+        let indexed = !def.indexes.is_empty() || def.columns.iter().any(ColumnDef::is_unique);
+        let rows = if indexed { storage.scan(&name)? } else { Vec::new() };
+        let count = if def.view.is_some() { 0 } else if rows.is_empty() { storage.count(&name)? } else { rows.len() };
+        let mut indexes: Vec<Index> =
+            def.indexes.into_iter().map(|index| Index::new(index.name, index.column, &rows)).collect();
+        index::update_keys(&mut indexes, &name, &def.columns, &rows);
+        let table = Table { columns: def.columns, indexes, sequence: def.sequence, view: def.view, count };
+        tables.insert(name, table);
+    }
+    Ok(tables)
+}
+
+fn io_error(err: io::Error, loc: Location) -> BackendError {
+    BackendError::new(format!("I/O error: {}", err), loc)
+}
+
+// The writes that turn a table's rows `before` into `after`, which holds
+// the same rows in the same order, less the ones deleted and with others
+// changed: the positions of the rows to delete, and then the rows to write
+// over the ones at their positions once those are gone
+fn changes(before: &[Vec<Value>], after: &[Vec<Value>]) -> (Vec<usize>, Vec<(usize, Vec<Value>)>) {
+    let (mut deleted, mut updated) = (Vec::new(), Vec::new());
+    let mut j = 0;
+    for (i, row) in before.iter().enumerate() {
+        if after.get(j) == Some(row) {
+            j += 1;
+        } else if before.len() - i > after.len() - j {
+            deleted.push(i);
+        } else {
+            updated.push((j, after[j].clone()));
+            j += 1;
+        }
+    }
+    (deleted, updated)
+}
+
+impl Backend for StorageBackend {
+    fn create_table(&mut self, name: &QualifiedName, columns: &[ColumnDef]) -> Result<(), BackendError> {
+        let key = name.to_string();
+        if self.tables.contains_key(&key) {
+            return Err(already_exists(&self.tables, name));
+        }
+        check_columns(name, columns, &self.schema(name))?;
+
+        let mut table = Table { columns: columns.to_vec(), indexes: Vec::new(), sequence: 0, view: None, count: 0 };
+        index::update_keys(&mut table.indexes, &key, columns, &[]);
+        self.write(name.loc, |storage| storage.define(&key, &table.def()))?;
+        self.tables.insert(key, table);
+        Ok(())
+    }
+
+    fn create_index(
+        &mut self,
+        name: &QualifiedName,
+        table: &QualifiedName,
+        column: &QualifiedName,
+    ) -> Result<(), BackendError> {
+        let taken = self.has_index(name);
+        let rows = self.load_rows(table)?;
+        let index = create_index(name, table, column, &self.table(table)?.columns, &rows, taken)?;
+
+        let key = table.to_string();
+        let mut def = self.tables[&key].def();
+        def.indexes.push(IndexDef { name: index.name.clone(), column: index.column });
+        self.write(name.loc, |storage| storage.define(&key, &def))?;
+        self.tables.get_mut(&key).unwrap().indexes.push(index);
+        Ok(())
+    }
+
+    fn insert(
+        &mut self,
+        table: &QualifiedName,
+        columns: &[QualifiedName],
+        rows: InsertFrom,
+    ) -> Result<usize, BackendError> {
+        let entry = self.table(table)?;
+        let mut sequence = entry.sequence;
+        let load = |name: &QualifiedName| self.load(name);
+        let indexes = &entry.indexes;
+        let values =
+            insert_rows(table, &entry.columns, indexes, columns, rows, &load, &mut sequence, &self.schema(table))?;
+
+        let key = table.to_string();
+        let mut def = (sequence != entry.sequence).then(|| entry.def());
+        if let Some(def) = &mut def {
+            def.sequence = sequence;
+        }
+        self.write(table.loc, |storage| {
+            storage.append(&key, &values)?;
+            def.map_or(Ok(()), |def| storage.define(&key, &def))
+        })?;
+
+        let entry = self.tables.get_mut(&key).unwrap();
+        entry.count += values.len();
+        entry.sequence = sequence;
+        for row in &values {
+            entry.indexes.iter_mut().for_each(|index| index.push(row));
+        }
+        Ok(values.len())
+    }
+
+    fn select(&self, query: &Select) -> Result<ResultSet, BackendError> {
+        select_rows(query, &|name| self.load(name))
+    }
+
+    fn explain(&self, query: &Select) -> Result<ResultSet, BackendError> {
+        explain_rows(query, &|name| self.load(name))
+    }
+
+    fn update(
+        &mut self,
+        table: &QualifiedName,
+        assignments: &[Assignment],
+        filter: Option<&Expr>,
+    ) -> Result<usize, BackendError> {
+        let mut rows = self.load_rows(table)?;
+        let entry = self.table(table)?;
+        let (columns, indexes) = (&entry.columns, &entry.indexes);
+        let changes = update_rows(table, columns, &rows, indexes, assignments, filter, &self.schema(table))?;
+        let count = changes.len();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        for (i, updated) in &changes {
+            rows[*i] = updated.clone();
+        }
+        let key = table.to_string();
+        self.write(table.loc, |storage| storage.update(&key, &changes))?;
+        self.set_rows(&key, &rows);
+        Ok(count)
+    }
+
+    fn delete(&mut self, table: &QualifiedName, filter: Option<&Expr>) -> Result<usize, BackendError> {
+        let rows = self.load_rows(table)?;
+        let entry = self.table(table)?;
+        let keep = delete_rows(table, &entry.columns, &rows, &entry.indexes, filter)?;
+        let count = keep.iter().filter(|k| !**k).count();
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut changed = foreign_key::on_delete(table, &rows, &keep, &self.schema(table))?;
+        if changed.is_empty() {
+            let kept = rows.iter().zip(&keep).filter(|(_, k)| **k).map(|(row, _)| row.clone()).collect();
+            changed.insert(table.to_string(), kept);
+        }
+        let key = table.to_string();
+        let mut writes = Vec::with_capacity(changed.len());
+        for (name, after) in &changed {
+            let (deleted, updated) = if *name == key {
+                changes(&rows, after)
+            } else {
+                changes(&self.storage.scan(name).map_err(|e| io_error(e, table.loc))?, after)
+            };
+            writes.push((name, deleted, updated));
+        }
+
+        // Every table the delete reaches is written before the one commit
+        self.write(table.loc, |storage| {
+            for (name, deleted, updated) in &writes {
+                if !deleted.is_empty() {
+                    storage.delete(name, deleted)?;
+                }
+                if !updated.is_empty() {
+                    storage.update(name, updated)?;
+                }
+            }
+            Ok(())
+        })?;
+        for (name, rows) in &changed {
+            self.set_rows(name, rows);
+        }
+        Ok(count)
+    }
+
+    fn columns(&self, table: &QualifiedName) -> Result<Vec<ColumnDef>, BackendError> {
+        Ok(self.table(table)?.columns.clone())
+    }
+
+    fn alter_table(&mut self, table: &QualifiedName, op: &AlterOp) -> Result<(), BackendError> {
+        // A rename leaves the rows alone, there is no need to read them
+        let rows = match op {
+            AlterOp::RenameColumn { .. } => Vec::new(),
+            _ => self.load_rows(table)?,
+        };
+        let entry = self.table(table)?;
+        let altered = alter_table(table, &entry.columns, &rows, &entry.indexes, op, &self.schema(table))?;
+
+        let key = table.to_string();
+        let mut entry = entry.clone();
+        entry.columns = altered.columns;
+        move_indexes(&mut entry.indexes, &altered.index_columns);
+        index::update_keys(&mut entry.indexes, &key, &entry.columns, altered.rows.as_deref().unwrap_or(&rows));
+        let mut changed = vec![(key.clone(), entry)];
+        // The foreign keys of other tables follow a renamed column
+        if let AlterOp::RenameColumn { column, name } = op {
+            for (other, columns) in renamed_references(&self.tables, table, column.parts.last().unwrap(), name) {
+                let mut entry = self.tables[&other].clone();
+                entry.columns = columns;
+                changed.push((other, entry));
+            }
+        }
+
+        self.write(table.loc, |storage| {
+            changed.iter().try_for_each(|(name, entry)| storage.define(name, &entry.def()))?;
+            altered.rows.as_ref().map_or(Ok(()), |rows| storage.replace(&key, rows))
+        })?;
+        self.tables.extend(changed);
+        if let Some(rows) = &altered.rows {
+            self.set_rows(&key, rows);
+        }
+        Ok(())
+    }
+
+    fn drop_table(&mut self, name: &QualifiedName) -> Result<(), BackendError> {
+        if let Some(referencing) = self.schema(name).referenced_by(&name.to_string(), None) {
+            return Err(BackendError::new(
+                format!("Cannot drop table {}, {} references it", name, referencing),
+                name.loc,
+            ));
+        }
+        self.table(name)?;
+        let key = name.to_string();
+        self.write(name.loc, |storage| storage.drop(&key))?;
+        self.tables.remove(&key);
+        Ok(())
+    }
+
+    fn has_index(&self, name: &QualifiedName) -> bool {
+        has_index(&self.tables, name)
+    }
+
+    fn create_view(&mut self, name: &QualifiedName, query: &Select) -> Result<(), BackendError> {
+        let key = name.to_string();
+        if self.tables.contains_key(&key) {
+            return Err(already_exists(&self.tables, name));
+        }
+        check_view(query, &|name| self.load(name))?;
+
+        let view = Some(query.clone());
+        let table = Table { columns: Vec::new(), indexes: Vec::new(), sequence: 0, view, count: 0 };
+        self.write(name.loc, |storage| storage.define(&key, &table.def()))?;
+        self.tables.insert(key, table);
+        Ok(())
+    }
+
+    fn drop_view(&mut self, name: &QualifiedName) -> Result<(), BackendError> {
+        if !self.has_view(name) {
+            return Err(unknown_view(name));
+        }
+        let key = name.to_string();
+        self.write(name.loc, |storage| storage.drop(&key))?;
+        self.tables.remove(&key);
+        Ok(())
+    }
+
+    fn has_view(&self, name: &QualifiedName) -> bool {
+        has_view(&self.tables, name)
+    }
+
+    fn begin(&mut self, loc: Location) -> Result<(), BackendError> {
+        if self.snapshot.is_some() {
+            return Err(super::already_in_transaction(loc));
+        }
+        self.storage.begin().map_err(|e| io_error(e, loc))?;
+        self.snapshot = Some(self.tables.clone());
+        Ok(())
+    }
+
+    // A commit that fails drops the transaction, as if it was rolled back
+    fn commit(&mut self, loc: Location) -> Result<(), BackendError> {
+        let snapshot = self.snapshot.take().ok_or_else(|| super::no_transaction(loc))?;
+        if let Err(err) = self.storage.commit() {
+            let _ = self.storage.rollback();
+            self.tables = snapshot;
+            return Err(io_error(err, loc));
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self, loc: Location) -> Result<(), BackendError> {
+        self.tables = self.snapshot.take().ok_or_else(|| super::no_transaction(loc))?;
+        self.storage.rollback().map_err(|e| io_error(e, loc))
+    }
+
+    // A transaction in progress is left out of the backup, it goes on
+    fn backup(&mut self) -> io::Result<Backup> {
+        self.storage.backup()
+    }
+
+    fn restore(&mut self, path: &Path) -> io::Result<()> {
+        if self.snapshot.is_some() {
+            return Err(io::Error::other("Cannot restore a database in the middle of a transaction"));
+        }
+        self.storage.restore(path)?;
+        self.tables = read_tables(self.storage.as_ref())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::super::log::LogStorage;
+    use super::super::pager::temp_path;
+    use super::super::{QueryResult, execute};
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    // A log that fails to write definitions when told to, and counts the
+    // times a table is read whole
+    struct Faulty {
+        log: LogStorage,
+        fail: Arc<AtomicBool>,
+        scans: Arc<AtomicUsize>,
+    }
+
+    impl Storage for Faulty {
+        fn tables(&self) -> io::Result<Vec<(String, TableDef)>> {
+            self.log.tables()
+        }
+
+        fn scan(&self, table: &str) -> io::Result<Vec<Vec<Value>>> {
+            self.scans.fetch_add(1, Ordering::SeqCst);
+            self.log.scan(table)
+        }
+
+        fn scan_range(&self, table: &str, range: Range<usize>) -> io::Result<Vec<Vec<Value>>> {
+            self.log.scan_range(table, range)
+        }
+
+        fn define(&mut self, table: &str, def: &TableDef) -> io::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(io::Error::other("disk full"));
+            }
+            self.log.define(table, def)
+        }
+
+        fn drop(&mut self, table: &str) -> io::Result<()> {
+            self.log.drop(table)
+        }
+
+        fn append(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+            self.log.append(table, rows)
+        }
+
+        fn replace(&mut self, table: &str, rows: &[Vec<Value>]) -> io::Result<()> {
+            self.log.replace(table, rows)
+        }
+
+        fn commit(&mut self) -> io::Result<()> {
+            self.log.commit()
+        }
+
+        fn rollback(&mut self) -> io::Result<()> {
+            self.log.rollback()
+        }
+
+        fn savepoint(&mut self) -> io::Result<()> {
+            self.log.savepoint()
+        }
+
+        fn rollback_to_savepoint(&mut self) -> io::Result<()> {
+            self.log.rollback_to_savepoint()
+        }
+    }
+
+    fn open(path: &std::path::Path) -> (StorageBackend, Arc<AtomicBool>, Arc<AtomicUsize>) {
+        let (fail, scans) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+        let log = LogStorage::open(path).unwrap();
+        let storage = Faulty { log, fail: Arc::clone(&fail), scans: Arc::clone(&scans) };
+        (StorageBackend::new(Box::new(storage)).unwrap(), fail, scans)
+    }
+
+    fn run(backend: &mut StorageBackend, source: &str) -> Result<QueryResult, BackendError> {
+        let mut result = QueryResult::Done;
+        for tokens in split_statements(lex(source).unwrap()) {
+            result = execute(backend, &parse(tokens).unwrap())?;
+        }
+        Ok(result)
+    }
+
+    fn query(backend: &mut StorageBackend, source: &str) -> Vec<Vec<Value>> {
+        match run(backend, source).unwrap() {
+            QueryResult::Rows(rows) => rows.rows,
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_a_statement_failing_partway_leaves_the_transaction_as_it_was() {
+        let path = temp_path("storage-partial");
+        let (mut backend, fail, _) = open(&path);
+        run(&mut backend, "create table t (id serial primary key, n int); begin").unwrap();
+        run(&mut backend, "insert into t (n) values (1)").unwrap();
+
+        // The insert appends its row, then fails to write the new sequence
+        fail.store(true, Ordering::SeqCst);
+        let err = run(&mut backend, "insert into t (n) values (2)").unwrap_err();
+        assert_eq!(err.message(), "I/O error: disk full");
+        fail.store(false, Ordering::SeqCst);
+
+        assert_eq!(query(&mut backend, "select n from t"), vec![vec![Value::Int(1)]]);
+        run(&mut backend, "insert into t (n) values (3); commit").unwrap();
+        drop(backend);
+
+        let (mut backend, _, _) = open(&path);
+        let rows = vec![vec![Value::Int(1), Value::Int(1)], vec![Value::Int(2), Value::Int(3)]];
+        assert_eq!(query(&mut backend, "select * from t"), rows);
+    }
+
+    #[test]
+    fn test_scans_read_a_batch_at_a_time() {
+        let path = temp_path("storage-batches");
+        let (mut backend, _, scans) = open(&path);
+        run(&mut backend, "create table t (n int)").unwrap();
+        let values: Vec<String> = (0..3000).map(|i| format!("({})", i)).collect();
+        run(&mut backend, &format!("insert into t values {}", values.join(", "))).unwrap();
+
+        let count = scans.load(Ordering::SeqCst);
+        let sums = query(&mut backend, "select count(*), sum(n) from t");
+        assert_eq!(sums, vec![vec![Value::Int(3000), Value::Int(4498500)]]);
+        assert_eq!(query(&mut backend, "select n from t limit 1 offset 2500"), vec![vec![Value::Int(2500)]]);
+        assert_eq!(scans.load(Ordering::SeqCst), count);
+    }
+
+    #[test]
+    fn test_inserts_look_keys_up_without_reading_tables() {
+        let path = temp_path("storage-keys");
+        let (mut backend, _, scans) = open(&path);
+        run(
+            &mut backend,
+            "create table users (id int primary key, name text);
+             create table notes (
+                 id serial primary key, user_id int references users (id), parent int references notes (id)
+             )",
+        )
+        .unwrap();
+
+        let count = scans.load(Ordering::SeqCst);
+        for i in 1..=200 {
+            run(&mut backend, &format!("insert into users values ({}, 'u{}')", i, i)).unwrap();
+            run(&mut backend, &format!("insert into notes (user_id) values ({})", i + 1)).unwrap_err();
+            let parent = if i == 1 { "null".to_string() } else { (i / 2).to_string() };
+            run(&mut backend, &format!("insert into notes (user_id, parent) values ({}, {})", i, parent)).unwrap();
+        }
+        let err = run(&mut backend, "insert into users values (7, 'again')").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 7 for primary key id");
+        // A row may name one inserted along with it
+        run(&mut backend, "insert into notes (user_id, parent) values (1, 202), (2, 201)").unwrap();
+        let err = run(&mut backend, "insert into notes (user_id, parent) values (3, 403)").unwrap_err();
+        assert_eq!(err.message(), "Value 403 for column parent is not in notes.id");
+        assert_eq!(scans.load(Ordering::SeqCst), count);
+        drop(backend);
+
+        // The keys are built again when the database is opened
+        let (mut backend, _, _) = open(&path);
+        let err = run(&mut backend, "insert into users values (200, 'again')").unwrap_err();
+        assert_eq!(err.message(), "Duplicate value 200 for primary key id");
+        let name = query(&mut backend, "select name from users where id = 150");
+        assert_eq!(name, vec![vec![Value::Text("u150".to_string())]]);
+    }
+
+    #[test]
+    fn test_changes_turn_rows_into_others() {
+        let rows = |values: &[i64]| values.iter().map(|&n| vec![Value::Int(n)]).collect::<Vec<_>>();
+        let cases: [(&[i64], &[i64]); 5] = [
+            (&[1, 2, 3], &[1, 2, 3]),
+            (&[1, 2, 3], &[2]),
+            (&[1, 2, 3, 4], &[1, 0, 4]),
+            (&[1, 1, 2, 1], &[1, 1]),
+            (&[1, 2, 3], &[]),
+        ];
+        for (before, after) in cases {
+            let (mut rows, after) = (rows(before), rows(after));
+            let (deleted, updated) = changes(&rows, &after);
+            for &i in deleted.iter().rev() {
+                rows.remove(i);
+            }
+            updated.into_iter().for_each(|(i, row)| rows[i] = row);
+            assert_eq!(rows, after);
+        }
+        assert_eq!(changes(&rows(&[1, 2, 3]), &rows(&[1, 5, 3])), (vec![], vec![(1, vec![Value::Int(5)])]));
+        assert_eq!(changes(&rows(&[1, 2, 3]), &rows(&[1, 3])), (vec![1], vec![]));
+    }
+}
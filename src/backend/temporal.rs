@@ -0,0 +1,179 @@
+use super::Value;
+use crate::parser::DataType;
+
+/*
+    Dates, times and timestamps are stored as plain numbers so they compare,
+    sort and index like ints:
+
+        date        days since 1970-01-01, i32
+        time        microseconds since midnight, i64
+        timestamp   microseconds since 1970-01-01 00:00:00, i64
+
+    The calendar is the proleptic Gregorian one and there are no time zones,
+    a timestamp is whatever wall clock time it was written with. Text is
+    read and written in the ISO 8601 forms, "2024-01-31", "13:45:00.25" and
+    "2024-01-31 13:45:00", with a T allowed between date and time.
+ */
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+// Days from 1970-01-01 to a date, after Howard Hinnant's days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) as i64 + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Exactly `len` ASCII digits
+fn digits(s: &str, len: usize) -> Option<u32> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+pub fn parse_date(s: &str) -> Option<i32> {
+    let mut parts = s.split('-');
+    let year = digits(parts.next()?, 4)? as i64;
+    let month = digits(parts.next()?, 2)?;
+    let day = digits(parts.next()?, 2)?;
+    if parts.next().is_some() || year == 0 || !(1..=12).contains(&month) {
+        return None;
+    }
+    if day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) as i32)
+}
+
+pub fn parse_time(s: &str) -> Option<i64> {
+    let (clock, fraction) = match s.split_once('.') {
+        Some((clock, fraction)) => (clock, Some(fraction)),
+        None => (s, None),
+    };
+    let mut parts = clock.split(':');
+    let hour = digits(parts.next()?, 2)? as i64;
+    let minute = digits(parts.next()?, 2)? as i64;
+    let second = digits(parts.next()?, 2)? as i64;
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Up to six digits of fraction, anything finer than a microsecond is not kept
+    let micros = match fraction {
+        Some(fraction) if !fraction.is_empty() && fraction.len() <= 6 => {
+            digits(fraction, fraction.len())? as i64 * 10_i64.pow(6 - fraction.len() as u32)
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(((hour * 60 + minute) * 60 + second) * MICROS_PER_SECOND + micros)
+}
+
+// A date alone is midnight of that day
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let (date, time) = match s.get(10..11) {
+        Some(" " | "T") => (&s[..10], parse_time(&s[11..])?),
+        Some(_) => return None,
+        None => (s, 0),
+    };
+    Some(parse_date(date)? as i64 * MICROS_PER_DAY + time)
+}
+
+// The value of a temporal literal or of text stored in a temporal column
+pub fn parse(data_type: DataType, s: &str) -> Option<Value> {
+    match data_type {
+        DataType::Date => parse_date(s).map(Value::Date),
+        DataType::Time => parse_time(s).map(Value::Time),
+        DataType::Timestamp => parse_timestamp(s).map(Value::Timestamp),
+        _ => None,
+    }
+}
+
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Fractions of a second are only shown when there are any
+pub fn format_time(micros: i64) -> String {
+    let seconds = micros / MICROS_PER_SECOND;
+    let clock = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match micros % MICROS_PER_SECOND {
+        0 => clock,
+        fraction => format!("{}.{}", clock, format!("{:06}", fraction).trim_end_matches('0')),
+    }
+}
+
+pub fn format_timestamp(micros: i64) -> String {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    format!("{} {}", format_date(days as i32), format_time(micros.rem_euclid(MICROS_PER_DAY)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates_round_trip() {
+        for (text, days) in [("1970-01-01", 0), ("1969-12-31", -1), ("2000-03-01", 11017), ("2024-02-29", 19782)] {
+            assert_eq!(parse_date(text), Some(days), "{}", text);
+            assert_eq!(format_date(days), text);
+        }
+        assert_eq!(format_date(parse_date("0001-01-01").unwrap()), "0001-01-01");
+        assert_eq!(format_date(parse_date("9999-12-31").unwrap()), "9999-12-31");
+    }
+
+    #[test]
+    fn test_invalid_dates() {
+        for text in ["2023-02-29", "2024-13-01", "2024-00-10", "2024-04-31", "24-01-01", "2024-1-01", "2024/01/01", "0000-01-01", ""] {
+            assert_eq!(parse_date(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_times() {
+        assert_eq!(parse_time("00:00:00"), Some(0));
+        assert_eq!(parse_time("13:45:30.25").map(format_time), Some("13:45:30.25".to_string()));
+        assert_eq!(parse_time("23:59:59.000001").map(format_time), Some("23:59:59.000001".to_string()));
+        for text in ["24:00:00", "12:60:00", "12:00", "12:00:00.", "12:00:00.1234567", "1:00:00"] {
+            assert_eq!(parse_time(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let noon = parse_timestamp("2024-01-31 12:00:00").unwrap();
+        assert_eq!(parse_timestamp("2024-01-31T12:00:00"), Some(noon));
+        assert_eq!(parse_timestamp("2024-01-31"), Some(noon - 12 * 3600 * MICROS_PER_SECOND));
+        assert_eq!(format_timestamp(noon), "2024-01-31 12:00:00");
+        assert_eq!(format_timestamp(-1), "1969-12-31 23:59:59.999999");
+        assert_eq!(parse_timestamp("2024-01-31x12:00:00"), None);
+        assert_eq!(parse_timestamp("2024-01-31 25:00:00"), None);
+    }
+}
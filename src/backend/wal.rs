@@ -0,0 +1,160 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::pager::{PAGE_SIZE, Page, PageId};
+
+/*
+    The write-ahead log sits next to the database file, "data.db-wal". Before
+    the pager writes any page into the database it appends the new contents
+    of every changed page to the log, followed by a commit record, and syncs
+    the log. Only then are the pages copied into the database, after which
+    the log is emptied again.
+
+    A crash before the commit record is on disk leaves the database as it
+    was, the half written log is ignored. A crash after it leaves a complete
+    log, which is replayed the next time the database is opened. Either way
+    a statement is in the file entirely or not at all.
+
+        frame   page id u32, page PAGE_SIZE bytes
+        ...
+        commit  u32::MAX, frame count u32, checksum u64
+
+    The checksum is FNV-1a over all frame bytes, so a log whose frames were
+    only partly written is not mistaken for a complete one.
+ */
+
+const COMMIT: u32 = u32::MAX;
+
+pub(super) fn checksum(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+pub(super) const CHECKSUM_SEED: u64 = 0xcbf29ce484222325;
+
+pub struct Wal {
+    file: File,
+}
+
+pub fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    PathBuf::from(wal)
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> io::Result<Wal> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(wal_path(path))?;
+        Ok(Wal { file })
+    }
+
+    // Appends the pages and a commit record and waits until they are on disk
+    pub fn commit(&mut self, pages: &[(PageId, &Page)]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(pages.len() * (4 + PAGE_SIZE) + 16);
+        let mut hash = CHECKSUM_SEED;
+        for (id, page) in pages {
+            let start = buf.len();
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(&page[..]);
+            hash = checksum(hash, &buf[start..]);
+        }
+        buf.extend_from_slice(&COMMIT.to_le_bytes());
+        buf.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&hash.to_le_bytes());
+
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&buf)?;
+        self.file.sync_data()
+    }
+
+    // The pages of a complete log, or nothing if the log is empty or was
+    // cut short before its commit record
+    pub fn committed(&mut self) -> io::Result<Vec<(PageId, Box<Page>)>> {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut pages = Vec::new();
+        let mut hash = CHECKSUM_SEED;
+        let mut at = 0;
+        while at + 4 <= bytes.len() {
+            let id = u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+            if id == COMMIT {
+                let Some(record) = bytes.get(at + 4..at + 16) else {
+                    break;
+                };
+                let count = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+                let expected = u64::from_le_bytes(record[4..].try_into().unwrap());
+                if count == pages.len() && expected == hash {
+                    return Ok(pages);
+                }
+                break;
+            }
+
+            let Some(frame) = bytes.get(at..at + 4 + PAGE_SIZE) else {
+                break;
+            };
+            hash = checksum(hash, frame);
+            pages.push((id, Box::new(frame[4..].try_into().unwrap())));
+            at += 4 + PAGE_SIZE;
+        }
+        Ok(Vec::new())
+    }
+
+    // Empties the log once its pages are safely in the database
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::pager::temp_path;
+    use super::*;
+
+    fn page(fill: u8) -> Box<Page> {
+        Box::new([fill; PAGE_SIZE])
+    }
+
+    #[test]
+    fn test_committed_pages_read_back() {
+        let path = temp_path("wal-commit");
+        let mut wal = Wal::open(&path).unwrap();
+        let (a, b) = (page(1), page(2));
+        wal.commit(&[(3, &a), (0, &b)]).unwrap();
+
+        let pages = wal.committed().unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!((pages[0].0, pages[0].1[0]), (3, 1));
+        assert_eq!((pages[1].0, pages[1].1[0]), (0, 2));
+
+        wal.clear().unwrap();
+        assert!(wal.committed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_torn_log_is_ignored() {
+        let path = temp_path("wal-torn");
+        let mut wal = Wal::open(&path).unwrap();
+        wal.commit(&[(1, &page(1)), (2, &page(2))]).unwrap();
+
+        let len = std::fs::metadata(wal_path(&path)).unwrap().len();
+        for cut in [len - 1, len - 16, 4 + PAGE_SIZE as u64, 10] {
+            wal.file.set_len(cut).unwrap();
+            assert!(wal.committed().unwrap().is_empty(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_frame_is_ignored() {
+        let path = temp_path("wal-corrupt");
+        let mut wal = Wal::open(&path).unwrap();
+        wal.commit(&[(1, &page(1))]).unwrap();
+
+        wal.file.seek(SeekFrom::Start(100)).unwrap();
+        wal.file.write_all(&[9]).unwrap();
+        assert!(wal.committed().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+use std::net::TcpListener;
+
+use sqrldb::Database;
+use sqrldb::server::serve;
+
+/*
+    Serves a database over the Postgres wire protocol:
+
+        sqrldb-server [address] [file]
+
+    The address defaults to 127.0.0.1:5432. With a file the tables are kept
+    in it, without one they live in memory until the server stops. Clients
+    are served at the same time, each on a thread of its own.
+ */
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:5432";
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let address = args.next().map_or(DEFAULT_ADDRESS.to_string(), |arg| arg.to_string_lossy().into_owned());
+
+    let db = match args.next() {
+        Some(path) => match Database::open(&path) {
+            Ok(db) => db,
+            Err(err) => {
+                eprintln!("Error: could not open {}: {}", path.to_string_lossy(), err);
+                std::process::exit(1);
+            }
+        },
+        None => Database::in_memory(),
+    };
+
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Error: could not listen on {}: {}", address, err);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Listening on {}", address);
+    if let Err(err) = serve(&listener, &db) {
+        eprintln!("Error: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,773 @@
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::backend::{
+    Backend, BackendError, CancelToken, Engine, MemoryBackend, QueryResult, ResultSet, UserFunctions,
+    Value, dump, execute, with_cancellation, with_user_functions,
+};
+use crate::error::SqlError;
+use crate::lexer::{Location, lex};
+use crate::parser::{DataType, parse, split_statements};
+use crate::prepared::{PreparedStatement, StatementCache};
+
+/*
+    The way in for programs that embed sqrldb: a backend plus everything
+    needed to run SQL text against it, so callers never touch the lexer or
+    the parser.
+
+        let db = Database::in_memory();
+        db.execute("create table t (id int, name text); insert into t values (1, 'a')")?;
+        for row in db.query("select name from t")? {
+            let name: String = row.get(0).unwrap();
+        }
+
+    Statements run with parameters, through execute_with and query_with, are
+    prepared once and kept, so running the same SQL again only binds values.
+
+    Functions written in Rust can be called from SQL once registered:
+
+        db.register_function("double", &[DataType::Int], DataType::Int, |args| match args[0] {
+            Value::Int(i) => Ok(Value::Int(i * 2)),
+            _ => Ok(Value::Null),
+        })?;
+
+    A Database can be shared between threads, each using it through &self
+    or a clone of its own. Clones share the tables, the prepared statements
+    and the registered functions. When the backend can serve several
+    connections, the in memory one can, every clone gets a connection of
+    its own and so transactions of its own, reading a snapshot of the
+    tables. Otherwise clones share the one connection and take turns, a
+    statement at a time, and a transaction begun through one clone is the
+    transaction of all of them.
+ */
+
+const STATEMENT_CACHE_SIZE: usize = 64;
+
+pub struct Database {
+    backend: Arc<Mutex<Box<dyn Backend + Send>>>,
+    statements: Arc<Mutex<StatementCache>>,
+    functions: Arc<RwLock<Arc<UserFunctions>>>,
+}
+
+// A thread that panicked while holding a lock leaves nothing half done
+// worth refusing to go on for: statements only change a table once they
+// are known to succeed
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Database {
+    // Tables are kept in the file at `path`, which is created if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Database> {
+        Database::open_with(&Engine::disk(path))
+    }
+
+    // Tables are kept by the storage engine, see the engine module
+    pub fn open_with(engine: &Engine) -> io::Result<Database> {
+        Ok(Database::with_backend(engine.open()?))
+    }
+
+    // Tables only live as long as the Database
+    pub fn in_memory() -> Database {
+        Database::with_backend(Box::new(MemoryBackend::new()))
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend + Send>) -> Database {
+        Database {
+            backend: Arc::new(Mutex::new(backend)),
+            statements: Arc::new(Mutex::new(StatementCache::new(STATEMENT_CACHE_SIZE))),
+            functions: Arc::new(RwLock::new(Arc::new(UserFunctions::default()))),
+        }
+    }
+
+    // The functions as they are now, a statement keeps them while it runs
+    pub(crate) fn functions(&self) -> Arc<UserFunctions> {
+        Arc::clone(&self.functions.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    // Runs `f` on the backend with the registered functions callable
+    fn run<T>(&self, f: impl FnOnce(&mut dyn Backend) -> T) -> T {
+        let functions = self.functions();
+        let mut backend = lock(&self.backend);
+        with_user_functions(&functions, || f(backend.as_mut()))
+    }
+
+    // The connection of this Database to itself, until the guard is dropped,
+    // for a session that runs statements one at a time across a transaction
+    #[cfg(feature = "server")]
+    pub(crate) fn connection(&self) -> MutexGuard<'_, Box<dyn Backend + Send>> {
+        lock(&self.backend)
+    }
+
+    fn prepare(&self, sql: &str) -> Result<PreparedStatement, SqlError> {
+        lock(&self.statements).prepare(sql).cloned()
+    }
+
+    /*
+        Makes `f` callable from SQL as `name(...)`, taking arguments of the
+        types in `params` and returning a `returns`. Arguments are converted
+        the way a column converts what is stored in it, an int passed for a
+        real parameter arrives as a real, and anything else of the wrong type
+        fails the statement before `f` is called. A null argument is passed
+        on as Value::Null. An error `f` returns fails the statement with that
+        message.
+
+        Registering a name again replaces the function, built-in ones like
+        upper included. Aggregates such as count cannot be replaced.
+     */
+    pub fn register_function<F>(
+        &self,
+        name: &str,
+        params: &[DataType],
+        returns: DataType,
+        f: F,
+    ) -> Result<(), SqlError>
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let mut functions = self.functions.write().unwrap_or_else(PoisonError::into_inner);
+        Arc::make_mut(&mut functions)
+            .register(name, params, returns, Arc::new(f))
+            .map_err(|message| BackendError::new(message, Location::new(1, 1)).into())
+    }
+
+    // Runs every statement of the script in order and returns what the last
+    // one produced. The first failure stops the script, the statements before
+    // it have already run.
+    pub fn execute(&self, sql: &str) -> Result<QueryResult, SqlError> {
+        let results = self.execute_script(sql, OnError::Stop)?;
+        results.into_iter().last().map_or(Ok(QueryResult::Done), |statement| statement.result)
+    }
+
+    /*
+        Runs every statement of the script in order, like a migration file,
+        and returns how each one went. Statements end at semicolons outside
+        of string literals and comments. With OnError::Stop the first failing
+        statement is the last one run, with OnError::Continue every statement
+        runs whatever happened to the ones before.
+
+        The script is lexed as a whole before anything runs, so a script that
+        does not lex, an unterminated string say, fails without running any
+        of it.
+     */
+    pub fn execute_script(&self, sql: &str, on_error: OnError) -> Result<Vec<StatementResult>, SqlError> {
+        let mut results = Vec::new();
+        for tokens in split_statements(lex(sql)?) {
+            let start = tokens.first().unwrap();
+            let (loc, from) = (start.location(), start.span().start());
+            let to = tokens.last().unwrap().span().end();
+
+            let result = parse(tokens)
+                .map_err(SqlError::from)
+                .and_then(|statement| self.run(|backend| execute(backend, &statement)).map_err(SqlError::from));
+            let failed = result.is_err();
+            results.push(StatementResult { sql: sql[from..to].to_string(), loc, result });
+            if failed && on_error == OnError::Stop {
+                break;
+            }
+        }
+        Ok(results)
+    }
+
+    // Runs a single statement with `values` bound to its parameters
+    pub fn execute_with(&self, sql: &str, values: &[Value]) -> Result<QueryResult, SqlError> {
+        let prepared = self.prepare(sql)?;
+        self.run(|backend| prepared.execute(backend, values))
+    }
+
+    // Runs a single statement that returns rows, a select
+    pub fn query(&self, sql: &str) -> Result<Rows, SqlError> {
+        self.query_with(sql, &[])
+    }
+
+    pub fn query_with(&self, sql: &str, values: &[Value]) -> Result<Rows, SqlError> {
+        let prepared = self.prepare(sql)?;
+        match self.run(|backend| prepared.execute(backend, values))? {
+            QueryResult::Rows(result) => Ok(Rows::new(result)),
+            _ => Err(BackendError::new("Expected a statement that returns rows", prepared.location()).into()),
+        }
+    }
+
+    /*
+        Runs the script like execute, stopping it with SqlError::QueryCancelled
+        once it has run for longer than `timeout`. The timeout is for the
+        whole script, and statements that already finished stay done.
+     */
+    pub fn execute_with_timeout(&self, sql: &str, timeout: Duration) -> Result<QueryResult, SqlError> {
+        with_cancellation(&CancelToken::new(), Some(timeout), || self.execute(sql))
+    }
+
+    // Runs the script like execute, stopping it with SqlError::QueryCancelled
+    // once `token` is cancelled, from another thread say
+    pub fn execute_cancellable(&self, sql: &str, token: &CancelToken) -> Result<QueryResult, SqlError> {
+        with_cancellation(token, None, || self.execute(sql))
+    }
+}
+
+impl Database {
+    /*
+        Copies a database kept in a file into a new file at `path`, which
+        can be opened like any other. The copy holds every committed
+        statement and nothing of a transaction still in progress, which goes
+        on unaffected. Statements only wait for the backup to note which
+        pages it copies, they run while it copies them, and nothing has to
+        be closed first.
+     */
+    pub fn backup(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let backup = lock(&self.backend).backup()?;
+        backup.write(path.as_ref())
+    }
+
+    // Replaces every table with the ones of the backup at `path`. Fails
+    // while a transaction is in progress.
+    pub fn restore(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        lock(&self.backend).restore(path.as_ref())
+    }
+
+    // The SQL that creates the tables, rows, indexes and views again
+    pub fn dump(&self) -> Result<String, SqlError> {
+        self.run(|backend| dump(backend)).map_err(SqlError::from)
+    }
+}
+
+/*
+    With the async feature statements can also be awaited, for callers that
+    live on an async runtime such as tokio. They run on a pool of threads of
+    their own, see the pool module, so the runtime's threads never wait on a
+    lock or the disk. An async statement runs on the same connection as the
+    Database it was called on, in its transaction if it is in one.
+ */
+#[cfg(feature = "async")]
+impl Database {
+    // The same connection, for a statement to take to another thread
+    fn share(&self) -> Database {
+        Database {
+            backend: Arc::clone(&self.backend),
+            statements: Arc::clone(&self.statements),
+            functions: Arc::clone(&self.functions),
+        }
+    }
+
+    pub async fn execute_async(&self, sql: &str) -> Result<QueryResult, SqlError> {
+        let (db, sql) = (self.share(), sql.to_string());
+        crate::pool::spawn(move || db.execute(&sql)).await
+    }
+
+    pub async fn query_async(&self, sql: &str) -> Result<Rows, SqlError> {
+        let (db, sql) = (self.share(), sql.to_string());
+        crate::pool::spawn(move || db.query(&sql)).await
+    }
+}
+
+impl Clone for Database {
+    fn clone(&self) -> Database {
+        let connection = lock(&self.backend).connect();
+        Database {
+            backend: connection.map_or_else(|| Arc::clone(&self.backend), |backend| Arc::new(Mutex::new(backend))),
+            statements: Arc::clone(&self.statements),
+            functions: Arc::clone(&self.functions),
+        }
+    }
+}
+
+// Whether a script goes on after a statement fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    Stop,
+    Continue,
+}
+
+// One statement of a script, its text without the semicolon, where it starts
+// and what running it gave
+#[derive(Debug)]
+pub struct StatementResult {
+    pub sql: String,
+    pub loc: Location,
+    pub result: Result<QueryResult, SqlError>,
+}
+
+// The rows of a query, in order
+pub struct Rows {
+    columns: Arc<[String]>,
+    rows: std::vec::IntoIter<Vec<Value>>,
+}
+
+impl Rows {
+    fn new(result: ResultSet) -> Rows {
+        Rows { columns: result.columns.into(), rows: result.rows.into_iter() }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    // The rows not yet handed out
+    pub(crate) fn remaining(&self) -> &[Vec<Value>] {
+        self.rows.as_slice()
+    }
+}
+
+impl Iterator for Rows {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Row> {
+        let values = self.rows.next()?;
+        Some(Row { columns: self.columns.clone(), values })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Rows {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    // The value of column `index` as a T, None when there is no such column
+    // or it holds another type. A NULL only converts to an Option.
+    pub fn get<T: FromValue>(&self, index: usize) -> Option<T> {
+        T::from_value(self.values.get(index)?)
+    }
+
+    // Like get, finding the column by its name
+    pub fn get_by_name<T: FromValue>(&self, name: &str) -> Option<T> {
+        let index = self.columns.iter().position(|column| column == name)?;
+        self.get(index)
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.values
+    }
+}
+
+// A Rust type a column value can be read as
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Option<Value> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Option<i64> {
+        match value {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Option<i32> {
+        i64::from_value(value).and_then(|i| i32::try_from(i).ok())
+    }
+}
+
+// Ints read as reals too, the way they compare with them
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<f64> {
+        match value {
+            Value::Real(r) => Some(*r),
+            Value::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<bool> {
+        match value {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<String> {
+        match value {
+            Value::Text(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Option<Option<T>> {
+        match value {
+            Value::Null => Some(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::temp_path;
+
+    #[test]
+    fn test_execute_and_query() {
+        let db = Database::in_memory();
+        let result = db
+            .execute("create table users (id int, name text, score real); insert into users values (1, 'ann', 2.5), (2, null, 1.0);")
+            .unwrap();
+        assert_eq!(result, QueryResult::Affected(2));
+
+        let rows = db.query("select id, name, score from users order by id").unwrap();
+        assert_eq!(rows.columns(), ["id", "name", "score"]);
+        assert_eq!(rows.len(), 2);
+
+        let rows: Vec<(i64, Option<String>, f64)> = rows
+            .map(|row| (row.get(0).unwrap(), row.get(1).unwrap(), row.get_by_name("score").unwrap()))
+            .collect();
+        assert_eq!(rows, vec![(1, Some("ann".to_string()), 2.5), (2, None, 1.0)]);
+    }
+
+    #[test]
+    fn test_typed_access() {
+        let db = Database::in_memory();
+        db.execute("create table t (a int, b text); insert into t values (1, null)").unwrap();
+        let row = db.query("select a, b from t").unwrap().next().unwrap();
+
+        assert_eq!(row.get::<i32>(0), Some(1));
+        assert_eq!(row.get::<f64>(0), Some(1.0));
+        assert_eq!(row.get::<String>(0), None);
+        assert_eq!(row.get::<String>(1), None);
+        assert_eq!(row.get::<Option<String>>(1), Some(None));
+        assert_eq!(row.get::<Value>(2), None);
+        assert_eq!(row.get_by_name::<i64>("missing"), None);
+        assert_eq!(row.into_values(), vec![Value::Int(1), Value::Null]);
+    }
+
+    #[test]
+    fn test_parameters() {
+        let db = Database::in_memory();
+        db.execute("create table t (id int, name text)").unwrap();
+        for (id, name) in [(1, "a"), (2, "b"), (3, "c")] {
+            let values = [Value::Int(id), Value::Text(name.to_string())];
+            db.execute_with("insert into t values ($1, $2)", &values).unwrap();
+        }
+
+        let names: Vec<String> = db
+            .query_with("select name from t where id > ? order by id", &[Value::Int(1)])
+            .unwrap()
+            .map(|row| row.get(0).unwrap())
+            .collect();
+        assert_eq!(names, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let db = Database::in_memory();
+        assert!(matches!(db.execute("select #"), Err(SqlError::Lex(_))));
+        assert!(matches!(db.query("select from"), Err(SqlError::Parse(_))));
+        assert!(matches!(db.query("select * from missing"), Err(SqlError::Backend(_))));
+
+        let err = db.query("create table t (id int)").err().unwrap();
+        assert_eq!(err.to_string(), "Expected a statement that returns rows at 1:1");
+
+        // A failing statement stops the script, the ones before it stay done
+        assert!(db.execute("insert into t values (1); insert into t values ('x'); insert into t values (3)").is_err());
+        assert_eq!(db.query("select id from t").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_execute_script() {
+        let script = "create table t (id int primary key, note text);
+                      insert into t values (1, 'a;b');  -- a comment; with a semicolon
+                      insert into t values (1, 'again');
+                      insert into t values (2, 'c');";
+
+        let db = Database::in_memory();
+        let results = db.execute_script(script, OnError::Stop).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1].sql, "insert into t values (1, 'a;b')");
+        assert_eq!(results[2].loc, Location::new(3, 23));
+        assert!(results[..2].iter().all(|statement| statement.result.is_ok()));
+        assert!(matches!(results[2].result, Err(SqlError::Backend(_))));
+        assert_eq!(db.query("select id from t").unwrap().len(), 1);
+
+        let db = Database::in_memory();
+        let results = db.execute_script(script, OnError::Continue).unwrap();
+        let ok: Vec<bool> = results.iter().map(|statement| statement.result.is_ok()).collect();
+        assert_eq!(ok, vec![true, true, false, true]);
+        assert!(matches!(results[3].result, Ok(QueryResult::Affected(1))));
+        assert_eq!(db.query("select id from t").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_execute_script_errors() {
+        let db = Database::in_memory();
+        let results = db.execute_script("create table t (a int); selec 1; insert into t values (1)", OnError::Continue);
+        let results = results.unwrap();
+        assert!(matches!(results[1].result, Err(SqlError::Parse(_))));
+        assert_eq!(db.query("select a from t").unwrap().len(), 1);
+
+        // Nothing runs when the script does not lex
+        assert!(matches!(db.execute_script("insert into t values (2); select 'open", OnError::Continue), Err(SqlError::Lex(_))));
+        assert_eq!(db.query("select a from t").unwrap().len(), 1);
+
+        assert!(db.execute_script("  ;; -- nothing\n", OnError::Stop).unwrap().is_empty());
+        assert_eq!(db.execute("").unwrap(), QueryResult::Done);
+    }
+
+    #[test]
+    fn test_register_function() {
+        let db = Database::in_memory();
+        db.execute("create table t (id int, name text, score real)").unwrap();
+        db.execute("insert into t values (1, 'ann', 2.5), (2, null, 4.0)").unwrap();
+        db.register_function("greet", &[DataType::Text], DataType::Text, |args| match &args[0] {
+            Value::Text(name) => Ok(Value::Text(format!("hello {}", name))),
+            _ => Ok(Value::Text("hello nobody".to_string())),
+        })
+        .unwrap();
+        db.register_function("half", &[DataType::Real], DataType::Real, |args| match args[0] {
+            Value::Real(r) => Ok(Value::Real(r / 2.0)),
+            _ => Err("half of nothing".to_string()),
+        })
+        .unwrap();
+
+        let rows: Vec<String> =
+            db.query("select GREET(name) from t order by id").unwrap().map(|row| row.get(0).unwrap()).collect();
+        assert_eq!(rows, vec!["hello ann", "hello nobody"]);
+        let rows = db.query_with("select id from t where half(score) > ?", &[Value::Real(1.5)]).unwrap();
+        let ids: Vec<i64> = rows.map(|row| row.get(0).unwrap()).collect();
+        assert_eq!(ids, vec![2]);
+
+        // Ints are converted for a real parameter
+        db.execute("insert into t values (3, greet('bob'), half(3))").unwrap();
+        let row = db.query("select name, score from t where id = 3").unwrap().next().unwrap();
+        assert_eq!(row.into_values(), vec![Value::Text("hello bob".to_string()), Value::Real(1.5)]);
+
+        let cases = [
+            ("select greet(id) from t", "Cannot apply greet to int"),
+            ("select greet() from t", "Function greet takes 1 argument, got 0"),
+            ("select half(null) from t", "half of nothing"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(db.query(source).err().unwrap().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_register_function_replaces() {
+        let db = Database::in_memory();
+        db.execute("create table t (a int); insert into t values (1)").unwrap();
+        db.register_function("upper", &[DataType::Int], DataType::Int, |args| Ok(args[0].clone())).unwrap();
+        let row = db.query("select upper(a) from t").unwrap().next().unwrap();
+        assert_eq!(row.get::<i64>(0), Some(1));
+
+        db.register_function("upper", &[DataType::Int], DataType::Int, |_| Ok(Value::Text("x".to_string()))).unwrap();
+        let err = db.query("select upper(a) from t").err().unwrap();
+        assert_eq!(err.message(), "Function upper returned text, expected int");
+
+        let err = db.register_function("Count", &[], DataType::Int, |_| Ok(Value::Int(0))).unwrap_err();
+        assert_eq!(err.message(), "Cannot replace aggregate function count");
+
+        // Functions belong to the database they were registered with
+        let other = Database::in_memory();
+        other.execute("create table t (a int); insert into t values (1)").unwrap();
+        assert_eq!(other.query("select upper(a) from t").err().unwrap().message(), "Cannot apply upper to int");
+    }
+
+    #[test]
+    fn test_open() {
+        let path = temp_path("database-open");
+        let db = Database::open(&path).unwrap();
+        db.execute("create table t (id int); insert into t values (7)").unwrap();
+        drop(db);
+
+        let db = Database::open(&path).unwrap();
+        let ids: Vec<i64> = db.query("select id from t").unwrap().map(|row| row.get(0).unwrap()).collect();
+        assert_eq!(ids, vec![7]);
+    }
+
+    #[test]
+    fn test_open_with() {
+        let path = temp_path("database-open-with");
+        let db = Database::open_with(&Engine::log(&path)).unwrap();
+        db.execute("create table t (id int); insert into t values (7), (8); delete from t where id = 8").unwrap();
+        drop(db);
+
+        let db = Database::open_with(&Engine::log(&path)).unwrap();
+        let ids: Vec<i64> = db.query("select id from t").unwrap().map(|row| row.get(0).unwrap()).collect();
+        assert_eq!(ids, vec![7]);
+        // The log is no file the disk engine can read
+        assert!(Database::open_with(&Engine::disk(&path)).is_err());
+
+        let db = Database::open_with(&Engine::memory()).unwrap();
+        assert_eq!(db.execute("create table t (id int)").unwrap(), QueryResult::Done);
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let count = |db: &Database| db.query("select count(*) from t").unwrap().next().unwrap().get::<i64>(0);
+        let engines: [fn(&Path) -> Engine; 2] = [|path| Engine::disk(path), |path| Engine::log(path)];
+        for engine in engines {
+            let (path, backup) = (temp_path("database-live"), temp_path("database-backup"));
+            let db = Database::open_with(&engine(&path)).unwrap();
+            db.execute("create table t (n int); insert into t values (1), (2)").unwrap();
+
+            // The transaction is left out of the backup and goes on
+            db.execute("begin; insert into t values (3)").unwrap();
+            db.backup(&backup).unwrap();
+            let err = db.restore(&backup).unwrap_err();
+            assert_eq!(err.to_string(), "Cannot restore a database in the middle of a transaction");
+            db.execute("commit; drop table t; create table u (a text)").unwrap();
+
+            db.restore(&backup).unwrap();
+            assert_eq!(count(&db), Some(2));
+            assert!(db.query("select * from u").is_err());
+            assert_eq!(count(&Database::open_with(&engine(&backup)).unwrap()), Some(2));
+            drop(db);
+            assert_eq!(count(&Database::open_with(&engine(&path)).unwrap()), Some(2));
+        }
+
+        let err = Database::in_memory().backup(temp_path("database-memory")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_dump() {
+        let db = Database::in_memory();
+        db.execute("create table t (n int, s text); insert into t values (1, 'a''b')").unwrap();
+        let sql = db.dump().unwrap();
+        assert_eq!(sql, "BEGIN;\nCREATE TABLE t (n INT, s TEXT);\nINSERT INTO t VALUES (1, 'a''b');\nCOMMIT;\n");
+
+        let copy = Database::in_memory();
+        copy.execute(&sql).unwrap();
+        assert_eq!(copy.dump().unwrap(), sql);
+    }
+
+    #[test]
+    fn test_timeout_and_cancel() {
+        let db = Database::in_memory();
+        db.execute("create table t (n int); insert into t values (1)").unwrap();
+        for _ in 0..10 {
+            db.execute("insert into t select n + 1 from t").unwrap();
+        }
+        // A billion rows, far more than the test would wait for
+        let slow = "select count(*) from t a join t b on true join t c on a.n = c.n + b.n";
+
+        let err = db.execute_with_timeout(slow, Duration::from_millis(20)).unwrap_err();
+        assert!(matches!(err, SqlError::QueryCancelled(_)));
+        assert_eq!((err.code(), err.message()), ("57014", "Query cancelled after running for 20ms"));
+
+        let token = CancelToken::new();
+        let cancel = token.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        let err = db.execute_cancellable(slow, &token).unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!((err.code(), err.message()), ("57014", "Query cancelled"));
+
+        // A cancelled update changes nothing
+        let token = CancelToken::new();
+        token.cancel();
+        let err = db.execute_cancellable("update t set n = 0", &token).unwrap_err();
+        assert!(matches!(err, SqlError::QueryCancelled(_)));
+        let row = db.query("select count(*), min(n) from t").unwrap().next().unwrap();
+        assert_eq!((row.get::<i64>(0), row.get::<i64>(1)), (Some(1024), Some(1)));
+
+        // Fast statements finish well within the timeout
+        let result = db.execute_with_timeout("select count(*) from t", Duration::from_secs(60)).unwrap();
+        assert!(matches!(result, QueryResult::Rows(_)));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async() {
+        fn sendable<T: Send>(_: &T) {}
+        let db = Database::in_memory();
+        sendable(&db.query_async("select 1"));
+        db.execute_async("create table t (n int); begin").await.unwrap();
+        let (a, b, c) = tokio::join!(
+            db.execute_async("insert into t values (1)"),
+            db.execute_async("insert into t values (2)"),
+            db.execute_async("insert into t values (3)"),
+        );
+        for result in [a, b, c] {
+            assert_eq!(result.unwrap(), QueryResult::Affected(1));
+        }
+        assert_eq!(db.query_async("select n from t").await.unwrap().len(), 3);
+
+        // Still in the transaction begun above
+        db.execute_async("rollback").await.unwrap();
+        let rows = db.query_async("select count(*) from t").await.unwrap();
+        assert_eq!(rows.into_iter().next().unwrap().get::<i64>(0), Some(0));
+
+        let err = db.query_async("select * from missing").await.err().unwrap();
+        assert_eq!(err.message(), "Unknown table missing");
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        fn shareable<T: Send + Sync + Clone>(_: &T) {}
+        let db = Database::in_memory();
+        shareable(&db);
+        db.execute("create table t (n int)").unwrap();
+        db.register_function("twice", &[DataType::Int], DataType::Int, |args| match args[0] {
+            Value::Int(i) => Ok(Value::Int(i * 2)),
+            _ => Ok(Value::Null),
+        })
+        .unwrap();
+
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let db = &db;
+                scope.spawn(move || {
+                    for n in 0..10 {
+                        db.execute_with("insert into t values (twice($1))", &[Value::Int(i * 10 + n)]).unwrap();
+                    }
+                });
+            }
+        });
+        let row = db.query("select count(*), sum(n) from t").unwrap().next().unwrap();
+        assert_eq!((row.get::<i64>(0), row.get::<i64>(1)), (Some(40), Some(1560)));
+    }
+
+    #[test]
+    fn test_clones_have_their_own_transactions() {
+        let db = Database::in_memory();
+        db.execute("create table t (n int); insert into t values (1)").unwrap();
+        let clone = db.clone();
+        let worker = std::thread::spawn(move || {
+            clone.execute("begin; insert into t values (2)").unwrap();
+            clone
+        });
+        let clone = worker.join().unwrap();
+
+        // The insert is not committed yet, the other clone cannot see it
+        let count = |db: &Database| db.query("select count(*) from t").unwrap().next().unwrap().get::<i64>(0);
+        assert_eq!((count(&db), count(&clone)), (Some(1), Some(2)));
+        clone.execute("commit").unwrap();
+        assert_eq!(count(&db), Some(2));
+    }
+
+    #[test]
+    fn test_clones_of_a_disk_database_share_the_connection() {
+        let db = Database::open(temp_path("database-clone")).unwrap();
+        let clone = db.clone();
+        db.execute("create table t (n int); begin; insert into t values (1)").unwrap();
+        clone.execute("rollback").unwrap();
+        assert_eq!(db.query("select * from t").unwrap().len(), 0);
+    }
+}
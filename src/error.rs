@@ -0,0 +1,283 @@
+use std::fmt;
+
+use crate::backend::{BackendError, ErrorKind};
+use crate::lexer::{LexError, Location};
+use crate::parser::{ColumnConstraint, ParseError};
+
+/*
+    One error type for everything between the source text and a result, so an
+    embedder can run a script and still tell which stage gave up on it.
+
+    The codes are SQLSTATEs, the five character codes Postgres clients already
+    know: a statement that doesn't lex or parse is a syntax error, a broken
+    constraint has its own code and anything else the backend rejects has the
+    code of its kind, see ErrorKind, or the internal error's when it has none
+    of its own. A statement that was cancelled, or ran past its timeout, is
+    QueryCancelled rather than a backend error, with Postgres' query_canceled
+    code.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlError {
+    Lex(LexError),
+    Parse(ParseError),
+    Backend(BackendError),
+    QueryCancelled(BackendError),
+}
+
+impl SqlError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SqlError::Lex(_) | SqlError::Parse(_) => "42601",
+            SqlError::Backend(err) => match err.violation().map(|v| &v.constraint) {
+                Some(ColumnConstraint::NotNull) => "23502",
+                Some(ColumnConstraint::PrimaryKey | ColumnConstraint::Unique) => "23505",
+                Some(ColumnConstraint::References(_)) => "23503",
+                Some(ColumnConstraint::AutoIncrement) | None => kind_code(err.kind()),
+            },
+            SqlError::QueryCancelled(_) => "57014",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            SqlError::Lex(err) => err.message(),
+            SqlError::Parse(err) => err.message(),
+            SqlError::Backend(err) | SqlError::QueryCancelled(err) => err.message(),
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        match self {
+            SqlError::Lex(err) => err.location(),
+            SqlError::Parse(err) => err.location(),
+            SqlError::Backend(err) | SqlError::QueryCancelled(err) => err.location(),
+        }
+    }
+
+    /*
+        The error with the line of source it points at and a caret under the
+        offending word, source being the text that was run:
+
+            error[42601]: Expected from, got t
+             --> 1:10
+              |
+            1 | select a t
+              |          ^
+     */
+    pub fn render(&self, source: &str) -> String {
+        let loc = self.location();
+        let mut out = format!("error[{}]: {}\n --> {}:{}\n", self.code(), self.message(), loc.line(), loc.col());
+
+        // A location past the end of the source has no line to show
+        let Some(line) = source.lines().nth(loc.line().saturating_sub(1)) else {
+            return out;
+        };
+
+        let number = loc.line().to_string();
+        let gutter = " ".repeat(number.len());
+        let chars: Vec<char> = line.chars().collect();
+        let start = loc.col().saturating_sub(1).min(chars.len());
+
+        // Tabs are kept in the padding so the caret lines up however wide the
+        // terminal draws them, and wide characters take two columns
+        let padding: String = chars[..start]
+            .iter()
+            .map(|&c| match c {
+                '\t' => "\t",
+                c if is_wide(c) => "  ",
+                _ => " ",
+            })
+            .collect();
+        let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+        let columns = |c: &char| 1 + is_wide(*c) as usize;
+        let width = match chars.get(start) {
+            Some(c) if is_word(c) => chars[start..].iter().take_while(|c| is_word(c)).map(columns).sum(),
+            Some(c) => columns(c),
+            None => 1,
+        };
+
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", number, line));
+        out.push_str(&format!("{} | {}{}\n", gutter, padding, "^".repeat(width)));
+        out
+    }
+}
+
+// Whether a terminal draws the character two columns wide: the CJK blocks,
+// Hangul, fullwidth forms and emoji
+fn is_wide(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115f
+            | 0x2e80..=0x303e
+            | 0x3041..=0x33ff
+            | 0x3400..=0x4dbf
+            | 0x4e00..=0x9fff
+            | 0xa000..=0xa4cf
+            | 0xac00..=0xd7a3
+            | 0xf900..=0xfaff
+            | 0xfe30..=0xfe4f
+            | 0xff00..=0xff60
+            | 0xffe0..=0xffe6
+            | 0x1f300..=0x1f64f
+            | 0x1f900..=0x1f9ff
+            | 0x20000..=0x3fffd
+    )
+}
+
+fn kind_code(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Other => "XX000",
+        ErrorKind::UndefinedTable => "42P01",
+        ErrorKind::UndefinedColumn => "42703",
+        ErrorKind::UndefinedFunction => "42883",
+        ErrorKind::DatatypeMismatch => "42804",
+        ErrorKind::Grouping => "42803",
+        ErrorKind::DivisionByZero => "22012",
+        ErrorKind::OutOfRange => "22003",
+        ErrorKind::InvalidText => "22P02",
+        ErrorKind::TransactionActive => "25001",
+        ErrorKind::NoTransaction => "25P01",
+        ErrorKind::TransactionAborted => "25P02",
+        ErrorKind::SerializationFailure => "40001",
+        ErrorKind::NotAllowed => "42501",
+        ErrorKind::Cancelled => "57014",
+    }
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlError::Lex(err) => err.fmt(f),
+            SqlError::Parse(err) => err.fmt(f),
+            SqlError::Backend(err) | SqlError::QueryCancelled(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SqlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SqlError::Lex(err) => Some(err),
+            SqlError::Parse(err) => Some(err),
+            SqlError::Backend(err) | SqlError::QueryCancelled(err) => Some(err),
+        }
+    }
+}
+
+impl From<LexError> for SqlError {
+    fn from(err: LexError) -> SqlError {
+        SqlError::Lex(err)
+    }
+}
+
+impl From<ParseError> for SqlError {
+    fn from(err: ParseError) -> SqlError {
+        SqlError::Parse(err)
+    }
+}
+
+impl From<BackendError> for SqlError {
+    fn from(err: BackendError) -> SqlError {
+        if err.is_cancelled() { SqlError::QueryCancelled(err) } else { SqlError::Backend(err) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, MemoryBackend, execute};
+    use crate::lexer::lex;
+    use crate::parser::{parse, split_statements};
+
+    fn run(source: &str) -> Result<(), SqlError> {
+        run_on(&mut MemoryBackend::new(), source)
+    }
+
+    fn run_on(backend: &mut dyn Backend, source: &str) -> Result<(), SqlError> {
+        for tokens in split_statements(lex(source)?) {
+            execute(backend, &parse(tokens)?)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(run("select #").unwrap_err().code(), "42601");
+        assert_eq!(run("select a t").unwrap_err().code(), "42601");
+        let cases = [
+            ("select * from missing", "42P01"),
+            ("select b from t", "42703"),
+            ("select upper(a) from t", "42883"),
+            ("select a + 'a' from t", "42883"),
+            ("insert into t values ('a')", "42804"),
+            ("select a from t group by a + 1", "42803"),
+            ("select a / 0 from t", "22012"),
+            ("select a + 9223372036854775807 from t", "22003"),
+            ("select cast('x' as int) from t", "22P02"),
+            ("begin; begin", "25001"),
+            ("commit", "25P01"),
+            ("create table t (a int)", "XX000"),
+        ];
+        for (source, code) in cases {
+            let source = format!("create table t (a int); insert into t values (1); {}", source);
+            assert_eq!(run(&source).unwrap_err().code(), code, "{}", source);
+        }
+
+        let err = run("create table t (id int primary key); insert into t values (1), (1)").unwrap_err();
+        assert_eq!(err.code(), "23505");
+        assert!(matches!(err, SqlError::Backend(_)));
+
+        let err = run("create table t (id int not null); insert into t values (null)").unwrap_err();
+        assert_eq!(err.code(), "23502");
+    }
+
+    #[test]
+    fn test_commit_conflict_code() {
+        let mut first = MemoryBackend::new();
+        let mut second = first.connect().unwrap();
+        run_on(&mut first, "create table t (a int); insert into t values (1)").unwrap();
+        run_on(&mut first, "begin; update t set a = 2").unwrap();
+        run_on(second.as_mut(), "begin; update t set a = 3").unwrap();
+        run_on(&mut first, "commit").unwrap();
+        assert_eq!(run_on(second.as_mut(), "commit").unwrap_err().code(), "40001");
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "create table t (a int);\nselect a t";
+        let err = run(source).unwrap_err();
+        assert_eq!(err.location(), Location::new(2, 10));
+        assert_eq!(
+            err.render(source),
+            "error[42601]: Expected from, got t\n --> 2:10\n  |\n2 | select a t\n  |          ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_underlines_the_word() {
+        let source = "select\tmissing from t";
+        let err = SqlError::from(BackendError::new("Unknown column missing", Location::new(1, 8)));
+        assert_eq!(
+            err.render(source),
+            "error[XX000]: Unknown column missing\n --> 1:8\n  |\n1 | select\tmissing from t\n  |       \t^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_wide_characters() {
+        let source = "select 名前, naïve from t";
+        let err = run(&format!("create table t (a int); {}", source)).unwrap_err();
+        assert_eq!(err.message(), "Unknown column 名前");
+        let err = SqlError::from(BackendError::new(err.message(), Location::new(1, 8)));
+        assert_eq!(err.render(source).lines().last(), Some("  |        ^^^^"));
+        let err = SqlError::from(BackendError::new("Unknown column naïve", Location::new(1, 12)));
+        assert_eq!(err.render(source).lines().last(), Some("  |              ^^^^^"));
+    }
+
+    #[test]
+    fn test_render_past_the_end() {
+        let err = SqlError::from(BackendError::new("Oops", Location::new(3, 1)));
+        assert_eq!(err.render("select 1"), "error[XX000]: Oops\n --> 3:1\n");
+    }
+}
@@ -0,0 +1,104 @@
+use crate::lexer::{lex, unlex};
+use crate::parser::{Statement, parse, split_statements};
+
+/*
+    Entry points for fuzzers, the targets in fuzz/ call these. Each takes
+    any bytes at all, runs them through a stage and checks what has to hold
+    whatever the input. Errors are fine, a panic is a bug: a program
+    embedding sqrldb must be able to hand it any text.
+
+        cargo +nightly fuzz run lex
+        cargo +nightly fuzz run parse
+ */
+
+// Lexes the bytes, when they are UTF-8. Tokens must cover the input in
+// order, on char boundaries, and unlex must give text that lexes back to
+// the same tokens.
+pub fn fuzz_lex(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(tokens) = lex(source) else {
+        return;
+    };
+
+    let mut end = 0;
+    for token in &tokens {
+        let span = token.span();
+        assert!(end <= span.start() && span.start() <= span.end() && span.end() <= source.len());
+        assert!(source.is_char_boundary(span.start()) && source.is_char_boundary(span.end()));
+        end = span.end();
+    }
+
+    let text = unlex(&tokens);
+    let again = lex(&text).unwrap_or_else(|err| panic!("{:?} unlexed to {:?}, which fails: {}", source, text, err));
+    assert!(
+        again.len() == tokens.len() && again.iter().zip(&tokens).all(|(a, b)| a.equals(b)),
+        "{:?} unlexed to {:?}, which lexes differently",
+        source,
+        text
+    );
+}
+
+// Lexes and parses every statement of the bytes, when they are UTF-8, and
+// shows the selects that parse. A parse error must point inside the input.
+pub fn fuzz_parse(data: &[u8]) {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(tokens) = lex(source) else {
+        return;
+    };
+    let lines = source.split('\n').count();
+    for tokens in split_statements(tokens) {
+        match parse(tokens) {
+            Ok(Statement::Select(select)) => {
+                select.to_string();
+            }
+            Ok(_) => {}
+            Err(err) => assert!(err.location().line() <= lines, "{:?} failed at {}", source, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Inputs that once needed care: multi-byte text next to every kind of
+    // token, unterminated literals and bytes that are not UTF-8
+    const INPUTS: [&[u8]; 10] = [
+        "select 'héllo', \"naïve\" from t where a = '中文'".as_bytes(),
+        "select é from t".as_bytes(),
+        "0x中".as_bytes(),
+        "1e中".as_bytes(),
+        "$中$ text $中$".as_bytes(),
+        "$tag$ 中 $tag".as_bytes(),
+        "'unterminated é".as_bytes(),
+        "/* é".as_bytes(),
+        "select a from t where b in (select c from d) order by a desc limit 1; sélect".as_bytes(),
+        &[0xff, 0xfe, b's', 0xc3],
+    ];
+
+    // Inputs nested or chained far deeper than the parser allows
+    fn deep_inputs() -> Vec<String> {
+        let (long, deep) = (100_000, 10_000);
+        let subquery = "(select a from t where a in ";
+        vec![
+            format!("select 1{} from t", " + 1".repeat(long)),
+            format!("select * from t where {}true", "not ".repeat(long)),
+            format!("select {}1{} from t", "(".repeat(deep), ")".repeat(deep)),
+            format!("select a from t where a in {}(1){}", subquery.repeat(deep), ")".repeat(deep)),
+            format!("select * from {}t{}", "(select * from ".repeat(deep), ") as s".repeat(deep)),
+        ]
+    }
+
+    #[test]
+    fn test_fuzz_entry_points() {
+        let deep = deep_inputs();
+        for input in INPUTS.into_iter().chain(deep.iter().map(|input| input.as_bytes())) {
+            fuzz_lex(input);
+            fuzz_parse(input);
+        }
+    }
+}
@@ -1,13 +1,58 @@
-use std::{fmt, str::Bytes};
+use std::borrow::Cow;
+use std::fmt;
 
 #[derive(Debug, Clone,Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Location {
     line: usize,
     col: usize
 }
 
+impl Location {
+    pub fn new(line: usize, col: usize) -> Location {
+        Location { line, col }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn col(&self) -> usize {
+        self.col
+    }
+}
+
+
+// Byte offsets into the source a token was lexed from, start inclusive and
+// end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    // The text the span covers, source must be what was lexed
+    pub fn slice<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.start..self.end]
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Keyword {
     Select,
     From,
@@ -19,6 +64,69 @@ pub enum Keyword {
     Values,
     Int,
     Text,
+    Where,
+    And,
+    Or,
+    Not,
+    Null,
+    Update,
+    Set,
+    Delete,
+    Drop,
+    Primary,
+    Key,
+    Begin,
+    Commit,
+    Rollback,
+    Transaction,
+    Index,
+    On,
+    Unique,
+    Is,
+    Boolean,
+    BigInt,
+    Real,
+    Varchar,
+    Date,
+    Time,
+    Timestamp,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
+    Group,
+    Having,
+    Join,
+    Inner,
+    Left,
+    Outer,
+    In,
+    Explain,
+    Copy,
+    To,
+    With,
+    Json,
+    Alter,
+    Add,
+    Column,
+    Rename,
+    Default,
+    If,
+    Exists,
+    Cast,
+    Serial,
+    Autoincrement,
+    References,
+    Cascade,
+    Restrict,
+    View,
+    Distinct,
+    Union,
+    Intersect,
+    Except,
+    All,
 }
 
 impl Keyword {
@@ -34,16 +142,168 @@ impl Keyword {
             Keyword::Values => "values",
             Keyword::Int => "int",
             Keyword::Text => "text",
+            Keyword::Where => "where",
+            Keyword::And => "and",
+            Keyword::Or => "or",
+            Keyword::Not => "not",
+            Keyword::Null => "null",
+            Keyword::Update => "update",
+            Keyword::Set => "set",
+            Keyword::Delete => "delete",
+            Keyword::Drop => "drop",
+            Keyword::Primary => "primary",
+            Keyword::Key => "key",
+            Keyword::Begin => "begin",
+            Keyword::Commit => "commit",
+            Keyword::Rollback => "rollback",
+            Keyword::Transaction => "transaction",
+            Keyword::Index => "index",
+            Keyword::On => "on",
+            Keyword::Unique => "unique",
+            Keyword::Is => "is",
+            Keyword::Boolean => "boolean",
+            Keyword::BigInt => "bigint",
+            Keyword::Real => "real",
+            Keyword::Varchar => "varchar",
+            Keyword::Date => "date",
+            Keyword::Time => "time",
+            Keyword::Timestamp => "timestamp",
+            Keyword::Order => "order",
+            Keyword::By => "by",
+            Keyword::Asc => "asc",
+            Keyword::Desc => "desc",
+            Keyword::Limit => "limit",
+            Keyword::Offset => "offset",
+            Keyword::Group => "group",
+            Keyword::Having => "having",
+            Keyword::Join => "join",
+            Keyword::Inner => "inner",
+            Keyword::Left => "left",
+            Keyword::Outer => "outer",
+            Keyword::In => "in",
+            Keyword::Explain => "explain",
+            Keyword::Copy => "copy",
+            Keyword::To => "to",
+            Keyword::With => "with",
+            Keyword::Json => "json",
+            Keyword::Alter => "alter",
+            Keyword::Add => "add",
+            Keyword::Column => "column",
+            Keyword::Rename => "rename",
+            Keyword::Default => "default",
+            Keyword::If => "if",
+            Keyword::Exists => "exists",
+            Keyword::Cast => "cast",
+            Keyword::Serial => "serial",
+            Keyword::Autoincrement => "autoincrement",
+            Keyword::References => "references",
+            Keyword::Cascade => "cascade",
+            Keyword::Restrict => "restrict",
+            Keyword::View => "view",
+            Keyword::Distinct => "distinct",
+            Keyword::Union => "union",
+            Keyword::Intersect => "intersect",
+            Keyword::Except => "except",
+            Keyword::All => "all",
         }
     }
 }
 
+const KEYWORDS: &[Keyword] = &[
+    Keyword::Select,
+    Keyword::From,
+    Keyword::As,
+    Keyword::Table,
+    Keyword::Create,
+    Keyword::Insert,
+    Keyword::Into,
+    Keyword::Values,
+    Keyword::Int,
+    Keyword::Text,
+    Keyword::Where,
+    Keyword::And,
+    Keyword::Or,
+    Keyword::Not,
+    Keyword::Null,
+    Keyword::Update,
+    Keyword::Set,
+    Keyword::Delete,
+    Keyword::Drop,
+    Keyword::Primary,
+    Keyword::Key,
+    Keyword::Begin,
+    Keyword::Commit,
+    Keyword::Rollback,
+    Keyword::Transaction,
+    Keyword::Index,
+    Keyword::On,
+    Keyword::Unique,
+    Keyword::Is,
+    Keyword::Boolean,
+    Keyword::BigInt,
+    Keyword::Real,
+    Keyword::Varchar,
+    Keyword::Date,
+    Keyword::Time,
+    Keyword::Timestamp,
+    Keyword::Order,
+    Keyword::By,
+    Keyword::Asc,
+    Keyword::Desc,
+    Keyword::Limit,
+    Keyword::Offset,
+    Keyword::Group,
+    Keyword::Having,
+    Keyword::Join,
+    Keyword::Inner,
+    Keyword::Left,
+    Keyword::Outer,
+    Keyword::In,
+    Keyword::Explain,
+    Keyword::Copy,
+    Keyword::To,
+    Keyword::With,
+    Keyword::Json,
+    Keyword::Alter,
+    Keyword::Add,
+    Keyword::Column,
+    Keyword::Rename,
+    Keyword::Default,
+    Keyword::If,
+    Keyword::Exists,
+    Keyword::Cast,
+    Keyword::Serial,
+    Keyword::Autoincrement,
+    Keyword::References,
+    Keyword::Cascade,
+    Keyword::Restrict,
+    Keyword::View,
+    Keyword::Distinct,
+    Keyword::Union,
+    Keyword::Intersect,
+    Keyword::Except,
+    Keyword::All,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Symbol {
     Semicolon,
     Asterix,
     Comma,
     LeftParen,
-    RightParen
+    RightParen,
+    Equals,
+    NotEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Plus,
+    Minus,
+    Slash,
+    Period,
+    Arrow,
+    DoubleArrow,
 }
 
 impl Symbol {
@@ -54,33 +314,100 @@ impl Symbol {
             Symbol::Comma => ",",
             Symbol::LeftParen => "(",
             Symbol::RightParen => ")",
+            Symbol::Equals => "=",
+            Symbol::NotEqual => "<>",
+            Symbol::LessThan => "<",
+            Symbol::LessThanOrEqual => "<=",
+            Symbol::GreaterThan => ">",
+            Symbol::GreaterThanOrEqual => ">=",
+            Symbol::Plus => "+",
+            Symbol::Minus => "-",
+            Symbol::Slash => "/",
+            Symbol::Period => ".",
+            Symbol::Arrow => "->",
+            Symbol::DoubleArrow => "->>",
         }
     }
 }
 
+const SYMBOLS: &[Symbol] = &[
+    Symbol::Semicolon,
+    Symbol::Asterix,
+    Symbol::Comma,
+    Symbol::LeftParen,
+    Symbol::RightParen,
+    Symbol::Equals,
+    Symbol::NotEqual,
+    Symbol::LessThan,
+    Symbol::LessThanOrEqual,
+    Symbol::GreaterThan,
+    Symbol::GreaterThanOrEqual,
+    Symbol::Plus,
+    Symbol::Minus,
+    Symbol::Slash,
+    Symbol::Period,
+    Symbol::Arrow,
+    Symbol::DoubleArrow,
+];
+
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenKind {
     Keyword,
     Symbol,
     Identifier,
     StringLiteral,
     NumericLiteral,
+    BoolLiteral,
+    Comment,
+    // A placeholder for a value bound when a prepared statement runs, "$1"
+    // or "?"
+    Parameter,
 }
 
+// The value borrows from the source wherever it can: keywords, symbols and
+// booleans point at their static spelling and most other tokens at the
+// source text itself. Only what the lexer has to rewrite, an escaped quote
+// or an identifier folded to lower case, is owned.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Token {
-    value: String,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token<'a> {
+    value: Cow<'a, str>,
     kind: TokenKind,
     loc: Location,
+    span: Span,
 }
 
-impl Token {
+impl Token<'_> {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn location(&self) -> Location {
+        self.loc
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            value: Cow::Owned(self.value.into_owned()),
+            ..self
+        }
+    }
+
     pub fn equals(&self, other: &Token) -> bool {
         self.value == other.value && self.kind == other.kind
     }
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
@@ -90,6 +417,30 @@ impl fmt::Display for Token {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    message: String,
+    loc: Location,
+}
+
+impl LexError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> Location {
+        self.loc
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.loc.line, self.loc.col)
+    }
+}
+
+impl std::error::Error for LexError {}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Cursor {
     pointer: usize,
@@ -97,9 +448,83 @@ pub struct Cursor {
 }
 
 
-pub type LexerFn = fn(&str, Cursor) -> Option<(Token, Cursor)>;
+impl Cursor {
+    // Moves the cursor past c, the character it currently points at. Every
+    // lexer goes through here so a newline is counted the same way everywhere.
+    fn advance(&mut self, c: char) {
+        self.pointer += c.len_utf8();
+        if c == '\n' {
+            self.loc.line += 1;
+            self.loc.col = 1;
+        } else {
+            self.loc.col += 1;
+        }
+    }
+}
+
+// The pointer is a byte offset that always sits on a char boundary, so the
+// input is read a whole char at a time rather than byte by byte
+fn char_at(input: &str, pointer: usize) -> Option<char> {
+    input[pointer..].chars().next()
+}
+
+pub type LexerFn = fn(&str, Cursor) -> Option<(Token<'_>, Cursor)>;
+
+// A single underscore may separate two digits, "1_000", but not start or end
+// a digit group or appear twice in a row
+fn is_digit_separator(input: &str, pointer: usize, is_digit: fn(char) -> bool) -> bool {
+    let prev = input[..pointer].chars().next_back();
+    let next = char_at(input, pointer + 1);
+    prev.is_some_and(is_digit) && next.is_some_and(is_digit)
+}
+
+// 0x/0X hex and 0b/0B binary literals. The value keeps the prefix and any
+// separators, it's up to the parser to interpret it.
+fn lex_prefixed_numeric(input: &str, ic: Cursor, is_digit: fn(char) -> bool) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+    for c in input[ic.pointer..ic.pointer + 2].chars() {
+        cur.advance(c);
+    }
+
+    while let Some(c) = char_at(input, cur.pointer) {
+        if c == '_' && !is_digit_separator(input, cur.pointer, is_digit) {
+            return None;
+        }
+        if c != '_' && !is_digit(c) {
+            break;
+        }
+        cur.advance(c);
+    }
+
+    // No digits after the prefix
+    if cur.pointer == ic.pointer + 2 {
+        return None;
+    }
+
+    // No fractions or exponents, and no out of range digits like "0b12"
+    if char_at(input, cur.pointer).is_some_and(|c| c == '.' || c.is_alphanumeric()) {
+        return None;
+    }
+
+    Some((
+        Token {
+            value: Cow::Borrowed(&input[ic.pointer..cur.pointer]),
+            kind: TokenKind::NumericLiteral,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
 
-pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
+pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let rest = &input[ic.pointer..];
+    if rest.starts_with("0x") || rest.starts_with("0X") {
+        return lex_prefixed_numeric(input, ic, |c| c.is_ascii_hexdigit());
+    }
+    if rest.starts_with("0b") || rest.starts_with("0B") {
+        return lex_prefixed_numeric(input, ic, |c| c == '0' || c == '1');
+    }
 
     let mut cur = ic; // mutable copy of our input cursor, so that we can move it forward as we are reading characters
 
@@ -112,18 +537,15 @@ pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
     let mut exp_marker_found = false;
 
     // Iterate over characters starting at current pointer
-    while (cur.pointer) < input.len() {
-        // SAFETY: assume ASCII
+    while let Some(c) = char_at(input, cur.pointer) {
         /*
             start here 
             look at first digit 
             decide what it is (digit, period, exponent)
             t
          */
-        let c = input.as_bytes()[cur.pointer] as char;
-        cur.loc.col += 1;
 
-        let is_digit = c >= '0' && c <= '9';
+        let is_digit = c.is_ascii_digit();
         let is_period = c == '.';
         let is_exp_marker = c == 'e' || c == 'E';
 
@@ -134,16 +556,17 @@ pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
                 return None;
             }
             period_found = is_period;
-            cur.pointer += 1;
+            cur.advance(c);
             continue;
         }
 
+        // No periods after a period or in the exponent
         if is_period {
-            if period_found {
+            if period_found || exp_marker_found {
                 return None;
             }
             period_found = true;
-            cur.pointer += 1;
+            cur.advance(c);
             continue;
         }
 
@@ -151,22 +574,25 @@ pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
             if exp_marker_found {
                 return None;
             }
-            period_found = true;     // no periods allowed after exp
             exp_marker_found = true;
+            cur.advance(c);
 
-            // expMarker must be followed by digits
-            if (cur.pointer) == input.len() - 1 {
-                return None;
+            if let Some(sign @ ('-' | '+')) = char_at(input, cur.pointer) {
+                cur.advance(sign);
             }
 
-            let c_next = input.as_bytes()[cur.pointer + 1] as char;
-            cur.pointer += 1;
-            cur.loc.col += 1;
+            // expMarker must be followed by digits, after the sign if there is one
+            if !char_at(input, cur.pointer).is_some_and(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            continue;
+        }
 
-            if c_next == '-' || c_next == '+' {
-                cur.pointer += 1;
-                cur.loc.col += 1;
+        if c == '_' {
+            if !is_digit_separator(input, cur.pointer, |c| c.is_ascii_digit()) {
+                return None;
             }
+            cur.advance(c);
             continue;
         }
 
@@ -174,212 +600,1513 @@ pub fn lex_numeric(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
             break;
         }
 
-        cur.pointer += 1;
+        cur.advance(c);
     }
 
-    // No characters accumulated
-    if cur.pointer == ic.pointer {
+    // No characters accumulated, or only a period which is the symbol
+    if cur.pointer == ic.pointer || &input[ic.pointer..cur.pointer] == "." {
         return None;
     }
 
     let value = &input[ic.pointer ..cur.pointer];
     Some((
         Token {
-            value: value.to_string(),
+            value: Cow::Borrowed(value),
             kind: TokenKind::NumericLiteral,
             loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
         },
         cur,
     ))
 }
 
-fn lex_character_delimited(input: &str, ic: Cursor, delimiter: char) -> Option<(Token, Cursor)> { 
+fn lex_character_delimited(input: &str, ic: Cursor, delimiter: char) -> Option<(Token<'_>, Cursor)> { 
 
     let mut cur = ic;
 
-    if input.len() == 0 {
-        return None;
-    }
-
-    if input.as_bytes()[cur.pointer] as char != delimiter {
+    if char_at(input, cur.pointer)? != delimiter {
         return None;
     }
 
-    cur.loc.col += 1;
-    cur.pointer += 1;
-
-    let mut value = String::new();
-    while (cur.pointer) < input.len() {
-        let c = input.as_bytes()[cur.pointer] as char;
+    cur.advance(delimiter);
 
+    let start = cur.pointer;
+    let mut escaped = false;
+    while let Some(c) = char_at(input, cur.pointer) {
         // SQL escapes through double characters not backslash
         if c == delimiter {
-            if cur.pointer + 1 >= input.len() || input.as_bytes()[cur.pointer + 1] as char != delimiter {
+            if char_at(input, cur.pointer + c.len_utf8()) != Some(delimiter) {
+                // Only a literal with an escape in it has to be copied
+                let body = &input[start..cur.pointer];
+                let value = if escaped {
+                    let single = delimiter.to_string();
+                    Cow::Owned(body.replace(&single.repeat(2), &single))
+                } else {
+                    Cow::Borrowed(body)
+                };
+                cur.advance(c);
                 return Some((
                     Token {
-                        value: value.to_string(),
+                        value,
                         loc: ic.loc,
-                        kind: TokenKind::StringLiteral
+                        kind: TokenKind::StringLiteral,
+                        span: Span::new(ic.pointer, cur.pointer),
                     },
                     cur
                 ))
-            } else {
-                value.push(delimiter);
-                cur.pointer += 2;
-                cur.loc.col += 2;
             }
+
+            escaped = true;
+            cur.advance(c);
+            cur.advance(c);
+            continue;
         }
-        value.push(c);
-        cur.loc.col += 1;
-        cur.pointer += 1;
+        cur.advance(c);
         
     }
-    return None
+    None
 }
 
-fn lex_string(input: &str, ic: Cursor) -> Option<(Token, Cursor)> {
-    return lex_character_delimited(input, ic, '\'');
+fn lex_string(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    lex_character_delimited(input, ic, '\'')
 }
 
+// The body of an E'...' string at pointer with its backslash escapes decoded,
+// and the pointer just past the closing quote. None when there is no escape
+// string at pointer, Err says why the one there can't be read.
+//
+// The escapes are Postgres's: \b \f \n \r \t, \ooo in octal, \xhh in hex,
+// \uXXXX and \UXXXXXXXX for a code point, and a backslash before any other
+// character is that character. Octal and hex escapes give a byte, so
+// E'\xc3\xa9' is é, and the bytes have to add up to valid UTF-8. No
+// escape gives a NUL, and an octal one past \377 is an error.
+fn escape_string(input: &str, pointer: usize) -> Option<Result<(String, usize), &'static str>> {
+    let rest = &input[pointer..];
+    if !(rest.starts_with("E'") || rest.starts_with("e'")) {
+        return None;
+    }
+
+    let mut bytes = Vec::new();
+    let mut chars = rest.char_indices().skip(2).peekable();
+    // Up to max digits of the radix, taken off the front of chars
+    let digits = |chars: &mut std::iter::Peekable<_>, radix: u32, max: usize| {
+        let mut value = 0;
+        let mut count = 0;
+        while count < max
+            && let Some(d) = chars.peek().and_then(|(_, c): &(usize, char)| c.to_digit(radix))
+        {
+            value = value * radix + d;
+            count += 1;
+            chars.next();
+        }
+        (value, count)
+    };
+    let mut high_surrogate = None;
 
-/* 
+    loop {
+        let Some((i, c)) = chars.next() else {
+            return Some(Err("Unterminated string"));
+        };
+        let decoded = match c {
+            '\'' if chars.peek().is_some_and(|(_, c)| *c == '\'') => {
+                chars.next();
+                '\''
+            }
+            '\'' => {
+                if high_surrogate.is_some() {
+                    return Some(Err("Invalid Unicode surrogate pair"));
+                }
+                let value = String::from_utf8(bytes).map_err(|_| "Invalid UTF-8 in escape string");
+                return Some(value.map(|value| (value, pointer + i + 1)));
+            }
+            '\\' => {
+                let Some((_, c)) = chars.next() else {
+                    return Some(Err("Unterminated string"));
+                };
+                match c {
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '0'..='7' => {
+                        let (rest, count) = digits(&mut chars, 8, 2);
+                        let value = c.to_digit(8).unwrap() * 8u32.pow(count as u32) + rest;
+                        if value == 0 || value > 0o377 {
+                            return Some(Err("Invalid escape"));
+                        }
+                        bytes.push(value as u8);
+                        continue;
+                    }
+                    'x' => match digits(&mut chars, 16, 2) {
+                        (_, 0) => 'x',
+                        (0, _) => return Some(Err("Invalid escape")),
+                        (value, _) => {
+                            bytes.push(value as u8);
+                            continue;
+                        }
+                    },
+                    'u' | 'U' => {
+                        let len = if c == 'u' { 4 } else { 8 };
+                        let (value, count) = digits(&mut chars, 16, len);
+                        if count < len {
+                            return Some(Err("Invalid Unicode escape"));
+                        }
+                        // A code point past U+FFFF can be written as a pair of
+                        // UTF-16 surrogates, \uD83D\uDE00 for example
+                        match (high_surrogate.take(), value) {
+                            (None, 0xD800..=0xDBFF) => {
+                                high_surrogate = Some(value);
+                                continue;
+                            }
+                            (Some(high), 0xDC00..=0xDFFF) => {
+                                char::from_u32(0x10000 + ((high - 0xD800) << 10) + (value - 0xDC00)).unwrap()
+                            }
+                            (Some(_), _) => return Some(Err("Invalid Unicode surrogate pair")),
+                            (None, value) => match char::from_u32(value) {
+                                Some(c) if c != '\0' => c,
+                                _ => return Some(Err("Invalid Unicode escape")),
+                            },
+                        }
+                    }
+                    c => c,
+                }
+            }
+            c => c,
+        };
+        if high_surrogate.is_some() {
+            return Some(Err("Invalid Unicode surrogate pair"));
+        }
+        bytes.extend_from_slice(decoded.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+}
 
-fn lex_keyword(input: &str, cursor: Cursor) -> Option<(Token, Cursor)> {
+// E'...' with backslash escapes, the decoded text is the token's value
+fn lex_escape_string(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let (value, end) = escape_string(input, ic.pointer)?.ok()?;
+    let mut cur = ic;
+    for c in input[ic.pointer..end].chars() {
+        cur.advance(c);
+    }
 
+    Some((
+        Token {
+            value: Cow::Owned(value),
+            kind: TokenKind::StringLiteral,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
 }
 
-fn lex_symbol(input: &str, cursor: Cursor) -> Option<(Token, Cursor)> { 
+// The opening delimiter of a dollar-quoted string at pointer, "$$" or "$tag$"
+// where the tag follows the same rules as a bare identifier
+fn dollar_tag(input: &str, pointer: usize) -> Option<&str> {
+    let rest = input[pointer..].strip_prefix('$')?;
+    let end = rest.find('$')?;
+    let tag = &rest[..end];
+
+    let mut chars = tag.chars();
+    let valid = chars.next().is_none_or(is_identifier_start) && chars.all(is_identifier_char);
+    if !valid {
+        return None;
+    }
 
+    Some(&input[pointer..pointer + end + 2])
 }
 
-fn lex_identifier(input: &str, cursor: Cursor) -> Option<(Token, Cursor)> { 
+// $$...$$ or $tag$...$tag$, the contents are taken verbatim with no escaping
+fn lex_dollar_string(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+
+    let tag = dollar_tag(input, ic.pointer)?;
+    for c in tag.chars() {
+        cur.advance(c);
+    }
+
+    // Unterminated, lex() reports it at the opening tag
+    let len = input[cur.pointer..].find(tag)?;
+    let value = &input[cur.pointer..cur.pointer + len];
+    for c in value.chars().chain(tag.chars()) {
+        cur.advance(c);
+    }
 
+    Some((
+        Token {
+            value: Cow::Borrowed(value),
+            kind: TokenKind::StringLiteral,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
 }
-*/
 
-pub fn lex(source: String) -> Result<Vec<Token>, String> {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut cur = Cursor {
-        pointer: 0,
-        loc: Location {line:1, col:1,}
-    };
-    'lex: while (cur.pointer) < source.len() {
-        let lexers: Vec<LexerFn> = vec![
-            //lex_keyword,
-            //lex_symbol,
-            lex_string,
-            lex_numeric,
-            //lex_identifier,
-        ];
 
-        for l in lexers {
-            if let Some((token, new_cursor)) = l(&source,cur) {
-                cur = new_cursor;
-    
-                if !token.value.is_empty() {
-                    tokens.push(token);
-                }
-    
-                continue 'lex;
-            }
+// A keyword only ends at whitespace, a symbol, or the end of the input, so that
+// "selects" is not read as "select" followed by "s"
+fn is_word_boundary(input: &str, pointer: usize) -> bool {
+    match input[pointer..].chars().next() {
+        None => true,
+        Some(c) => {
+            c.is_whitespace()
+                || SYMBOLS.iter().any(|s| input[pointer..].starts_with(s.as_str()))
         }
-    
-    // Error if no lexer matched
-        let hint = if let Some(last) = tokens.last() {
-            format!(" after {}", last.value)
-        } else {
-            "".to_string()
-        };
-
-        return Err(format!(
-            "Unable to lex token{} at {}:{}",
-            hint, cur.loc.line, cur.loc.col
-        ));
     }
-        Ok(tokens)
 }
-    
 
-    
+// Whether the input at pointer is word, ignoring case, as a whole word
+fn starts_with_word(input: &str, pointer: usize, word: &str) -> bool {
+    match input.get(pointer..pointer + word.len()) {
+        Some(candidate) => {
+            candidate.eq_ignore_ascii_case(word) && is_word_boundary(input, pointer + word.len())
+        }
+        None => false,
+    }
+}
 
+fn lex_keyword(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Longest match wins so a keyword that is a prefix of another one
+    // doesn't cut it short
+    let mut matched: Option<Keyword> = None;
+    for keyword in KEYWORDS {
+        let kw = keyword.as_str();
+        if !starts_with_word(input, ic.pointer, kw) {
+            continue;
+        }
 
-    fn make_cursor() -> Cursor {
-        Cursor {
-            pointer: 0,
-            loc: Location { line: 1, col: 1 },
+        if matched.is_none_or(|m| m.as_str().len() < kw.len()) {
+            matched = Some(*keyword);
         }
     }
 
-    #[test]
-    fn test_integer() {
-        let source = "123";
-        let result = lex_numeric(source, make_cursor());
-        assert!(result.is_some(), "Expected to lex an integer");
-        let (token, cur) = result.unwrap();
-        println!("{:?}", token);
-        println!("{:?}", cur);
-        assert_eq!(token.value, "123");
-        assert_eq!(token.kind, TokenKind::NumericLiteral);
-        assert_eq!(cur.pointer, source.len());
-    }
-    #[test]
-    fn test_float() {
-        let source = "3.14";
-        let result = lex_numeric(source, make_cursor());
-        assert!(result.is_some(), "Expected to lex a float");
-        let (token, cur) = result.unwrap();
-        println!("{}", token);
-        assert_eq!(token.value, "3.14");
-        assert_eq!(token.kind, TokenKind::NumericLiteral);
-        assert_eq!(cur.pointer, source.len());
-    }
+    let keyword = matched?;
+    cur.pointer += keyword.as_str().len();
+    cur.loc.col += keyword.as_str().len();
 
+    Some((
+        Token {
+            value: Cow::Borrowed(keyword.as_str()),
+            kind: TokenKind::Keyword,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
 
-    #[test]
-    fn test_scientific_notation() {
-        let source = "2.5e10";
-        let result = lex_numeric(source, make_cursor());
-        
-        assert!(result.is_some(), "Expected to lex scientific notation");
-        let (token, cur) = result.unwrap();
-        println!("{}", token);
-        println!("{:?}", cur);
-        assert_eq!(token.value, "2.5e10");
-        assert_eq!(token.kind, TokenKind::NumericLiteral);
-        assert_eq!(cur.pointer, source.len());
+// true and false are values rather than keywords. null is already the
+// Keyword::Null keyword.
+fn lex_bool(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+
+    let value = ["true", "false"]
+        .into_iter()
+        .find(|word| starts_with_word(input, ic.pointer, word))?;
+
+    cur.pointer += value.len();
+    cur.loc.col += value.len();
+
+    Some((
+        Token {
+            value: Cow::Borrowed(value),
+            kind: TokenKind::BoolLiteral,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
+
+// "-- ..." up to the end of the line, or "/* ... */" which may span lines and
+// nest like in Postgres, so "/* a /* b */ c */" is one comment. The token
+// keeps the comment's full text; lex() drops it like whitespace.
+fn lex_comment(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+    let rest = &input[ic.pointer..];
+
+    if rest.starts_with("--") {
+        // The line ending is not part of the comment, CRLF or not
+        while let Some(c) = char_at(input, cur.pointer) {
+            if c == '\n' || input[cur.pointer..].starts_with("\r\n") {
+                break;
+            }
+            cur.advance(c);
+        }
+    } else if rest.starts_with("/*") {
+        cur.advance('/');
+        cur.advance('*');
+        let mut depth = 1;
+        while depth > 0 {
+            // Unterminated, lex() reports it at the opening "/*"
+            let c = char_at(input, cur.pointer)?;
+            if input[cur.pointer..].starts_with("*/") {
+                cur.advance('*');
+                cur.advance('/');
+                depth -= 1;
+            } else if input[cur.pointer..].starts_with("/*") {
+                cur.advance('/');
+                cur.advance('*');
+                depth += 1;
+            } else {
+                cur.advance(c);
+            }
+        }
+    } else {
+        return None;
     }
 
-    #[test]
-    fn test_scientific_notation_with_sign() {
-        let source = "1e-5";
-        let result = lex_numeric(source, make_cursor());
-        assert!(result.is_some(), "Expected to lex scientific notation with sign");
-        let (token, cur) = result.unwrap();
-        println!("{}", token);
-        println!("{:?}", cur);
-        assert_eq!(token.value, "1e-5");
-        assert_eq!(token.kind, TokenKind::NumericLiteral);
-        assert_eq!(cur.pointer, source.len());
+    Some((
+        Token {
+            value: Cow::Borrowed(&input[ic.pointer..cur.pointer]),
+            kind: TokenKind::Comment,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
+
+fn lex_symbol(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+
+    // Only an unterminated block comment gets here, it must not lex as "/" "*"
+    if input[ic.pointer..].starts_with("/*") {
+        return None;
     }
 
+    // Longest match wins so "<=" is one symbol rather than "<" and "="
+    let symbol = SYMBOLS
+        .iter()
+        .filter(|s| input[ic.pointer..].starts_with(s.as_str()))
+        .max_by_key(|s| s.as_str().len())?;
 
+    cur.pointer += symbol.as_str().len();
+    cur.loc.col += symbol.as_str().len();
 
-    #[test]
-    fn test_string() {
-        let source = "\'SQL\'";
-        let result = lex_string(source, make_cursor());
-        assert!(result.is_some(), "Expected to lex a string");
-        let (token, cur) = result.unwrap();
-        println!("{}", token);
-        println!("{:?}", cur);
-        assert_eq!(token.value, "SQL");
-        assert_eq!(token.kind, TokenKind::StringLiteral);
-        assert_eq!(cur.pointer, source.len() - 1 );
-        
+    Some((
+        Token {
+            value: Cow::Borrowed(symbol.as_str()),
+            kind: TokenKind::Symbol,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
+
+// "$n" numbers its parameter, a "?" is numbered by the parser
+fn lex_parameter(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    let mut cur = ic;
+
+    match char_at(input, cur.pointer)? {
+        '?' => cur.advance('?'),
+        '$' => {
+            cur.advance('$');
+            while let Some(c) = char_at(input, cur.pointer).filter(|c| c.is_ascii_digit()) {
+                cur.advance(c);
+            }
+            // "$" alone or "$1a" is not a parameter
+            let digits = cur.pointer - ic.pointer - 1;
+            if digits == 0 || char_at(input, cur.pointer).is_some_and(is_identifier_char) {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+
+    Some((
+        Token {
+            value: Cow::Borrowed(&input[ic.pointer..cur.pointer]),
+            kind: TokenKind::Parameter,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
+
+// Identifiers start with a letter of any script or an underscore, and go
+// on with letters, digits and underscores
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+fn lex_identifier(input: &str, ic: Cursor) -> Option<(Token<'_>, Cursor)> {
+    // Quoted identifiers keep their exact text and case
+    if let Some((token, cur)) = lex_character_delimited(input, ic, '"') {
+        return Some((
+            Token {
+                kind: TokenKind::Identifier,
+                ..token
+            },
+            cur,
+        ));
+    }
+
+    // The E of an escape string lex_escape_string couldn't read is not a
+    // name, lex() reports what is wrong with the string instead
+    if escape_string(input, ic.pointer).is_some() {
+        return None;
+    }
+
+    let mut cur = ic;
+
+    let c = char_at(input, cur.pointer).filter(|c| is_identifier_start(*c))?;
+    cur.advance(c);
+    while let Some(c) = char_at(input, cur.pointer).filter(|c| is_identifier_char(*c)) {
+        cur.advance(c);
+    }
+
+    // Unquoted identifiers fold to lower case, like Postgres, which only
+    // folds ASCII letters: "Été" is "Été" but "ÉTÉ" is "ÉtÉ". One without
+    // ASCII upper case can be borrowed as is.
+    let text = &input[ic.pointer..cur.pointer];
+    let value = if text.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(text.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(text)
+    };
+    Some((
+        Token {
+            value,
+            kind: TokenKind::Identifier,
+            loc: ic.loc,
+            span: Span::new(ic.pointer, cur.pointer),
+        },
+        cur,
+    ))
+}
+
+// Optional pre-pass for SQL pasted from documents: maps typographic quotes to
+// their ASCII counterparts and any non-ASCII whitespace (NBSP, em space, ...)
+// to a plain space. lex() does not call this, callers opt in.
+pub fn normalize(source: &str) -> String {
+    source
+        .chars()
+        .map(|c| match c {
+            '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' => '\'',
+            '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' => '"',
+            c if !c.is_ascii() && c.is_whitespace() => ' ',
+            c => c,
+        })
+        .collect()
+}
+
+/*
+    The lexers are tried in this order and the first one to return Some wins,
+    so the position in this table is the precedence:
+    - lex_keyword and lex_bool must come before lex_identifier, otherwise
+      every keyword and boolean lexes as an identifier
+    - lex_numeric accepts a leading period, so ".5" is a number; it has to
+      stay above lex_symbol or that would lex as the period symbol and 5
+    - lex_comment must come before lex_symbol, otherwise "--" lexes as two
+      minus signs and a block comment opens with a slash and an asterisk
+    - lex_string and lex_identifier only start at a quote or a letter, which
+      no other lexer accepts
+    - lex_escape_string must come before lex_identifier, which would take
+      the E of E'...' as a name
+    - lex_parameter comes after lex_dollar_string, a tag can't start with a
+      digit so "$1" is never the opening of a dollar-quoted string
+ */
+const LEXERS: &[LexerFn] = &[
+    lex_keyword,
+    lex_bool,
+    lex_numeric,
+    lex_comment,
+    lex_symbol,
+    lex_string,
+    lex_escape_string,
+    lex_dollar_string,
+    lex_parameter,
+    lex_identifier,
+];
+
+// Called with every token lex() produces, in order. Handy to see how far the
+// lexer got before it gave up on a statement.
+pub type TraceFn<'a> = &'a mut dyn FnMut(&Token);
+
+// Lexes one token per call to next(), reading the source in place so a long
+// script is never copied or lexed further than it is read. Whitespace and
+// comments are skipped unless asked for with with_comments(), and the
+// iterator ends after the first error.
+pub struct Lexer<'a> {
+    source: &'a str,
+    cur: Cursor,
+    // where the last token came from in source, for the "after ..." hint
+    last: Option<Span>,
+    failed: bool,
+    comments: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            cur: Cursor {
+                pointer: 0,
+                loc: Location { line: 1, col: 1 },
+            },
+            last: None,
+            failed: false,
+            comments: false,
+        }
+    }
+
+    // Emits comments as TokenKind::Comment tokens instead of skipping them,
+    // for tools like formatters that have to keep them. The parser does not
+    // expect them, so these tokens are not meant for parse().
+    pub fn with_comments(mut self) -> Lexer<'a> {
+        self.comments = true;
+        self
+    }
+
+    fn error(&self) -> LexError {
+        let hint = match self.last {
+            Some(span) => format!(" after {}", span.slice(self.source)),
+            None => "".to_string(),
+        };
+
+        let message = if self.source[self.cur.pointer..].starts_with("/*") {
+            "Unterminated block comment".to_string()
+        } else if dollar_tag(self.source, self.cur.pointer).is_some() {
+            "Unterminated dollar-quoted string".to_string()
+        } else if let Some(Err(message)) = escape_string(self.source, self.cur.pointer) {
+            message.to_string()
+        } else {
+            format!("Unable to lex token{}", hint)
+        };
+
+        LexError {
+            message,
+            loc: self.cur.loc,
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        'lex: while let Some(c) = char_at(self.source, self.cur.pointer) {
+            // Whitespace only separates tokens, it never produces one
+            if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+                self.cur.advance(c);
+                continue 'lex;
+            }
+
+            for l in LEXERS {
+                if let Some((token, new_cursor)) = l(self.source, self.cur) {
+                    self.cur = new_cursor;
+
+                    if token.kind == TokenKind::Comment && !self.comments {
+                        continue 'lex;
+                    }
+
+                    self.last = Some(token.span);
+                    return Some(Ok(token));
+                }
+            }
+
+            // Error if no lexer matched
+            self.failed = true;
+            return Some(Err(self.error()));
+        }
+
+        None
+    }
+}
+
+impl std::iter::FusedIterator for Lexer<'_> {}
+
+pub fn lex(source: &str) -> Result<Vec<Token<'_>>, LexError> {
+    Lexer::new(source).collect()
+}
+
+pub fn lex_traced<'a>(source: &'a str, mut trace: Option<TraceFn>) -> Result<Vec<Token<'a>>, LexError> {
+    let mut tokens: Vec<Token> = Vec::new();
+    for token in Lexer::new(source) {
+        let token = token?;
+        if let Some(trace) = trace.as_mut() {
+            trace(&token);
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Identifiers that would not lex back to themselves bare need quoting: mixed
+// case, spaces, keywords and so on
+fn needs_quoting(identifier: &str) -> bool {
+    let bare = Cursor {
+        pointer: 0,
+        loc: Location { line: 1, col: 1 },
+    };
+    match lex_identifier(identifier, bare) {
+        Some((token, cur)) => {
+            token.value != identifier
+                || cur.pointer != identifier.len()
+                || lex_keyword(identifier, bare).is_some()
+                || lex_bool(identifier, bare).is_some()
+        }
+        None => true,
+    }
+}
+
+// Turns tokens back into SQL text. Whitespace and comments are not kept, but
+// the result lexes back to tokens that are equal() to the input.
+pub fn unlex(tokens: &[Token]) -> String {
+    let mut sql = String::new();
+    let mut prev: Option<&Token> = None;
+
+    for token in tokens {
+        if token.kind == TokenKind::Comment {
+            continue;
+        }
+
+        let no_space_before = token.kind == TokenKind::Symbol
+            && matches!(token.value(), ";" | "," | ")");
+        let no_space_after_prev = prev.is_some_and(|p| p.kind == TokenKind::Symbol && p.value == "(");
+        if prev.is_some() && !no_space_before && !no_space_after_prev {
+            sql.push(' ');
+        }
+
+        match token.kind {
+            TokenKind::StringLiteral => {
+                sql.push('\'');
+                sql.push_str(&token.value.replace('\'', "''"));
+                sql.push('\'');
+            }
+            TokenKind::Identifier if needs_quoting(&token.value) => {
+                sql.push('"');
+                sql.push_str(&token.value.replace('"', "\"\""));
+                sql.push('"');
+            }
+            _ => sql.push_str(&token.value),
+        }
+
+        prev = Some(token);
+    }
+
+    sql
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_cursor() -> Cursor {
+        Cursor {
+            pointer: 0,
+            loc: Location { line: 1, col: 1 },
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        let source = "123";
+        let result = lex_numeric(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex an integer");
+        let (token, cur) = result.unwrap();
+        println!("{:?}", token);
+        println!("{:?}", cur);
+        assert_eq!(token.value, "123");
+        assert_eq!(token.kind, TokenKind::NumericLiteral);
+        assert_eq!(cur.pointer, source.len());
+    }
+    #[test]
+    fn test_float() {
+        let source = "3.14";
+        let result = lex_numeric(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex a float");
+        let (token, cur) = result.unwrap();
+        println!("{}", token);
+        assert_eq!(token.value, "3.14");
+        assert_eq!(token.kind, TokenKind::NumericLiteral);
+        assert_eq!(cur.pointer, source.len());
+    }
+
+
+    #[test]
+    fn test_scientific_notation() {
+        let source = "2.5e10";
+        let result = lex_numeric(source, make_cursor());
+        
+        assert!(result.is_some(), "Expected to lex scientific notation");
+        let (token, cur) = result.unwrap();
+        println!("{}", token);
+        println!("{:?}", cur);
+        assert_eq!(token.value, "2.5e10");
+        assert_eq!(token.kind, TokenKind::NumericLiteral);
+        assert_eq!(cur.pointer, source.len());
+    }
+
+    #[test]
+    fn test_scientific_notation_with_sign() {
+        let source = "1e-5";
+        let result = lex_numeric(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex scientific notation with sign");
+        let (token, cur) = result.unwrap();
+        println!("{}", token);
+        println!("{:?}", cur);
+        assert_eq!(token.value, "1e-5");
+        assert_eq!(token.kind, TokenKind::NumericLiteral);
+        assert_eq!(cur.pointer, source.len());
+    }
+
+
+
+    #[test]
+    fn test_exponent_requires_digits() {
+        for source in ["1e-", "2e+", "3ex", "4e", "5e+-1", "1e5.2", "1e5e2"] {
+            assert!(lex_numeric(source, make_cursor()).is_none(), "Expected {} to be rejected", source);
+        }
+
+        for source in ["1e-5", "2E+10", "3e7", ".5e1"] {
+            let (token, cur) = lex_numeric(source, make_cursor()).unwrap();
+            assert_eq!(token.value, source);
+            assert_eq!(cur.pointer, source.len());
+        }
+    }
+
+    #[test]
+    fn test_hex_and_binary() {
+        for source in ["0xFF", "0Xff", "0b1010", "0B1", "0xdead_beef"] {
+            let result = lex_numeric(source, make_cursor());
+            assert!(result.is_some(), "Expected to lex {}", source);
+            let (token, cur) = result.unwrap();
+            assert_eq!(token.value, source);
+            assert_eq!(token.kind, TokenKind::NumericLiteral);
+            assert_eq!(cur.pointer, source.len());
+        }
+    }
+
+    #[test]
+    fn test_invalid_hex_and_binary() {
+        for source in ["0x", "0xFF.5", "0x1.", "0b102", "0b", "0xG1", "0x_1", "0x1_"] {
+            assert!(lex_numeric(source, make_cursor()).is_none(), "Expected {} to be rejected", source);
+        }
+    }
+
+    #[test]
+    fn test_underscore_separators() {
+        for source in ["1_000", "1_000_000", "3.141_592", "1_0e1_0"] {
+            let (token, cur) = lex_numeric(source, make_cursor()).unwrap();
+            assert_eq!(token.value, source);
+            assert_eq!(cur.pointer, source.len());
+        }
+
+        for source in ["1__0", "1_", "1_.5", "1._5", "1e_5"] {
+            assert!(lex_numeric(source, make_cursor()).is_none(), "Expected {} to be rejected", source);
+        }
+    }
+
+    #[test]
+    fn test_string() {
+        let source = "\'SQL\'";
+        let result = lex_string(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex a string");
+        let (token, cur) = result.unwrap();
+        println!("{}", token);
+        println!("{:?}", cur);
+        assert_eq!(token.value, "SQL");
+        assert_eq!(token.kind, TokenKind::StringLiteral);
+        assert_eq!(cur.pointer, source.len());
+        
+    }
+
+    #[test]
+    fn test_normalize_smart_quotes() {
+        let source = normalize("\u{2018}SQL\u{2019}");
+        assert_eq!(source, "'SQL'");
+        let (token, _) = lex_string(&source, make_cursor()).unwrap();
+        assert_eq!(token.value, "SQL");
+        assert_eq!(token.kind, TokenKind::StringLiteral);
+    }
+
+    #[test]
+    fn test_normalize_whitespace() {
+        assert_eq!(normalize("1\u{00A0}2\u{3000}3"), "1 2 3");
+        assert_eq!(normalize("\u{201C}Name\u{201D}"), "\"Name\"");
+        assert_eq!(normalize("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_keywords() {
+        for keyword in KEYWORDS {
+            let lower = keyword.as_str().to_string();
+            let upper = lower.to_uppercase();
+            for source in [lower.clone(), upper] {
+                let result = lex_keyword(&source, make_cursor());
+                assert!(result.is_some(), "Expected to lex keyword {}", source);
+                let (token, cur) = result.unwrap();
+                assert_eq!(token.value, lower);
+                assert_eq!(token.kind, TokenKind::Keyword);
+                assert_eq!(cur.pointer, source.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_where_keywords() {
+        let tokens = lex("select * from t where id not null").unwrap();
+        let keywords: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::Keyword)
+            .map(|t| t.value())
+            .collect();
+        assert_eq!(keywords, vec!["select", "from", "where", "not", "null"]);
+    }
+
+    #[test]
+    fn test_bool_and_null_literals() {
+        let tokens = lex("true FALSE Null").unwrap();
+        assert_eq!(tokens[0].value, "true");
+        assert_eq!(tokens[0].kind, TokenKind::BoolLiteral);
+        assert_eq!(tokens[1].value, "false");
+        assert_eq!(tokens[1].kind, TokenKind::BoolLiteral);
+        assert_eq!(tokens[2].value, "null");
+        assert_eq!(tokens[2].kind, TokenKind::Keyword);
+    }
+
+    #[test]
+    fn test_bool_boundary() {
+        assert!(lex_bool("truely", make_cursor()).is_none());
+
+        let tokens = lex("truely").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "truely");
+        assert_eq!(tokens[0].kind, TokenKind::Identifier);
+
+        let tokens = lex("nullable").unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Identifier);
+    }
+
+    #[test]
+    fn test_keyword_mixed_case() {
+        let (token, _) = lex_keyword("SeLeCt", make_cursor()).unwrap();
+        assert_eq!(token.value, "select");
+    }
+
+    #[test]
+    fn test_keyword_boundary() {
+        assert!(lex_keyword("selects", make_cursor()).is_none());
+        assert!(lex_keyword("into1", make_cursor()).is_none());
+
+        let (token, cur) = lex_keyword("select*", make_cursor()).unwrap();
+        assert_eq!(token.value, "select");
+        assert_eq!(cur.pointer, 6);
+    }
+
+    #[test]
+    fn test_symbols() {
+        for source in [";", "*", ",", "(", ")"] {
+            let result = lex_symbol(source, make_cursor());
+            assert!(result.is_some(), "Expected to lex symbol {}", source);
+            let (token, cur) = result.unwrap();
+            assert_eq!(token.value, source);
+            assert_eq!(token.kind, TokenKind::Symbol);
+            assert_eq!(cur.pointer, 1);
+            assert_eq!(cur.loc.col, 2);
+        }
+    }
+
+    #[test]
+    fn test_operators() {
+        for source in ["=", "<>", "<", "<=", ">", ">=", "+", "-", "/", ".", "->", "->>"] {
+            let (token, cur) = lex_symbol(source, make_cursor()).unwrap();
+            assert_eq!(token.value, source);
+            assert_eq!(token.kind, TokenKind::Symbol);
+            assert_eq!(cur.pointer, source.len());
+        }
+    }
+
+    #[test]
+    fn test_longest_operator() {
+        let tokens = lex("<=").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, "<=");
+        assert_eq!(tokens[0].kind, TokenKind::Symbol);
+    }
+
+    #[test]
+    fn test_operator_between_identifiers() {
+        let tokens = lex("a<b").unwrap();
+        let kinds: Vec<&TokenKind> = tokens.iter().map(|t| &t.kind).collect();
+        assert_eq!(kinds, vec![&TokenKind::Identifier, &TokenKind::Symbol, &TokenKind::Identifier]);
+        assert_eq!(tokens[1].value, "<");
+        assert_eq!(tokens[2].loc.col, 3);
+    }
+
+    #[test]
+    fn test_period_symbol() {
+        let tokens = lex("t.id").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec!["t", ".", "id"]);
+        assert_eq!(tokens[1].kind, TokenKind::Symbol);
+        assert!(lex_numeric(".", make_cursor()).is_none());
+    }
+
+    #[test]
+    fn test_not_a_symbol() {
+        assert!(lex_symbol("a", make_cursor()).is_none());
+        assert!(lex_symbol("1", make_cursor()).is_none());
+    }
+
+    #[test]
+    fn test_keywords_and_symbols() {
+        let tokens = lex("select*from;").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec!["select", "*", "from", ";"]);
+        assert_eq!(tokens[1].kind, TokenKind::Symbol);
+        assert_eq!(tokens[1].loc.col, 7);
+    }
+
+    #[test]
+    fn test_identifier() {
+        for (source, expected) in [("foo", "foo"), ("_bar1", "_bar1"), ("Foo", "foo")] {
+            let result = lex_identifier(source, make_cursor());
+            assert!(result.is_some(), "Expected to lex identifier {}", source);
+            let (token, cur) = result.unwrap();
+            assert_eq!(token.value, expected);
+            assert_eq!(token.kind, TokenKind::Identifier);
+            assert_eq!(cur.pointer, source.len());
+        }
+    }
+
+    #[test]
+    fn test_quoted_identifier() {
+        let source = "\"Weird Name\"";
+        let result = lex_identifier(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex a quoted identifier");
+        let (token, cur) = result.unwrap();
+        assert_eq!(token.value, "Weird Name");
+        assert_eq!(token.kind, TokenKind::Identifier);
+        assert_eq!(cur.pointer, source.len());
+    }
+
+    #[test]
+    fn test_not_an_identifier() {
+        assert!(lex_identifier("1abc", make_cursor()).is_none());
+        assert!(lex_identifier("*", make_cursor()).is_none());
+    }
+
+    #[test]
+    fn test_keyword_before_identifier() {
+        let tokens = lex("select*from").unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Keyword);
+
+        let tokens = lex("selects").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, TokenKind::Identifier);
+        assert_eq!(tokens[0].value, "selects");
+    }
+
+    #[test]
+    fn test_token_accessors() {
+        let tokens = lex("select\n  foo").unwrap();
+        let token = &tokens[1];
+        assert_eq!(token.value(), "foo");
+        assert_eq!(token.kind(), &TokenKind::Identifier);
+        assert_eq!(token.location().line(), 2);
+        assert_eq!(token.location().col(), 3);
+    }
+
+    #[test]
+    fn test_line_comment() {
+        let tokens = lex("select 1 -- the answer").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec!["select", "1"]);
+
+        let tokens = lex("-- header\nselect 1").unwrap();
+        assert_eq!(tokens[0].loc, Location { line: 2, col: 1 });
+    }
+
+    #[test]
+    fn test_block_comment() {
+        let tokens = lex("select /* first\n   second */ 1").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[1].value, "1");
+        assert_eq!(tokens[1].loc, Location { line: 2, col: 14 });
+
+        let (token, cur) = lex_comment("/**/", make_cursor()).unwrap();
+        assert_eq!(token.value, "/**/");
+        assert_eq!(token.kind, TokenKind::Comment);
+        assert_eq!(cur.pointer, 4);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let err = lex("select\n  /* oops\n 1").unwrap_err();
+        assert_eq!(err.message(), "Unterminated block comment");
+        assert_eq!(err.location(), Location { line: 2, col: 3 });
+        assert!(lex_comment("/*/", make_cursor()).is_none());
+    }
+
+    #[test]
+    fn test_nested_block_comment() {
+        let tokens = lex("select /* a /* b */ c */ 1").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec!["select", "1"]);
+
+        let err = lex("select /* a /* b */ 1").unwrap_err();
+        assert_eq!(err.message(), "Unterminated block comment");
+        assert_eq!(err.location(), Location { line: 1, col: 8 });
+    }
+
+    #[test]
+    fn test_lexer_with_comments() {
+        let source = "-- header\nselect /* x */ 1";
+        let tokens: Vec<Token> = Lexer::new(source).with_comments().collect::<Result<_, _>>().unwrap();
+        let lexed: Vec<(&str, &TokenKind)> = tokens.iter().map(|t| (t.value(), t.kind())).collect();
+        assert_eq!(
+            lexed,
+            vec![
+                ("-- header", &TokenKind::Comment),
+                ("select", &TokenKind::Keyword),
+                ("/* x */", &TokenKind::Comment),
+                ("1", &TokenKind::NumericLiteral),
+            ]
+        );
+        assert_eq!(tokens[2].loc, Location { line: 2, col: 8 });
+        assert_eq!(unlex(&tokens), "select 1");
+    }
+
+    #[test]
+    fn test_comment_is_not_an_operator() {
+        let tokens = lex("1-2/3").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values, vec!["1", "-", "2", "/", "3"]);
+    }
+
+    #[test]
+    fn test_empty_string_literal() {
+        let tokens = lex("''").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, TokenKind::StringLiteral);
+        assert_eq!(tokens[0].value, "");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let tokens = lex("select 1;").unwrap();
+        let json = serde_json::to_string(&tokens).unwrap();
+        let decoded: Vec<Token> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, tokens);
+    }
+
+    fn assert_equal_tokens(a: &[Token], b: &[Token]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert!(x.equals(y), "{} != {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_unlex_round_trip() {
+        let tokens = lex("select a , b from t ;").unwrap();
+        let sql = unlex(&tokens);
+        assert_eq!(sql, "select a, b from t;");
+        assert_equal_tokens(&lex(&sql).unwrap(), &tokens);
+    }
+
+    #[test]
+    fn test_unlex_quoting() {
+        let source = "insert into \"My Table\" values ( 'it''s' , $$x$$, \"select\", -1.5e3, true ) -- done";
+        let tokens = lex(source).unwrap();
+        let sql = unlex(&tokens);
+        assert_eq!(
+            sql,
+            "insert into \"My Table\" values ('it''s', 'x', \"select\", - 1.5e3, true)"
+        );
+        assert_equal_tokens(&lex(&sql).unwrap(), &tokens);
+    }
+
+    #[test]
+    fn test_lexer_iterator() {
+        let mut lexer = Lexer::new("select 1;");
+        assert_eq!(lexer.next().unwrap().unwrap().value(), "select");
+        assert_eq!(lexer.next().unwrap().unwrap().value(), "1");
+        assert_eq!(lexer.next().unwrap().unwrap().value(), ";");
+        assert!(lexer.next().is_none());
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lexer_stops_at_first_error() {
+        let mut lexer = Lexer::new("select # 1");
+        assert!(lexer.next().unwrap().is_ok());
+        let err = lexer.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Unable to lex token after select at 1:8");
+        assert!(lexer.next().is_none());
+
+        // nothing past the error is lexed when stopping early
+        let first: Vec<_> = Lexer::new("a b # c").take(2).collect();
+        assert!(first.iter().all(|t| t.is_ok()));
+    }
+
+    #[test]
+    fn test_lexer_borrows_the_source() {
+        // A slice of a larger buffer lexes in place, and only as far as it is read
+        let buffer = "select 1; select 2; #".to_string();
+        let mut lexer = Lexer::new(&buffer[10..]);
+        let first: Vec<String> = lexer.by_ref().take(3).map(|t| t.unwrap().value().to_string()).collect();
+        assert_eq!(first, vec!["select", "2", ";"]);
+        assert_eq!(lexer.next().unwrap().unwrap_err().to_string(), "Unable to lex token after ; at 1:11");
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let source = "SELECT \"Name\", 'it''s' FROM t -- done";
+        let tokens = lex(source).unwrap();
+        let spans: Vec<&str> = tokens.iter().map(|t| t.span().slice(source)).collect();
+        assert_eq!(spans, vec!["SELECT", "\"Name\"", ",", "'it''s'", "FROM", "t"]);
+        assert_eq!(tokens[1].span(), Span::new(7, 13));
+    }
+
+    #[test]
+    fn test_tokens_borrow_the_source() {
+        let tokens = lex("SELECT id, Name, 'a', 'it''s', 1.5 FROM t").unwrap();
+        let borrowed: Vec<bool> = tokens.iter().map(|t| matches!(t.value, Cow::Borrowed(_))).collect();
+        // Only the folded identifier and the escaped string are copied
+        assert_eq!(
+            borrowed,
+            vec![true, true, true, false, true, true, true, false, true, true, true, true]
+        );
+        assert_eq!(tokens[3].value(), "name");
+        assert_eq!(tokens[7].value(), "it's");
+    }
+
+    #[test]
+    fn test_many_tokens() {
+        let statement = "insert into t values (1, 'a', \"B\", 2.5e3) -- row\n";
+        let source = statement.repeat(500);
+        let tokens = lex(&source).unwrap();
+
+        let expected = lex(statement).unwrap();
+        assert_eq!(expected.len(), 13);
+        assert_eq!(tokens.len(), expected.len() * 500);
+        for (i, token) in tokens.iter().enumerate() {
+            assert!(token.equals(&expected[i % expected.len()]));
+            assert_eq!(token.loc.line, i / expected.len() + 1);
+        }
+    }
+
+    #[test]
+    fn test_full_statement() {
+        let tokens = lex("SELECT id, \"Name\" FROM users;").unwrap();
+        let lexed: Vec<(&str, &TokenKind)> = tokens.iter().map(|t| (t.value(), t.kind())).collect();
+        assert_eq!(
+            lexed,
+            vec![
+                ("select", &TokenKind::Keyword),
+                ("id", &TokenKind::Identifier),
+                (",", &TokenKind::Symbol),
+                ("Name", &TokenKind::Identifier),
+                ("from", &TokenKind::Keyword),
+                ("users", &TokenKind::Identifier),
+                (";", &TokenKind::Symbol),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let result = lex("");
+        assert_eq!(result, Ok(vec![]));
+    }
+
+    #[test]
+    fn test_whitespace_only_input() {
+        let result = lex(" \t\n  ");
+        assert_eq!(result, Ok(vec![]));
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let tokens = lex("select\n  1").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].value, "select");
+        assert_eq!(tokens[1].value, "1");
+        assert_eq!(tokens[1].kind, TokenKind::NumericLiteral);
+        assert_eq!(tokens[1].loc, Location { line: 2, col: 3 });
+    }
+
+    #[test]
+    fn test_two_strings() {
+        let tokens = lex("'a' 'b'").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].value, "a");
+        assert_eq!(tokens[1].value, "b");
+        assert!(tokens.iter().all(|t| t.kind == TokenKind::StringLiteral));
+        assert_eq!(tokens[1].loc.col, 5);
+    }
+
+    #[test]
+    fn test_non_ascii_string() {
+        let source = "'caf\u{e9} \u{1F43F}'";
+        let (token, cur) = lex_string(source, make_cursor()).unwrap();
+        assert_eq!(token.value, "caf\u{e9} \u{1F43F}");
+        assert_eq!(cur.pointer, source.len());
+        assert_eq!(cur.loc.col, 9);
+
+        let tokens = lex("select 'caf\u{e9}', 1").unwrap();
+        assert_eq!(tokens[1].value, "caf\u{e9}");
+        assert_eq!(tokens[3].loc.col, 16);
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        assert!(lex_numeric("\u{e9}", make_cursor()).is_none());
+        assert!(lex_symbol("\u{e9}", make_cursor()).is_none());
+
+        let tokens = lex("select Prénom, 名前, _x1, ÉTÉ from café where naïve = 'ü'").unwrap();
+        let values: Vec<&str> = tokens.iter().map(|t| t.value()).collect();
+        assert_eq!(values[..8], ["select", "prénom", ",", "名前", ",", "_x1", ",", "ÉtÉ"]);
+        assert_eq!(tokens[9].value, "café");
+        assert_eq!(tokens[9].kind, TokenKind::Identifier);
+
+        // Columns count characters, not bytes
+        assert_eq!(tokens[3].loc, Location { line: 1, col: 16 });
+        assert_eq!(tokens[11].loc, Location { line: 1, col: 45 });
+        assert_eq!(tokens[13].loc, Location { line: 1, col: 53 });
+        let err = lex("select '中文' # x").unwrap_err();
+        assert_eq!(err.location(), Location { line: 1, col: 13 });
+
+        // Typographic quotes and symbols are still not letters
+        assert!(lex("\u{2018}x\u{2019}").is_err());
+        assert!(lex("a € b").is_err());
+        assert_eq!(lex("$名$ x $名$").unwrap()[0].value, " x ");
+        assert!(lex("$1名").is_err());
+    }
+
+    #[test]
+    fn test_dollar_string() {
+        let source = "$$it's fine$$";
+        let (token, cur) = lex_dollar_string(source, make_cursor()).unwrap();
+        assert_eq!(token.value, "it's fine");
+        assert_eq!(token.kind, TokenKind::StringLiteral);
+        assert_eq!(cur.pointer, source.len());
+    }
+
+    #[test]
+    fn test_tagged_dollar_string() {
+        let tokens = lex("select $x$ a $$ b $x$, 1").unwrap();
+        assert_eq!(tokens[1].value, " a $$ b ");
+        assert_eq!(tokens[1].kind, TokenKind::StringLiteral);
+        assert_eq!(tokens[3].loc.col, 24);
+    }
+
+    #[test]
+    fn test_unterminated_dollar_string() {
+        let err = lex("select $$ oops").unwrap_err();
+        assert_eq!(err.message(), "Unterminated dollar-quoted string");
+        assert_eq!(err.location(), Location { line: 1, col: 8 });
+
+        assert!(dollar_tag("$1$", 0).is_none());
+        let err = lex("$1x").unwrap_err();
+        assert_eq!(err.message(), "Unable to lex token");
+    }
+
+    #[test]
+    fn test_escape_string() {
+        let source = r"E'a\nb\t\'c'' \\ \q' x";
+        let (token, cur) = lex_escape_string(source, make_cursor()).unwrap();
+        assert_eq!(token.value, "a\nb\t'c' \\ q");
+        assert_eq!(token.kind, TokenKind::StringLiteral);
+        assert_eq!(cur.pointer, source.len() - 2);
+
+        let cases = [
+            (r"e'\101\7'", "A\u{7}"),
+            (r"E'\x41\xc3\xa9\xg'", "Aéxg"),
+            (r"E'\u00e9 \U0001F600 \uD83D\uDE00'", "é 😀 😀"),
+            (r"E'\b\f\r'", "\u{8}\u{c}\r"),
+        ];
+        for (source, value) in cases {
+            assert_eq!(lex(source).unwrap()[0].value, value, "{}", source);
+        }
+
+        // Still a name when no quote follows, and a string keeps its backslashes
+        let tokens = lex(r"select e, '\n' from t").unwrap();
+        assert_eq!(tokens[1].kind, TokenKind::Identifier);
+        assert_eq!(tokens[3].value, r"\n");
+
+        // Newlines decoded from escapes survive unlex
+        let tokens = lex(r"select E'two\nlines'").unwrap();
+        assert_eq!(lex(&unlex(&tokens)).unwrap()[1].value, "two\nlines");
+    }
+
+    #[test]
+    fn test_invalid_escape_string() {
+        let cases = [
+            (r"select E'oops", "Unterminated string"),
+            (r"select E'oops\'", "Unterminated string"),
+            (r"select E'\u12'", "Invalid Unicode escape"),
+            (r"select E'\U00110000'", "Invalid Unicode escape"),
+            (r"select E'\u0000'", "Invalid Unicode escape"),
+            (r"select E'\uD83Dx'", "Invalid Unicode surrogate pair"),
+            (r"select E'\xff'", "Invalid UTF-8 in escape string"),
+            (r"select E'\400'", "Invalid escape"),
+            (r"select E'\777'", "Invalid escape"),
+            (r"select E'\0'", "Invalid escape"),
+            (r"select E'\000'", "Invalid escape"),
+            (r"select E'\x00'", "Invalid escape"),
+        ];
+        for (source, message) in cases {
+            let err = lex(source).unwrap_err();
+            assert_eq!(err.message(), message, "{}", source);
+            assert_eq!(err.location(), Location { line: 1, col: 8 });
+        }
+    }
+
+    #[test]
+    fn test_parameters() {
+        let tokens = lex("select * from t where a = $1 and b = $12 or c=?").unwrap();
+        let parameters: Vec<&str> = tokens
+            .iter()
+            .filter(|t| t.kind == TokenKind::Parameter)
+            .map(|t| t.value())
+            .collect();
+        assert_eq!(parameters, vec!["$1", "$12", "?"]);
+        assert_eq!(unlex(&tokens), "select * from t where a = $1 and b = $12 or c = ?");
+
+        for source in ["$", "$1a", "$_"] {
+            assert!(lex_parameter(source, make_cursor()).is_none(), "Expected {} to be rejected", source);
+        }
+        let (token, _) = lex_parameter("$3)", make_cursor()).unwrap();
+        assert_eq!(token.value, "$3");
+    }
+
+    #[test]
+    fn test_escaped_quote() {
+        let source = "'it''s'";
+        let (token, cur) = lex_string(source, make_cursor()).unwrap();
+        assert_eq!(token.value, "it's");
+        assert_eq!(cur.pointer, source.len());
+        assert_eq!(cur.loc.col, 8);
+    }
+
+    #[test]
+    fn test_escaped_quote_at_end() {
+        let source = "'end'''";
+        let (token, cur) = lex_string(source, make_cursor()).unwrap();
+        assert_eq!(token.value, "end'");
+        assert_eq!(cur.pointer, source.len());
+    }
+
+    #[test]
+    fn test_newline_in_string() {
+        let source = "'a\nbc'";
+        let result = lex_string(source, make_cursor());
+        assert!(result.is_some(), "Expected to lex a multi-line string");
+        let (token, cur) = result.unwrap();
+        assert_eq!(token.value, "a\nbc");
+        assert_eq!(token.loc, Location { line: 1, col: 1 });
+        assert_eq!(cur.loc, Location { line: 2, col: 4 });
+    }
+
+    #[test]
+    fn test_crlf_line_numbers() {
+        let tokens = lex("select 1 -- one\r\nfrom t\r\n  where 'a\r\nb' = x").unwrap();
+        let locs: Vec<(&str, Location)> = tokens.iter().map(|t| (t.value(), t.loc)).collect();
+        assert_eq!(
+            locs,
+            vec![
+                ("select", Location { line: 1, col: 1 }),
+                ("1", Location { line: 1, col: 8 }),
+                ("from", Location { line: 2, col: 1 }),
+                ("t", Location { line: 2, col: 6 }),
+                ("where", Location { line: 3, col: 3 }),
+                ("a\r\nb", Location { line: 3, col: 9 }),
+                ("=", Location { line: 4, col: 4 }),
+                ("x", Location { line: 4, col: 6 }),
+            ]
+        );
+
+        let tokens = Lexer::new("-- one\r\n1").with_comments().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(tokens[0].value(), "-- one");
+    }
+
+    #[test]
+    fn test_error_line_in_long_script() {
+        let mut source = "insert into t values (1, 'a\nb');\r\n".repeat(49);
+        source.push_str("insert into t values (#);\r\n");
+        let err = lex(&source).unwrap_err();
+        assert_eq!(err.location(), Location { line: 99, col: 23 });
+    }
+
+    #[test]
+    fn test_location_after_numeric() {
+        let tokens = lex("1 2\n3.5e2 x").unwrap();
+        let locs: Vec<Location> = tokens.iter().map(|t| t.loc).collect();
+        assert_eq!(
+            locs,
+            vec![
+                Location { line: 1, col: 1 },
+                Location { line: 1, col: 3 },
+                Location { line: 2, col: 1 },
+                Location { line: 2, col: 7 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_columns() {
+        let tokens = lex("select\tid from t").unwrap();
+        let cols: Vec<usize> = tokens.iter().map(|t| t.loc.col).collect();
+        assert_eq!(cols, vec![1, 8, 11, 16]);
+    }
+
+    #[test]
+    fn test_precedence_leading_period() {
+        let tokens = lex(".5").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value, ".5");
+        assert_eq!(tokens[0].kind, TokenKind::NumericLiteral);
+    }
+
+    #[test]
+    fn test_lex_error() {
+        let err = lex("select\n  #").unwrap_err();
+        assert_eq!(err.message(), "Unable to lex token after select");
+        assert_eq!(err.location(), Location { line: 2, col: 3 });
+        assert_eq!(err.to_string(), "Unable to lex token after select at 2:3");
+    }
+
+    #[test]
+    fn test_lex_error_without_hint() {
+        let err = lex("#").unwrap_err();
+        assert_eq!(err.to_string(), "Unable to lex token at 1:1");
+
+        let boxed: Box<dyn std::error::Error> = Box::new(err);
+        assert_eq!(boxed.to_string(), "Unable to lex token at 1:1");
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut seen: Vec<Token<'static>> = Vec::new();
+        let result = lex_traced("select 1", Some(&mut |t: &Token| seen.push(t.clone().into_owned())));
+        assert!(result.is_ok());
+        let events: Vec<(&str, &TokenKind)> = seen.iter().map(|t| (t.value(), t.kind())).collect();
+        assert_eq!(events, [("select", &TokenKind::Keyword), ("1", &TokenKind::NumericLiteral)]);
+        assert_eq!(seen, result.unwrap());
+
+        // Stops at the token it could not lex, having traced the ones before
+        seen.clear();
+        assert!(lex_traced("select #", Some(&mut |t: &Token| seen.push(t.clone().into_owned()))).is_err());
+        assert_eq!(seen.iter().map(Token::value).collect::<Vec<_>>(), ["select"]);
     }
 
 }
\ No newline at end of file
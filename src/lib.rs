@@ -0,0 +1,16 @@
+pub mod backend;
+pub mod database;
+pub mod error;
+pub mod fuzz;
+pub mod lexer;
+pub mod parser;
+#[cfg(feature = "async")]
+mod pool;
+pub mod prepared;
+pub mod results;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(test)]
+mod slt;
+
+pub use database::Database;
@@ -1,5 +1,160 @@
-pub mod lexer;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
 
-fn main() {
-    println!("Hello, world!");
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use sqrldb::backend::{
+    Backend, CancelToken, Engine, MemoryBackend, QueryResult, dump, execute, with_cancellation,
+};
+use sqrldb::error::SqlError;
+use sqrldb::lexer::{Symbol, TokenKind, lex};
+use sqrldb::parser::{parse, split_statements};
+
+/*
+    An interactive shell. With a file argument, `sqrldb data.db`, the tables
+    are kept in that file, without one they live in memory and are gone when
+    the shell exits.
+
+    Lines are collected until they form complete statements, that is until
+    the input lexes and ends in a semicolon, so a statement can be spread over
+    as many lines as needed. A semicolon inside a string or a comment does not
+    end the statement.
+
+    A line starting with a dot is a command to the shell rather than SQL:
+
+        .dump   prints the SQL that creates the database again
+
+    Ctrl-C while statements run cancels them, the shell stays open. At the
+    prompt it drops the statement being typed, and Ctrl-D leaves.
+ */
+
+const PROMPT: &str = "sqrldb> ";
+const CONTINUE_PROMPT: &str = "   ...> ";
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqrldb_history"))
+}
+
+// Whether the buffer holds whole statements and can be run. Input that does
+// not lex is complete once its last line ends in a semicolon, so the error
+// gets reported instead of the prompt waiting forever.
+fn is_complete(buffer: &str) -> bool {
+    match lex(buffer) {
+        Ok(tokens) => tokens
+            .last()
+            .is_some_and(|t| t.kind() == &TokenKind::Symbol && t.value() == Symbol::Semicolon.as_str()),
+        Err(_) => buffer.trim_end().ends_with(';'),
+    }
+}
+
+// The token Ctrl-C cancels, a new one for every run so that a Ctrl-C only
+// ever stops what runs when it is pressed
+type Interrupt = Arc<Mutex<CancelToken>>;
+
+// While rustyline reads a line Ctrl-C is a key it reads, the signal only
+// arrives while something runs
+fn interrupt() -> Interrupt {
+    let interrupt = Arc::new(Mutex::new(CancelToken::new()));
+    let current = Arc::clone(&interrupt);
+    let handler = move || current.lock().unwrap_or_else(PoisonError::into_inner).cancel();
+    if let Err(err) = ctrlc::set_handler(handler) {
+        eprintln!("Warning: Ctrl-C will end the shell rather than cancel a statement: {}", err);
+    }
+    interrupt
+}
+
+// Runs `f` so that Ctrl-C cancels it
+fn cancellable<T>(interrupt: &Interrupt, f: impl FnOnce() -> T) -> T {
+    let token = CancelToken::new();
+    *interrupt.lock().unwrap_or_else(PoisonError::into_inner) = token.clone();
+    with_cancellation(&token, None, f)
+}
+
+fn run(backend: &mut dyn Backend, source: &str, interrupt: &Interrupt) {
+    if let Err(err) = cancellable(interrupt, || run_statements(backend, source)) {
+        eprint!("{}", err.render(source));
+    }
+}
+
+// Runs the statements in order, printing each result, and stops at the first
+// one that fails
+fn run_statements(backend: &mut dyn Backend, source: &str) -> Result<(), SqlError> {
+    for statement in split_statements(lex(source)?) {
+        match execute(backend, &parse(statement)?)? {
+            QueryResult::Rows(rows) => {
+                println!("{}", rows);
+                println!("({} {})", rows.rows.len(), if rows.rows.len() == 1 { "row" } else { "rows" });
+            }
+            QueryResult::Affected(count) => {
+                println!("{} {} affected", count, if count == 1 { "row" } else { "rows" });
+            }
+            QueryResult::Done => println!("OK"),
+        }
+    }
+    Ok(())
+}
+
+fn run_command(backend: &dyn Backend, command: &str, interrupt: &Interrupt) {
+    match command {
+        ".dump" => match cancellable(interrupt, || dump(backend)) {
+            Ok(sql) => print!("{}", sql),
+            Err(err) => eprintln!("Error: {}", err.message()),
+        },
+        _ => eprintln!("Error: unknown command {}", command),
+    }
+}
+
+fn main() -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history file on the first run
+        let _ = editor.load_history(path);
+    }
+
+    let mut backend: Box<dyn Backend> = match std::env::args_os().nth(1) {
+        Some(path) => match Engine::disk(&path).open() {
+            Ok(backend) => backend,
+            Err(err) => {
+                eprintln!("Error: could not open {}: {}", path.to_string_lossy(), err);
+                std::process::exit(1);
+            }
+        },
+        None => Box::new(MemoryBackend::new()),
+    };
+    let interrupt = interrupt();
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() { PROMPT } else { CONTINUE_PROMPT };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if buffer.is_empty() && line.trim().is_empty() {
+                    continue;
+                }
+                if buffer.is_empty() && line.trim_start().starts_with('.') {
+                    editor.add_history_entry(line.trim())?;
+                    run_command(backend.as_ref(), line.trim(), &interrupt);
+                    continue;
+                }
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if is_complete(&buffer) {
+                    editor.add_history_entry(buffer.trim_end())?;
+                    run(backend.as_mut(), &buffer, &interrupt);
+                    buffer.clear();
+                }
+            }
+            // Ctrl-C drops the statement being typed, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,2809 @@
+use std::fmt;
+
+use crate::lexer::{Keyword, Location, Symbol, Token, TokenKind};
+
+/*
+    Names in the AST carry the Location they were parsed from so that later
+    stages (unknown table, unknown column, ...) can point back at the source.
+    The location is not part of a node's identity, two names are equal if
+    they name the same thing wherever they were written.
+ */
+
+// A dotted name like schema.table or table.column
+#[derive(Debug, Clone, Eq)]
+pub struct QualifiedName {
+    pub parts: Vec<String>,
+    pub loc: Location,
+}
+
+impl PartialEq for QualifiedName {
+    fn eq(&self, other: &Self) -> bool {
+        self.parts == other.parts
+    }
+}
+
+impl fmt::Display for QualifiedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.parts.join("."))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectItem {
+    Wildcard,
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+// INT is 32 bits and BIGINT 64, REAL a 64 bit float. VARCHAR(n) is text of
+// at most n characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Int,
+    BigInt,
+    Real,
+    Text,
+    Varchar(u32),
+    Boolean,
+    Date,
+    Time,
+    Timestamp,
+    Json,
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Int => write!(f, "int"),
+            DataType::BigInt => write!(f, "bigint"),
+            DataType::Real => write!(f, "real"),
+            DataType::Text => write!(f, "text"),
+            DataType::Varchar(length) => write!(f, "varchar({})", length),
+            DataType::Boolean => write!(f, "boolean"),
+            DataType::Date => write!(f, "date"),
+            DataType::Time => write!(f, "time"),
+            DataType::Timestamp => write!(f, "timestamp"),
+            DataType::Json => write!(f, "json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnConstraint {
+    PrimaryKey,
+    Unique,
+    NotNull,
+    // AUTOINCREMENT, or the SERIAL type: an insert that leaves the column
+    // out gets the next number of the table's sequence
+    AutoIncrement,
+    References(ForeignKey),
+}
+
+// What deleting a row does to the rows that reference it. RESTRICT, the
+// default, refuses the delete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+// `REFERENCES table (column) [ON DELETE action]`, every value of the column
+// has to be in the referenced one, which must be unique
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKey {
+    pub table: QualifiedName,
+    pub column: String,
+    pub on_delete: ReferentialAction,
+}
+
+#[derive(Debug, Clone, Eq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub data_type: DataType,
+    pub constraints: Vec<ColumnConstraint>,
+    // What an insert that leaves the column out stores, null without one
+    pub default: Option<Expr>,
+    pub loc: Location,
+}
+
+impl ColumnDef {
+    pub fn is_primary_key(&self) -> bool {
+        self.constraints.contains(&ColumnConstraint::PrimaryKey)
+    }
+
+    // A primary key is both unique and not null
+    pub fn is_unique(&self) -> bool {
+        self.is_primary_key() || self.constraints.contains(&ColumnConstraint::Unique)
+    }
+
+    // An auto-increment column is never null, like a SERIAL in Postgres
+    pub fn is_not_null(&self) -> bool {
+        self.is_primary_key() || self.is_auto_increment() || self.constraints.contains(&ColumnConstraint::NotNull)
+    }
+
+    pub fn is_auto_increment(&self) -> bool {
+        self.constraints.contains(&ColumnConstraint::AutoIncrement)
+    }
+
+    pub fn references(&self) -> Option<&ForeignKey> {
+        self.constraints.iter().find_map(|constraint| match constraint {
+            ColumnConstraint::References(key) => Some(key),
+            _ => None,
+        })
+    }
+}
+
+impl PartialEq for ColumnDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.data_type == other.data_type
+            && self.constraints == other.constraints
+            && self.default == other.default
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    // `json -> key` picks a member out as json, `->>` as text
+    JsonGet,
+    JsonGetText,
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "OR",
+            BinaryOp::And => "AND",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::JsonGet => "->",
+            BinaryOp::JsonGetText => "->>",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    NumericLiteral(String),
+    StringLiteral(String),
+    BoolLiteral(bool),
+    NullLiteral,
+    // A string read as another type, `DATE '2024-01-31'`
+    TypedLiteral { data_type: DataType, value: String },
+    Column(QualifiedName),
+    // `CAST(expr AS type)`
+    Cast { expr: Box<Expr>, data_type: DataType },
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    Binary { left: Box<Expr>, op: BinaryOp, right: Box<Expr> },
+    // Two or more operators of the same precedence, `a + b - c`, applied
+    // left to right as Binary nested to the left would be. The operands
+    // are kept side by side so that however long a chain is, walking it
+    // takes a loop and not a call per operator.
+    Chain { first: Box<Expr>, rest: Vec<(BinaryOp, Expr)> },
+    Function { name: QualifiedName, args: FunctionArgs },
+    // `expr IS NULL`, or `expr IS NOT NULL` when negated
+    IsNull { expr: Box<Expr>, negated: bool },
+    // `expr IN (1, 2)` and `expr IN (SELECT ...)`, NOT IN when negated
+    InList { expr: Box<Expr>, list: Vec<Expr>, negated: bool },
+    InSubquery { expr: Box<Expr>, query: Box<Select>, negated: bool },
+    // `$1`, numbered from 1, bound to a value when a prepared statement runs
+    Parameter(usize),
+}
+
+// The arguments of a call, `count(*)` passes a wildcard rather than values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionArgs {
+    Wildcard,
+    List(Vec<Expr>),
+}
+
+// One `column = value` of an UPDATE's SET list
+#[derive(Debug, Clone, Eq)]
+pub struct Assignment {
+    pub column: String,
+    pub value: Expr,
+    pub loc: Location,
+}
+
+impl PartialEq for Assignment {
+    fn eq(&self, other: &Self) -> bool {
+        self.column == other.column && self.value == other.value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionOp {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+// What an ALTER TABLE changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlterOp {
+    // Rows already in the table get the column's default, or null without one
+    AddColumn(ColumnDef),
+    DropColumn(QualifiedName),
+    RenameColumn { column: QualifiedName, name: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyDirection {
+    // COPY t FROM 'file' loads the file into the table
+    From,
+    // COPY t TO 'file' writes the table out to the file
+    To,
+}
+
+// How a COPY file is laid out. Files are always CSV, comma separated and
+// without a header line unless the statement asks otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyOptions {
+    pub header: bool,
+    pub delimiter: char,
+}
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions { header: false, delimiter: ',' }
+    }
+}
+
+// One key of an ORDER BY, ascending unless DESC was given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableSource {
+    Table(QualifiedName),
+    Subquery(Box<Select>),
+}
+
+// A table read by a select, `users`, `users as u` or `(select ...) as u`.
+// A subquery always has an alias.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRef {
+    pub source: TableSource,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    // What the table's columns are qualified with, the alias if it has one
+    pub fn qualifier(&self) -> &[String] {
+        match (&self.alias, &self.source) {
+            (Some(alias), _) => std::slice::from_ref(alias),
+            (None, TableSource::Table(name)) => &name.parts,
+            (None, TableSource::Subquery(_)) => &[],
+        }
+    }
+
+    // Where the table is named, for a subquery the table it reads from
+    pub fn loc(&self) -> Location {
+        match &self.source {
+            TableSource::Table(name) => name.loc,
+            TableSource::Subquery(query) => query.from.loc(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub on: Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+impl SetOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SetOperator::Union => "UNION",
+            SetOperator::Intersect => "INTERSECT",
+            SetOperator::Except => "EXCEPT",
+        }
+    }
+}
+
+// A select whose rows are combined with those of the query before it,
+// `union all select ...`. Without ALL duplicate rows are removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetOperation {
+    pub op: SetOperator,
+    pub all: bool,
+    pub query: Select,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Select {
+    pub distinct: bool,
+    pub columns: Vec<SelectItem>,
+    pub from: TableRef,
+    pub joins: Vec<Join>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    // Applied one after the other to the rows of the select, ORDER BY, LIMIT
+    // and OFFSET then apply to the rows they leave
+    pub set_operations: Vec<SetOperation>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+impl Select {
+    // Every table the select reads, the FROM table first
+    pub fn tables(&self) -> impl Iterator<Item = &TableRef> {
+        std::iter::once(&self.from).chain(self.joins.iter().map(|join| &join.table))
+    }
+}
+
+/*
+    Expressions and selects print back as SQL, which EXPLAIN shows. Binary
+    operations are wrapped in parentheses, so the text reads the same way
+    the tree does without knowing operator precedence.
+ */
+
+fn comma_separated<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::NumericLiteral(n) => write!(f, "{}", n),
+            Expr::StringLiteral(s) => write!(f, "'{}'", s.replace('\'', "''")),
+            Expr::BoolLiteral(b) => write!(f, "{}", b),
+            Expr::NullLiteral => write!(f, "NULL"),
+            Expr::TypedLiteral { data_type, value } => {
+                write!(f, "{} '{}'", data_type.to_string().to_uppercase(), value.replace('\'', "''"))
+            }
+            Expr::Column(name) => write!(f, "{}", name),
+            Expr::Cast { expr, data_type } => write!(f, "CAST({} AS {})", expr, data_type.to_string().to_uppercase()),
+            Expr::Unary { op: UnaryOp::Not, expr } => write!(f, "NOT {}", expr),
+            // A space keeps two minus signs from reading as a comment
+            Expr::Unary { op: UnaryOp::Neg, expr } => match expr.to_string() {
+                inner if inner.starts_with('-') => write!(f, "- {}", inner),
+                inner => write!(f, "-{}", inner),
+            },
+            Expr::Binary { left, op, right } => write!(f, "({} {} {})", left, op.as_str(), right),
+            // As the nested Binary it stands for prints
+            Expr::Chain { first, rest } => {
+                write!(f, "{}{}", "(".repeat(rest.len()), first)?;
+                rest.iter().try_for_each(|(op, expr)| write!(f, " {} {})", op.as_str(), expr))
+            }
+            Expr::Function { name, args: FunctionArgs::Wildcard } => write!(f, "{}(*)", name),
+            Expr::Function { name, args: FunctionArgs::List(args) } => {
+                write!(f, "{}(", name)?;
+                comma_separated(f, args)?;
+                write!(f, ")")
+            }
+            Expr::IsNull { expr, negated } => write!(f, "{} IS {}NULL", expr, if *negated { "NOT " } else { "" }),
+            Expr::InList { expr, list, negated } => {
+                write!(f, "{} {}IN (", expr, if *negated { "NOT " } else { "" })?;
+                comma_separated(f, list)?;
+                write!(f, ")")
+            }
+            Expr::InSubquery { expr, query, negated } => {
+                write!(f, "{} {}IN ({})", expr, if *negated { "NOT " } else { "" }, query)
+            }
+            Expr::Parameter(n) => write!(f, "${}", n),
+        }
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectItem::Wildcard => write!(f, "*"),
+            SelectItem::Expr { expr, alias: Some(alias) } => write!(f, "{} AS {}", expr, alias),
+            SelectItem::Expr { expr, alias: None } => write!(f, "{}", expr),
+        }
+    }
+}
+
+impl fmt::Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            TableSource::Table(name) => write!(f, "{}", name)?,
+            TableSource::Subquery(query) => write!(f, "({})", query)?,
+        }
+        match &self.alias {
+            Some(alias) => write!(f, " AS {}", alias),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.expr, if self.descending { " DESC" } else { "" })
+    }
+}
+
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SELECT {}", if self.distinct { "DISTINCT " } else { "" })?;
+        comma_separated(f, &self.columns)?;
+        write!(f, " FROM {}", self.from)?;
+        for join in &self.joins {
+            let kind = match join.kind {
+                JoinKind::Inner => "JOIN",
+                JoinKind::Left => "LEFT JOIN",
+            };
+            write!(f, " {} {} ON {}", kind, join.table, join.on)?;
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " WHERE {}", filter)?;
+        }
+        if !self.group_by.is_empty() {
+            write!(f, " GROUP BY ")?;
+            comma_separated(f, &self.group_by)?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " HAVING {}", having)?;
+        }
+        for operation in &self.set_operations {
+            write!(f, " {}{} {}", operation.op.as_str(), if operation.all { " ALL" } else { "" }, operation.query)?;
+        }
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY ")?;
+            comma_separated(f, &self.order_by)?;
+        }
+        if let Some(limit) = &self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        if let Some(offset) = &self.offset {
+            write!(f, " OFFSET {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+// Where the rows of an INSERT come from, tuples written out in VALUES or
+// the rows of a select
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Query(Box<Select>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Select(Box<Select>),
+    // Shows how a select would be run instead of running it
+    Explain(Box<Select>),
+    // With IF NOT EXISTS creating something that exists does nothing, and
+    // with IF EXISTS neither does dropping something that does not
+    CreateTable { name: QualifiedName, columns: Vec<ColumnDef>, if_not_exists: bool },
+    CreateIndex { name: QualifiedName, table: QualifiedName, column: QualifiedName, if_not_exists: bool },
+    DropTable { name: QualifiedName, if_exists: bool },
+    // A view is a named select, read like a table by running the select
+    CreateView { name: QualifiedName, query: Box<Select> },
+    DropView { name: QualifiedName, if_exists: bool },
+    // The values of each row go to `columns` in order, every column of the
+    // table when there is no column list
+    Insert { table: QualifiedName, columns: Vec<QualifiedName>, source: InsertSource },
+    Update { table: QualifiedName, assignments: Vec<Assignment>, filter: Option<Expr> },
+    Delete { table: QualifiedName, filter: Option<Expr> },
+    // Transaction statements name nothing, so they keep their own location
+    // for errors like a COMMIT without a BEGIN
+    Transaction { op: TransactionOp, loc: Location },
+    Copy { table: QualifiedName, direction: CopyDirection, path: String, options: CopyOptions },
+    AlterTable { table: QualifiedName, op: AlterOp },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    message: String,
+    loc: Location,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn location(&self) -> Location {
+        self.loc
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.loc.line(), self.loc.col())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn is_keyword(token: &Token, keyword: Keyword) -> bool {
+    token.kind() == &TokenKind::Keyword && token.value() == keyword.as_str()
+}
+
+fn is_symbol(token: &Token, symbol: Symbol) -> bool {
+    token.kind() == &TokenKind::Symbol && token.value() == symbol.as_str()
+}
+
+// A decision the parser made, see parse_traced
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseEvent {
+    // The kind of statement, chosen by its first keyword
+    Statement(&'static str),
+    // An expression read in full, as it prints back as SQL
+    Expr(String),
+}
+
+// Called with every decision parse_traced() makes, in order. Together with
+// the tokens from lex_traced(), it shows how far a statement got before the
+// parser gave up on it.
+pub type ParseTraceFn<'a> = &'a mut dyn FnMut(&ParseEvent);
+
+// The tokens of a statement plus a position, so the parse functions don't
+// have to thread an index through every call
+pub struct TokenStream<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    // How many "?" parameters were read and whether a "$n" one was, a
+    // statement uses one style or the other
+    positional: usize,
+    numbered: bool,
+    // How deep the parse is in the statement, see MAX_DEPTH
+    depth: usize,
+    trace: Option<ParseTraceFn<'a>>,
+}
+
+/*
+    How deep expressions and subqueries may nest. Everything that walks a
+    statement afterwards, printing, cloning, dropping and evaluating it,
+    recurses into it, so a deeper one would overflow the stack. A chain
+    like `1 + 2 + 3` is not nested, however long, see Expr::Chain. A
+    subquery counts as SUBQUERY_DEPTH levels, running one takes that much
+    more stack.
+ */
+const MAX_DEPTH: usize = 100;
+const SUBQUERY_DEPTH: usize = 3;
+
+impl<'a> TokenStream<'a> {
+    pub fn new(tokens: Vec<Token<'a>>) -> TokenStream<'a> {
+        TokenStream { tokens, pos: 0, positional: 0, numbered: false, depth: 0, trace: None }
+    }
+
+    // Reports a decision to the trace, `event` is only built when there is one
+    fn trace(&mut self, event: impl FnOnce() -> ParseEvent) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace(&event());
+        }
+    }
+
+    pub fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.pos)
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&Token<'a>> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    // The location of the next token, or of the last one once the stream
+    // has run out
+    pub fn location(&self) -> Location {
+        self.peek()
+            .or(self.tokens.last())
+            .map_or(Location::new(1, 1), |t| t.location())
+    }
+
+    pub fn next_is_keyword(&self, keyword: Keyword) -> bool {
+        self.peek().is_some_and(|t| is_keyword(t, keyword))
+    }
+
+    pub fn next_is_symbol(&self, symbol: Symbol) -> bool {
+        self.peek().is_some_and(|t| is_symbol(t, symbol))
+    }
+
+    // Consumes the next token if it is the given symbol
+    pub fn consume_symbol(&mut self, symbol: Symbol) -> bool {
+        let found = self.next_is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    // Consumes the next token if it is the given keyword
+    pub fn consume_keyword(&mut self, keyword: Keyword) -> bool {
+        let found = self.next_is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    // An error at the next token saying what was expected there instead
+    pub fn error(&self, expected: &str) -> ParseError {
+        let message = match self.peek() {
+            Some(token) => format!("Expected {}, got {}", expected, token.value()),
+            None => format!("Expected {}, got end of input", expected),
+        };
+        ParseError {
+            message,
+            loc: self.location(),
+        }
+    }
+
+    // Goes `levels` deeper into the statement, every call is matched by a
+    // leave once they are parsed
+    fn enter(&mut self, levels: usize) -> Result<(), ParseError> {
+        self.depth += levels;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError { message: "Expression nested too deeply".to_string(), loc: self.location() });
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self, levels: usize) {
+        self.depth -= levels;
+    }
+
+    pub fn expect_keyword(&mut self, keyword: Keyword) -> Result<&Token<'a>, ParseError> {
+        if !self.next_is_keyword(keyword) {
+            return Err(self.error(keyword.as_str()));
+        }
+        Ok(self.next().unwrap())
+    }
+
+    pub fn expect_symbol(&mut self, symbol: Symbol) -> Result<&Token<'a>, ParseError> {
+        if !self.next_is_symbol(symbol) {
+            return Err(self.error(symbol.as_str()));
+        }
+        Ok(self.next().unwrap())
+    }
+}
+
+fn parse_identifier(tokens: &mut TokenStream) -> Result<String, ParseError> {
+    match tokens.peek() {
+        Some(token) if token.kind() == &TokenKind::Identifier => {
+            let name = token.value().to_string();
+            tokens.next();
+            Ok(name)
+        }
+        _ => Err(tokens.error("identifier")),
+    }
+}
+
+// Identifiers separated by periods, a period always needs an identifier on
+// both sides
+fn parse_qualified_name(tokens: &mut TokenStream) -> Result<QualifiedName, ParseError> {
+    let loc = tokens.location();
+    let mut parts = vec![parse_identifier(tokens)?];
+    while tokens.consume_symbol(Symbol::Period) {
+        parts.push(parse_identifier(tokens)?);
+    }
+    Ok(QualifiedName { parts, loc })
+}
+
+fn parse_select_item(tokens: &mut TokenStream) -> Result<SelectItem, ParseError> {
+    if tokens.consume_symbol(Symbol::Asterix) {
+        return Ok(SelectItem::Wildcard);
+    }
+
+    let expr = parse_expr(tokens)?;
+
+    let mut alias = None;
+    if tokens.consume_keyword(Keyword::As) {
+        alias = Some(parse_identifier(tokens)?);
+    }
+
+    Ok(SelectItem::Expr { expr, alias })
+}
+
+// The AS of an alias is optional, `users u` works too
+fn parse_table_ref(tokens: &mut TokenStream) -> Result<TableRef, ParseError> {
+    let source = if tokens.consume_symbol(Symbol::LeftParen) {
+        let query = parse_query(tokens)?;
+        tokens.expect_symbol(Symbol::RightParen)?;
+        TableSource::Subquery(Box::new(query))
+    } else {
+        TableSource::Table(parse_qualified_name(tokens)?)
+    };
+    let bare = tokens.peek().is_some_and(|token| token.kind() == &TokenKind::Identifier);
+    let alias = if tokens.consume_keyword(Keyword::As) || bare {
+        Some(parse_identifier(tokens)?)
+    } else if matches!(source, TableSource::Subquery(_)) {
+        return Err(tokens.error("subquery alias"));
+    } else {
+        None
+    };
+    Ok(TableRef { source, alias })
+}
+
+fn parse_select(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    Ok(Statement::Select(Box::new(parse_query(tokens)?)))
+}
+
+fn parse_set_operator(tokens: &mut TokenStream) -> Option<SetOperator> {
+    const OPERATORS: [(Keyword, SetOperator); 3] = [
+        (Keyword::Union, SetOperator::Union),
+        (Keyword::Intersect, SetOperator::Intersect),
+        (Keyword::Except, SetOperator::Except),
+    ];
+    OPERATORS.into_iter().find_map(|(keyword, op)| tokens.consume_keyword(keyword).then_some(op))
+}
+
+fn parse_set_operation(tokens: &mut TokenStream, op: SetOperator) -> Result<SetOperation, ParseError> {
+    let all = tokens.consume_keyword(Keyword::All);
+    if !all {
+        tokens.consume_keyword(Keyword::Distinct);
+    }
+    Ok(SetOperation { op, all, query: parse_query_body(tokens)? })
+}
+
+/*
+    A select on its own, also read inside parentheses as a subquery, with
+    the selects combined with it. INTERSECT binds tighter than UNION and
+    EXCEPT, as in PostgreSQL, so in
+
+        select a from t union select b from u intersect select c from v
+
+    the intersection of the last two is what the union adds. Otherwise the
+    operations apply left to right.
+ */
+fn parse_query(tokens: &mut TokenStream) -> Result<Select, ParseError> {
+    tokens.enter(SUBQUERY_DEPTH)?;
+    let mut query = parse_query_body(tokens)?;
+    while let Some(op) = parse_set_operator(tokens) {
+        let mut operation = parse_set_operation(tokens, op)?;
+        while op != SetOperator::Intersect && tokens.consume_keyword(Keyword::Intersect) {
+            let intersection = parse_set_operation(tokens, SetOperator::Intersect)?;
+            operation.query.set_operations.push(intersection);
+        }
+        query.set_operations.push(operation);
+    }
+
+    if tokens.consume_keyword(Keyword::Order) {
+        tokens.expect_keyword(Keyword::By)?;
+        loop {
+            let expr = parse_expr(tokens)?;
+            let descending = tokens.consume_keyword(Keyword::Desc);
+            if !descending {
+                tokens.consume_keyword(Keyword::Asc);
+            }
+            query.order_by.push(OrderBy { expr, descending });
+            if !tokens.consume_symbol(Symbol::Comma) {
+                break;
+            }
+        }
+    }
+
+    // LIMIT and OFFSET may come in either order, as in PostgreSQL
+    loop {
+        if query.limit.is_none() && tokens.consume_keyword(Keyword::Limit) {
+            query.limit = Some(parse_expr(tokens)?);
+        } else if query.offset.is_none() && tokens.consume_keyword(Keyword::Offset) {
+            query.offset = Some(parse_expr(tokens)?);
+        } else {
+            break;
+        }
+    }
+
+    tokens.leave(SUBQUERY_DEPTH);
+    Ok(query)
+}
+
+// SELECT up to HAVING, what a set operation combines
+fn parse_query_body(tokens: &mut TokenStream) -> Result<Select, ParseError> {
+    tokens.expect_keyword(Keyword::Select)?;
+    let distinct = tokens.consume_keyword(Keyword::Distinct);
+    if !distinct {
+        tokens.consume_keyword(Keyword::All);
+    }
+
+    let mut columns = vec![parse_select_item(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        columns.push(parse_select_item(tokens)?);
+    }
+
+    tokens.expect_keyword(Keyword::From)?;
+    let from = parse_table_ref(tokens)?;
+
+    let mut joins = Vec::new();
+    loop {
+        let kind = if tokens.consume_keyword(Keyword::Left) {
+            tokens.consume_keyword(Keyword::Outer);
+            JoinKind::Left
+        } else if tokens.consume_keyword(Keyword::Inner) || tokens.next_is_keyword(Keyword::Join) {
+            JoinKind::Inner
+        } else {
+            break;
+        };
+        tokens.expect_keyword(Keyword::Join)?;
+        let table = parse_table_ref(tokens)?;
+        tokens.expect_keyword(Keyword::On)?;
+        joins.push(Join { kind, table, on: parse_expr(tokens)? });
+    }
+
+    let filter = parse_where(tokens)?;
+
+    let mut group_by = Vec::new();
+    if tokens.consume_keyword(Keyword::Group) {
+        tokens.expect_keyword(Keyword::By)?;
+        group_by.push(parse_expr(tokens)?);
+        while tokens.consume_symbol(Symbol::Comma) {
+            group_by.push(parse_expr(tokens)?);
+        }
+    }
+    let having = if tokens.consume_keyword(Keyword::Having) { Some(parse_expr(tokens)?) } else { None };
+
+    Ok(Select {
+        distinct,
+        columns,
+        from,
+        joins,
+        filter,
+        group_by,
+        having,
+        set_operations: Vec::new(),
+        order_by: Vec::new(),
+        limit: None,
+        offset: None,
+    })
+}
+
+fn parse_where(tokens: &mut TokenStream) -> Result<Option<Expr>, ParseError> {
+    if tokens.consume_keyword(Keyword::Where) {
+        return Ok(Some(parse_expr(tokens)?));
+    }
+    Ok(None)
+}
+
+fn parse_data_type(tokens: &mut TokenStream) -> Result<DataType, ParseError> {
+    const TYPES: [(Keyword, DataType); 9] = [
+        (Keyword::Int, DataType::Int),
+        (Keyword::BigInt, DataType::BigInt),
+        (Keyword::Real, DataType::Real),
+        (Keyword::Text, DataType::Text),
+        (Keyword::Boolean, DataType::Boolean),
+        (Keyword::Date, DataType::Date),
+        (Keyword::Time, DataType::Time),
+        (Keyword::Timestamp, DataType::Timestamp),
+        (Keyword::Json, DataType::Json),
+    ];
+    for (keyword, data_type) in TYPES {
+        if tokens.consume_keyword(keyword) {
+            return Ok(data_type);
+        }
+    }
+    if tokens.consume_keyword(Keyword::Varchar) {
+        tokens.expect_symbol(Symbol::LeftParen)?;
+        let length = tokens
+            .peek()
+            .filter(|t| t.kind() == &TokenKind::NumericLiteral)
+            .and_then(|t| t.value().parse::<u32>().ok())
+            .filter(|length| *length > 0)
+            .ok_or_else(|| tokens.error("varchar length"))?;
+        tokens.next();
+        tokens.expect_symbol(Symbol::RightParen)?;
+        return Ok(DataType::Varchar(length));
+    }
+    Err(tokens.error("column type"))
+}
+
+// A column definition. SERIAL is not a type of its own but an int with
+// AUTOINCREMENT, as in Postgres.
+fn parse_column_def(tokens: &mut TokenStream) -> Result<ColumnDef, ParseError> {
+    let loc = tokens.location();
+    let name = parse_identifier(tokens)?;
+    let mut constraints = Vec::new();
+    let data_type = if tokens.consume_keyword(Keyword::Serial) {
+        constraints.push(ColumnConstraint::AutoIncrement);
+        DataType::Int
+    } else {
+        parse_data_type(tokens)?
+    };
+
+    // Constraints may come in any order. A plain NULL, the opposite of NOT
+    // NULL, is accepted and changes nothing as columns are nullable anyway.
+    let mut default = None;
+    loop {
+        if default.is_none() && tokens.consume_keyword(Keyword::Default) {
+            // Like in Postgres a default stops before comparisons and
+            // boolean operators, so "default 0 not null" reads as expected.
+            // Those need parentheses.
+            default = Some(parse_json_access(tokens)?);
+        } else if tokens.consume_keyword(Keyword::Primary) {
+            tokens.expect_keyword(Keyword::Key)?;
+            constraints.push(ColumnConstraint::PrimaryKey);
+        } else if tokens.consume_keyword(Keyword::Unique) {
+            constraints.push(ColumnConstraint::Unique);
+        } else if tokens.consume_keyword(Keyword::Not) {
+            tokens.expect_keyword(Keyword::Null)?;
+            constraints.push(ColumnConstraint::NotNull);
+        } else if tokens.consume_keyword(Keyword::Autoincrement) {
+            constraints.push(ColumnConstraint::AutoIncrement);
+        } else if tokens.consume_keyword(Keyword::References) {
+            constraints.push(ColumnConstraint::References(parse_foreign_key(tokens)?));
+        } else if !tokens.consume_keyword(Keyword::Null) {
+            break;
+        }
+    }
+
+    Ok(ColumnDef { name, data_type, constraints, default, loc })
+}
+
+// The rest of REFERENCES table (column) [ON DELETE CASCADE | SET NULL | RESTRICT]
+fn parse_foreign_key(tokens: &mut TokenStream) -> Result<ForeignKey, ParseError> {
+    let table = parse_qualified_name(tokens)?;
+    tokens.expect_symbol(Symbol::LeftParen)?;
+    let column = parse_identifier(tokens)?;
+    tokens.expect_symbol(Symbol::RightParen)?;
+
+    let mut on_delete = ReferentialAction::Restrict;
+    if tokens.consume_keyword(Keyword::On) {
+        tokens.expect_keyword(Keyword::Delete)?;
+        on_delete = if tokens.consume_keyword(Keyword::Cascade) {
+            ReferentialAction::Cascade
+        } else if tokens.consume_keyword(Keyword::Set) {
+            tokens.expect_keyword(Keyword::Null)?;
+            ReferentialAction::SetNull
+        } else if tokens.consume_keyword(Keyword::Restrict) {
+            ReferentialAction::Restrict
+        } else {
+            return Err(tokens.error("cascade, set null or restrict"));
+        };
+    }
+    Ok(ForeignKey { table, column, on_delete })
+}
+
+// IF NOT EXISTS, which is optional
+fn parse_if_not_exists(tokens: &mut TokenStream) -> Result<bool, ParseError> {
+    if !tokens.consume_keyword(Keyword::If) {
+        return Ok(false);
+    }
+    tokens.expect_keyword(Keyword::Not)?;
+    tokens.expect_keyword(Keyword::Exists)?;
+    Ok(true)
+}
+
+// CREATE INDEX [IF NOT EXISTS] name ON table (column), the CREATE is
+// already consumed
+fn parse_create_index(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Index)?;
+    let if_not_exists = parse_if_not_exists(tokens)?;
+    let name = parse_qualified_name(tokens)?;
+    tokens.expect_keyword(Keyword::On)?;
+    let table = parse_qualified_name(tokens)?;
+    tokens.expect_symbol(Symbol::LeftParen)?;
+    let column = parse_qualified_name(tokens)?;
+    tokens.expect_symbol(Symbol::RightParen)?;
+    Ok(Statement::CreateIndex { name, table, column, if_not_exists })
+}
+
+fn parse_create(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Create)?;
+    if tokens.next_is_keyword(Keyword::Index) {
+        return parse_create_index(tokens);
+    }
+    if tokens.consume_keyword(Keyword::View) {
+        let name = parse_qualified_name(tokens)?;
+        tokens.expect_keyword(Keyword::As)?;
+        return Ok(Statement::CreateView { name, query: Box::new(parse_query(tokens)?) });
+    }
+    parse_create_table(tokens)
+}
+
+// CREATE TABLE [IF NOT EXISTS] name (columns), the CREATE is already
+// consumed
+fn parse_create_table(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Table)?;
+    let if_not_exists = parse_if_not_exists(tokens)?;
+    let name = parse_qualified_name(tokens)?;
+    tokens.expect_symbol(Symbol::LeftParen)?;
+
+    // At least one column, and a comma must always be followed by another
+    // column, so both "()" and "(id int,)" are rejected here
+    let mut columns = vec![parse_column_def(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        columns.push(parse_column_def(tokens)?);
+    }
+
+    tokens.expect_symbol(Symbol::RightParen)?;
+
+    Ok(Statement::CreateTable { name, columns, if_not_exists })
+}
+
+/*
+    Expressions are parsed one precedence level per function, loosest first,
+    following PostgreSQL's operator precedence:
+
+        OR
+        AND
+        NOT
+        = <> < <= > >=     (non associative, "a = b = c" is an error)
+        -> ->>
+        + -
+        * /
+        unary -
+
+    Every binary level except comparison is left associative.
+ */
+
+fn binary(left: Expr, op: BinaryOp, right: Expr) -> Expr {
+    Expr::Binary { left: Box::new(left), op, right: Box::new(right) }
+}
+
+// Consumes the next token if it is one of the operator symbols of a level
+fn consume_operator(tokens: &mut TokenStream, operators: &[(Symbol, BinaryOp)]) -> Option<BinaryOp> {
+    let (_, op) = operators.iter().find(|(symbol, _)| tokens.next_is_symbol(*symbol))?;
+    tokens.next();
+    Some(*op)
+}
+
+fn parse_expr(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    tokens.enter(1)?;
+    let expr = parse_or(tokens)?;
+    tokens.leave(1);
+    tokens.trace(|| ParseEvent::Expr(expr.to_string()));
+    Ok(expr)
+}
+
+// `operand (operator operand)*`, a Binary for one operator and a Chain for more
+fn parse_chain(
+    tokens: &mut TokenStream,
+    operator: impl Fn(&mut TokenStream) -> Option<BinaryOp>,
+    operand: fn(&mut TokenStream) -> Result<Expr, ParseError>,
+) -> Result<Expr, ParseError> {
+    let first = operand(tokens)?;
+    let mut rest = Vec::new();
+    while let Some(op) = operator(tokens) {
+        rest.push((op, operand(tokens)?));
+    }
+    Ok(match rest.len() {
+        0 => first,
+        1 => {
+            let (op, right) = rest.pop().unwrap();
+            binary(first, op, right)
+        }
+        _ => Expr::Chain { first: Box::new(first), rest },
+    })
+}
+
+fn parse_or(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    parse_chain(tokens, |tokens| tokens.consume_keyword(Keyword::Or).then_some(BinaryOp::Or), parse_and)
+}
+
+fn parse_and(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    parse_chain(tokens, |tokens| tokens.consume_keyword(Keyword::And).then_some(BinaryOp::And), parse_not)
+}
+
+fn parse_not(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    if tokens.consume_keyword(Keyword::Not) {
+        tokens.enter(1)?;
+        let expr = parse_not(tokens)?;
+        tokens.leave(1);
+        return Ok(Expr::Unary { op: UnaryOp::Not, expr: Box::new(expr) });
+    }
+    parse_comparison(tokens)
+}
+
+const COMPARISON_OPERATORS: [(Symbol, BinaryOp); 6] = [
+    (Symbol::Equals, BinaryOp::Eq),
+    (Symbol::NotEqual, BinaryOp::NotEq),
+    (Symbol::LessThan, BinaryOp::Lt),
+    (Symbol::LessThanOrEqual, BinaryOp::LtEq),
+    (Symbol::GreaterThan, BinaryOp::Gt),
+    (Symbol::GreaterThanOrEqual, BinaryOp::GtEq),
+];
+
+// IS [NOT] NULL sits at the same level as the comparisons
+fn parse_comparison(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    let left = parse_json_access(tokens)?;
+    if tokens.consume_keyword(Keyword::Is) {
+        let negated = tokens.consume_keyword(Keyword::Not);
+        tokens.expect_keyword(Keyword::Null)?;
+        return Ok(Expr::IsNull { expr: Box::new(left), negated });
+    }
+    let negated = tokens.consume_keyword(Keyword::Not);
+    if negated || tokens.next_is_keyword(Keyword::In) {
+        tokens.expect_keyword(Keyword::In)?;
+        return parse_in(tokens, left, negated);
+    }
+    match consume_operator(tokens, &COMPARISON_OPERATORS) {
+        Some(op) => Ok(binary(left, op, parse_json_access(tokens)?)),
+        None => Ok(left),
+    }
+}
+
+fn parse_in(tokens: &mut TokenStream, left: Expr, negated: bool) -> Result<Expr, ParseError> {
+    let expr = Box::new(left);
+    tokens.expect_symbol(Symbol::LeftParen)?;
+    if tokens.next_is_keyword(Keyword::Select) {
+        let query = Box::new(parse_query(tokens)?);
+        tokens.expect_symbol(Symbol::RightParen)?;
+        return Ok(Expr::InSubquery { expr, query, negated });
+    }
+
+    let mut list = vec![parse_expr(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        list.push(parse_expr(tokens)?);
+    }
+    tokens.expect_symbol(Symbol::RightParen)?;
+    Ok(Expr::InList { expr, list, negated })
+}
+
+// `data -> 'tags' ->> 0` reads left to right
+fn parse_json_access(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    const OPERATORS: [(Symbol, BinaryOp); 2] =
+        [(Symbol::Arrow, BinaryOp::JsonGet), (Symbol::DoubleArrow, BinaryOp::JsonGetText)];
+    parse_chain(tokens, |tokens| consume_operator(tokens, &OPERATORS), parse_additive)
+}
+
+fn parse_additive(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    const OPERATORS: [(Symbol, BinaryOp); 2] = [(Symbol::Plus, BinaryOp::Add), (Symbol::Minus, BinaryOp::Sub)];
+    parse_chain(tokens, |tokens| consume_operator(tokens, &OPERATORS), parse_multiplicative)
+}
+
+fn parse_multiplicative(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    const OPERATORS: [(Symbol, BinaryOp); 2] = [(Symbol::Asterix, BinaryOp::Mul), (Symbol::Slash, BinaryOp::Div)];
+    parse_chain(tokens, |tokens| consume_operator(tokens, &OPERATORS), parse_unary)
+}
+
+fn parse_unary(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    if tokens.consume_symbol(Symbol::Minus) {
+        tokens.enter(1)?;
+        let expr = parse_unary(tokens)?;
+        tokens.leave(1);
+        return Ok(Expr::Unary { op: UnaryOp::Neg, expr: Box::new(expr) });
+    }
+    parse_primary(tokens)
+}
+
+fn parse_primary(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    if tokens.consume_symbol(Symbol::LeftParen) {
+        let expr = parse_expr(tokens)?;
+        tokens.expect_symbol(Symbol::RightParen)?;
+        return Ok(expr);
+    }
+    if tokens.consume_keyword(Keyword::Null) {
+        return Ok(Expr::NullLiteral);
+    }
+    if tokens.consume_keyword(Keyword::Cast) {
+        tokens.expect_symbol(Symbol::LeftParen)?;
+        let expr = parse_expr(tokens)?;
+        tokens.expect_keyword(Keyword::As)?;
+        let data_type = parse_data_type(tokens)?;
+        tokens.expect_symbol(Symbol::RightParen)?;
+        return Ok(Expr::Cast { expr: Box::new(expr), data_type });
+    }
+    const TYPED_LITERALS: [(Keyword, DataType); 4] = [
+        (Keyword::Date, DataType::Date),
+        (Keyword::Time, DataType::Time),
+        (Keyword::Timestamp, DataType::Timestamp),
+        (Keyword::Json, DataType::Json),
+    ];
+    for (keyword, data_type) in TYPED_LITERALS {
+        if tokens.consume_keyword(keyword) {
+            let value = match tokens.peek() {
+                Some(token) if token.kind() == &TokenKind::StringLiteral => token.value().to_string(),
+                _ => return Err(tokens.error("string literal")),
+            };
+            tokens.next();
+            return Ok(Expr::TypedLiteral { data_type, value });
+        }
+    }
+
+    let token = match tokens.peek() {
+        Some(token) => token,
+        None => return Err(tokens.error("expression")),
+    };
+
+    let value = token.value().to_string();
+    let expr = match token.kind() {
+        TokenKind::Parameter => return parse_parameter(tokens),
+        TokenKind::NumericLiteral => Expr::NumericLiteral(value),
+        TokenKind::StringLiteral => Expr::StringLiteral(value),
+        TokenKind::BoolLiteral => Expr::BoolLiteral(value == "true"),
+        TokenKind::Identifier => {
+            let name = parse_qualified_name(tokens)?;
+            if tokens.next_is_symbol(Symbol::LeftParen) {
+                return parse_call(tokens, name);
+            }
+            return Ok(Expr::Column(name));
+        }
+        _ => return Err(tokens.error("expression")),
+    };
+
+    tokens.next();
+    Ok(expr)
+}
+
+// A "?" takes the next number, "$n" says which parameter it is
+fn parse_parameter(tokens: &mut TokenStream) -> Result<Expr, ParseError> {
+    let value = tokens.peek().map_or("", |t| t.value());
+    let n = match value.strip_prefix('$') {
+        Some(_) if tokens.positional > 0 => return Err(tokens.error("? parameter")),
+        Some(digits) => match digits.parse::<usize>() {
+            Ok(n) if n > 0 => {
+                tokens.numbered = true;
+                n
+            }
+            _ => return Err(tokens.error("parameter number")),
+        },
+        None if tokens.numbered => return Err(tokens.error("$n parameter")),
+        None => {
+            tokens.positional += 1;
+            tokens.positional
+        }
+    };
+    tokens.next();
+    Ok(Expr::Parameter(n))
+}
+
+// The parenthesised arguments of a call to `name`, which may be empty
+fn parse_call(tokens: &mut TokenStream, name: QualifiedName) -> Result<Expr, ParseError> {
+    tokens.expect_symbol(Symbol::LeftParen)?;
+    let args = if tokens.consume_symbol(Symbol::Asterix) {
+        FunctionArgs::Wildcard
+    } else if tokens.next_is_symbol(Symbol::RightParen) {
+        FunctionArgs::List(Vec::new())
+    } else {
+        let mut args = vec![parse_expr(tokens)?];
+        while tokens.consume_symbol(Symbol::Comma) {
+            args.push(parse_expr(tokens)?);
+        }
+        FunctionArgs::List(args)
+    };
+    tokens.expect_symbol(Symbol::RightParen)?;
+    Ok(Expr::Function { name, args })
+}
+
+// A parenthesised, comma separated list of expressions
+fn parse_tuple(tokens: &mut TokenStream) -> Result<Vec<Expr>, ParseError> {
+    tokens.expect_symbol(Symbol::LeftParen)?;
+
+    let mut exprs = vec![parse_expr(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        exprs.push(parse_expr(tokens)?);
+    }
+
+    tokens.expect_symbol(Symbol::RightParen)?;
+    Ok(exprs)
+}
+
+// Tuples of different lengths, or a select with a different number of
+// columns, are accepted here, checking them against the table is left to
+// whoever executes the statement
+fn parse_insert(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Insert)?;
+    tokens.expect_keyword(Keyword::Into)?;
+    let table = parse_qualified_name(tokens)?;
+    let mut columns = Vec::new();
+    if tokens.consume_symbol(Symbol::LeftParen) {
+        columns.push(parse_qualified_name(tokens)?);
+        while tokens.consume_symbol(Symbol::Comma) {
+            columns.push(parse_qualified_name(tokens)?);
+        }
+        tokens.expect_symbol(Symbol::RightParen)?;
+    }
+    if tokens.next_is_keyword(Keyword::Select) {
+        let query = Box::new(parse_query(tokens)?);
+        return Ok(Statement::Insert { table, columns, source: InsertSource::Query(query) });
+    }
+    tokens.expect_keyword(Keyword::Values)?;
+
+    let mut rows = vec![parse_tuple(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        rows.push(parse_tuple(tokens)?);
+    }
+
+    Ok(Statement::Insert { table, columns, source: InsertSource::Values(rows) })
+}
+
+fn parse_assignment(tokens: &mut TokenStream) -> Result<Assignment, ParseError> {
+    let loc = tokens.location();
+    let column = parse_identifier(tokens)?;
+    tokens.expect_symbol(Symbol::Equals)?;
+    let value = parse_expr(tokens)?;
+    Ok(Assignment { column, value, loc })
+}
+
+fn parse_update(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Update)?;
+    let table = parse_qualified_name(tokens)?;
+    tokens.expect_keyword(Keyword::Set)?;
+
+    let mut assignments = vec![parse_assignment(tokens)?];
+    while tokens.consume_symbol(Symbol::Comma) {
+        assignments.push(parse_assignment(tokens)?);
+    }
+
+    let filter = parse_where(tokens)?;
+    Ok(Statement::Update { table, assignments, filter })
+}
+
+fn parse_delete(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Delete)?;
+    tokens.expect_keyword(Keyword::From)?;
+    let table = parse_qualified_name(tokens)?;
+    let filter = parse_where(tokens)?;
+    Ok(Statement::Delete { table, filter })
+}
+
+// BEGIN [TRANSACTION], COMMIT [TRANSACTION] or ROLLBACK [TRANSACTION]
+fn parse_transaction(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    let loc = tokens.location();
+    let op = if tokens.consume_keyword(Keyword::Begin) {
+        TransactionOp::Begin
+    } else if tokens.consume_keyword(Keyword::Commit) {
+        TransactionOp::Commit
+    } else {
+        tokens.expect_keyword(Keyword::Rollback)?;
+        TransactionOp::Rollback
+    };
+    tokens.consume_keyword(Keyword::Transaction);
+    Ok(Statement::Transaction { op, loc })
+}
+
+fn parse_string(tokens: &mut TokenStream) -> Result<String, ParseError> {
+    match tokens.peek() {
+        Some(token) if token.kind() == &TokenKind::StringLiteral => {
+            let value = token.value().to_string();
+            tokens.next();
+            Ok(value)
+        }
+        _ => Err(tokens.error("string literal")),
+    }
+}
+
+// One of FORMAT CSV, HEADER [true | false] or DELIMITER 'c'. The option
+// names are plain identifiers, like in Postgres, so they stay usable as
+// column names.
+fn parse_copy_option(tokens: &mut TokenStream, options: &mut CopyOptions) -> Result<(), ParseError> {
+    let name = match tokens.peek() {
+        Some(token) if token.kind() == &TokenKind::Identifier => token.value().to_string(),
+        _ => return Err(tokens.error("copy option")),
+    };
+    match name.as_str() {
+        "format" => {
+            tokens.next();
+            match tokens.peek() {
+                Some(token) if token.kind() == &TokenKind::Identifier && token.value() == "csv" => tokens.next(),
+                _ => return Err(tokens.error("csv")),
+            };
+        }
+        "header" => {
+            tokens.next();
+            options.header = match tokens.peek() {
+                Some(token) if token.kind() == &TokenKind::BoolLiteral => {
+                    let header = token.value() == "true";
+                    tokens.next();
+                    header
+                }
+                _ => true,
+            };
+        }
+        "delimiter" => {
+            tokens.next();
+            let delimiter = match tokens.peek() {
+                Some(token) if token.kind() == &TokenKind::StringLiteral => {
+                    let mut chars = token.value().chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if !matches!(c, '"' | '\n' | '\r') => c,
+                        _ => return Err(tokens.error("single character delimiter")),
+                    }
+                }
+                _ => return Err(tokens.error("string literal")),
+            };
+            tokens.next();
+            options.delimiter = delimiter;
+        }
+        _ => return Err(tokens.error("copy option")),
+    }
+    Ok(())
+}
+
+// DROP TABLE [IF EXISTS] name
+fn parse_drop(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Drop)?;
+    let view = tokens.consume_keyword(Keyword::View);
+    if !view {
+        tokens.expect_keyword(Keyword::Table)?;
+    }
+    let if_exists = tokens.consume_keyword(Keyword::If);
+    if if_exists {
+        tokens.expect_keyword(Keyword::Exists)?;
+    }
+    let name = parse_qualified_name(tokens)?;
+    if view {
+        return Ok(Statement::DropView { name, if_exists });
+    }
+    Ok(Statement::DropTable { name, if_exists })
+}
+
+// ALTER TABLE name followed by one of
+//     ADD [COLUMN] column type [constraints] [DEFAULT value]
+//     DROP [COLUMN] column
+//     RENAME [COLUMN] column TO name
+fn parse_alter_table(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Alter)?;
+    tokens.expect_keyword(Keyword::Table)?;
+    let table = parse_qualified_name(tokens)?;
+
+    let op = if tokens.consume_keyword(Keyword::Add) {
+        tokens.consume_keyword(Keyword::Column);
+        AlterOp::AddColumn(parse_column_def(tokens)?)
+    } else if tokens.consume_keyword(Keyword::Drop) {
+        tokens.consume_keyword(Keyword::Column);
+        AlterOp::DropColumn(parse_qualified_name(tokens)?)
+    } else if tokens.consume_keyword(Keyword::Rename) {
+        tokens.consume_keyword(Keyword::Column);
+        let column = parse_qualified_name(tokens)?;
+        tokens.expect_keyword(Keyword::To)?;
+        AlterOp::RenameColumn { column, name: parse_identifier(tokens)? }
+    } else {
+        return Err(tokens.error("add, drop or rename"));
+    };
+
+    Ok(Statement::AlterTable { table, op })
+}
+
+// COPY table FROM 'path' or COPY table TO 'path', then optionally
+// [WITH] (option, ...)
+fn parse_copy(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Copy)?;
+    let table = parse_qualified_name(tokens)?;
+    let direction = if tokens.consume_keyword(Keyword::From) {
+        CopyDirection::From
+    } else if tokens.consume_keyword(Keyword::To) {
+        CopyDirection::To
+    } else {
+        return Err(tokens.error("from or to"));
+    };
+    let path = parse_string(tokens)?;
+
+    let mut options = CopyOptions::default();
+    if tokens.consume_keyword(Keyword::With) || tokens.next_is_symbol(Symbol::LeftParen) {
+        tokens.expect_symbol(Symbol::LeftParen)?;
+        parse_copy_option(tokens, &mut options)?;
+        while tokens.consume_symbol(Symbol::Comma) {
+            parse_copy_option(tokens, &mut options)?;
+        }
+        tokens.expect_symbol(Symbol::RightParen)?;
+    }
+
+    Ok(Statement::Copy { table, direction, path, options })
+}
+
+fn parse_statement(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    let statements: [(&[Keyword], &'static str, StatementParser); 10] = [
+        (&[Keyword::Create], "create", parse_create),
+        (&[Keyword::Select], "select", parse_select),
+        (&[Keyword::Explain], "explain", parse_explain),
+        (&[Keyword::Insert], "insert", parse_insert),
+        (&[Keyword::Update], "update", parse_update),
+        (&[Keyword::Delete], "delete", parse_delete),
+        (&[Keyword::Begin, Keyword::Commit, Keyword::Rollback], "transaction", parse_transaction),
+        (&[Keyword::Copy], "copy", parse_copy),
+        (&[Keyword::Alter], "alter", parse_alter_table),
+        (&[Keyword::Drop], "drop", parse_drop),
+    ];
+    let Some((_, kind, parse)) =
+        statements.into_iter().find(|(keywords, _, _)| keywords.iter().any(|k| tokens.next_is_keyword(*k)))
+    else {
+        return Err(tokens.error("statement"));
+    };
+    tokens.trace(|| ParseEvent::Statement(kind));
+    parse(tokens)
+}
+
+type StatementParser = fn(&mut TokenStream) -> Result<Statement, ParseError>;
+
+fn parse_explain(tokens: &mut TokenStream) -> Result<Statement, ParseError> {
+    tokens.expect_keyword(Keyword::Explain)?;
+    if !tokens.next_is_keyword(Keyword::Select) {
+        return Err(tokens.error("select"));
+    }
+    Ok(Statement::Explain(Box::new(parse_query(tokens)?)))
+}
+
+// Splits a script into one token list per statement, dropping the semicolons.
+// Empty statements, like the one after a trailing semicolon, are skipped.
+pub fn split_statements(tokens: Vec<Token<'_>>) -> Vec<Vec<Token<'_>>> {
+    let mut statements = Vec::new();
+    let mut current = Vec::new();
+
+    for token in tokens {
+        if is_symbol(&token, Symbol::Semicolon) {
+            if !current.is_empty() {
+                statements.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        current.push(token);
+    }
+
+    if !current.is_empty() {
+        statements.push(current);
+    }
+
+    statements
+}
+
+pub fn parse(tokens: Vec<Token<'_>>) -> Result<Statement, ParseError> {
+    parse_all(TokenStream::new(tokens))
+}
+
+// Like parse, telling `trace` what it decided as it goes
+pub fn parse_traced<'a>(tokens: Vec<Token<'a>>, trace: ParseTraceFn<'a>) -> Result<Statement, ParseError> {
+    let mut tokens = TokenStream::new(tokens);
+    tokens.trace = Some(trace);
+    parse_all(tokens)
+}
+
+// A statement that takes up every token
+fn parse_all(mut tokens: TokenStream) -> Result<Statement, ParseError> {
+    let statement = parse_statement(&mut tokens)?;
+
+    tokens.consume_symbol(Symbol::Semicolon);
+    if !tokens.is_at_end() {
+        return Err(tokens.error("end of statement"));
+    }
+
+    Ok(statement)
+}
+
+// A lone expression, such as a column default printed back as SQL
+pub fn parse_expression(tokens: Vec<Token<'_>>) -> Result<Expr, ParseError> {
+    let mut tokens = TokenStream::new(tokens);
+    let expr = parse_expr(&mut tokens)?;
+    if !tokens.is_at_end() {
+        return Err(tokens.error("end of expression"));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    fn parse_str(source: &str) -> Result<Statement, ParseError> {
+        parse(lex(source).unwrap())
+    }
+
+    fn name(parts: &[&str]) -> QualifiedName {
+        QualifiedName {
+            parts: parts.iter().map(|p| p.to_string()).collect(),
+            loc: Location::new(1, 1),
+        }
+    }
+
+    fn column(column: &str, data_type: DataType) -> ColumnDef {
+        ColumnDef {
+            name: column.to_string(),
+            data_type,
+            constraints: Vec::new(),
+            default: None,
+            loc: Location::new(1, 1),
+        }
+    }
+
+    fn stream(source: &str) -> TokenStream<'_> {
+        TokenStream::new(lex(source).unwrap())
+    }
+
+    #[test]
+    fn test_stream_peek_then_next() {
+        let mut tokens = stream("select id");
+        let peeked = tokens.peek().cloned();
+        assert_eq!(tokens.next().cloned(), peeked);
+        assert_eq!(tokens.peek().unwrap().value(), "id");
+        assert_eq!(tokens.next().unwrap().value(), "id");
+        assert!(tokens.next().is_none());
+        assert!(tokens.peek().is_none());
+        assert!(tokens.is_at_end());
+    }
+
+    #[test]
+    fn test_stream_expect_keyword() {
+        let mut tokens = stream("select id");
+        let token = tokens.expect_keyword(Keyword::Select).unwrap();
+        assert_eq!(token.value(), "select");
+        assert_eq!(tokens.peek().unwrap().value(), "id");
+    }
+
+    #[test]
+    fn test_stream_expect_keyword_failure() {
+        let mut tokens = stream("select id");
+        let err = tokens.expect_keyword(Keyword::Insert).unwrap_err();
+        assert_eq!(err.message(), "Expected insert, got select");
+        assert_eq!(err.location(), Location::new(1, 1));
+        // a failed expect doesn't consume anything
+        assert_eq!(tokens.peek().unwrap().value(), "select");
+
+        tokens.next();
+        tokens.next();
+        let err = tokens.expect_symbol(Symbol::Semicolon).unwrap_err();
+        assert_eq!(err.to_string(), "Expected ;, got end of input at 1:8");
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let tokens = lex("select 1; insert into t values (';');").unwrap();
+        let statements = split_statements(tokens);
+        assert_eq!(statements.len(), 2);
+
+        let first: Vec<&str> = statements[0].iter().map(|t| t.value()).collect();
+        assert_eq!(first, vec!["select", "1"]);
+
+        let second: Vec<&str> = statements[1].iter().map(|t| t.value()).collect();
+        assert_eq!(second, vec!["insert", "into", "t", "values", "(", ";", ")"]);
+        assert_eq!(statements[1][5].kind(), &TokenKind::StringLiteral);
+    }
+
+    #[test]
+    fn test_split_statements_without_trailing_semicolon() {
+        let tokens = lex("select a from t;; select b from t").unwrap();
+        let statements = split_statements(tokens);
+        assert_eq!(statements.len(), 2);
+        assert!(statements.into_iter().all(|s| parse(s).is_ok()));
+        assert!(split_statements(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_select_wildcard() {
+        let statement = parse_str("select * from users;").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Select(Box::new(Select {
+                distinct: false,
+                columns: vec![SelectItem::Wildcard],
+                from: TableRef { source: TableSource::Table(name(&["users"])), alias: None },
+                joins: vec![],
+                filter: None,
+                group_by: vec![],
+                having: None,
+                set_operations: Vec::new(),
+                order_by: vec![],
+                limit: None,
+                offset: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_select_columns_with_alias() {
+        let statement = parse_str("select id, name as n from t;").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Select(Box::new(Select {
+                distinct: false,
+                columns: vec![
+                    SelectItem::Expr { expr: column_expr("id"), alias: None },
+                    SelectItem::Expr { expr: column_expr("name"), alias: Some("n".to_string()) },
+                ],
+                from: TableRef { source: TableSource::Table(name(&["t"])), alias: None },
+                joins: vec![],
+                filter: None,
+                group_by: vec![],
+                having: None,
+                set_operations: Vec::new(),
+                order_by: vec![],
+                limit: None,
+                offset: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_select_qualified_names() {
+        let statement = parse_str("select t.id, s.t.name as n from s.t").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Select(Box::new(Select {
+                distinct: false,
+                columns: vec![
+                    SelectItem::Expr { expr: Expr::Column(name(&["t", "id"])), alias: None },
+                    SelectItem::Expr { expr: Expr::Column(name(&["s", "t", "name"])), alias: Some("n".to_string()) },
+                ],
+                from: TableRef { source: TableSource::Table(name(&["s", "t"])), alias: None },
+                joins: vec![],
+                filter: None,
+                group_by: vec![],
+                having: None,
+                set_operations: Vec::new(),
+                order_by: vec![],
+                limit: None,
+                offset: None,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_select_joins() {
+        let sql = "select * from users as u join orders o on u.id = o.user_id left outer join t on true";
+        let statement = parse_str(sql).unwrap();
+        let Statement::Select(select) = statement else {
+            panic!("Expected a select");
+        };
+        let users = TableRef { source: TableSource::Table(name(&["users"])), alias: Some("u".to_string()) };
+        assert_eq!(select.from, users);
+        assert_eq!(
+            select.joins,
+            vec![
+                Join {
+                    kind: JoinKind::Inner,
+                    table: TableRef { source: TableSource::Table(name(&["orders"])), alias: Some("o".to_string()) },
+                    on: binary(Expr::Column(name(&["u", "id"])), BinaryOp::Eq, Expr::Column(name(&["o", "user_id"]))),
+                },
+                Join {
+                    kind: JoinKind::Left,
+                    table: TableRef { source: TableSource::Table(name(&["t"])), alias: None },
+                    on: Expr::BoolLiteral(true),
+                },
+            ]
+        );
+        assert_eq!(select.from.qualifier(), ["u"]);
+        assert_eq!(select.joins[1].table.qualifier(), ["t"]);
+
+        let statement = parse_str("select * from a inner join b on x left join c on y where z").unwrap();
+        let Statement::Select(select) = statement else {
+            panic!("Expected a select");
+        };
+        let kinds: Vec<JoinKind> = select.joins.iter().map(|join| join.kind).collect();
+        assert_eq!(kinds, vec![JoinKind::Inner, JoinKind::Left]);
+        assert!(select.filter.is_some());
+    }
+
+    #[test]
+    fn test_select_join_errors() {
+        let err = parse_str("select * from a join b").unwrap_err();
+        assert_eq!(err.message(), "Expected on, got end of input");
+        let err = parse_str("select * from a left b on x").unwrap_err();
+        assert_eq!(err.message(), "Expected join, got b");
+        let err = parse_str("select * from a inner on x").unwrap_err();
+        assert_eq!(err.message(), "Expected join, got on");
+    }
+
+    #[test]
+    fn test_subqueries() {
+        let statement = parse_str("select * from (select id from a where id in (1, 2)) as sub").unwrap();
+        let Statement::Select(select) = statement else {
+            panic!("Expected a select");
+        };
+        assert_eq!(select.from.qualifier(), ["sub"]);
+        let TableSource::Subquery(inner) = select.from.source else {
+            panic!("Expected a subquery");
+        };
+        assert_eq!(inner.from.qualifier(), ["a"]);
+        let list = vec![number("1"), number("2")];
+        let in_list = Expr::InList { expr: Box::new(column_expr("id")), list, negated: false };
+        assert_eq!(inner.filter, Some(in_list));
+
+        let filter = filter_str("id not in (select user_id from orders)");
+        let Expr::InSubquery { expr, query, negated: true } = filter else {
+            panic!("Expected a NOT IN subquery, got {:?}", filter);
+        };
+        assert_eq!(*expr, column_expr("id"));
+        assert_eq!(query.from.qualifier(), ["orders"]);
+
+        // IN binds like a comparison
+        let sum = binary(column_expr("a"), BinaryOp::Add, number("1"));
+        let in_list = Expr::InList { expr: Box::new(sum), list: vec![number("2")], negated: false };
+        assert_eq!(filter_str("a + 1 in (2) and b"), binary(in_list, BinaryOp::And, column_expr("b")));
+    }
+
+    #[test]
+    fn test_subquery_errors() {
+        let cases = [
+            ("select * from (select id from a)", "Expected subquery alias, got end of input"),
+            ("select * from (select id from a as s", "Expected ), got end of input"),
+            ("select * from t where id in ()", "Expected expression, got )"),
+            ("select * from t where id not 1", "Expected in, got 1"),
+            ("select * from t where id in 1", "Expected (, got 1"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let mut events = Vec::new();
+        let statement = parse_traced(lex("select 1 from t").unwrap(), &mut |event| events.push(event.clone()));
+        assert!(statement.is_ok());
+        assert_eq!(events, [ParseEvent::Statement("select"), ParseEvent::Expr("1".to_string())]);
+
+        // The decisions made before the parser gave up
+        events.clear();
+        let statement =
+            parse_traced(lex("update t set a = b + 1 where").unwrap(), &mut |event| events.push(event.clone()));
+        assert!(statement.is_err());
+        let expected = [ParseEvent::Statement("update"), ParseEvent::Expr("(b + 1)".to_string())];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let parens = |n: usize| format!("select {}1{} from t", "(".repeat(n), ")".repeat(n));
+        assert!(parse_str(&parens(MAX_DEPTH - SUBQUERY_DEPTH - 1)).is_ok());
+        let cases = [
+            parens(MAX_DEPTH),
+            format!("select * from t where {}true", "not ".repeat(MAX_DEPTH)),
+            format!("select {}1 from t", "- ".repeat(MAX_DEPTH)),
+            format!("select * from {}t{}", "(select * from ".repeat(MAX_DEPTH), ") as s".repeat(MAX_DEPTH)),
+        ];
+        for source in cases {
+            assert_eq!(parse_str(&source).unwrap_err().message(), "Expression nested too deeply");
+        }
+
+        // Operands side by side are not nested, however many there are
+        let list = vec!["1"; 10 * MAX_DEPTH].join(", ");
+        assert!(parse_str(&format!("select * from t where a in ({}) and b = 1 or c = 2", list)).is_ok());
+        assert!(parse_str(&format!("select 1 from t{}", " union select 1 from t".repeat(MAX_DEPTH))).is_ok());
+        assert!(parse_str(&format!("select 1{} from t", " + 1".repeat(10 * MAX_DEPTH))).is_ok());
+        let chain = vec!["a = 1"; 10 * MAX_DEPTH].join(" or ");
+        assert!(parse_str(&format!("select * from t where ({}) and b = 1", chain)).is_ok());
+    }
+
+    #[test]
+    fn test_explain() {
+        let Statement::Explain(select) = parse_str("explain select * from t where id = 1").unwrap() else {
+            panic!("Expected an explain");
+        };
+        assert!(select.filter.is_some());
+        let err = parse_str("explain delete from t").unwrap_err();
+        assert_eq!(err.message(), "Expected select, got delete");
+    }
+
+    #[test]
+    fn test_selects_print_back_as_sql() {
+        let sources = [
+            "SELECT * FROM t",
+            "SELECT a AS b, count(*), sum(x) FROM s.t AS u LEFT JOIN v ON (u.id = v.id) WHERE NOT a IS NOT NULL \
+             GROUP BY a HAVING (count(*) > 1) ORDER BY b DESC, a LIMIT 10 OFFSET 2",
+            "SELECT -x FROM (SELECT x FROM t) AS s WHERE x NOT IN (1, 'it''s', NULL, DATE '2024-01-31')",
+            "SELECT x FROM t WHERE (x IN (SELECT y FROM u) OR (x = 0.5))",
+            "SELECT DISTINCT a FROM t UNION ALL SELECT b FROM u EXCEPT SELECT c FROM v INTERSECT SELECT d FROM w \
+             ORDER BY a LIMIT 1",
+        ];
+        for source in sources {
+            let Statement::Select(select) = parse_str(source).unwrap() else {
+                panic!("Expected a select");
+            };
+            assert_eq!(select.to_string(), source.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+        assert_eq!(filter_str("a or b and c = 1 + 2 * 3").to_string(), "(a OR (b AND (c = (1 + (2 * 3)))))");
+    }
+
+    #[test]
+    fn test_select_order_by_limit_offset() {
+        let source = "select * from t order by a desc, b + 1 asc, c offset 2 limit 10";
+        let Statement::Select(select) = parse_str(source).unwrap() else {
+            panic!("Expected a select");
+        };
+        assert_eq!(
+            select.order_by,
+            vec![
+                OrderBy { expr: column_expr("a"), descending: true },
+                OrderBy { expr: binary(column_expr("b"), BinaryOp::Add, number("1")), descending: false },
+                OrderBy { expr: column_expr("c"), descending: false },
+            ]
+        );
+        assert_eq!(select.limit, Some(number("10")));
+        assert_eq!(select.offset, Some(number("2")));
+
+        let cases = [
+            ("select * from t order a", "Expected by, got a"),
+            ("select * from t order by", "Expected expression, got end of input"),
+            ("select * from t order by a,", "Expected expression, got end of input"),
+            ("select * from t limit 1 limit 2", "Expected end of statement, got limit"),
+            ("select * from t limit 1 order by a", "Expected end of statement, got order"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let source = "select distinct a from t union select b from u intersect all select c from v \
+                      except select d from w order by a limit 1";
+        let Statement::Select(select) = parse_str(source).unwrap() else {
+            panic!("Expected a select");
+        };
+        assert!(select.distinct);
+        let operations: Vec<(SetOperator, bool, usize)> = select
+            .set_operations
+            .iter()
+            .map(|operation| (operation.op, operation.all, operation.query.set_operations.len()))
+            .collect();
+        // The intersection binds to the select before it
+        assert_eq!(operations, vec![(SetOperator::Union, false, 1), (SetOperator::Except, false, 0)]);
+        let intersection = &select.set_operations[0].query.set_operations[0];
+        assert_eq!((intersection.op, intersection.all), (SetOperator::Intersect, true));
+        assert_eq!(select.order_by.len(), 1);
+        assert!(select.set_operations.iter().all(|operation| operation.query.order_by.is_empty()));
+
+        let Statement::Select(select) = parse_str("select all a from t union distinct select a from u").unwrap() else {
+            panic!("Expected a select");
+        };
+        assert!(!select.distinct && !select.set_operations[0].all);
+
+        let cases = [
+            ("select a from t union", "Expected select, got end of input"),
+            ("select a from t order by a union select b from u", "Expected end of statement, got union"),
+            ("select a from t union all all select b from u", "Expected select, got all"),
+            ("select distinct from t", "Expected expression, got from"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_select_group_by_having_and_calls() {
+        let source = "select dept, count(*), max(age + 1) as oldest from t group by dept, city having count(x) > 1";
+        let Statement::Select(select) = parse_str(source).unwrap() else {
+            panic!("Expected a select");
+        };
+        let call = |function: &str, args| Expr::Function { name: name(&[function]), args };
+        assert_eq!(
+            select.columns,
+            vec![
+                SelectItem::Expr { expr: column_expr("dept"), alias: None },
+                SelectItem::Expr { expr: call("count", FunctionArgs::Wildcard), alias: None },
+                SelectItem::Expr {
+                    expr: call(
+                        "max",
+                        FunctionArgs::List(vec![binary(column_expr("age"), BinaryOp::Add, number("1"))]),
+                    ),
+                    alias: Some("oldest".to_string()),
+                },
+            ]
+        );
+        assert_eq!(select.group_by, vec![column_expr("dept"), column_expr("city")]);
+        assert_eq!(
+            select.having,
+            Some(binary(call("count", FunctionArgs::List(vec![column_expr("x")])), BinaryOp::Gt, number("1")))
+        );
+
+        assert_eq!(filter_str("f() = 1"), binary(call("f", FunctionArgs::List(vec![])), BinaryOp::Eq, number("1")));
+
+        let cases = [
+            ("select * from t group dept", "Expected by, got dept"),
+            ("select count(* from t", "Expected ), got from"),
+            ("select f(a,) from t", "Expected expression, got )"),
+            ("select * from t having", "Expected expression, got end of input"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_malformed_qualified_names() {
+        let err = parse_str("select a..b from t").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got .");
+        assert_eq!(err.location(), Location::new(1, 10));
+
+        let err = parse_str("select t. from t").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got from");
+
+        let err = parse_str("select .id from t").unwrap_err();
+        assert_eq!(err.message(), "Expected expression, got .");
+
+        let err = parse_str("select id from t.").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got end of input");
+    }
+
+    #[test]
+    fn test_name_locations() {
+        let statement = parse_str("select id,\n  t.name from s.t").unwrap();
+        let Statement::Select(select) = statement else {
+            panic!("Expected a select");
+        };
+        let Select { columns, from, .. } = *select;
+        let locs: Vec<Location> = columns
+            .iter()
+            .map(|c| match c {
+                SelectItem::Expr { expr: Expr::Column(name), .. } => name.loc,
+                item => panic!("Expected a column, got {:?}", item),
+            })
+            .collect();
+        assert_eq!(locs, vec![Location::new(1, 8), Location::new(2, 3)]);
+        assert_eq!(from.loc(), Location::new(2, 15));
+        assert_eq!(from.qualifier(), ["s", "t"]);
+
+        let statement = parse_str("create table foo (id int,\n name text)").unwrap();
+        let Statement::CreateTable { name, columns, .. } = statement else {
+            panic!("Expected a create table");
+        };
+        assert_eq!(name.loc, Location::new(1, 14));
+        assert_eq!(columns[1].loc, Location::new(2, 2));
+    }
+
+    #[test]
+    fn test_numeric_is_not_a_qualified_name() {
+        let tokens = lex("3.14").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind(), &TokenKind::NumericLiteral);
+    }
+
+    #[test]
+    fn test_select_without_semicolon() {
+        assert!(parse_str("select id from t").is_ok());
+    }
+
+    #[test]
+    fn test_missing_from() {
+        let err = parse_str("select id t;").unwrap_err();
+        assert_eq!(err.message(), "Expected from, got t");
+        assert_eq!(err.location(), Location::new(1, 11));
+    }
+
+    #[test]
+    fn test_trailing_tokens() {
+        let err = parse_str("select id from t; select").unwrap_err();
+        assert_eq!(err.message(), "Expected end of statement, got select");
+        assert_eq!(err.location(), Location::new(1, 19));
+    }
+
+    #[test]
+    fn test_create_table() {
+        let statement = parse_str("create table foo (id int, name text);").unwrap();
+        assert_eq!(
+            statement,
+            Statement::CreateTable {
+                name: name(&["foo"]),
+                columns: vec![
+                    column("id", DataType::Int),
+                    column("name", DataType::Text),
+                ],
+                if_not_exists: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_create_table_types() {
+        let statement = parse_str("create table t (a bigint, b real, c varchar(20), d boolean)").unwrap();
+        let Statement::CreateTable { columns, .. } = statement else {
+            panic!("Expected a create table");
+        };
+        let types: Vec<DataType> = columns.iter().map(|c| c.data_type).collect();
+        assert_eq!(types, vec![DataType::BigInt, DataType::Real, DataType::Varchar(20), DataType::Boolean]);
+        assert_eq!(DataType::Varchar(20).to_string(), "varchar(20)");
+
+        for (source, message) in [
+            ("create table t (a varchar)", "Expected (, got )"),
+            ("create table t (a varchar(0))", "Expected varchar length, got 0"),
+            ("create table t (a varchar(1.5))", "Expected varchar length, got 1.5"),
+            ("create table t (a varchar(x))", "Expected varchar length, got x"),
+        ] {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_create_table_constraints() {
+        let statement =
+            parse_str("create table t (id int primary key, email text not null unique, note text null)").unwrap();
+        let Statement::CreateTable { columns, .. } = statement else {
+            panic!("Expected a create table");
+        };
+        assert_eq!(columns[0].constraints, vec![ColumnConstraint::PrimaryKey]);
+        assert_eq!(columns[1].constraints, vec![ColumnConstraint::NotNull, ColumnConstraint::Unique]);
+        assert_eq!(columns[2].constraints, vec![]);
+
+        assert!(columns[0].is_unique() && columns[0].is_not_null());
+        assert!(columns[1].is_unique() && !columns[1].is_primary_key());
+        assert!(!columns[2].is_unique() && !columns[2].is_not_null());
+    }
+
+    #[test]
+    fn test_create_table_defaults() {
+        let statement =
+            parse_str("create table t (id serial primary key, n bigint autoincrement, at date default '2024-01-31')")
+                .unwrap();
+        let Statement::CreateTable { columns, .. } = statement else {
+            panic!("Expected a create table");
+        };
+        assert_eq!(columns[0].data_type, DataType::Int);
+        assert_eq!(columns[0].constraints, vec![ColumnConstraint::AutoIncrement, ColumnConstraint::PrimaryKey]);
+        assert_eq!(columns[1].constraints, vec![ColumnConstraint::AutoIncrement]);
+        assert!(columns[1].is_auto_increment() && columns[1].is_not_null());
+        assert_eq!(columns[2].default, Some(Expr::StringLiteral("2024-01-31".to_string())));
+
+        let err = parse_str("create table t (a serial(4))").unwrap_err();
+        assert_eq!(err.message(), "Expected ), got (");
+    }
+
+    #[test]
+    fn test_create_table_references() {
+        let statement = parse_str(
+            "create table t (a int references users (id), b int not null references s.users(id) on delete cascade,
+             c text references t (a) on delete set null unique, d int references u (id) on delete restrict)",
+        )
+        .unwrap();
+        let Statement::CreateTable { columns, .. } = statement else {
+            panic!("Expected a create table");
+        };
+        let actions: Vec<ReferentialAction> = columns.iter().map(|c| c.references().unwrap().on_delete).collect();
+        let (restrict, cascade, set_null) =
+            (ReferentialAction::Restrict, ReferentialAction::Cascade, ReferentialAction::SetNull);
+        assert_eq!(actions, vec![restrict, cascade, set_null, restrict]);
+        let key = columns[1].references().unwrap();
+        assert_eq!((key.table.to_string(), key.column.as_str()), ("s.users".to_string(), "id"));
+        assert!(columns[1].is_not_null() && columns[2].is_unique());
+
+        let err = parse_str("create table t (a int references users)").unwrap_err();
+        assert_eq!(err.message(), "Expected (, got )");
+        let err = parse_str("create table t (a int references users (id) on delete nothing)").unwrap_err();
+        assert_eq!(err.message(), "Expected cascade, set null or restrict, got nothing");
+        let err = parse_str("create table t (a int references users (id) on update cascade)").unwrap_err();
+        assert_eq!(err.message(), "Expected delete, got update");
+    }
+
+    #[test]
+    fn test_create_table_malformed_constraints() {
+        let err = parse_str("create table t (id int primary)").unwrap_err();
+        assert_eq!(err.message(), "Expected key, got )");
+
+        let err = parse_str("create table t (id int not unique)").unwrap_err();
+        assert_eq!(err.message(), "Expected null, got unique");
+
+        let err = parse_str("create table t (id int key)").unwrap_err();
+        assert_eq!(err.message(), "Expected ), got key");
+    }
+
+    #[test]
+    fn test_create_table_empty_columns() {
+        let err = parse_str("create table foo ();").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got )");
+        assert_eq!(err.location(), Location::new(1, 19));
+    }
+
+    #[test]
+    fn test_create_table_trailing_comma() {
+        let err = parse_str("create table foo (id int,);").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got )");
+    }
+
+    #[test]
+    fn test_create_table_missing_type() {
+        let err = parse_str("create table foo (id);").unwrap_err();
+        assert_eq!(err.message(), "Expected column type, got )");
+    }
+
+    #[test]
+    fn test_insert() {
+        let statement = parse_str("insert into foo values (1, 'bar');").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Insert {
+                table: name(&["foo"]),
+                columns: Vec::new(),
+                source: InsertSource::Values(vec![vec![
+                    Expr::NumericLiteral("1".to_string()),
+                    Expr::StringLiteral("bar".to_string()),
+                ]]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_insert_columns() {
+        let statement = parse_str("insert into t (b, a) values (1, 2), (3, 4)").unwrap();
+        let Statement::Insert { columns, source: InsertSource::Values(rows), .. } = statement else {
+            panic!("Expected an insert");
+        };
+        assert_eq!(columns, vec![name(&["b"]), name(&["a"])]);
+        assert_eq!(rows.len(), 2);
+
+        for (source, message) in [
+            ("insert into t () values (1)", "Expected identifier, got )"),
+            ("insert into t (a, ) values (1)", "Expected identifier, got )"),
+            ("insert into t (a values (1)", "Expected ), got values"),
+        ] {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_insert_multiple_rows() {
+        let statement = parse_str("insert into t values (1,'a'),(2,'b'),(x);").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Insert {
+                table: name(&["t"]),
+                columns: Vec::new(),
+                source: InsertSource::Values(vec![
+                    vec![Expr::NumericLiteral("1".to_string()), Expr::StringLiteral("a".to_string())],
+                    vec![Expr::NumericLiteral("2".to_string()), Expr::StringLiteral("b".to_string())],
+                    vec![Expr::Column(name(&["x"]))],
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_insert_select() {
+        let statement = parse_str("insert into t (a) select b from u where b > 1").unwrap();
+        let Statement::Insert { table, columns, source: InsertSource::Query(query) } = statement else {
+            panic!("Expected an insert from a select");
+        };
+        assert_eq!((table, columns), (name(&["t"]), vec![name(&["a"])]));
+        assert_eq!(query.to_string(), "SELECT b FROM u WHERE (b > 1)");
+
+        for (source, message) in [
+            ("insert into t select", "Expected expression, got end of input"),
+            ("insert into t (select 1)", "Expected identifier, got select"),
+            ("insert into t values (1) select 2", "Expected end of statement, got select"),
+        ] {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    fn filter_str(condition: &str) -> Expr {
+        match parse_str(&format!("select * from t where {}", condition)).unwrap() {
+            Statement::Select(select) if select.filter.is_some() => select.filter.unwrap(),
+            statement => panic!("Expected a filtered select, got {:?}", statement),
+        }
+    }
+
+    fn column_expr(column: &str) -> Expr {
+        Expr::Column(name(&[column]))
+    }
+
+    fn number(value: &str) -> Expr {
+        Expr::NumericLiteral(value.to_string())
+    }
+
+    #[test]
+    fn test_where_comparison() {
+        assert_eq!(
+            filter_str("age >= 30"),
+            binary(column_expr("age"), BinaryOp::GtEq, number("30"))
+        );
+        assert_eq!(
+            filter_str("name <> 'bob'"),
+            binary(column_expr("name"), BinaryOp::NotEq, Expr::StringLiteral("bob".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_where_and_binds_tighter_than_or() {
+        // a = 1 or (b = 2 and c = 3)
+        assert_eq!(
+            filter_str("a = 1 or b = 2 and c = 3"),
+            binary(
+                binary(column_expr("a"), BinaryOp::Eq, number("1")),
+                BinaryOp::Or,
+                binary(
+                    binary(column_expr("b"), BinaryOp::Eq, number("2")),
+                    BinaryOp::And,
+                    binary(column_expr("c"), BinaryOp::Eq, number("3")),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn test_where_not_and_parentheses() {
+        // not (a or b) and c
+        assert_eq!(
+            filter_str("not (a or b) and c"),
+            binary(
+                Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(binary(column_expr("a"), BinaryOp::Or, column_expr("b"))),
+                },
+                BinaryOp::And,
+                column_expr("c"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_where_arithmetic_precedence() {
+        // x - 1 - 2 * -y > 0  is  ((x - 1) - (2 * (-y))) > 0
+        let filter = filter_str("x - 1 - 2 * -y > 0");
+        assert_eq!(
+            filter,
+            binary(
+                Expr::Chain {
+                    first: Box::new(column_expr("x")),
+                    rest: vec![
+                        (BinaryOp::Sub, number("1")),
+                        (
+                            BinaryOp::Sub,
+                            binary(
+                                number("2"),
+                                BinaryOp::Mul,
+                                Expr::Unary { op: UnaryOp::Neg, expr: Box::new(column_expr("y")) },
+                            ),
+                        ),
+                    ],
+                },
+                BinaryOp::Gt,
+                number("0"),
+            )
+        );
+        // A chain prints as the nested operators it stands for
+        assert_eq!(filter.to_string(), "(((x - 1) - (2 * -y)) > 0)");
+    }
+
+    #[test]
+    fn test_where_literals_and_qualified_columns() {
+        assert_eq!(
+            filter_str("t.done = true"),
+            binary(Expr::Column(name(&["t", "done"])), BinaryOp::Eq, Expr::BoolLiteral(true))
+        );
+    }
+
+    #[test]
+    fn test_where_comparison_is_not_associative() {
+        let err = parse_str("select * from t where a = b = c").unwrap_err();
+        assert_eq!(err.message(), "Expected end of statement, got =");
+    }
+
+    #[test]
+    fn test_where_is_null() {
+        let is_null = |expr, negated| Expr::IsNull { expr: Box::new(expr), negated };
+        assert_eq!(
+            filter_str("a + 1 is null or not b is not null"),
+            binary(
+                is_null(binary(column_expr("a"), BinaryOp::Add, number("1")), false),
+                BinaryOp::Or,
+                Expr::Unary { op: UnaryOp::Not, expr: Box::new(is_null(column_expr("b"), true)) },
+            )
+        );
+        assert_eq!(filter_str("a = null"), binary(column_expr("a"), BinaryOp::Eq, Expr::NullLiteral));
+
+        let err = parse_str("select * from t where a is 1").unwrap_err();
+        assert_eq!(err.message(), "Expected null, got 1");
+        let err = parse_str("select * from t where a is null = true").unwrap_err();
+        assert_eq!(err.message(), "Expected end of statement, got =");
+    }
+
+    #[test]
+    fn test_typed_literals() {
+        assert_eq!(
+            filter_str("d >= date '2024-01-01'"),
+            binary(
+                column_expr("d"),
+                BinaryOp::GtEq,
+                Expr::TypedLiteral { data_type: DataType::Date, value: "2024-01-01".to_string() },
+            )
+        );
+        assert_eq!(
+            filter_str("TIMESTAMP '2024-01-01 10:00:00' < t"),
+            binary(
+                Expr::TypedLiteral { data_type: DataType::Timestamp, value: "2024-01-01 10:00:00".to_string() },
+                BinaryOp::Lt,
+                column_expr("t"),
+            )
+        );
+
+        let err = parse_str("select * from t where d = date 1").unwrap_err();
+        assert_eq!(err.message(), "Expected string literal, got 1");
+    }
+
+    #[test]
+    fn test_where_missing_operand() {
+        let err = parse_str("select * from t where a >").unwrap_err();
+        assert_eq!(err.message(), "Expected expression, got end of input");
+
+        let err = parse_str("select * from t where (a = 1").unwrap_err();
+        assert_eq!(err.message(), "Expected ), got end of input");
+    }
+
+    #[test]
+    fn test_insert_negative_number() {
+        let statement = parse_str("insert into t values (-1)").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Insert {
+                table: name(&["t"]),
+                columns: Vec::new(),
+                source: InsertSource::Values(vec![vec![Expr::Unary { op: UnaryOp::Neg, expr: Box::new(number("1")) }]]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let statement = parse_str("update t set a = a + 1, b = 'x' where id = 2").unwrap();
+        assert_eq!(
+            statement,
+            Statement::Update {
+                table: name(&["t"]),
+                assignments: vec![
+                    Assignment {
+                        column: "a".to_string(),
+                        value: binary(column_expr("a"), BinaryOp::Add, number("1")),
+                        loc: Location::new(1, 14),
+                    },
+                    Assignment {
+                        column: "b".to_string(),
+                        value: Expr::StringLiteral("x".to_string()),
+                        loc: Location::new(1, 25),
+                    },
+                ],
+                filter: Some(binary(column_expr("id"), BinaryOp::Eq, number("2"))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_update_assignment_location() {
+        let Statement::Update { assignments, .. } = parse_str("update t set a = 1,\n  b = 2").unwrap() else {
+            panic!("Expected an update");
+        };
+        assert_eq!(assignments[1].loc, Location::new(2, 3));
+    }
+
+    #[test]
+    fn test_update_errors() {
+        let err = parse_str("update t where a = 1").unwrap_err();
+        assert_eq!(err.message(), "Expected set, got where");
+
+        let err = parse_str("update t set a 1").unwrap_err();
+        assert_eq!(err.message(), "Expected =, got 1");
+
+        let err = parse_str("update t set a = 1,").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got end of input");
+    }
+
+    #[test]
+    fn test_delete() {
+        assert_eq!(
+            parse_str("delete from t").unwrap(),
+            Statement::Delete { table: name(&["t"]), filter: None }
+        );
+        assert_eq!(
+            parse_str("delete from t where not done;").unwrap(),
+            Statement::Delete {
+                table: name(&["t"]),
+                filter: Some(Expr::Unary { op: UnaryOp::Not, expr: Box::new(column_expr("done")) }),
+            }
+        );
+
+        let err = parse_str("delete t").unwrap_err();
+        assert_eq!(err.message(), "Expected from, got t");
+    }
+
+    #[test]
+    fn test_parameters() {
+        assert_eq!(
+            parse_str("delete from t where a = ? or b = ?").unwrap(),
+            Statement::Delete {
+                table: name(&["t"]),
+                filter: Some(binary(
+                    binary(column_expr("a"), BinaryOp::Eq, Expr::Parameter(1)),
+                    BinaryOp::Or,
+                    binary(column_expr("b"), BinaryOp::Eq, Expr::Parameter(2)),
+                )),
+            }
+        );
+        assert_eq!(Expr::Parameter(3).to_string(), "$3");
+
+        let err = parse_str("delete from t where a = ? or b = $1").unwrap_err();
+        assert_eq!(err.message(), "Expected ? parameter, got $1");
+    }
+
+    #[test]
+    fn test_create_index() {
+        let statement = parse_str("create index by_age on people (age);").unwrap();
+        assert_eq!(
+            statement,
+            Statement::CreateIndex {
+                name: name(&["by_age"]),
+                table: name(&["people"]),
+                column: name(&["age"]),
+                if_not_exists: false,
+            }
+        );
+
+        let Statement::CreateIndex { column, .. } = parse_str("create index i on t (\n  c)").unwrap() else {
+            panic!("Expected a create index");
+        };
+        assert_eq!(column.loc, Location::new(2, 3));
+    }
+
+    #[test]
+    fn test_create_index_errors() {
+        let err = parse_str("create index on t (c)").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got on");
+
+        let err = parse_str("create index i t (c)").unwrap_err();
+        assert_eq!(err.message(), "Expected on, got t");
+
+        let err = parse_str("create index i on t c").unwrap_err();
+        assert_eq!(err.message(), "Expected (, got c");
+
+        let err = parse_str("create trigger t").unwrap_err();
+        assert_eq!(err.message(), "Expected table, got trigger");
+    }
+
+    #[test]
+    fn test_transaction_statements() {
+        let statements = split_statements(lex("begin; commit transaction;\n  ROLLBACK").unwrap())
+            .into_iter()
+            .map(|tokens| parse(tokens).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                Statement::Transaction { op: TransactionOp::Begin, loc: Location::new(1, 1) },
+                Statement::Transaction { op: TransactionOp::Commit, loc: Location::new(1, 8) },
+                Statement::Transaction { op: TransactionOp::Rollback, loc: Location::new(2, 3) },
+            ]
+        );
+
+        let err = parse_str("begin work").unwrap_err();
+        assert_eq!(err.message(), "Expected end of statement, got work");
+    }
+
+    #[test]
+    fn test_json_operators() {
+        let Statement::Select(select) = parse_str("select a from t where d -> 'x' ->> 0 = 'y'").unwrap() else {
+            panic!("Expected a select");
+        };
+        assert_eq!(select.filter.unwrap().to_string(), "(((d -> 'x') ->> 0) = 'y')");
+
+        // Arithmetic binds tighter, like in Postgres
+        let Statement::Select(select) = parse_str("select d -> 1 + 1 from t").unwrap() else {
+            panic!("Expected a select");
+        };
+        assert_eq!(select.columns[0].to_string(), "(d -> (1 + 1))");
+
+        let Statement::CreateTable { columns, .. } = parse_str("create table t (d json)").unwrap() else {
+            panic!("Expected a create table");
+        };
+        assert_eq!(columns[0].data_type, DataType::Json);
+    }
+
+    #[test]
+    fn test_cast() {
+        let Statement::Select(select) = parse_str("select cast(a + 1 as varchar(3)), cast('1' as int) from t").unwrap()
+        else {
+            panic!("Expected a select");
+        };
+        assert_eq!(select.columns[0].to_string(), "CAST((a + 1) AS VARCHAR(3))");
+        assert_eq!(
+            select.columns[1],
+            SelectItem::Expr {
+                expr: Expr::Cast { expr: Box::new(Expr::StringLiteral("1".to_string())), data_type: DataType::Int },
+                alias: None
+            }
+        );
+
+        assert_eq!(parse_str("select cast(a) from t").unwrap_err().message(), "Expected as, got )");
+        let err = parse_str("select cast(a as number) from t").unwrap_err();
+        assert_eq!(err.message(), "Expected column type, got number");
+    }
+
+    #[test]
+    fn test_if_exists_modifiers() {
+        let Statement::CreateTable { if_not_exists, .. } = parse_str("create table if not exists t (a int)").unwrap() else {
+            panic!("Expected a create table");
+        };
+        assert!(if_not_exists);
+        let Statement::CreateIndex { name: index, if_not_exists, .. } =
+            parse_str("create index if not exists i on t (a)").unwrap()
+        else {
+            panic!("Expected a create index");
+        };
+        assert_eq!((index, if_not_exists), (name(&["i"]), true));
+
+        assert_eq!(parse_str("drop table t").unwrap(), Statement::DropTable { name: name(&["t"]), if_exists: false });
+        assert_eq!(
+            parse_str("DROP TABLE IF EXISTS s.t;").unwrap(),
+            Statement::DropTable { name: name(&["s", "t"]), if_exists: true }
+        );
+
+        let cases = [
+            ("create table if exists t (a int)", "Expected not, got exists"),
+            ("create index if not i on t (a)", "Expected exists, got i"),
+            ("drop table if not exists t", "Expected exists, got not"),
+            ("drop t", "Expected table, got t"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_views() {
+        let Statement::CreateView { name: view, query } =
+            parse_str("create view s.v as select u.id from users u join orders o on u.id = o.user_id").unwrap()
+        else {
+            panic!("Expected a create view");
+        };
+        assert_eq!(view, name(&["s", "v"]));
+        assert_eq!(query.to_string(), "SELECT u.id FROM users AS u JOIN orders AS o ON (u.id = o.user_id)");
+        let drop = parse_str("drop view if exists v").unwrap();
+        assert_eq!(drop, Statement::DropView { name: name(&["v"]), if_exists: true });
+
+        let cases = [
+            ("create view v select 1 from t", "Expected as, got select"),
+            ("create view v as insert into t values (1)", "Expected select, got insert"),
+            ("drop view", "Expected identifier, got end of input"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_alter_table() {
+        let Statement::AlterTable { table, op: AlterOp::AddColumn(column) } =
+            parse_str("alter table t add column c int default 1 + 1 not null").unwrap()
+        else {
+            panic!("Expected an add column");
+        };
+        assert_eq!(table, name(&["t"]));
+        assert_eq!((column.name.as_str(), column.data_type), ("c", DataType::Int));
+        assert_eq!(column.constraints, vec![ColumnConstraint::NotNull]);
+        assert_eq!(column.default.unwrap().to_string(), "(1 + 1)");
+
+        let Statement::AlterTable { op: AlterOp::AddColumn(column), .. } =
+            parse_str("alter table t add c text").unwrap()
+        else {
+            panic!("Expected an add column");
+        };
+        assert_eq!(column.default, None);
+
+        assert_eq!(
+            parse_str("alter table t drop column c").unwrap(),
+            Statement::AlterTable { table: name(&["t"]), op: AlterOp::DropColumn(name(&["c"])) }
+        );
+        assert_eq!(
+            parse_str("ALTER TABLE t RENAME c TO d").unwrap(),
+            Statement::AlterTable {
+                table: name(&["t"]),
+                op: AlterOp::RenameColumn { column: name(&["c"]), name: "d".to_string() },
+            }
+        );
+    }
+
+    #[test]
+    fn test_alter_table_errors() {
+        let cases = [
+            ("alter t add c int", "Expected table, got t"),
+            ("alter table t modify c int", "Expected add, drop or rename, got modify"),
+            ("alter table t add column c", "Expected column type, got end of input"),
+            ("alter table t add c int default 1 default 2", "Expected end of statement, got default"),
+            ("alter table t rename c d", "Expected to, got d"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(parse_str(source).unwrap_err().message(), message, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_copy() {
+        assert_eq!(
+            parse_str("copy t from 'in.csv'").unwrap(),
+            Statement::Copy {
+                table: name(&["t"]),
+                direction: CopyDirection::From,
+                path: "in.csv".to_string(),
+                options: CopyOptions::default(),
+            }
+        );
+        assert_eq!(
+            parse_str("COPY s.t TO '/tmp/out.csv' WITH (FORMAT csv, HEADER, DELIMITER ';');").unwrap(),
+            Statement::Copy {
+                table: name(&["s", "t"]),
+                direction: CopyDirection::To,
+                path: "/tmp/out.csv".to_string(),
+                options: CopyOptions { header: true, delimiter: ';' },
+            }
+        );
+        let Statement::Copy { options, .. } = parse_str("copy t from 'f' (header false, delimiter '\t')").unwrap() else {
+            panic!("Expected a copy");
+        };
+        assert_eq!(options, CopyOptions { header: false, delimiter: '\t' });
+    }
+
+    #[test]
+    fn test_copy_errors() {
+        let err = parse_str("copy t into 'f'").unwrap_err();
+        assert_eq!(err.message(), "Expected from or to, got into");
+
+        let err = parse_str("copy t from f").unwrap_err();
+        assert_eq!(err.message(), "Expected string literal, got f");
+
+        let err = parse_str("copy t from 'f' with (format json)").unwrap_err();
+        assert_eq!(err.message(), "Expected csv, got json");
+
+        let err = parse_str("copy t from 'f' with (delimiter ';;')").unwrap_err();
+        assert_eq!(err.message(), "Expected single character delimiter, got ;;");
+
+        let err = parse_str("copy t from 'f' with (quote '\"')").unwrap_err();
+        assert_eq!(err.message(), "Expected copy option, got quote");
+
+        let err = parse_str("copy t from 'f' with header").unwrap_err();
+        assert_eq!(err.message(), "Expected (, got header");
+    }
+
+    #[test]
+    fn test_insert_missing_paren() {
+        let err = parse_str("insert into t values (1, 'a';").unwrap_err();
+        assert_eq!(err.message(), "Expected ), got ;");
+        assert_eq!(err.location(), Location::new(1, 29));
+    }
+
+    #[test]
+    fn test_insert_unparenthesised_row() {
+        let err = parse_str("insert into t values 1;").unwrap_err();
+        assert_eq!(err.message(), "Expected (, got 1");
+    }
+
+    #[test]
+    fn test_unknown_statement() {
+        let err = parse_str("foo;").unwrap_err();
+        assert_eq!(err.message(), "Expected statement, got foo");
+    }
+
+    #[test]
+    fn test_unexpected_end_of_input() {
+        let err = parse_str("select id from").unwrap_err();
+        assert_eq!(err.message(), "Expected identifier, got end of input");
+        assert_eq!(err.location(), Location::new(1, 11));
+    }
+}
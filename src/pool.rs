@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+/*
+    The threads the async API runs statements on. Statements block, on locks
+    and on the disk, so they can't run on the threads of an async runtime
+    without holding up every other task there. Instead each one is handed to
+    this pool, and the caller gets a future that is woken once it is done.
+
+    The future only uses the Waker it is polled with, so it works under any
+    runtime, tokio's included. There is one pool for the whole process, with
+    a thread per core, started the first time it is used. Dropping a future
+    doesn't stop its statement, which still runs to the end.
+ */
+
+type Job = Box<dyn FnOnce() + Send>;
+
+fn pool() -> &'static Mutex<Sender<Job>> {
+    static POOL: OnceLock<Mutex<Sender<Job>>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        for i in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("sqrldb-worker-{}", i))
+                .spawn(move || work(&receiver))
+                .expect("cannot start a worker thread");
+        }
+        Mutex::new(sender)
+    })
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is only held while waiting, not while the job runs
+        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+// What a job left behind and who to tell once it has
+struct Slot<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+pub struct Pending<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+// Runs `f` on the pool. A panic in `f` comes out of the future.
+pub fn spawn<T, F>(f: F) -> Pending<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Slot { result: None, waker: None }));
+    let done = Arc::clone(&slot);
+    let job = Box::new(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        let mut slot = done.lock().unwrap_or_else(PoisonError::into_inner);
+        slot.result = Some(result);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    });
+    // The workers never stop, so the channel stays open
+    pool().lock().unwrap_or_else(PoisonError::into_inner).send(job).unwrap();
+    Pending { slot }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.slot.lock().unwrap_or_else(PoisonError::into_inner);
+        match slot.result.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_jobs_run_on_the_pool() {
+        let caller = thread::current().id();
+        let jobs: Vec<Pending<(usize, thread::ThreadId)>> =
+            (0..20).map(|i| spawn(move || (i * 2, thread::current().id()))).collect();
+        for (i, job) in jobs.into_iter().enumerate() {
+            let (doubled, id) = job.await;
+            assert_eq!(doubled, i * 2);
+            assert_ne!(id, caller);
+        }
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "in the job")]
+    async fn test_panics_reach_the_caller() {
+        spawn(|| panic!("in the job")).await
+    }
+}
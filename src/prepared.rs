@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use crate::backend::{Backend, BackendError, ErrorKind, QueryResult, Value, execute, literal};
+use crate::error::SqlError;
+use crate::lexer::{Location, lex};
+use crate::parser::{AlterOp, Expr, FunctionArgs, InsertSource, Select, SelectItem, Statement, TableSource, parse};
+
+/*
+    A statement parsed once and run any number of times with different values
+    for its parameters, `$1` or `?`. The values are bound into the statement
+    as literals, so whatever a parameter holds it is never read as SQL.
+ */
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    statement: Statement,
+    parameters: usize,
+    loc: Location,
+}
+
+impl PreparedStatement {
+    // Source holds a single statement, a trailing semicolon is allowed
+    pub fn prepare(source: &str) -> Result<PreparedStatement, SqlError> {
+        let tokens = lex(source)?;
+        let loc = tokens.first().map_or(Location::new(1, 1), |t| t.location());
+        let mut statement = parse(tokens)?;
+
+        // "$2" alone still takes two values, like in Postgres
+        let mut parameters = 0;
+        visit_statement(&mut statement, &mut |expr| {
+            if let Expr::Parameter(n) = expr {
+                parameters = parameters.max(*n);
+            }
+        });
+
+        Ok(PreparedStatement { statement, parameters, loc })
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.parameters
+    }
+
+    // Where the statement starts, for errors about the statement as a whole
+    pub fn location(&self) -> Location {
+        self.loc
+    }
+
+    // The statement with values[0] in place of $1 and so on
+    pub fn bind(&self, values: &[Value]) -> Result<Statement, BackendError> {
+        if values.len() != self.parameters {
+            return Err(BackendError::new(
+                format!("Expected {} parameters, got {}", self.parameters, values.len()),
+                self.loc,
+            ));
+        }
+        if values.iter().any(|value| matches!(value, Value::Real(r) if !r.is_finite())) {
+            return Err(BackendError::of_kind(ErrorKind::OutOfRange, "Real out of range", self.loc));
+        }
+
+        let mut statement = self.statement.clone();
+        visit_statement(&mut statement, &mut |expr| {
+            if let Expr::Parameter(n) = expr {
+                *expr = literal(values[*n - 1].clone());
+            }
+        });
+        Ok(statement)
+    }
+
+    pub fn execute(&self, backend: &mut dyn Backend, values: &[Value]) -> Result<QueryResult, SqlError> {
+        Ok(execute(backend, &self.bind(values)?)?)
+    }
+}
+
+// Calls `f` on every expression of the statement, including the ones inside
+// other expressions and subqueries, outermost first
+fn visit_statement(statement: &mut Statement, f: &mut dyn FnMut(&mut Expr)) {
+    match statement {
+        Statement::Select(select) | Statement::Explain(select) | Statement::CreateView { query: select, .. } => {
+            visit_select(select, f)
+        }
+        Statement::Insert { source: InsertSource::Values(rows), .. } => {
+            rows.iter_mut().flatten().for_each(|expr| visit_expr(expr, f))
+        }
+        Statement::Insert { source: InsertSource::Query(query), .. } => visit_select(query, f),
+        Statement::Update { assignments, filter, .. } => {
+            assignments.iter_mut().for_each(|a| visit_expr(&mut a.value, f));
+            filter.iter_mut().for_each(|expr| visit_expr(expr, f));
+        }
+        Statement::Delete { filter, .. } => filter.iter_mut().for_each(|expr| visit_expr(expr, f)),
+        Statement::AlterTable { op: AlterOp::AddColumn(column), .. } => {
+            column.default.iter_mut().for_each(|expr| visit_expr(expr, f))
+        }
+        Statement::CreateTable { .. }
+        | Statement::CreateIndex { .. }
+        | Statement::Transaction { .. }
+        | Statement::DropTable { .. }
+        | Statement::DropView { .. }
+        | Statement::Copy { .. }
+        | Statement::AlterTable { .. } => {}
+    }
+}
+
+fn visit_select(select: &mut Select, f: &mut dyn FnMut(&mut Expr)) {
+    for item in &mut select.columns {
+        if let SelectItem::Expr { expr, .. } = item {
+            visit_expr(expr, f);
+        }
+    }
+    if let TableSource::Subquery(query) = &mut select.from.source {
+        visit_select(query, f);
+    }
+    for join in &mut select.joins {
+        if let TableSource::Subquery(query) = &mut join.table.source {
+            visit_select(query, f);
+        }
+        visit_expr(&mut join.on, f);
+    }
+    let exprs = select
+        .filter
+        .iter_mut()
+        .chain(&mut select.group_by)
+        .chain(&mut select.having)
+        .chain(select.order_by.iter_mut().map(|order| &mut order.expr))
+        .chain(&mut select.limit)
+        .chain(&mut select.offset);
+    for expr in exprs {
+        visit_expr(expr, f);
+    }
+    for operation in &mut select.set_operations {
+        visit_select(&mut operation.query, f);
+    }
+}
+
+fn visit_expr(expr: &mut Expr, f: &mut dyn FnMut(&mut Expr)) {
+    f(expr);
+    match expr {
+        Expr::Unary { expr, .. } | Expr::IsNull { expr, .. } | Expr::Cast { expr, .. } => visit_expr(expr, f),
+        Expr::Binary { left, right, .. } => {
+            visit_expr(left, f);
+            visit_expr(right, f);
+        }
+        Expr::Chain { first, rest } => {
+            visit_expr(first, f);
+            rest.iter_mut().for_each(|(_, expr)| visit_expr(expr, f));
+        }
+        Expr::Function { args: FunctionArgs::List(args), .. } => args.iter_mut().for_each(|arg| visit_expr(arg, f)),
+        Expr::InList { expr, list, .. } => {
+            visit_expr(expr, f);
+            list.iter_mut().for_each(|item| visit_expr(item, f));
+        }
+        Expr::InSubquery { expr, query, .. } => {
+            visit_expr(expr, f);
+            visit_select(query, f);
+        }
+        _ => {}
+    }
+}
+
+/*
+    Prepared statements keyed by their source text, so running the same SQL
+    again skips lexing and parsing. When full, the statement used least
+    recently makes room. Only the parse is kept: how a select is run depends
+    on the tables at the time, so the planner still runs on every execution.
+ */
+pub struct StatementCache {
+    capacity: usize,
+    statements: HashMap<String, (PreparedStatement, u64)>,
+    // Bumped on every lookup, the entry with the lowest use is evicted
+    clock: u64,
+}
+
+impl StatementCache {
+    // A cache holds at least one statement
+    pub fn new(capacity: usize) -> StatementCache {
+        StatementCache { capacity: capacity.max(1), statements: HashMap::new(), clock: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    pub fn prepare(&mut self, source: &str) -> Result<&PreparedStatement, SqlError> {
+        self.clock += 1;
+        if !self.statements.contains_key(source) {
+            let prepared = PreparedStatement::prepare(source)?;
+            if self.statements.len() >= self.capacity {
+                let oldest = self.statements.iter().min_by_key(|(_, (_, used))| *used).map(|(sql, _)| sql.clone());
+                if let Some(oldest) = oldest {
+                    self.statements.remove(&oldest);
+                }
+            }
+            self.statements.insert(source.to_string(), (prepared, self.clock));
+        }
+
+        let (prepared, used) = self.statements.get_mut(source).unwrap();
+        *used = self.clock;
+        Ok(prepared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MemoryBackend, ResultSet};
+
+    fn backend() -> MemoryBackend {
+        let mut backend = MemoryBackend::new();
+        let setup = PreparedStatement::prepare("create table users (id int primary key, name text, born date)").unwrap();
+        setup.execute(&mut backend, &[]).unwrap();
+        backend
+    }
+
+    fn rows(result: QueryResult) -> Vec<Vec<Value>> {
+        match result {
+            QueryResult::Rows(ResultSet { rows, .. }) => rows,
+            result => panic!("Expected rows, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_bind_and_execute() {
+        let mut backend = backend();
+        let insert = PreparedStatement::prepare("insert into users values ($1, $2, $3);").unwrap();
+        assert_eq!(insert.parameter_count(), 3);
+        for (id, name) in [(1, "ann"), (2, "bob'; drop table users; --")] {
+            let values = [Value::Int(id), Value::Text(name.to_string()), Value::Date(19000)];
+            assert_eq!(insert.execute(&mut backend, &values).unwrap(), QueryResult::Affected(1));
+        }
+
+        let select = PreparedStatement::prepare("select name from users where id = ? or name = ?").unwrap();
+        let result = select.execute(&mut backend, &[Value::Int(2), Value::Null]).unwrap();
+        assert_eq!(rows(result), vec![vec![Value::Text("bob'; drop table users; --".to_string())]]);
+
+        let select = PreparedStatement::prepare("select id from users where born = $1 order by id limit $2").unwrap();
+        let result = select.execute(&mut backend, &[Value::Date(19000), Value::Int(1)]).unwrap();
+        assert_eq!(rows(result), vec![vec![Value::Int(1)]]);
+    }
+
+    #[test]
+    fn test_parameters_in_subqueries() {
+        let mut backend = backend();
+        let insert = PreparedStatement::prepare("insert into users values (1, 'ann', null), (2, 'bob', null)").unwrap();
+        insert.execute(&mut backend, &[]).unwrap();
+
+        let select = PreparedStatement::prepare(
+            "select u.name from (select * from users where id > $1) u where u.id in (select id from users where name <> $2)",
+        )
+        .unwrap();
+        assert_eq!(select.parameter_count(), 2);
+        let result = select.execute(&mut backend, &[Value::Int(0), Value::Text("ann".to_string())]).unwrap();
+        assert_eq!(rows(result), vec![vec![Value::Text("bob".to_string())]]);
+    }
+
+    #[test]
+    fn test_wrong_parameter_count() {
+        let select = PreparedStatement::prepare("select id from users where id = $2").unwrap();
+        assert_eq!(select.parameter_count(), 2);
+        let err = select.bind(&[Value::Int(1)]).unwrap_err();
+        assert_eq!(err.to_string(), "Expected 2 parameters, got 1 at 1:1");
+        assert!(select.bind(&[Value::Real(f64::NAN), Value::Int(1)]).is_err());
+    }
+
+    #[test]
+    fn test_unbound_parameter() {
+        let mut backend = backend();
+        let insert = PreparedStatement::prepare("insert into users values (1, 'ann', null)").unwrap();
+        insert.execute(&mut backend, &[]).unwrap();
+        let tokens = lex("select id from users where id = $1").unwrap();
+        let err = execute(&mut backend, &parse(tokens).unwrap()).unwrap_err();
+        assert_eq!(err.message(), "No value bound for parameter $1");
+    }
+
+    #[test]
+    fn test_mixed_parameter_styles() {
+        let err = PreparedStatement::prepare("select id from users where id = $1 or id = ?").unwrap_err();
+        assert_eq!(err.to_string(), "Expected $n parameter, got ? at 1:44");
+        let err = PreparedStatement::prepare("select id from users where id = $0").unwrap_err();
+        assert_eq!(err.message(), "Expected parameter number, got $0");
+    }
+
+    #[test]
+    fn test_statement_cache() {
+        let mut cache = StatementCache::new(2);
+        assert!(cache.is_empty());
+        let a = "select id from users where id = $1";
+        let b = "select name from users";
+        let c = "select born from users";
+
+        assert_eq!(cache.prepare(a).unwrap().parameter_count(), 1);
+        cache.prepare(b).unwrap();
+        cache.prepare(a).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // b was used least recently
+        cache.prepare(c).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.statements.contains_key(a));
+        assert!(!cache.statements.contains_key(b));
+
+        assert!(cache.prepare("select").is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
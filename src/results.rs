@@ -0,0 +1,241 @@
+use crate::backend::{Json, Value};
+use crate::database::Rows;
+
+/*
+    Query results in the forms other tools read, so rows can go straight to
+    a web API or a dataframe library without being copied value by value.
+
+    JSON is an array with an object per row, its keys the column names:
+
+        [{"id":1,"name":"ann"},{"id":2,"name":null}]
+
+    Ints and reals are numbers, a real that is infinite or not a number is
+    null as JSON has no such numbers. Dates, times and timestamps are the
+    strings they print as, and a json column is its document as it is, not
+    a string holding it. A column name that appears twice in the result is
+    a key that appears twice in the object.
+
+    With the arrow feature the rows can become an Arrow record batch
+    instead, see to_arrow.
+
+    Both take the rows a Rows has not yet handed out, all of them when it
+    has not been iterated.
+ */
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Int(i) => i.to_string(),
+        Value::Real(r) if r.is_finite() => format!("{:?}", r),
+        Value::Real(_) | Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Json(document) => document.clone(),
+        Value::Text(s) => Json::String(s.clone()).to_string(),
+        value => Json::String(value.to_string()).to_string(),
+    }
+}
+
+// The rows as a JSON array of objects
+pub fn to_json(rows: &Rows) -> String {
+    let keys: Vec<String> = rows.columns().iter().map(|name| Json::String(name.clone()).to_string()).collect();
+    let objects: Vec<String> = rows
+        .remaining()
+        .iter()
+        .map(|row| {
+            let members: Vec<String> =
+                keys.iter().zip(row).map(|(key, value)| format!("{}:{}", key, json_value(value))).collect();
+            format!("{{{}}}", members.join(","))
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+#[cfg(feature = "arrow")]
+mod arrow {
+    use std::sync::Arc;
+
+    use arrow_array::{
+        Array, ArrayRef, BooleanArray, Date32Array, Float64Array, Int64Array, NullArray, RecordBatch,
+        RecordBatchOptions, StringArray, Time64MicrosecondArray, TimestampMicrosecondArray,
+    };
+    use arrow_schema::{ArrowError, DataType, Field, Schema, TimeUnit};
+
+    use crate::backend::Value;
+    use crate::database::Rows;
+
+    /*
+        Rows carry no column types, so a column's Arrow type comes from its
+        values: Int64 for ints, Float64 for reals or for ints mixed with
+        reals, Utf8 for text and json, Boolean, Date32 for dates and
+        microsecond Time64 and Timestamp, without a time zone, for times and
+        timestamps. A column of nulls only is Null. Every column is
+        nullable.
+     */
+
+    fn data_type(value: &Value) -> DataType {
+        match value {
+            Value::Int(_) => DataType::Int64,
+            Value::Real(_) => DataType::Float64,
+            Value::Text(_) | Value::Json(_) => DataType::Utf8,
+            Value::Bool(_) => DataType::Boolean,
+            Value::Null => DataType::Null,
+            Value::Date(_) => DataType::Date32,
+            Value::Time(_) => DataType::Time64(TimeUnit::Microsecond),
+            Value::Timestamp(_) => DataType::Timestamp(TimeUnit::Microsecond, None),
+        }
+    }
+
+    // The type of column `i`, an error when its values have types that do
+    // not go in one Arrow array
+    pub(super) fn column_type(name: &str, i: usize, rows: &[Vec<Value>]) -> Result<DataType, ArrowError> {
+        let mut column_type = DataType::Null;
+        for value in rows.iter().map(|row| &row[i]) {
+            column_type = match (column_type, data_type(value)) {
+                (DataType::Null, other) | (other, DataType::Null) => other,
+                (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => DataType::Float64,
+                (left, right) if left == right => left,
+                (_, _) => {
+                    return Err(ArrowError::InvalidArgumentError(format!(
+                        "Column {} holds values of more than one type, {} is a {}",
+                        name,
+                        value,
+                        value.type_name()
+                    )));
+                }
+            };
+        }
+        Ok(column_type)
+    }
+
+    fn collect<T, A>(values: impl Iterator<Item = Value>, f: impl Fn(Value) -> Option<T>) -> ArrayRef
+    where
+        A: FromIterator<Option<T>> + Array + 'static,
+    {
+        Arc::new(values.map(f).collect::<A>())
+    }
+
+    fn array(data_type: &DataType, values: impl Iterator<Item = Value>) -> ArrayRef {
+        match data_type {
+            DataType::Int64 => collect::<_, Int64Array>(values, |v| if let Value::Int(i) = v { Some(i) } else { None }),
+            DataType::Float64 => collect::<_, Float64Array>(values, |v| match v {
+                Value::Real(r) => Some(r),
+                Value::Int(i) => Some(i as f64),
+                _ => None,
+            }),
+            DataType::Utf8 => collect::<_, StringArray>(values, |v| match v {
+                Value::Text(s) | Value::Json(s) => Some(s),
+                _ => None,
+            }),
+            DataType::Boolean => {
+                collect::<_, BooleanArray>(values, |v| if let Value::Bool(b) = v { Some(b) } else { None })
+            }
+            DataType::Date32 => {
+                collect::<_, Date32Array>(values, |v| if let Value::Date(d) = v { Some(d) } else { None })
+            }
+            DataType::Time64(_) => {
+                collect::<_, Time64MicrosecondArray>(values, |v| if let Value::Time(t) = v { Some(t) } else { None })
+            }
+            DataType::Timestamp(..) => collect::<_, TimestampMicrosecondArray>(values, |v| {
+                if let Value::Timestamp(t) = v { Some(t) } else { None }
+            }),
+            _ => Arc::new(NullArray::new(values.count())),
+        }
+    }
+
+    // The rows as one Arrow record batch, a column per result column
+    pub fn to_arrow(rows: &Rows) -> Result<RecordBatch, ArrowError> {
+        let remaining = rows.remaining();
+        let mut fields = Vec::new();
+        let mut columns = Vec::new();
+        for (i, name) in rows.columns().iter().enumerate() {
+            let data_type = column_type(name, i, remaining)?;
+            columns.push(array(&data_type, remaining.iter().map(|row| row[i].clone())));
+            fields.push(Field::new(name, data_type, true));
+        }
+        RecordBatch::try_new_with_options(
+            Arc::new(Schema::new(fields)),
+            columns,
+            &RecordBatchOptions::new().with_row_count(Some(remaining.len())),
+        )
+    }
+}
+
+#[cfg(feature = "arrow")]
+pub use arrow::to_arrow;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+
+    fn setup() -> Database {
+        let db = Database::in_memory();
+        db.execute(
+            "create table t (id int, name text, score real, ok boolean, born date, at timestamp, doc json);
+             insert into t values
+                 (1, 'ann \"a\"', 2.5, true, date '2000-01-02', timestamp '2024-01-31 13:45:00', '{\"a\": [1, 2]}'),
+                 (2, null, null, null, null, null, null)",
+        )
+        .unwrap();
+        db
+    }
+
+    #[test]
+    fn test_to_json() {
+        let db = setup();
+        let rows = db.query("select * from t order by id").unwrap();
+        assert_eq!(
+            to_json(&rows),
+            concat!(
+                r#"[{"id":1,"name":"ann \"a\"","score":2.5,"ok":true,"born":"2000-01-02","#,
+                r#""at":"2024-01-31 13:45:00","doc":{"a":[1,2]}},"#,
+                r#"{"id":2,"name":null,"score":null,"ok":null,"born":null,"at":null,"doc":null}]"#
+            )
+        );
+
+        // It parses back as JSON
+        let parsed: serde_json::Value = serde_json::from_str(&to_json(&rows)).unwrap();
+        assert_eq!(parsed[0]["doc"]["a"][1], 2);
+
+        // Only the rows not yet read
+        let mut rows = db.query("select id, id * 1.5 as x from t order by id").unwrap();
+        rows.next();
+        assert_eq!(to_json(&rows), r#"[{"id":2,"x":3.0}]"#);
+        rows.next();
+        assert_eq!(to_json(&rows), "[]");
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_to_arrow() {
+        use arrow_array::{Array, Date32Array, Float64Array, Int64Array, StringArray};
+        use arrow_schema::DataType;
+
+        let db = setup();
+        let batch = to_arrow(&db.query("select id, name, score, born, doc, null as nothing from t").unwrap()).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let types: Vec<DataType> = batch.schema().fields().iter().map(|field| field.data_type().clone()).collect();
+        assert_eq!(
+            types,
+            vec![DataType::Int64, DataType::Utf8, DataType::Float64, DataType::Date32, DataType::Utf8, DataType::Null]
+        );
+
+        let ids = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(ids.values(), &[1, 2]);
+        let names = batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!((names.value(0), names.is_null(1)), ("ann \"a\"", true));
+        let scores = batch.column(2).as_any().downcast_ref::<Float64Array>().unwrap();
+        assert_eq!(scores.value(0), 2.5);
+        let born = batch.column(3).as_any().downcast_ref::<Date32Array>().unwrap();
+        assert_eq!(born.value(0), 10_958);
+        let docs = batch.column(4).as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(docs.value(0), r#"{"a":[1,2]}"#);
+
+        // Ints mixed with reals are reals, anything else mixed is refused
+        db.execute("create table m (a int, b real); insert into m values (1, null), (null, 0.5)").unwrap();
+        let batch = to_arrow(&db.query("select coalesce(a, b) from m").unwrap()).unwrap();
+        assert_eq!(batch.schema().field(0).data_type(), &DataType::Float64);
+        let rows = [vec![Value::Int(1)], vec![Value::Null], vec![Value::Text("x".to_string())]];
+        let err = arrow::column_type("a", 0, &rows).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid argument error: Column a holds values of more than one type, x is a text");
+    }
+}
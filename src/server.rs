@@ -0,0 +1,619 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::sync::MutexGuard;
+
+use crate::backend::{Backend, BackendError, ErrorKind, QueryResult, ResultSet, Value, execute, with_user_functions};
+use crate::database::Database;
+use crate::error::SqlError;
+use crate::lexer::{Location, lex};
+use crate::parser::{Statement, TransactionOp, parse, split_statements};
+
+/*
+    Enough of the PostgreSQL frontend/backend protocol (version 3) for psql
+    and Postgres drivers to connect and run SQL:
+
+    - startup, answering an SSL request with "no" and accepting any user
+      without a password
+    - the simple query protocol, a query message with one or more statements
+      that are run in order until one fails
+    - row descriptions, data rows and command tags for the results
+
+    Values are always sent as text. The extended query protocol (parse, bind,
+    execute) is answered with an error, drivers that support the simple
+    protocol can be told to use it. COPY is refused, the files it reads and
+    writes are the server's.
+
+    Every client is served on a thread of its own through a clone of the
+    Database, see Database for what clones share. A client in a transaction
+    holds its connection until the transaction ends, so where clones share
+    one connection, with a database in a file, other clients wait for it
+    rather than join the transaction. Once a statement in a transaction
+    fails, the others are refused until ROLLBACK, and COMMIT rolls back, as
+    in Postgres. A transaction still open when the client goes is rolled
+    back.
+ */
+
+const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+// Anything longer is not a message a client would send
+const MAX_MESSAGE_LEN: usize = 1 << 24;
+
+pub fn serve(listener: &TcpListener, db: &Database) -> io::Result<()> {
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            // A client that goes away mid-message only ends its own session
+            scope.spawn(move || handle(&stream, &stream, db));
+        }
+        Ok(())
+    })
+}
+
+// Runs one client session, on a clone of `db`, until it terminates or the
+// connection closes
+pub fn handle(reader: impl Read, writer: impl Write, db: &Database) -> io::Result<()> {
+    let mut conn = Connection {
+        reader: BufReader::new(reader),
+        writer: BufWriter::new(writer),
+        db,
+        held: None,
+        status: Status::Idle,
+    };
+    if !conn.startup()? {
+        return Ok(());
+    }
+
+    // Cloning waits for a transaction on a shared connection to end, the
+    // client has been answered by now
+    let db = db.clone();
+    let mut conn = Connection { db: &db, ..conn };
+    let result = conn.session();
+    if let Some(mut backend) = conn.held.take() {
+        let _ = backend.rollback(Location::new(1, 1));
+    }
+    result
+}
+
+// Where the client is, sent with every ReadyForQuery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Idle,
+    Transaction,
+    // A statement of the transaction failed, only its end is run
+    Failed,
+}
+
+struct Connection<'d, R: Read, W: Write> {
+    reader: BufReader<R>,
+    writer: BufWriter<W>,
+    db: &'d Database,
+    // The connection, for as long as a transaction is in progress
+    held: Option<MutexGuard<'d, Box<dyn Backend + Send>>>,
+    status: Status,
+}
+
+impl<R: Read, W: Write> Connection<'_, R, W> {
+    fn session(&mut self) -> io::Result<()> {
+        // After an error in the extended protocol everything up to the next
+        // sync is skipped, the way Postgres does it
+        let mut skipping = false;
+        loop {
+            let Some((tag, body)) = self.read_message()? else {
+                return Ok(());
+            };
+            match tag {
+                b'Q' => {
+                    let query = String::from_utf8_lossy(body.strip_suffix(&[0]).unwrap_or(&body)).into_owned();
+                    self.simple_query(&query)?;
+                }
+                b'X' => return Ok(()),
+                b'S' => {
+                    skipping = false;
+                    self.ready()?;
+                }
+                // Flush, nothing is held back anyway
+                b'H' => {}
+                _ if skipping => {}
+                _ => {
+                    skipping = true;
+                    self.error("0A000", "The extended query protocol is not supported", None)?;
+                }
+            }
+            self.writer.flush()?;
+        }
+    }
+
+    fn read_i32(&mut self) -> io::Result<i32> {
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(i32::from_be_bytes(bytes))
+    }
+
+    // A message body, the length counts its own four bytes
+    fn read_body(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_i32()?;
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| (4..=MAX_MESSAGE_LEN).contains(len))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid message length"))?;
+        let mut body = vec![0; len - 4];
+        self.reader.read_exact(&mut body)?;
+        Ok(body)
+    }
+
+    // None once the client has closed the connection
+    fn read_message(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let mut tag = [0; 1];
+        match self.reader.read_exact(&mut tag) {
+            Ok(()) => Ok(Some((tag[0], self.read_body()?))),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn send(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&[tag])?;
+        self.writer.write_all(&(body.len() as i32 + 4).to_be_bytes())?;
+        self.writer.write_all(body)
+    }
+
+    // False when the client only wanted to cancel a query, which never
+    // needs doing as queries run to completion before the next is read
+    fn startup(&mut self) -> io::Result<bool> {
+        loop {
+            let body = self.read_body()?;
+            let code = body.get(..4).map_or(0, |code| i32::from_be_bytes(code.try_into().unwrap()));
+            match code {
+                SSL_REQUEST | GSSENC_REQUEST => {
+                    self.writer.write_all(b"N")?;
+                    self.writer.flush()?;
+                }
+                CANCEL_REQUEST => return Ok(false),
+                PROTOCOL_VERSION => break,
+                _ => {
+                    self.error("08P01", "Unsupported protocol version", None)?;
+                    self.writer.flush()?;
+                    return Ok(false);
+                }
+            }
+        }
+
+        // AuthenticationOk
+        self.send(b'R', &0i32.to_be_bytes())?;
+        for (name, value) in [
+            ("server_version", "14.0"),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+        ] {
+            let mut body = Vec::new();
+            put_str(&mut body, name);
+            put_str(&mut body, value);
+            self.send(b'S', &body)?;
+        }
+        // BackendKeyData, for cancel requests which are ignored
+        let mut key = std::process::id().to_be_bytes().to_vec();
+        key.extend_from_slice(&0i32.to_be_bytes());
+        self.send(b'K', &key)?;
+        self.ready()?;
+        self.writer.flush()?;
+        Ok(true)
+    }
+
+    fn ready(&mut self) -> io::Result<()> {
+        let status = match self.status {
+            Status::Idle => b"I",
+            Status::Transaction => b"T",
+            Status::Failed => b"E",
+        };
+        self.send(b'Z', status)
+    }
+
+    // ErrorResponse, `position` is where in the query the error is
+    fn error(&mut self, code: &str, message: &str, position: Option<usize>) -> io::Result<()> {
+        let mut body = Vec::new();
+        for (field, value) in [(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', message)] {
+            body.push(field);
+            put_str(&mut body, value);
+        }
+        if let Some(position) = position {
+            body.push(b'P');
+            put_str(&mut body, &position.to_string());
+        }
+        body.push(0);
+        self.send(b'E', &body)
+    }
+
+    fn simple_query(&mut self, query: &str) -> io::Result<()> {
+        match self.run(query) {
+            Ok(0) => self.send(b'I', &[])?,
+            Ok(_) => {}
+            Err(err) => {
+                let position = position(query, err.location());
+                self.error(err.code(), err.message(), Some(position))?;
+            }
+        }
+        self.ready()
+    }
+
+    // Runs the statements of a query in order, sending each one's result,
+    // and returns how many there were
+    fn run(&mut self, query: &str) -> Result<usize, SqlError> {
+        let statements = split_statements(lex(query).inspect_err(|_| self.fail())?);
+        let count = statements.len();
+        for tokens in statements {
+            let loc = tokens.first().unwrap().location();
+            let mut statement = parse(tokens).inspect_err(|_| self.fail())?;
+            // Ending a failed transaction only ever rolls it back
+            if let Statement::Transaction { op: op @ TransactionOp::Commit, .. } = &mut statement
+                && self.status == Status::Failed
+            {
+                *op = TransactionOp::Rollback;
+            }
+            let result = self.execute(&statement, loc)?;
+            // The client being gone shows up on the next read
+            let _ = self.send_result(&statement, result);
+        }
+        Ok(count)
+    }
+
+    fn fail(&mut self) {
+        if self.status == Status::Transaction {
+            self.status = Status::Failed;
+        }
+    }
+
+    fn execute(&mut self, statement: &Statement, loc: Location) -> Result<QueryResult, BackendError> {
+        let db = self.db;
+        let mut backend = self.held.take().unwrap_or_else(|| db.connection());
+        let result = match statement {
+            Statement::Transaction { .. } => execute(backend.as_mut(), statement),
+            // The path would be one on the server's machine, which is not for
+            // anyone who can connect to read or write
+            Statement::Copy { .. } => Err(BackendError::of_kind(
+                ErrorKind::NotAllowed,
+                "COPY to or from a file is not allowed over a connection",
+                loc,
+            )),
+            _ if self.status == Status::Failed => Err(BackendError::of_kind(
+                ErrorKind::TransactionAborted,
+                "The transaction is aborted, statements are ignored until it ends",
+                loc,
+            )),
+            _ => with_user_functions(&db.functions(), || execute(backend.as_mut(), statement)),
+        };
+        self.status = match statement {
+            Statement::Transaction { op, .. } => match (op, &result) {
+                (TransactionOp::Begin, Ok(_)) => Status::Transaction,
+                (TransactionOp::Begin, Err(_)) => self.status,
+                // A commit that fails has rolled back
+                _ => Status::Idle,
+            },
+            _ if result.is_err() && self.status != Status::Idle => Status::Failed,
+            _ => self.status,
+        };
+        if self.status != Status::Idle {
+            self.held = Some(backend);
+        }
+        result
+    }
+
+    fn send_result(&mut self, statement: &Statement, result: QueryResult) -> io::Result<()> {
+        let tag = match result {
+            QueryResult::Rows(rows) => {
+                self.send_rows(&rows)?;
+                format!("SELECT {}", rows.rows.len())
+            }
+            QueryResult::Affected(count) => match statement {
+                Statement::Insert { .. } => format!("INSERT 0 {}", count),
+                Statement::Update { .. } => format!("UPDATE {}", count),
+                _ => format!("DELETE {}", count),
+            },
+            QueryResult::Done => match statement {
+                Statement::CreateTable { .. } => "CREATE TABLE".to_string(),
+                Statement::CreateIndex { .. } => "CREATE INDEX".to_string(),
+                Statement::AlterTable { .. } => "ALTER TABLE".to_string(),
+                Statement::DropTable { .. } => "DROP TABLE".to_string(),
+                Statement::CreateView { .. } => "CREATE VIEW".to_string(),
+                Statement::DropView { .. } => "DROP VIEW".to_string(),
+                Statement::Transaction { op: TransactionOp::Begin, .. } => "BEGIN".to_string(),
+                Statement::Transaction { op: TransactionOp::Commit, .. } => "COMMIT".to_string(),
+                _ => "ROLLBACK".to_string(),
+            },
+        };
+        let mut body = Vec::new();
+        put_str(&mut body, &tag);
+        self.send(b'C', &body)
+    }
+
+    // RowDescription, then one DataRow per row
+    fn send_rows(&mut self, rows: &ResultSet) -> io::Result<()> {
+        let mut body = (rows.columns.len() as i16).to_be_bytes().to_vec();
+        for (i, column) in rows.columns.iter().enumerate() {
+            put_str(&mut body, column);
+            // No table or column number
+            body.extend_from_slice(&0i32.to_be_bytes());
+            body.extend_from_slice(&0i16.to_be_bytes());
+            let oid = rows.rows.iter().map(|row| type_oid(&row[i])).find(|oid| *oid != 0).unwrap_or(TEXT_OID);
+            body.extend_from_slice(&oid.to_be_bytes());
+            // Variable size, no modifier, text format
+            body.extend_from_slice(&(-1i16).to_be_bytes());
+            body.extend_from_slice(&(-1i32).to_be_bytes());
+            body.extend_from_slice(&0i16.to_be_bytes());
+        }
+        self.send(b'T', &body)?;
+
+        for row in &rows.rows {
+            let mut body = (row.len() as i16).to_be_bytes().to_vec();
+            for value in row {
+                match text(value) {
+                    Some(text) => {
+                        body.extend_from_slice(&(text.len() as i32).to_be_bytes());
+                        body.extend_from_slice(text.as_bytes());
+                    }
+                    None => body.extend_from_slice(&(-1i32).to_be_bytes()),
+                }
+            }
+            self.send(b'D', &body)?;
+        }
+        Ok(())
+    }
+}
+
+// A null terminated string
+fn put_str(body: &mut Vec<u8>, s: &str) {
+    body.extend_from_slice(s.as_bytes());
+    body.push(0);
+}
+
+const TEXT_OID: i32 = 25;
+
+// The Postgres type a value is sent as, 0 for null which has none
+fn type_oid(value: &Value) -> i32 {
+    match value {
+        Value::Int(_) => 20,
+        Value::Real(_) => 701,
+        Value::Text(_) => TEXT_OID,
+        Value::Bool(_) => 16,
+        Value::Null => 0,
+        Value::Date(_) => 1082,
+        Value::Time(_) => 1083,
+        Value::Timestamp(_) => 1114,
+        Value::Json(_) => 114,
+    }
+}
+
+// The text format of a value, None for null. Postgres spells booleans t and
+// f, everything else reads the same as in sqrldb.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(if *b { "t" } else { "f" }.to_string()),
+        value => Some(value.to_string()),
+    }
+}
+
+// The 1-based character position of a location in the query, which is how
+// Postgres points at an error
+fn position(query: &str, loc: Location) -> usize {
+    let line_start: usize = query.split('\n').take(loc.line().saturating_sub(1)).map(|line| line.chars().count() + 1).sum();
+    line_start + loc.col()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::temp_path;
+    use std::net::TcpStream;
+
+    // A minimal frontend, enough to check what the server sends back
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn connect(addr: std::net::SocketAddr) -> Client {
+            let mut stream = TcpStream::connect(addr).unwrap();
+
+            // Ask for SSL first like psql does, the server says no
+            stream.write_all(&8i32.to_be_bytes()).unwrap();
+            stream.write_all(&SSL_REQUEST.to_be_bytes()).unwrap();
+            let mut answer = [0; 1];
+            stream.read_exact(&mut answer).unwrap();
+            assert_eq!(&answer, b"N");
+
+            let mut body = PROTOCOL_VERSION.to_be_bytes().to_vec();
+            put_str(&mut body, "user");
+            put_str(&mut body, "test");
+            body.push(0);
+            stream.write_all(&(body.len() as i32 + 4).to_be_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+
+            let mut client = Client { stream };
+            let tags: Vec<u8> = client.until_ready().iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"RSSSSSSKZ");
+            client
+        }
+
+        fn read(&mut self) -> (u8, Vec<u8>) {
+            let mut header = [0; 5];
+            self.stream.read_exact(&mut header).unwrap();
+            let len = i32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+            let mut body = vec![0; len - 4];
+            self.stream.read_exact(&mut body).unwrap();
+            (header[0], body)
+        }
+
+        fn until_ready(&mut self) -> Vec<(u8, Vec<u8>)> {
+            let mut messages = Vec::new();
+            loop {
+                let message = self.read();
+                let done = message.0 == b'Z';
+                messages.push(message);
+                if done {
+                    return messages;
+                }
+            }
+        }
+
+        fn send(&mut self, tag: u8, body: &[u8]) {
+            self.stream.write_all(&[tag]).unwrap();
+            self.stream.write_all(&(body.len() as i32 + 4).to_be_bytes()).unwrap();
+            self.stream.write_all(body).unwrap();
+        }
+
+        fn query(&mut self, sql: &str) -> Vec<(u8, Vec<u8>)> {
+            let mut body = Vec::new();
+            put_str(&mut body, sql);
+            self.send(b'Q', &body);
+            self.until_ready()
+        }
+    }
+
+    // The strings of a body made of null terminated fields
+    fn strings(body: &[u8]) -> Vec<String> {
+        body.split(|b| *b == 0).filter(|s| !s.is_empty()).map(|s| String::from_utf8_lossy(s).into_owned()).collect()
+    }
+
+    fn data_row(body: &[u8]) -> Vec<Option<String>> {
+        let count = i16::from_be_bytes(body[..2].try_into().unwrap());
+        let mut at = 2;
+        (0..count)
+            .map(|_| {
+                let len = i32::from_be_bytes(body[at..at + 4].try_into().unwrap());
+                at += 4;
+                if len < 0 {
+                    return None;
+                }
+                let value = String::from_utf8_lossy(&body[at..at + len as usize]).into_owned();
+                at += len as usize;
+                Some(value)
+            })
+            .collect()
+    }
+
+    fn with_server(session: impl FnOnce(std::net::SocketAddr) + Send + 'static) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || session(addr));
+
+        let db = Database::in_memory();
+        let (stream, _) = listener.accept().unwrap();
+        handle(&stream, &stream, &db).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn test_simple_query() {
+        with_server(|addr| {
+            let mut client = Client::connect(addr);
+
+            let messages = client.query(
+                "create table t (id int, name text, ok boolean); insert into t values (1, 'a', true), (2, null, false)",
+            );
+            let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"CCZ");
+            assert_eq!(strings(&messages[0].1), vec!["CREATE TABLE"]);
+            assert_eq!(strings(&messages[1].1), vec!["INSERT 0 2"]);
+            assert_eq!(messages[2].1, b"I");
+
+            let messages = client.query("select id, name, ok from t order by id");
+            let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"TDDCZ");
+            let description = &messages[0].1;
+            assert_eq!(i16::from_be_bytes(description[..2].try_into().unwrap()), 3);
+            // "id", then the table oid and column number, then the type oid
+            assert_eq!(i32::from_be_bytes(description[11..15].try_into().unwrap()), 20);
+            assert_eq!(data_row(&messages[1].1), vec![Some("1".to_string()), Some("a".to_string()), Some("t".to_string())]);
+            assert_eq!(data_row(&messages[2].1), vec![Some("2".to_string()), None, Some("f".to_string())]);
+            assert_eq!(strings(&messages[3].1), vec!["SELECT 2"]);
+
+            client.send(b'X', &[]);
+        });
+    }
+
+    #[test]
+    fn test_errors_and_transactions() {
+        with_server(|addr| {
+            let mut client = Client::connect(addr);
+
+            let messages = client.query("begin; create table t (id int primary key)");
+            assert_eq!(messages.last().unwrap().1, b"T");
+
+            // The statements after a failing one are not run
+            let messages = client.query("insert into t values (1), (1); insert into t values (2)");
+            let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"EZ");
+            let fields = strings(&messages[0].1);
+            assert!(fields.contains(&"C23505".to_string()), "{:?}", fields);
+
+            // Until it ends the transaction refuses statements, and commit rolls back
+            let messages = client.query("insert into t values (2)");
+            let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"EZ");
+            assert!(strings(&messages[0].1).contains(&"C25P02".to_string()));
+            assert_eq!(messages[1].1, b"E");
+            let messages = client.query("commit;\nselect * from\n  missing");
+            assert_eq!(strings(&messages[0].1), vec!["ROLLBACK"]);
+            let fields = strings(&messages[1].1);
+            assert!(fields.contains(&"MUnknown table missing".to_string()), "{:?}", fields);
+            assert!(fields.contains(&"P25".to_string()), "{:?}", fields);
+            assert_eq!(messages.last().unwrap().1, b"I");
+            let messages = client.query("select * from t");
+            assert!(strings(&messages[0].1).contains(&"MUnknown table t".to_string()));
+
+            let messages = client.query("copy t to '/tmp/t.csv'");
+            let fields = strings(&messages[0].1);
+            assert!(fields.contains(&"MCOPY to or from a file is not allowed over a connection".to_string()));
+            assert!(fields.contains(&"C42501".to_string()));
+
+            // Outside of a transaction a failure changes nothing
+            let messages = client.query("select 1 / 0");
+            assert_eq!(messages.last().unwrap().1, b"I");
+
+            let messages = client.query(" ; ");
+            assert_eq!(messages[0].0, b'I');
+
+            // Parse is refused and the rest is skipped until Sync
+            client.send(b'P', b"\0select 1\0\0\0");
+            client.send(b'B', b"\0\0\0\0\0\0\0\0");
+            client.send(b'S', &[]);
+            let tags: Vec<u8> = client.until_ready().iter().map(|(tag, _)| *tag).collect();
+            assert_eq!(tags, b"EZ");
+        });
+    }
+
+    #[test]
+    fn test_clients_at_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Clones of a database in a file share its one connection
+        let db = Database::open(temp_path("server-clients")).unwrap();
+        std::thread::spawn(move || serve(&listener, &db));
+
+        let mut first = Client::connect(addr);
+        first.query("create table t (id int)");
+        let messages = first.query("begin; insert into t values (1)");
+        assert_eq!(messages.last().unwrap().1, b"T");
+
+        // Another client is served while the first is still connected, its
+        // query waits for the first one's transaction rather than join it
+        let mut second = Client::connect(addr);
+        let mut body = Vec::new();
+        put_str(&mut body, "select count(*) from t");
+        second.send(b'Q', &body);
+
+        // Going away without a commit rolls the transaction back
+        first.send(b'X', &[]);
+        let messages = second.until_ready();
+        let tags: Vec<u8> = messages.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, b"TDCZ");
+        assert_eq!(data_row(&messages[1].1), vec![Some("0".to_string())]);
+        assert_eq!(messages[3].1, b"I");
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::Path;
+
+use crate::Database;
+use crate::backend::{Engine, Value};
+
+/*
+    A runner for sqllogictest files, the text format SQLite and others keep
+    their correctness tests in. A file is a list of records separated by
+    blank lines, each running one statement:
+
+        statement ok
+        create table t (a int, b text)
+
+        statement error Unknown table nope
+        select * from nope
+
+        query IT rowsort
+        select a, b from t
+        ----
+        1 one
+        2 NULL
+
+    `statement ok` expects the statement to succeed and `statement error`
+    to fail, with a message containing the rest of the line when there is
+    one. `query` is followed by a letter per column, I for int, R for real,
+    T for text, B for boolean and D for anything else, and optionally by a
+    sort mode: rowsort sorts the rows, valuesort every value, before they
+    are compared, nosort, the default, keeps the order the query gave. The
+    expected rows follow `----`, a line each, the values separated by
+    spaces. A null is NULL and an empty text (empty).
+
+    Lines starting with # are comments. `skipif sqrldb` skips the record
+    after it, `onlyif x` skips it for any other x and `halt` ends the file.
+
+    The files are in tests/slt, each runs against a database of its own,
+    once for every storage engine that is built in.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expect {
+    Ok,
+    // The text the error message contains, which may be empty
+    Error(String),
+    Rows { types: Vec<char>, sort: Sort, rows: Vec<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    None,
+    Rows,
+    Values,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    // The line of the record's first line, counting from 1
+    line: usize,
+    sql: String,
+    expect: Expect,
+}
+
+const ENGINE: &str = "sqrldb";
+
+// The records of a file, the ones for another engine left out
+fn parse(text: &str) -> Result<Vec<Record>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut records = Vec::new();
+    let mut i = 0;
+    let mut skip = false;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let start = i + 1;
+        i += 1;
+
+        let expect = match words.as_slice() {
+            [] => continue,
+            [comment, ..] if comment.starts_with('#') => continue,
+            ["halt"] => break,
+            ["skipif", engine] => {
+                skip |= *engine == ENGINE;
+                continue;
+            }
+            ["onlyif", engine] => {
+                skip |= *engine != ENGINE;
+                continue;
+            }
+            ["statement", "ok"] => Expect::Ok,
+            ["statement", "error", message @ ..] => Expect::Error(message.join(" ")),
+            ["query", types, rest @ ..] => {
+                let sort = match rest.first() {
+                    None | Some(&"nosort") => Sort::None,
+                    Some(&"rowsort") => Sort::Rows,
+                    Some(&"valuesort") => Sort::Values,
+                    Some(mode) => return Err(format!("line {}: Unknown sort mode {}", start, mode)),
+                };
+                if let Some(c) = types.chars().find(|c| !"ITRBD".contains(*c)) {
+                    return Err(format!("line {}: Unknown column type {}", start, c));
+                }
+                Expect::Rows { types: types.chars().collect(), sort, rows: Vec::new() }
+            }
+            _ => return Err(format!("line {}: Expected statement or query, got {}", start, line)),
+        };
+
+        // The SQL runs up to a blank line, or for a query up to ----
+        let mut sql = Vec::new();
+        while i < lines.len() && !lines[i].trim().is_empty() && lines[i].trim_end() != "----" {
+            sql.push(lines[i]);
+            i += 1;
+        }
+        if sql.is_empty() {
+            return Err(format!("line {}: Expected SQL", start));
+        }
+        let mut expect = expect;
+        if let Expect::Rows { rows, .. } = &mut expect
+            && i < lines.len()
+            && lines[i].trim_end() == "----"
+        {
+            i += 1;
+            while i < lines.len() && !lines[i].trim().is_empty() {
+                rows.push(lines[i].split_whitespace().collect::<Vec<_>>().join(" "));
+                i += 1;
+            }
+        }
+
+        if !std::mem::take(&mut skip) {
+            records.push(Record { line: start, sql: sql.join("\n"), expect });
+        }
+    }
+    Ok(records)
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Text(s) if s.is_empty() => "(empty)".to_string(),
+        value => value.to_string(),
+    }
+}
+
+// Whether a value fits a column type letter, a null fits all of them
+fn has_type(value: &Value, letter: char) -> bool {
+    matches!(
+        (value, letter),
+        (Value::Null, _)
+            | (_, 'D')
+            | (Value::Int(_), 'I')
+            | (Value::Real(_), 'R')
+            | (Value::Text(_), 'T')
+            | (Value::Bool(_), 'B')
+    )
+}
+
+// Runs a record, Err says how the result differed from the expected one
+fn run(db: &Database, record: &Record) -> Result<(), String> {
+    match &record.expect {
+        Expect::Ok => db.execute(&record.sql).map(|_| ()).map_err(|err| format!("Expected ok, got error: {}", err)),
+        Expect::Error(message) => match db.execute(&record.sql) {
+            Ok(_) => Err("Expected an error, the statement succeeded".to_string()),
+            Err(err) if err.message().contains(message.as_str()) => Ok(()),
+            Err(err) => Err(format!("Expected an error containing '{}', got: {}", message, err)),
+        },
+        Expect::Rows { types, sort, rows: expected } => {
+            let result = db.query(&record.sql).map_err(|err| format!("Expected rows, got error: {}", err))?;
+            if result.columns().len() != types.len() {
+                return Err(format!("Expected {} columns, got {}", types.len(), result.columns().len()));
+            }
+            let mut rows = Vec::new();
+            for row in result {
+                let mismatch = row.values().iter().zip(types).find(|(value, letter)| !has_type(value, **letter));
+                if let Some((value, letter)) = mismatch {
+                    return Err(format!("Expected type {} for {}, got {}", letter, value, value.type_name()));
+                }
+                rows.push(row.values().iter().map(format_value).collect::<Vec<_>>());
+            }
+
+            let (actual, expected) = match sort {
+                Sort::None => (rows.iter().map(|row| row.join(" ")).collect(), expected.clone()),
+                Sort::Rows => {
+                    let mut actual: Vec<String> = rows.iter().map(|row| row.join(" ")).collect();
+                    let mut expected = expected.clone();
+                    actual.sort();
+                    expected.sort();
+                    (actual, expected)
+                }
+                Sort::Values => {
+                    let mut actual: Vec<String> = rows.into_iter().flatten().collect();
+                    let mut expected: Vec<String> =
+                        expected.iter().flat_map(|row| row.split(' ').map(str::to_string)).collect();
+                    actual.sort();
+                    expected.sort();
+                    (actual, expected)
+                }
+            };
+            if actual != expected {
+                return Err(format!("Expected\n    {}\ngot\n    {}", expected.join("\n    "), actual.join("\n    ")));
+            }
+            Ok(())
+        }
+    }
+}
+
+// Runs every record of the file against a new database of the engine,
+// returning a message per record that failed
+fn run_file(path: &Path, engine: &Engine) -> Vec<String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => return vec![format!("{}: {}", path.display(), err)],
+    };
+    let records = match parse(&text) {
+        Ok(records) => records,
+        Err(err) => return vec![format!("{}: {}", path.display(), err)],
+    };
+    let db = match Database::open_with(engine) {
+        Ok(db) => db,
+        Err(err) => return vec![format!("{}: {}", path.display(), err)],
+    };
+    let mut failures = Vec::new();
+    for record in &records {
+        if let Err(err) = run(&db, record) {
+            let (at, engine) = (path.display(), engine.name());
+            failures.push(format!("{}:{} ({}):\n{}\n{}", at, record.line, engine, record.sql, err));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::temp_path;
+
+    #[test]
+    fn test_parse_records() {
+        let text = "# setup\nstatement ok\ncreate table t\n  (a int)\n\n\
+                    statement error Unknown table\nselect * from x\n\n\
+                    skipif sqrldb\nstatement ok\nnot for us\n\n\
+                    query IT rowsort\nselect a, b\nfrom t\n----\n1   one\n2 NULL\n\n\
+                    halt\n\nstatement ok\nnever read";
+        let records = parse(text).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], Record { line: 2, sql: "create table t\n  (a int)".to_string(), expect: Expect::Ok });
+        assert_eq!(records[1].expect, Expect::Error("Unknown table".to_string()));
+        let rows = vec!["1 one".to_string(), "2 NULL".to_string()];
+        assert_eq!(records[2].line, 13);
+        assert_eq!(records[2].expect, Expect::Rows { types: vec!['I', 'T'], sort: Sort::Rows, rows });
+
+        assert_eq!(parse("query X\nselect 1").unwrap_err(), "line 1: Unknown column type X");
+        assert_eq!(parse("statement ok\n\n").unwrap_err(), "line 1: Expected SQL");
+        assert_eq!(parse("select 1").unwrap_err(), "line 1: Expected statement or query, got select 1");
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let db = Database::in_memory();
+        let records = parse(
+            "statement ok\ncreate table t (a int)\n\nstatement ok\ninsert into t values (1), (2)\n\n\
+             query I\nselect a from t\n----\n2\n1\n\nquery T\nselect a from t\n----\n1\n2\n\n\
+             statement error duplicate\ncreate table t (a int)",
+        )
+        .unwrap();
+        let results: Vec<Result<(), String>> = records.iter().map(|record| run(&db, record)).collect();
+        assert!(results[..2].iter().all(Result::is_ok));
+        assert_eq!(results[2], Err("Expected\n    2\n    1\ngot\n    1\n    2".to_string()));
+        assert_eq!(results[3], Err("Expected type T for 1, got int".to_string()));
+        assert_eq!(
+            results[4],
+            Err("Expected an error containing 'duplicate', got: Table t already exists at 1:14".to_string())
+        );
+    }
+
+    #[test]
+    fn test_logic_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/slt");
+        let mut paths: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "slt"));
+        paths.sort();
+        assert!(!paths.is_empty(), "No .slt files in {}", dir.display());
+
+        let engines = |path: &Path| {
+            let name = path.file_stem().unwrap().to_string_lossy();
+            [Engine::memory(), Engine::disk(temp_path(&name)), Engine::log(temp_path(&name))]
+        };
+        let failures: Vec<String> =
+            paths.iter().flat_map(|path| engines(path).map(|engine| run_file(path, &engine))).flatten().collect();
+        assert!(failures.is_empty(), "{} records failed:\n\n{}", failures.len(), failures.join("\n\n"));
+    }
+}